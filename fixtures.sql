@@ -0,0 +1,76 @@
+-- The database the tests read for what sample.db lacks. Rebuild it with
+--     rm fixtures.db && sqlite3 fixtures.db < fixtures.sql
+
+-- rowids at both ends of the range, which take nine-byte varints
+CREATE TABLE ids(id INTEGER PRIMARY KEY, name TEXT);
+INSERT INTO ids VALUES
+    (-9223372036854775808, 'min'),
+    (-5, 'minus five'),
+    (-1, 'minus one'),
+    (0, 'zero'),
+    (7, 'seven'),
+    (9223372036854775807, 'max');
+
+-- columns with an affinity and a collation, and views of them
+CREATE TABLE m(id INTEGER PRIMARY KEY, a INT, b TEXT COLLATE NOCASE);
+INSERT INTO m VALUES (1, 10, 'abc'), (2, 10, 'Abc'), (3, 9, 'xyz'), (4, '9x', 'ABD');
+CREATE VIEW mv AS SELECT id, a, b FROM m;
+CREATE VIEW mv2(x, y, z) AS SELECT b, a, a + 1 FROM mv;
+
+-- a column and an index declaring a collation that is not registered,
+-- which SQLite only lets in by rewriting the schema
+CREATE TABLE x(a TEXT COLLATE NOCASE, b TEXT);
+INSERT INTO x VALUES ('q', 'r');
+CREATE INDEX xb ON x(b COLLATE NOCASE);
+.dbconfig defensive off
+PRAGMA writable_schema = ON;
+UPDATE sqlite_schema SET sql = 'CREATE TABLE x(a TEXT COLLATE rev, b TEXT)' WHERE name = 'x';
+UPDATE sqlite_schema SET sql = 'CREATE INDEX xb ON x(b COLLATE rev)' WHERE name = 'xb';
+PRAGMA writable_schema = OFF;
+
+-- generated columns: a VIRTUAL one, which records leave out, between
+-- stored columns, and a STORED one
+CREATE TABLE g(
+    id INTEGER PRIMARY KEY,
+    a INT,
+    twice INT GENERATED ALWAYS AS (a * 2) VIRTUAL,
+    b TEXT,
+    label TEXT AS (b || ':' || twice) STORED
+);
+INSERT INTO g(id, a, b) VALUES (1, 3, 'x'), (2, NULL, 'y'), (3, 5, NULL);
+
+-- rows written before columns were added, whose records are short
+CREATE TABLE d(id INTEGER PRIMARY KEY, a TEXT);
+INSERT INTO d VALUES (1, 'old'), (2, 'older');
+ALTER TABLE d ADD COLUMN n INT DEFAULT 5;
+ALTER TABLE d ADD COLUMN s TEXT DEFAULT 'none';
+ALTER TABLE d ADD COLUMN z;
+INSERT INTO d VALUES (3, 'new', 7, 'some', 'z');
+
+-- the same for a WITHOUT ROWID table, whose rows are index entries
+CREATE TABLE dw(k PRIMARY KEY, a) WITHOUT ROWID;
+INSERT INTO dw VALUES (1, 2);
+ALTER TABLE dw ADD COLUMN z DEFAULT 9;
+INSERT INTO dw VALUES (2, 3, 4);
+
+-- an analyzed WITHOUT ROWID table with a descending primary key, whose
+-- statistics sqlite_stat1 keeps under the name of the table
+CREATE TABLE wr2(a, b, c, PRIMARY KEY(b DESC, a)) WITHOUT ROWID;
+INSERT INTO wr2 VALUES (1, 'x', 10), (2, 'x', 20), (1, 'y', 30), (2, 'y', 40), (3, 'w', 50);
+CREATE INDEX wr2c ON wr2(c);
+ANALYZE wr2;
+
+-- indexes to look rows up in: one with a descending column and NULLs,
+-- and one of a WITHOUT ROWID table that does not hold all its columns
+CREATE TABLE lk(id INTEGER PRIMARY KEY, a INT, b TEXT COLLATE NOCASE, c);
+INSERT INTO lk VALUES
+    (1, 1, 'x', 'one'),
+    (2, 2, 'X', 'two'),
+    (3, 2, 'y', NULL),
+    (4, NULL, 'x', 'four'),
+    (5, 3, NULL, 'five'),
+    (6, 2, 'x', 'six');
+CREATE INDEX lka ON lk(a DESC, b);
+CREATE TABLE lw(k TEXT, j INT, v, w, PRIMARY KEY(k, j DESC)) WITHOUT ROWID;
+INSERT INTO lw VALUES (1, 1, 5, 'p'), ('a', 2, NULL, 'q'), ('a', 1, 5, 'r'), ('b', 3, 6, NULL);
+CREATE INDEX lwv ON lw(v);
@@ -0,0 +1,300 @@
+//! How much of the file each table and index takes, as `sqlite3_analyzer`
+//! reports it: the pages of each b-tree by kind, how deep it is, how many
+//! entries it holds and how full its pages are.
+//! See https://www.sqlite.org/sqlanalyze.html
+
+use std::fmt::{self, Write};
+
+use crate::db::Database;
+use crate::freelist::Freelist;
+use crate::page::parse_cell;
+
+/// The space one b-tree takes.
+#[derive(Default)]
+pub struct TreeStats {
+    pub name: String,
+    /// The table an index is on; that of a table is its own name.
+    pub table: String,
+    pub is_index: bool,
+    pub interior_pages: u64,
+    pub leaf_pages: u64,
+    pub overflow_pages: u64,
+    /// The number of levels of pages, 1 for a tree that is only its root.
+    pub depth: u32,
+    /// The rows of a table, or the entries of an index.
+    pub entries: u64,
+    /// The bytes of the records of the entries, on overflow pages too.
+    pub payload_bytes: u64,
+    /// The bytes of the pages that hold nothing: those between the cell
+    /// pointers and the cells, the free blocks and fragments, and the end of
+    /// the last overflow page of a record.
+    pub unused_bytes: u64,
+    /// The free bytes in fragments too small to be free blocks, as the page
+    /// headers count them.
+    pub fragmented_bytes: u64,
+}
+
+impl TreeStats {
+    pub fn pages(&self) -> u64 {
+        self.interior_pages + self.leaf_pages + self.overflow_pages
+    }
+
+    pub fn average_payload(&self) -> f64 {
+        match self.entries {
+            0 => 0.0,
+            n => self.payload_bytes as f64 / n as f64,
+        }
+    }
+}
+
+/// The space used in a database file.
+pub struct Analysis {
+    pub page_size: usize,
+    /// The pages in the file.
+    pub pages: u32,
+    pub free_pages: u64,
+    /// Each b-tree: `sqlite_schema`, then the tables and indexes in the
+    /// order they were created.
+    pub trees: Vec<TreeStats>,
+}
+
+impl Analysis {
+    /// The percentage of the pages of the file that `pages` is.
+    fn percent(&self, pages: u64) -> f64 {
+        match self.pages {
+            0 => 0.0,
+            n => pages as f64 * 100.0 / n as f64,
+        }
+    }
+
+    /// The report as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"page_size\":{},\"pages\":{},\"free_pages\":{},\"trees\":[",
+            self.page_size, self.pages, self.free_pages
+        );
+        for (i, tree) in self.trees.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"name\":{},\"table\":{},\"type\":\"{}\",\"pages\":{},\
+                 \"interior_pages\":{},\"leaf_pages\":{},\"overflow_pages\":{},\
+                 \"depth\":{},\"entries\":{},\"payload_bytes\":{},\
+                 \"average_payload\":{:.1},\"unused_bytes\":{},\
+                 \"fragmented_bytes\":{},\"percent\":{:.1}}}",
+                json_string(&tree.name),
+                json_string(&tree.table),
+                if tree.is_index { "index" } else { "table" },
+                tree.pages(),
+                tree.interior_pages,
+                tree.leaf_pages,
+                tree.overflow_pages,
+                tree.depth,
+                tree.entries,
+                tree.payload_bytes,
+                tree.average_payload(),
+                tree.unused_bytes,
+                tree.fragmented_bytes,
+                self.percent(tree.pages()),
+            );
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Page size: {} bytes", self.page_size)?;
+        writeln!(f, "Pages in the file: {}", self.pages)?;
+        let free = self.free_pages;
+        writeln!(f, "Free pages: {free} ({:.1}%)", self.percent(free))?;
+        for tree in &self.trees {
+            writeln!(f)?;
+            match tree.is_index {
+                true => writeln!(f, "*** {} (index on {}) ***", tree.name, tree.table)?,
+                false => writeln!(f, "*** {} (table) ***", tree.name)?,
+            }
+            let pages = tree.pages();
+            writeln!(f, "Percentage of the file: {:.1}%", self.percent(pages))?;
+            writeln!(
+                f,
+                "Pages: {pages} ({} interior, {} leaf, {} overflow)",
+                tree.interior_pages, tree.leaf_pages, tree.overflow_pages
+            )?;
+            writeln!(f, "Depth: {}", tree.depth)?;
+            writeln!(f, "Entries: {}", tree.entries)?;
+            writeln!(f, "Payload: {} bytes", tree.payload_bytes)?;
+            writeln!(f, "Average payload: {:.1} bytes", tree.average_payload())?;
+            let size = pages * self.page_size as u64;
+            let unused = match size {
+                0 => 0.0,
+                size => tree.unused_bytes as f64 * 100.0 / size as f64,
+            };
+            writeln!(f, "Unused bytes: {} ({unused:.1}%)", tree.unused_bytes)?;
+            writeln!(f, "Fragmented bytes: {}", tree.fragmented_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Walk every b-tree of the database.
+pub fn analyze(db: &Database) -> Analysis {
+    let pages = db.size();
+    let mut walk = Walk {
+        db,
+        usable: db.info.usable_size() as u32,
+        seen: vec![false; pages as usize + 1],
+    };
+    let schema = TreeStats {
+        name: "sqlite_schema".to_string(),
+        table: "sqlite_schema".to_string(),
+        ..TreeStats::default()
+    };
+    let mut trees = vec![(schema, 1)];
+    for t in db.schema.iter().filter(|t| t.root_page != 0) {
+        if t.typ != "table" && t.typ != "index" {
+            continue;
+        }
+        let tree = TreeStats {
+            name: t.name.clone(),
+            table: t.table_name.clone(),
+            is_index: t.typ == "index",
+            ..TreeStats::default()
+        };
+        trees.push((tree, t.root_page));
+    }
+    for (tree, root) in &mut trees {
+        tree.depth = walk.page(tree, *root);
+    }
+    Analysis {
+        page_size: db.info.page_size(),
+        pages,
+        free_pages: Freelist::read(db).pages().count() as u64,
+        trees: trees.into_iter().map(|(tree, _)| tree).collect(),
+    }
+}
+
+struct Walk<'d> {
+    db: &'d Database,
+    usable: u32,
+    /// The pages already counted, by page number, so that a damaged file
+    /// cannot lead the walk round in circles.
+    seen: Vec<bool>,
+}
+
+impl Walk<'_> {
+    /// Add a page of a b-tree and those below it to its statistics,
+    /// returning the depth of the tree below it, itself included.
+    fn page(&mut self, tree: &mut TreeStats, page: u32) -> u32 {
+        match self.seen.get_mut(page as usize) {
+            Some(seen) if page != 0 && !*seen => *seen = true,
+            _ => return 0,
+        }
+        let Ok(data) = self.db.raw_page(page) else {
+            return 0;
+        };
+        let hdr = if page == 1 { 100 } else { 0 };
+        let (leaf, int_key) = match data[hdr] {
+            0x02 => (false, false),
+            0x05 => (false, true),
+            0x0a => (true, false),
+            0x0d => (true, true),
+            _ => return 0,
+        };
+        let cells = u16::from_be_bytes([data[hdr + 3], data[hdr + 4]]) as usize;
+        let header = hdr + if leaf { 8 } else { 12 };
+        let cells = cells.min(data.len().saturating_sub(header) / 2);
+        match leaf {
+            true => tree.leaf_pages += 1,
+            false => tree.interior_pages += 1,
+        }
+        tree.fragmented_bytes += data[hdr + 7] as u64;
+        // the cells of an interior table page are only keys, not entries
+        if leaf || !int_key {
+            tree.entries += cells as u64;
+        }
+
+        let mut used = (header + 2 * cells) as u64;
+        let mut depth = 0;
+        for i in 0..cells {
+            let at = header + 2 * i;
+            let pc = u16::from_be_bytes([data[at], data[at + 1]]) as usize;
+            let Some(cell) = parse_cell(data, pc, leaf, int_key, self.usable) else {
+                continue;
+            };
+            used += cell.size as u64;
+            tree.payload_bytes += cell.payload as u64;
+            if cell.payload > cell.local {
+                // every overflow page but the last is full
+                let spilled = (cell.payload - cell.local) as u64;
+                let per_page = self.usable as u64 - 4;
+                let pages = spilled.div_ceil(per_page);
+                tree.overflow_pages += pages;
+                tree.unused_bytes += pages * per_page - spilled;
+            }
+            if !leaf && pc + 4 <= data.len() {
+                let child =
+                    u32::from_be_bytes([data[pc], data[pc + 1], data[pc + 2], data[pc + 3]]);
+                depth = depth.max(self.page(tree, child));
+            }
+        }
+        if !leaf {
+            let right =
+                u32::from_be_bytes([data[hdr + 8], data[hdr + 9], data[hdr + 10], data[hdr + 11]]);
+            depth = depth.max(self.page(tree, right));
+        }
+        tree.unused_bytes += (self.usable as u64).saturating_sub(used);
+        depth + 1
+    }
+}
+
+/// A string as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut json = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::open_sample;
+
+    #[test]
+    fn test_analyze() {
+        let db = open_sample();
+        let analysis = analyze(&db);
+        let names = analysis
+            .trees
+            .iter()
+            .map(|t| t.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec!["sqlite_schema", "apples", "sqlite_sequence", "oranges"]
+        );
+        let oranges = &analysis.trees[3];
+        assert_eq!((oranges.pages(), oranges.depth, oranges.entries), (1, 1, 6));
+        assert_eq!((oranges.payload_bytes, oranges.unused_bytes), (220, 3844));
+        assert!(analysis
+            .to_string()
+            .contains("*** oranges (table) ***\nPercentage of the file: 25.0%"));
+        assert!(analysis
+            .to_json()
+            .starts_with("{\"page_size\":4096,\"pages\":4,\"free_pages\":0,"));
+        assert_eq!(json_string("a\"b\\\n"), "\"a\\\"b\\\\\\u000a\"");
+    }
+}
@@ -0,0 +1,677 @@
+use anyhow::{anyhow, bail};
+
+use crate::table::ColumnType;
+
+/// A parsed SQL statement with the values bound to its parameters. It can
+/// be executed any number of times, with the same or new bindings.
+/// See https://www.sqlite.org/lang_expr.html#parameters
+#[derive(Debug)]
+pub struct Statement {
+    pub kind: StatementKind,
+    /// Set for `EXPLAIN ...`, which describes the statement instead of
+    /// running it.
+    pub explain: Option<Explain>,
+    /// The name of each parameter by index, from 1; anonymous `?`
+    /// parameters have none.
+    parameters: Vec<Option<String>>,
+    bindings: Vec<ColumnType>,
+}
+
+#[derive(Debug)]
+pub enum StatementKind {
+    Select(Box<Select>),
+    CreateTable(CreateTable),
+    #[allow(dead_code)]
+    CreateIndex(CreateIndex),
+    CreateView(CreateView),
+    Pragma(Pragma),
+}
+
+/// What an `EXPLAIN` prefix asks for.
+/// See https://www.sqlite.org/lang_explain.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Explain {
+    QueryPlan,
+    /// Plain `EXPLAIN`: the bytecode program the statement compiles to.
+    Program,
+}
+
+/// A parameter of a statement, by index from 1 or by name with its prefix,
+/// as in `:name`.
+#[derive(Debug, Clone, Copy)]
+pub enum ParameterRef<'a> {
+    Index(usize),
+    Name(&'a str),
+}
+
+impl From<usize> for ParameterRef<'_> {
+    fn from(index: usize) -> Self {
+        ParameterRef::Index(index)
+    }
+}
+
+impl<'a> From<&'a str> for ParameterRef<'a> {
+    fn from(name: &'a str) -> Self {
+        ParameterRef::Name(name)
+    }
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, parameters: Vec<Option<String>>) -> Self {
+        Statement {
+            kind,
+            explain: None,
+            parameters,
+            bindings: vec![],
+        }
+    }
+
+    /// The number of parameters, which is the largest parameter index.
+    pub fn parameter_count(&self) -> usize {
+        self.parameters.len()
+    }
+
+    pub fn parameter_name(&self, index: usize) -> Option<&str> {
+        self.parameters.get(index.checked_sub(1)?)?.as_deref()
+    }
+
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        let i = self
+            .parameters
+            .iter()
+            .position(|p| p.as_deref() == Some(name))?;
+        Some(i + 1)
+    }
+
+    /// Bind a value to a parameter, replacing any value bound before.
+    pub fn bind<'a>(
+        &mut self,
+        parameter: impl Into<ParameterRef<'a>>,
+        value: ColumnType,
+    ) -> Result<(), anyhow::Error> {
+        let index = match parameter.into() {
+            ParameterRef::Index(i) if (1..=self.parameters.len()).contains(&i) => i,
+            ParameterRef::Index(i) => bail!("parameter index out of range: {i}"),
+            ParameterRef::Name(name) => self
+                .parameter_index(name)
+                .ok_or_else(|| anyhow!("no such parameter: {name}"))?,
+        };
+        if self.bindings.len() < index {
+            self.bindings.resize(index, ColumnType::Null);
+        }
+        self.bindings[index - 1] = value;
+        Ok(())
+    }
+
+    /// Reset all parameters to NULL.
+    pub fn clear_bindings(&mut self) {
+        self.bindings.clear();
+    }
+
+    /// The value bound to a parameter, NULL if none is.
+    pub fn binding(&self, index: usize) -> ColumnType {
+        index
+            .checked_sub(1)
+            .and_then(|i| self.bindings.get(i))
+            .cloned()
+            .unwrap_or(ColumnType::Null)
+    }
+}
+
+/// https://www.sqlite.org/lang_select.html
+#[derive(Debug)]
+pub struct Select {
+    pub with: Option<With>,
+    pub body: SelectCore,
+    /// Further selects combined with the body, in order. The operators all
+    /// have the same precedence and associate to the left.
+    pub compound: Vec<(CompoundOp, SelectCore)>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompoundOp {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+impl CompoundOp {
+    pub fn name(self) -> &'static str {
+        match self {
+            CompoundOp::Union => "UNION",
+            CompoundOp::UnionAll => "UNION ALL",
+            CompoundOp::Intersect => "INTERSECT",
+            CompoundOp::Except => "EXCEPT",
+        }
+    }
+}
+
+/// https://www.sqlite.org/lang_with.html
+#[derive(Debug)]
+pub struct With {
+    /// SQLite lets any CTE refer to itself, so this is only informational.
+    #[allow(dead_code)]
+    pub recursive: bool,
+    pub ctes: Vec<CommonTableExpr>,
+}
+
+#[derive(Debug)]
+pub struct CommonTableExpr {
+    pub name: String,
+    /// The optional column names, `name(a, b) AS (...)`.
+    pub columns: Vec<String>,
+    /// `AS MATERIALIZED` is `Some(true)`, `AS NOT MATERIALIZED` is `Some(false)`.
+    pub materialized: Option<bool>,
+    pub select: Box<Select>,
+}
+
+#[derive(Debug)]
+pub struct SelectCore {
+    pub distinct: bool,
+    pub columns: Vec<ResultColumn>,
+    pub from: Option<FromClause>,
+    pub where_clause: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    /// Named windows from the WINDOW clause.
+    pub windows: Vec<(String, WindowDef)>,
+}
+
+#[derive(Debug)]
+pub enum ResultColumn {
+    /// `*`
+    Star,
+    /// `table.*`
+    TableStar(String),
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+        /// The expression as written, which names the column if there is no alias.
+        text: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum FromClause {
+    Table {
+        name: String,
+        alias: Option<String>,
+    },
+    /// A subquery used as a derived table.
+    Subquery {
+        select: Box<Select>,
+        alias: Option<String>,
+    },
+    /// A table-valued function, `name(args)`, such as
+    /// `pragma_table_info('t')`. The `pragma_...` functions can also be
+    /// named without arguments.
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        alias: Option<String>,
+    },
+    Join {
+        left: Box<FromClause>,
+        right: Box<FromClause>,
+        kind: JoinKind,
+        constraint: Option<JoinConstraint>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    /// An inner join whose tables are read in the order written.
+    Cross,
+}
+
+#[derive(Debug)]
+pub enum JoinConstraint {
+    On(Expr),
+    Using(Vec<String>),
+}
+
+#[derive(Debug)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub desc: bool,
+    /// `NULLS FIRST` is `Some(true)`, `NULLS LAST` is `Some(false)`.
+    pub nulls_first: Option<bool>,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Literal(ColumnType),
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Between {
+        expr: Box<Expr>,
+        negated: bool,
+        low: Box<Expr>,
+        high: Box<Expr>,
+    },
+    InList {
+        expr: Box<Expr>,
+        negated: bool,
+        list: Vec<Expr>,
+    },
+    InSelect {
+        expr: Box<Expr>,
+        negated: bool,
+        select: Box<Select>,
+    },
+    Exists(Box<Select>),
+    /// `CASE [operand] WHEN ... THEN ... [ELSE ...] END`; with an operand
+    /// each WHEN value is compared to it, otherwise each is a condition.
+    Case {
+        operand: Option<Box<Expr>>,
+        branches: Vec<(Expr, Expr)>,
+        else_result: Option<Box<Expr>>,
+    },
+    /// `expr COLLATE collation`, which selects the collating sequence for
+    /// comparing the value.
+    Collate {
+        expr: Box<Expr>,
+        collation: String,
+    },
+    /// A parameter, by index from 1, whose value is bound to the statement.
+    Parameter(usize),
+    /// `CAST(expr AS type_name)`
+    Cast {
+        expr: Box<Expr>,
+        type_name: String,
+    },
+    /// A scalar subquery: the first column of the first row, or NULL.
+    Subquery(Box<Select>),
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        distinct: bool,
+        /// `count(*)`
+        star: bool,
+        /// `FILTER (WHERE expr)`: the rows an aggregate call takes.
+        filter: Option<Box<Expr>>,
+        /// Present for a window function call.
+        over: Option<Box<Over>>,
+    },
+}
+
+/// The window of a window function call.
+/// https://www.sqlite.org/windowfunctions.html
+#[derive(Debug)]
+pub enum Over {
+    /// `OVER name`, a window from the WINDOW clause.
+    Named(String),
+    /// `OVER (...)`
+    Spec(Box<WindowDef>),
+}
+
+#[derive(Debug)]
+pub struct WindowDef {
+    /// The named window this one builds on, as in `OVER (win ORDER BY x)`.
+    pub base: Option<String>,
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    pub frame: Option<Frame>,
+}
+
+#[derive(Debug)]
+pub struct Frame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound,
+    pub exclude: FrameExclude,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameUnits {
+    Rows,
+    Range,
+    Groups,
+}
+
+#[derive(Debug)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(Expr),
+    CurrentRow,
+    Following(Expr),
+    UnboundedFollowing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameExclude {
+    NoOthers,
+    CurrentRow,
+    Group,
+    Ties,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Plus,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Is,
+    IsNot,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Concat,
+}
+
+/// https://www.sqlite.org/lang_createtable.html
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct CreateTable {
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    /// The columns of the primary key, from a `PRIMARY KEY` column
+    /// constraint or a table-level `PRIMARY KEY (...)`.
+    pub primary_key: Vec<IndexedColumn>,
+    /// The PRIMARY KEY and UNIQUE constraints in the order written, each
+    /// with whether it is the primary key: see [`CreateTable::autoindexes`].
+    pub keys: Vec<(Vec<IndexedColumn>, bool)>,
+    pub foreign_keys: Vec<ForeignKey>,
+    /// Whether the table was declared `WITHOUT ROWID`: its rows are the
+    /// entries of an index b-tree in the order of the primary key.
+    pub without_rowid: bool,
+}
+
+impl CreateTable {
+    /// The index in `columns` of each column of the primary key, each once.
+    pub fn key_columns(&self) -> Vec<usize> {
+        let mut key = vec![];
+        for column in &self.primary_key {
+            let i = self
+                .columns
+                .iter()
+                .position(|c| c.name.eq_ignore_ascii_case(&column.name));
+            if let Some(i) = i.filter(|i| !key.contains(i)) {
+                key.push(i);
+            }
+        }
+        key
+    }
+
+    /// For a WITHOUT ROWID table, the column of each field of its records:
+    /// the columns of the primary key, then the others in order.
+    pub fn record_columns(&self) -> Option<Vec<usize>> {
+        if !self.without_rowid {
+            return None;
+        }
+        let mut order = self.key_columns();
+        let rest = self
+            .stored_columns()
+            .into_iter()
+            .filter(|i| !order.contains(i))
+            .collect::<Vec<_>>();
+        order.extend(rest);
+        Some(order)
+    }
+
+    /// The columns that have a field in the records, in order: all but the
+    /// VIRTUAL generated ones.
+    pub fn stored_columns(&self) -> Vec<usize> {
+        (0..self.columns.len())
+            .filter(|&i| !self.columns[i].is_virtual())
+            .collect()
+    }
+
+    /// The indexes SQLite makes for the PRIMARY KEY and UNIQUE constraints,
+    /// in the order it numbers them in their names,
+    /// `sqlite_autoindex_<table>_<n>`, each with whether it is the primary
+    /// key. A constraint on the same columns as one before shares its index,
+    /// and an INTEGER PRIMARY KEY has none: it is the rowid.
+    pub fn autoindexes(&self) -> Vec<(&[IndexedColumn], bool)> {
+        let collation = |c: &IndexedColumn| {
+            c.collation.clone().or_else(|| {
+                self.columns
+                    .iter()
+                    .find(|d| d.name.eq_ignore_ascii_case(&c.name))
+                    .and_then(|d| d.collation.clone())
+            })
+        };
+        let same = |a: &[IndexedColumn], b: &[IndexedColumn]| {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(a, b)| {
+                    a.name.eq_ignore_ascii_case(&b.name)
+                        && collation(a)
+                            .unwrap_or_default()
+                            .eq_ignore_ascii_case(&collation(b).unwrap_or_default())
+                })
+        };
+        let mut indexes: Vec<(&[IndexedColumn], bool)> = vec![];
+        for (columns, primary_key) in &self.keys {
+            if let ([column], true, false) = (&columns[..], *primary_key, self.without_rowid) {
+                let integer = self.columns.iter().any(|c| {
+                    c.name.eq_ignore_ascii_case(&column.name)
+                        && c.type_name
+                            .as_deref()
+                            .is_some_and(|t| t.eq_ignore_ascii_case("integer"))
+                });
+                if integer && !column.desc {
+                    continue;
+                }
+            }
+            match indexes.iter_mut().find(|(c, _)| same(c, columns)) {
+                Some(index) => index.1 |= *primary_key,
+                None => indexes.push((columns, *primary_key)),
+            }
+        }
+        indexes
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct ColumnDef {
+    pub name: String,
+    pub type_name: Option<String>,
+    pub primary_key: bool,
+    pub not_null: bool,
+    /// The `COLLATE` constraint, if any.
+    pub collation: Option<String>,
+    /// The `DEFAULT` value, which is also that of the rows written before
+    /// the column was added by `ALTER TABLE ... ADD COLUMN`.
+    pub default: Option<Expr>,
+    /// The `DEFAULT` as written, without any parentheses around it.
+    pub default_text: Option<String>,
+    /// `[GENERATED ALWAYS] AS (expr) [VIRTUAL | STORED]`
+    pub generated: Option<Generated>,
+}
+
+/// The expression computing a generated column, and whether its value is
+/// stored in the records or computed whenever the row is read.
+/// https://www.sqlite.org/gencol.html
+#[derive(Debug)]
+pub struct Generated {
+    pub expr: Expr,
+    pub stored: bool,
+}
+
+impl ColumnDef {
+    /// Whether the column is a VIRTUAL generated column, which has no field
+    /// in the records of the table.
+    pub fn is_virtual(&self) -> bool {
+        self.generated.as_ref().is_some_and(|g| !g.stored)
+    }
+
+    /// Whether the column is an INTEGER PRIMARY KEY, which is an alias for
+    /// the rowid.
+    pub fn is_rowid_alias(&self) -> bool {
+        self.primary_key
+            && self
+                .type_name
+                .as_deref()
+                .is_some_and(|t| t.eq_ignore_ascii_case("integer"))
+    }
+}
+
+/// A `FOREIGN KEY` constraint, or the `REFERENCES` clause of a column.
+/// https://www.sqlite.org/foreignkeys.html
+#[derive(Debug)]
+pub struct ForeignKey {
+    /// The columns of the child table.
+    pub columns: Vec<String>,
+    /// The parent table, and the columns of it the key refers to: none for
+    /// its primary key.
+    pub table: String,
+    pub to: Vec<String>,
+    /// The actions, such as `CASCADE`; `NO ACTION` if none is given.
+    pub on_update: String,
+    pub on_delete: String,
+}
+
+/// https://www.sqlite.org/lang_createindex.html
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct CreateIndex {
+    pub name: String,
+    pub table: String,
+    pub unique: bool,
+    pub columns: Vec<IndexedColumn>,
+    /// The WHERE clause of a partial index.
+    /// https://www.sqlite.org/partialindex.html
+    pub where_clause: Option<Expr>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct IndexedColumn {
+    pub name: String,
+    /// An explicit `COLLATE`; otherwise the column's own collation applies.
+    pub collation: Option<String>,
+    pub desc: bool,
+}
+
+/// https://www.sqlite.org/lang_createview.html
+#[derive(Debug)]
+pub struct CreateView {
+    pub name: String,
+    /// The optional column names, `name(a, b) AS ...`.
+    pub columns: Vec<String>,
+    pub select: Box<Select>,
+}
+
+/// `PRAGMA [schema.]name [= value]`, or `PRAGMA name(value)`.
+/// https://www.sqlite.org/pragma.html
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct Pragma {
+    pub schema: Option<String>,
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Expr {
+    /// The expression without any `COLLATE` applied to it.
+    pub fn skip_collate(&self) -> &Expr {
+        match self {
+            Expr::Collate { expr, .. } => expr.skip_collate(),
+            e => e,
+        }
+    }
+
+    /// Visit this expression and its sub-expressions, but not the contents of
+    /// nested subqueries.
+    pub fn walk<'a>(&'a self, f: &mut dyn FnMut(&'a Expr)) {
+        f(self);
+        match self {
+            Expr::Literal(_)
+            | Expr::Column { .. }
+            | Expr::Parameter(_)
+            | Expr::Exists(_)
+            | Expr::Subquery(_) => {}
+            Expr::Unary { expr, .. } => expr.walk(f),
+            Expr::Binary { left, right, .. } => {
+                left.walk(f);
+                right.walk(f);
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                expr.walk(f);
+                low.walk(f);
+                high.walk(f);
+            }
+            Expr::InList { expr, list, .. } => {
+                expr.walk(f);
+                list.iter().for_each(|e| e.walk(f));
+            }
+            Expr::InSelect { expr, .. } => expr.walk(f),
+            Expr::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                operand.iter().for_each(|e| e.walk(f));
+                for (when, then) in branches {
+                    when.walk(f);
+                    then.walk(f);
+                }
+                else_result.iter().for_each(|e| e.walk(f));
+            }
+            Expr::Cast { expr, .. } | Expr::Collate { expr, .. } => expr.walk(f),
+            Expr::Function {
+                args, filter, over, ..
+            } => {
+                args.iter().for_each(|e| e.walk(f));
+                filter.iter().for_each(|e| e.walk(f));
+                if let Some(Over::Spec(window)) = over.as_deref() {
+                    window.walk(f);
+                }
+            }
+        }
+    }
+}
+
+impl WindowDef {
+    /// Visit the expressions of the window definition, as [`Expr::walk`].
+    pub fn walk<'a>(&'a self, f: &mut dyn FnMut(&'a Expr)) {
+        self.partition_by.iter().for_each(|e| e.walk(f));
+        self.order_by.iter().for_each(|t| t.expr.walk(f));
+        if let Some(frame) = &self.frame {
+            for bound in [&frame.start, &frame.end] {
+                if let FrameBound::Preceding(e) | FrameBound::Following(e) = bound {
+                    e.walk(f);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use anyhow::bail;
+
+use crate::db::Database;
+use crate::page::{Cell, DbRecord, PageInfo, PageType};
+
+/// Visit every row of the table b-tree rooted at `root_page` in rowid order.
+/// The visitor returns `false` to stop the scan early; the return value tells
+/// whether the scan ran to completion.
+pub fn scan_table(
+    db: &Database,
+    root_page: u32,
+    visit: &mut dyn FnMut(i64, DbRecord) -> Result<bool, anyhow::Error>,
+) -> Result<bool, anyhow::Error> {
+    scan_table_from(db, root_page, i64::MIN, visit)
+}
+
+/// Visit the rows of the table b-tree rooted at `root_page` in rowid order,
+/// starting at the first one whose rowid is at least `start`. Only the
+/// subtrees that can hold such rows are read. The visitor returns `false`
+/// to stop, as for [`scan_table`].
+pub fn scan_table_from(
+    db: &Database,
+    root_page: u32,
+    start: i64,
+    visit: &mut dyn FnMut(i64, DbRecord) -> Result<bool, anyhow::Error>,
+) -> Result<bool, anyhow::Error> {
+    let page = db.read_page(root_page)?;
+    let first = search_row_id(&page, start);
+
+    match page.page_type {
+        PageType::LeafTable => {
+            for cell in page.cells.into_iter().skip(first) {
+                if let Cell::TableLeaf(leaf) = cell {
+                    if !visit(leaf.row_id, leaf.payload)? {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+        PageType::InternalTable => {
+            for cell in page.cells.into_iter().skip(first) {
+                if let Cell::TableInterior { left_child, .. } = cell {
+                    if !scan_table_from(db, left_child, start, visit)? {
+                        return Ok(false);
+                    }
+                }
+            }
+            if let Some(right) = page.rightmost_pointer {
+                return scan_table_from(db, right, start, visit);
+            }
+        }
+        t => bail!("expected a table b-tree page at {root_page}, found {t:?}"),
+    }
+
+    Ok(true)
+}
+
+/// Visit the entries of the index b-tree rooted at `root_page` in index
+/// order, starting at the first one for which `before_start` is false. The
+/// visitor returns `false` to stop, as for [`scan_table`].
+pub fn scan_index(
+    db: &Database,
+    root_page: u32,
+    before_start: &dyn Fn(&DbRecord) -> bool,
+    visit: &mut dyn FnMut(DbRecord) -> Result<bool, anyhow::Error>,
+) -> Result<bool, anyhow::Error> {
+    let page = db.read_page(root_page)?;
+
+    match page.page_type {
+        PageType::LeafIndex => {
+            for cell in page.cells {
+                if let Cell::IndexLeaf { payload } = cell {
+                    if !before_start(&payload) && !visit(payload)? {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+        PageType::InternalIndex => {
+            for cell in page.cells {
+                if let Cell::IndexInterior {
+                    left_child,
+                    payload,
+                } = cell
+                {
+                    // the left subtree holds only entries before this one
+                    if before_start(&payload) {
+                        continue;
+                    }
+                    if !scan_index(db, left_child, before_start, visit)? || !visit(payload)? {
+                        return Ok(false);
+                    }
+                }
+            }
+            if let Some(right) = page.rightmost_pointer {
+                return scan_index(db, right, before_start, visit);
+            }
+        }
+        t => bail!("expected an index b-tree page at {root_page}, found {t:?}"),
+    }
+
+    Ok(true)
+}
+
+/// Look up the row with the given rowid in the table b-tree rooted at
+/// `root_page`.
+pub fn find_row(
+    db: &Database,
+    root_page: u32,
+    row_id: i64,
+) -> Result<Option<DbRecord>, anyhow::Error> {
+    let page = db.read_page(root_page)?;
+    let i = search_row_id(&page, row_id);
+
+    match page.page_type {
+        PageType::LeafTable => Ok(match page.cells.into_iter().nth(i) {
+            Some(Cell::TableLeaf(leaf)) if leaf.row_id == row_id => Some(leaf.payload),
+            _ => None,
+        }),
+        PageType::InternalTable => match child_page(&page, i) {
+            0 => Ok(None),
+            child => find_row(db, child, row_id),
+        },
+        t => bail!("expected a table b-tree page at {root_page}, found {t:?}"),
+    }
+}
+
+/// The number of rows in the table b-tree rooted at `root_page`, or of
+/// entries in an index b-tree, counted from the page headers without
+/// decoding the records.
+pub fn count_rows(db: &Database, root_page: u32) -> Result<u64, anyhow::Error> {
+    let page = db.read_page(root_page)?;
+
+    match page.page_type {
+        PageType::LeafTable | PageType::LeafIndex => Ok(page.num_cells as u64),
+        PageType::InternalTable | PageType::InternalIndex => {
+            // the cells of an interior index page are entries too
+            let mut count = match page.page_type {
+                PageType::InternalIndex => page.num_cells as u64,
+                _ => 0,
+            };
+            for i in 0..=page.cells.len() {
+                count += count_rows(db, child_page(&page, i))?;
+            }
+            Ok(count)
+        }
+    }
+}
+
+/// A position in a b-tree, for stepping through its entries one at a time
+/// in key order. Table b-trees have their entries in the leaves only; index
+/// b-trees also have entries in the interior pages, between the subtrees.
+pub struct Cursor<'d> {
+    db: &'d Database,
+    root_page: u32,
+    /// The pages from the root down to the current entry, each with the
+    /// index of the cell the cursor is at or below. On an interior page
+    /// the index of the rightmost pointer is the number of cells.
+    stack: Vec<(PageInfo, usize)>,
+}
+
+impl<'d> Cursor<'d> {
+    pub fn new(db: &'d Database, root_page: u32) -> Self {
+        Cursor {
+            db,
+            root_page,
+            stack: vec![],
+        }
+    }
+
+    /// Move to the first entry. Returns false if there is none.
+    pub fn first(&mut self) -> Result<bool, anyhow::Error> {
+        self.stack.clear();
+        self.descend(self.root_page)?;
+        self.settle()
+    }
+
+    /// Move to the next entry. Returns false at the end.
+    pub fn next(&mut self) -> Result<bool, anyhow::Error> {
+        let Some((page, i)) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+        *i += 1;
+        if is_interior(page) {
+            // from an entry of an interior page into the subtree after it
+            let child = child_page(page, *i);
+            self.descend(child)?;
+        }
+        self.settle()
+    }
+
+    /// Move a table cursor to the first row whose rowid is at least
+    /// `row_id`. Returns false if there is none.
+    pub fn seek_row_id(&mut self, row_id: i64) -> Result<bool, anyhow::Error> {
+        self.stack.clear();
+        let mut page_num = self.root_page;
+        loop {
+            let page = self.db.read_page(page_num)?;
+            let i = search_row_id(&page, row_id);
+            match page.page_type {
+                PageType::LeafTable => {
+                    self.stack.push((page, i));
+                    return self.settle();
+                }
+                PageType::InternalTable => {
+                    page_num = child_page(&page, i);
+                    self.stack.push((page, i));
+                }
+                t => bail!("expected a table b-tree page at {page_num}, found {t:?}"),
+            }
+        }
+    }
+
+    /// Move an index cursor to the first entry for which `before_start` is
+    /// false, as for [`scan_index`]. Returns false if there is none.
+    pub fn seek_index(
+        &mut self,
+        before_start: &dyn Fn(&DbRecord) -> bool,
+    ) -> Result<bool, anyhow::Error> {
+        self.stack.clear();
+        let mut page_num = self.root_page;
+        loop {
+            let page = self.db.read_page(page_num)?;
+            let i = page
+                .cells
+                .iter()
+                .position(|cell| match cell {
+                    Cell::IndexLeaf { payload } | Cell::IndexInterior { payload, .. } => {
+                        !before_start(payload)
+                    }
+                    _ => false,
+                })
+                .unwrap_or(page.cells.len());
+            match page.page_type {
+                PageType::LeafIndex => {
+                    self.stack.push((page, i));
+                    return self.settle();
+                }
+                PageType::InternalIndex => {
+                    // entries from the start on may be in the subtree
+                    // before the first entry not before it
+                    page_num = child_page(&page, i);
+                    self.stack.push((page, i));
+                }
+                t => bail!("expected an index b-tree page at {page_num}, found {t:?}"),
+            }
+        }
+    }
+
+    /// The rowid of the current entry of a table cursor.
+    pub fn row_id(&self) -> Option<i64> {
+        match self.cell()? {
+            Cell::TableLeaf(leaf) => Some(leaf.row_id),
+            _ => None,
+        }
+    }
+
+    /// The record of the current entry.
+    pub fn record(&self) -> Option<&DbRecord> {
+        match self.cell()? {
+            Cell::TableLeaf(leaf) => Some(&leaf.payload),
+            Cell::IndexLeaf { payload } | Cell::IndexInterior { payload, .. } => Some(payload),
+            Cell::TableInterior { .. } => None,
+        }
+    }
+
+    fn cell(&self) -> Option<&Cell> {
+        let (page, i) = self.stack.last()?;
+        page.cells.get(*i)
+    }
+
+    /// Push the pages from `page_num` down to the leftmost leaf below it.
+    fn descend(&mut self, mut page_num: u32) -> Result<(), anyhow::Error> {
+        loop {
+            let page = self.db.read_page(page_num)?;
+            let interior = is_interior(&page);
+            if interior {
+                page_num = child_page(&page, 0);
+            }
+            self.stack.push((page, 0));
+            if !interior {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Having moved within the leaf at the top of the stack, go on to the
+    /// next entry if the leaf has no more: the entry of an index interior
+    /// page after the subtree, or the next subtree.
+    fn settle(&mut self) -> Result<bool, anyhow::Error> {
+        loop {
+            let Some((page, i)) = self.stack.last() else {
+                return Ok(false);
+            };
+            // only an index interior page is left at the top on an entry
+            if *i < page.cells.len() && page.page_type != PageType::InternalTable {
+                return Ok(true);
+            }
+            self.stack.pop();
+            let Some((parent, i)) = self.stack.last_mut() else {
+                return Ok(false);
+            };
+            if parent.page_type == PageType::InternalIndex && *i < parent.cells.len() {
+                // the parent's entry follows the subtree
+                return Ok(true);
+            }
+            *i += 1;
+            if *i <= parent.cells.len() {
+                let child = child_page(parent, *i);
+                self.descend(child)?;
+            }
+        }
+    }
+}
+
+/// Binary search the cells of a table b-tree page for `row_id`: on a leaf,
+/// the index of the first row with a rowid at least `row_id`; on an interior
+/// page, the index of the subtree that holds it, since each left child holds
+/// the rowids up to its cell's key. The cells are in key order, so this reads
+/// O(log n) of them.
+fn search_row_id(page: &PageInfo, row_id: i64) -> usize {
+    page.cells.partition_point(|cell| match cell {
+        Cell::TableLeaf(leaf) => leaf.row_id < row_id,
+        Cell::TableInterior { row_id: key, .. } => *key < row_id,
+        _ => false,
+    })
+}
+
+fn is_interior(page: &PageInfo) -> bool {
+    matches!(
+        page.page_type,
+        PageType::InternalIndex | PageType::InternalTable
+    )
+}
+
+/// The page of the `i`th subtree of an interior page: the left child of
+/// cell `i`, or the rightmost pointer after the last cell.
+fn child_page(page: &PageInfo, i: usize) -> u32 {
+    match page.cells.get(i) {
+        Some(Cell::TableInterior { left_child, .. } | Cell::IndexInterior { left_child, .. }) => {
+            *left_child
+        }
+        _ => page.rightmost_pointer.unwrap_or_default(),
+    }
+}
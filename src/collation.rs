@@ -0,0 +1,185 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use anyhow::bail;
+
+use crate::table::ColumnType;
+use crate::value::ValueKey;
+
+/// A user-defined collating function, comparing two texts.
+pub type CollationFn = dyn Fn(&str, &str) -> Ordering;
+
+/// A collating sequence: how text values compare. Values of other types
+/// compare the same under every collation.
+/// See https://www.sqlite.org/datatype3.html#collation
+#[derive(Clone, Default)]
+pub enum Collation {
+    /// Byte by byte.
+    #[default]
+    Binary,
+    /// Like BINARY, with ASCII upper case letters folded to lower case.
+    NoCase,
+    /// Like BINARY, ignoring trailing spaces.
+    RTrim,
+    Custom(Rc<CollationFn>),
+}
+
+impl fmt::Debug for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Collation::Binary => write!(f, "Binary"),
+            Collation::NoCase => write!(f, "NoCase"),
+            Collation::RTrim => write!(f, "RTrim"),
+            Collation::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Collations are equal if they are the same built-in one, or the same
+/// registered function.
+impl PartialEq for Collation {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Collation::Custom(a), Collation::Custom(b)) => Rc::ptr_eq(a, b),
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
+}
+
+impl Collation {
+    pub fn compare_text(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            Collation::NoCase => a
+                .bytes()
+                .map(|b| b.to_ascii_lowercase())
+                .cmp(b.bytes().map(|b| b.to_ascii_lowercase())),
+            Collation::RTrim => a.trim_end_matches(' ').cmp(b.trim_end_matches(' ')),
+            Collation::Custom(compare) => compare(a, b),
+        }
+    }
+
+    /// Compare two values in the sort order of SQLite, with text compared
+    /// under this collation.
+    pub fn compare(&self, a: &ColumnType, b: &ColumnType) -> Ordering {
+        match (a, b) {
+            (ColumnType::String(a), ColumnType::String(b)) => self.compare_text(a, b),
+            _ => a.sql_cmp(b),
+        }
+    }
+}
+
+/// The collating sequences known by name: the built-in ones and those
+/// registered by the application.
+#[derive(Default)]
+pub struct Collations {
+    custom: HashMap<String, Rc<CollationFn>>,
+}
+
+impl Collations {
+    /// Register a collating function under `name`, replacing any collation
+    /// of that name.
+    pub fn register(&mut self, name: &str, compare: impl Fn(&str, &str) -> Ordering + 'static) {
+        self.custom
+            .insert(name.to_ascii_lowercase(), Rc::new(compare));
+    }
+
+    /// The names of the collations, the registered ones first.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.custom.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names.extend(["BINARY", "NOCASE", "RTRIM"].map(String::from));
+        names
+    }
+
+    pub fn find(&self, name: &str) -> Result<Collation, anyhow::Error> {
+        if let Some(compare) = self.custom.get(&name.to_ascii_lowercase()) {
+            return Ok(Collation::Custom(compare.clone()));
+        }
+        Ok(match name.to_ascii_uppercase().as_str() {
+            "BINARY" => Collation::Binary,
+            "NOCASE" => Collation::NoCase,
+            "RTRIM" => Collation::RTrim,
+            _ => bail!("no such collation sequence: {name}"),
+        })
+    }
+}
+
+/// Makes hash keys for rows of values compared under the given collations,
+/// such that values that compare equal get equal keys. Text under a custom
+/// collation takes the key of the first equal text seen.
+pub(crate) struct CollatedKeys {
+    collations: Vec<Collation>,
+    seen: Vec<Vec<String>>,
+}
+
+impl CollatedKeys {
+    pub fn new(collations: Vec<Collation>) -> Self {
+        let seen = vec![vec![]; collations.len()];
+        CollatedKeys { collations, seen }
+    }
+
+    pub fn key(&mut self, row: &[ColumnType]) -> Vec<ValueKey> {
+        row.iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let ColumnType::String(s) = v else {
+                    return v.key();
+                };
+                let text = match self.collations.get(i) {
+                    None | Some(Collation::Binary) => s.clone(),
+                    Some(Collation::NoCase) => s.to_ascii_lowercase(),
+                    Some(Collation::RTrim) => s.trim_end_matches(' ').to_string(),
+                    Some(collation @ Collation::Custom(_)) => {
+                        // representatives are kept in collation order
+                        let seen = &mut self.seen[i];
+                        match seen.binary_search_by(|t| collation.compare_text(t, s)) {
+                            Ok(at) => seen[at].clone(),
+                            Err(at) => {
+                                seen.insert(at, s.clone());
+                                s.clone()
+                            }
+                        }
+                    }
+                };
+                ColumnType::String(text).key()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collations() {
+        let mut collations = Collations::default();
+        let nocase = collations.find("nocase").unwrap();
+        assert_eq!(nocase.compare_text("ABC", "abc"), Ordering::Equal);
+        assert_eq!(nocase.compare_text("a", "B"), Ordering::Less);
+        // only ASCII letters are folded
+        assert_ne!(nocase.compare_text("Ä", "ä"), Ordering::Equal);
+        let rtrim = collations.find("RTRIM").unwrap();
+        assert_eq!(rtrim.compare_text("a  ", "a"), Ordering::Equal);
+        assert_eq!(rtrim.compare_text(" a", "a"), Ordering::Less);
+        assert_eq!(
+            Collation::Binary.compare(&ColumnType::Int64(1), &ColumnType::String("a".into())),
+            Ordering::Less
+        );
+        assert!(collations.find("reverse").is_err());
+
+        collations.register("REVERSE", |a, b| b.cmp(a));
+        let reverse = collations.find("reverse").unwrap();
+        assert_eq!(reverse.compare_text("a", "b"), Ordering::Greater);
+
+        collations.register("length", |a, b| a.len().cmp(&b.len()));
+        let mut keys = CollatedKeys::new(vec![collations.find("length").unwrap(), nocase]);
+        let text = |s: &str| ColumnType::String(s.to_string());
+        let first = keys.key(&[text("ab"), text("X")]);
+        assert_eq!(keys.key(&[text("cd"), text("x")]), first);
+        assert_ne!(keys.key(&[text("abc"), text("x")]), first);
+    }
+}
@@ -0,0 +1,451 @@
+use crate::table::ColumnType;
+use crate::value::format_real;
+
+/// The type affinity of a column or expression: the storage class its
+/// values are converted to when stored or compared.
+/// See https://www.sqlite.org/datatype3.html#type_affinity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    Blob,
+}
+
+impl Affinity {
+    /// The affinity of a type name, by the rules of SQLite's
+    /// sqlite3AffinityType(): the first of INT, then CHAR, CLOB or TEXT,
+    /// then BLOB, then REAL, FLOA or DOUB found in the name decides, and
+    /// anything else is NUMERIC.
+    pub fn from_type_name(name: &str) -> Affinity {
+        let mut affinity = Affinity::Numeric;
+        let mut window = 0u32;
+        for b in name.bytes() {
+            window = (window << 8) | b.to_ascii_lowercase() as u32;
+            match &window.to_be_bytes() {
+                [_, b'i', b'n', b't'] => return Affinity::Integer,
+                b"char" | b"clob" | b"text" => affinity = Affinity::Text,
+                b"blob" if matches!(affinity, Affinity::Numeric | Affinity::Real) => {
+                    affinity = Affinity::Blob
+                }
+                b"real" | b"floa" | b"doub" if affinity == Affinity::Numeric => {
+                    affinity = Affinity::Real
+                }
+                _ => {}
+            }
+        }
+        affinity
+    }
+
+    /// The affinity of a column declared with `type_name`; a column without
+    /// a declared type has BLOB affinity.
+    pub fn of_column(type_name: Option<&str>) -> Affinity {
+        type_name.map_or(Affinity::Blob, Affinity::from_type_name)
+    }
+
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Affinity::Numeric | Affinity::Integer | Affinity::Real)
+    }
+
+    /// The affinity applied to both operands of a comparison, given the
+    /// operands' own affinities; expressions other than columns and CASTs
+    /// have none. Two columns compare numerically if either one is numeric,
+    /// and otherwise as they are. A single column imposes its affinity.
+    pub fn for_comparison(left: Option<Affinity>, right: Option<Affinity>) -> Option<Affinity> {
+        match (left, right) {
+            (Some(l), Some(r)) if l.is_numeric() || r.is_numeric() => Some(Affinity::Numeric),
+            (Some(_), Some(_)) => None,
+            (a, None) | (None, a) => a,
+        }
+    }
+}
+
+impl ColumnType {
+    /// The value as stored in a column with the given affinity. Text that
+    /// is a well-formed number becomes a number under the numeric
+    /// affinities, and numbers become text under TEXT.
+    pub fn apply_affinity(&self, affinity: Affinity) -> ColumnType {
+        match affinity {
+            Affinity::Text => match self {
+                ColumnType::String(_) | ColumnType::Blob(_) | ColumnType::Null => self.clone(),
+                v => ColumnType::String(v.to_text().unwrap_or_default()),
+            },
+            Affinity::Numeric | Affinity::Integer => match self {
+                ColumnType::String(s) => match parse_number(s) {
+                    Some(n) => integral_real_to_int(n),
+                    None => self.clone(),
+                },
+                ColumnType::Float(_) => integral_real_to_int(self.clone()),
+                v => v.clone(),
+            },
+            Affinity::Real => match self {
+                ColumnType::String(s) => match parse_number(s) {
+                    Some(n) => ColumnType::Float(n.to_f64().unwrap_or_default()),
+                    None => self.clone(),
+                },
+                v => match v.as_integer() {
+                    Some(n) => ColumnType::Float(n as f64),
+                    None => v.clone(),
+                },
+            },
+            Affinity::Blob => self.clone(),
+        }
+    }
+
+    /// A value read from a column with the given affinity. REAL columns
+    /// store reals with an integral value as integers to save space.
+    pub fn read_with_affinity(self, affinity: Affinity) -> ColumnType {
+        match (affinity, self.as_integer()) {
+            (Affinity::Real, Some(n)) => ColumnType::Float(n as f64),
+            _ => self,
+        }
+    }
+
+    /// `CAST(value AS type)` for a type with the given affinity. Unlike the
+    /// implicit conversions, a cast always converts; text that is not a
+    /// number converts by its longest numeric prefix.
+    /// See https://www.sqlite.org/lang_expr.html#castexpr
+    pub fn cast(&self, affinity: Affinity) -> ColumnType {
+        if self.is_null() {
+            return ColumnType::Null;
+        }
+        match affinity {
+            Affinity::Text => ColumnType::String(self.to_text().unwrap_or_default()),
+            Affinity::Blob => match self {
+                ColumnType::Blob(_) => self.clone(),
+                v => ColumnType::Blob(v.to_text().unwrap_or_default().into_bytes()),
+            },
+            Affinity::Real => ColumnType::Float(self.to_f64().unwrap_or_default()),
+            Affinity::Integer => match self {
+                ColumnType::String(s) => ColumnType::Int64(parse_integer_prefix(s)),
+                ColumnType::Blob(b) => {
+                    ColumnType::Int64(parse_integer_prefix(&String::from_utf8_lossy(b)))
+                }
+                // `as` saturates, and takes NaN to 0
+                ColumnType::Float(f) => ColumnType::Int64(*f as i64),
+                v => ColumnType::Int64(v.as_integer().unwrap_or_default()),
+            },
+            Affinity::Numeric => match self {
+                ColumnType::String(_) | ColumnType::Blob(_) => match self.to_numeric() {
+                    // only integral reals within 2^51 become integers here
+                    ColumnType::Float(f) if f == f.trunc() && f.abs() < 2251799813685248.0 => {
+                        ColumnType::Int64(f as i64)
+                    }
+                    n => n,
+                },
+                v => v.clone(),
+            },
+        }
+    }
+
+    /// Convert to a number for arithmetic: integers and reals stay as they
+    /// are, text and blobs use their longest numeric prefix (or 0), and NULL
+    /// stays NULL.
+    pub fn to_numeric(&self) -> ColumnType {
+        match self {
+            ColumnType::Null => ColumnType::Null,
+            ColumnType::Float(f) => ColumnType::Float(*f),
+            ColumnType::String(s) => parse_numeric_prefix(s),
+            ColumnType::Blob(b) => parse_numeric_prefix(&String::from_utf8_lossy(b)),
+            v => ColumnType::Int64(v.as_integer().unwrap_or_default()),
+        }
+    }
+
+    /// The value as an integer if it is one, or is text that is an integer
+    /// in full; None for reals, blobs and any other text.
+    pub fn to_exact_integer(&self) -> Option<i64> {
+        match self {
+            ColumnType::String(s) => match parse_number(s) {
+                Some(ColumnType::Int64(n)) => Some(n),
+                _ => None,
+            },
+            ColumnType::Blob(_) => None,
+            v => v.as_integer(),
+        }
+    }
+
+    /// The value as a float, after numeric conversion.
+    pub fn to_f64(&self) -> Option<f64> {
+        match self.to_numeric() {
+            ColumnType::Float(f) => Some(f),
+            ColumnType::Null => None,
+            v => v.as_integer().map(|n| n as f64),
+        }
+    }
+
+    /// The value as an integer, after numeric conversion; reals are truncated.
+    pub fn to_i64(&self) -> Option<i64> {
+        match self.to_numeric() {
+            ColumnType::Float(f) => Some(f as i64),
+            ColumnType::Null => None,
+            v => v.as_integer(),
+        }
+    }
+
+    /// Interpret the value as a boolean; NULL is neither true nor false.
+    pub fn truthy(&self) -> Option<bool> {
+        match self.to_numeric() {
+            ColumnType::Null => None,
+            ColumnType::Float(f) => Some(f != 0.0),
+            v => Some(v.as_integer() != Some(0)),
+        }
+    }
+
+    /// The text representation of the value, or None for NULL.
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            ColumnType::Null => None,
+            ColumnType::String(s) => Some(s.clone()),
+            ColumnType::Blob(b) => Some(String::from_utf8_lossy(b).to_string()),
+            ColumnType::Float(f) => Some(format_real(*f)),
+            v => v.as_integer().map(|n| n.to_string()),
+        }
+    }
+}
+
+/// A real with an integral value in the range of integers, as an integer.
+fn integral_real_to_int(v: ColumnType) -> ColumnType {
+    match v {
+        ColumnType::Float(f) if f == f.trunc() && f > i64::MIN as f64 && f < i64::MAX as f64 => {
+            ColumnType::Int64(f as i64)
+        }
+        v => v,
+    }
+}
+
+/// Text that is a number in full, apart from surrounding whitespace: an
+/// integer if it is written as one and fits, else a real.
+pub(crate) fn parse_number(s: &str) -> Option<ColumnType> {
+    let s = s.trim_matches(|c: char| c.is_ascii_whitespace());
+    let digits = s.strip_prefix(['+', '-']).unwrap_or(s);
+    let (mantissa, exponent) = match digits.find(['e', 'E']) {
+        Some(i) => (&digits[..i], Some(&digits[i + 1..])),
+        None => (digits, None),
+    };
+    let (int, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if int.len() + fraction.len() == 0 || !all_digits(int) || !all_digits(fraction) {
+        return None;
+    }
+    if let Some(exponent) = exponent {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if exponent.is_empty() || !all_digits(exponent) {
+            return None;
+        }
+    }
+
+    if exponent.is_none() && !mantissa.contains('.') {
+        if let Ok(n) = s.parse::<i64>() {
+            return Some(ColumnType::Int64(n));
+        }
+    }
+    s.parse::<f64>().ok().map(ColumnType::Float)
+}
+
+/// The integer at the start of `s`, after any whitespace, clamped to the
+/// range of integers; 0 if there is none.
+fn parse_integer_prefix(s: &str) -> i64 {
+    let s = s.trim_start_matches(|c: char| c.is_ascii_whitespace());
+    let (negative, digits) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let mut n: i64 = 0;
+    for d in digits.bytes().take_while(|b| b.is_ascii_digit()) {
+        let d = (d - b'0') as i64;
+        n = match n.checked_mul(10).and_then(|n| {
+            if negative {
+                n.checked_sub(d)
+            } else {
+                n.checked_add(d)
+            }
+        }) {
+            Some(n) => n,
+            None if negative => return i64::MIN,
+            None => return i64::MAX,
+        };
+    }
+    n
+}
+
+/// Parse the longest prefix of `s` that looks like a number, ignoring leading
+/// whitespace, as SQLite does when text is used in arithmetic.
+fn parse_numeric_prefix(s: &str) -> ColumnType {
+    let s = s.trim_start();
+    let bytes = s.as_bytes();
+    let mut end = 0;
+
+    if matches!(bytes.first(), Some(b'+') | Some(b'-')) {
+        end += 1;
+    }
+    let digits_start = end;
+    while end < bytes.len() && bytes[end].is_ascii_digit() {
+        end += 1;
+    }
+    let mut int_digits = end - digits_start;
+    let mut is_real = false;
+
+    if bytes.get(end) == Some(&b'.') {
+        let mut frac_end = end + 1;
+        while frac_end < bytes.len() && bytes[frac_end].is_ascii_digit() {
+            frac_end += 1;
+        }
+        if int_digits > 0 || frac_end > end + 1 {
+            int_digits += frac_end - end - 1;
+            is_real = true;
+            end = frac_end;
+        }
+    }
+
+    if int_digits == 0 {
+        return ColumnType::Int64(0);
+    }
+
+    if matches!(bytes.get(end), Some(b'e') | Some(b'E')) {
+        let mut exp_end = end + 1;
+        if matches!(bytes.get(exp_end), Some(b'+') | Some(b'-')) {
+            exp_end += 1;
+        }
+        if bytes.get(exp_end).is_some_and(|b| b.is_ascii_digit()) {
+            while exp_end < bytes.len() && bytes[exp_end].is_ascii_digit() {
+                exp_end += 1;
+            }
+            is_real = true;
+            end = exp_end;
+        }
+    }
+
+    let text = &s[..end];
+    if !is_real {
+        if let Ok(n) = text.parse::<i64>() {
+            return ColumnType::Int64(n);
+        }
+    }
+    ColumnType::Float(text.parse::<f64>().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ColumnType::*;
+
+    fn text(s: &str) -> ColumnType {
+        String(s.to_string())
+    }
+
+    #[test]
+    fn test_affinity_of_type_name() {
+        use Affinity::*;
+        for (name, affinity) in [
+            ("INTEGER", Integer),
+            ("tinyint", Integer),
+            ("BIGINT UNSIGNED", Integer),
+            ("VARCHAR(255)", Text),
+            ("NATIVE CHARACTER(70)", Text),
+            ("CLOB", Text),
+            ("BLOB", Blob),
+            ("REAL", Real),
+            ("DOUBLE PRECISION", Real),
+            ("FLOAT", Real),
+            ("NUMERIC", Numeric),
+            ("DECIMAL(10,5)", Numeric),
+            ("DATETIME", Numeric),
+            ("", Numeric),
+            // INT wins over everything, and the first match wins otherwise
+            ("CHARINT", Integer),
+            ("FLOATING POINT", Integer),
+            ("STRING", Numeric),
+            ("TEXTBLOB", Text),
+            ("BLOBREAL", Blob),
+        ] {
+            assert_eq!(Affinity::from_type_name(name), affinity, "{name}");
+        }
+        assert_eq!(Affinity::of_column(None), Blob);
+    }
+
+    #[test]
+    fn test_comparison_affinity() {
+        use Affinity::*;
+        assert_eq!(
+            Affinity::for_comparison(Some(Text), Some(Integer)),
+            Some(Numeric)
+        );
+        assert_eq!(
+            Affinity::for_comparison(Some(Blob), Some(Real)),
+            Some(Numeric)
+        );
+        assert_eq!(Affinity::for_comparison(Some(Text), Some(Blob)), None);
+        assert_eq!(Affinity::for_comparison(Some(Text), None), Some(Text));
+        assert_eq!(Affinity::for_comparison(None, Some(Blob)), Some(Blob));
+        assert_eq!(Affinity::for_comparison(None, None), None);
+    }
+
+    #[test]
+    fn test_apply_affinity() {
+        use Affinity::*;
+        assert_eq!(text(" 10 ").apply_affinity(Integer), Int64(10));
+        assert_eq!(text("3.0").apply_affinity(Numeric), Int64(3));
+        assert_eq!(text("3.5").apply_affinity(Numeric), Float(3.5));
+        assert_eq!(text("1e2").apply_affinity(Numeric), Int64(100));
+        assert_eq!(text("1e20").apply_affinity(Integer), Float(1e20));
+        assert_eq!(text("0x10").apply_affinity(Integer), text("0x10"));
+        assert_eq!(text("12abc").apply_affinity(Numeric), text("12abc"));
+        assert_eq!(Float(2.0).apply_affinity(Integer), Int64(2));
+        assert_eq!(text("2").apply_affinity(Real), Float(2.0));
+        assert_eq!(Int64(2).apply_affinity(Real), Float(2.0));
+        assert_eq!(Int64(10).apply_affinity(Text), text("10"));
+        assert_eq!(Float(2.5).apply_affinity(Text), text("2.5"));
+        assert_eq!(
+            ColumnType::Blob(b"10".to_vec()).apply_affinity(Numeric),
+            ColumnType::Blob(b"10".to_vec())
+        );
+        assert_eq!(Null.apply_affinity(Text), Null);
+        assert_eq!(text("10").apply_affinity(Blob), text("10"));
+        assert_eq!(Int64(3).read_with_affinity(Real), Float(3.0));
+        assert_eq!(Int64(3).read_with_affinity(Numeric), Int64(3));
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("12"), Some(Int64(12)));
+        assert_eq!(parse_number(" 1e5"), Some(Float(1e5)));
+        assert_eq!(parse_number("-.5 "), Some(Float(-0.5)));
+        assert_eq!(parse_number("1a"), None);
+        assert_eq!(parse_number("inf"), None);
+        assert_eq!(parse_number("1e"), None);
+    }
+
+    #[test]
+    fn test_cast() {
+        use Affinity::*;
+        assert_eq!(text("1e3").cast(Integer), Int64(1));
+        assert_eq!(text("  12abc").cast(Integer), Int64(12));
+        assert_eq!(text("9999999999999999999").cast(Integer), Int64(i64::MAX));
+        assert_eq!(text("-9999999999999999999").cast(Integer), Int64(i64::MIN));
+        assert_eq!(Float(1e30).cast(Integer), Int64(i64::MAX));
+        assert_eq!(Float(-2.7).cast(Integer), Int64(-2));
+        assert_eq!(text("abc").cast(Real), Float(0.0));
+        assert_eq!(Int64(3).cast(Real), Float(3.0));
+        assert_eq!(text("1e18").cast(Numeric), Float(1e18));
+        assert_eq!(text("4e15").cast(Numeric), Float(4e15));
+        assert_eq!(text("1e3").cast(Numeric), Int64(1000));
+        assert_eq!(text("-0.0").cast(Numeric), Int64(0));
+        assert_eq!(text("3.5x").cast(Numeric), Float(3.5));
+        assert_eq!(ColumnType::Blob(b"12".to_vec()).cast(Numeric), Int64(12));
+        assert_eq!(Float(2.0).cast(Numeric), Float(2.0));
+        assert_eq!(Int64(12).cast(Text), text("12"));
+        assert_eq!(Float(1.5).cast(Blob), ColumnType::Blob(b"1.5".to_vec()));
+        assert_eq!(Null.cast(Integer), Null);
+    }
+
+    #[test]
+    fn test_to_numeric() {
+        assert_eq!(text(" 12abc").to_numeric(), Int64(12));
+        assert_eq!(text("1.5e2x").to_numeric(), Float(150.0));
+        assert_eq!(text("abc").to_numeric(), Int64(0));
+        assert_eq!(text("-.5").to_numeric(), Float(-0.5));
+        assert_eq!(Null.to_numeric(), Null);
+    }
+}
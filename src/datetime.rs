@@ -0,0 +1,1084 @@
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::convert::parse_number;
+use crate::functions::printf;
+use crate::table::ColumnType;
+
+/// Milliseconds in a day.
+const DAY_MS: i64 = 86400000;
+/// The unix epoch as a julian day in milliseconds.
+const UNIX_EPOCH_JD: i64 = 210866760000000;
+/// 9999-12-31 23:59:59.999, the last moment the functions handle.
+const MAX_JD: i64 = 464269060799999;
+
+/// The units of `+NNN units` modifiers: the name, the largest magnitude
+/// allowed and the length of one unit in seconds.
+const UNITS: [(&str, f64, f64); 6] = [
+    ("second", 4.6427e14, 1.0),
+    ("minute", 7.7379e12, 60.0),
+    ("hour", 1.2897e11, 3600.0),
+    ("day", 5373485.0, 86400.0),
+    ("month", 176546.0, 2592000.0),
+    ("year", 14713.0, 31536000.0),
+];
+
+/// A moment being built from a time value and its modifiers. It is held as
+/// a julian day number in milliseconds, as a calendar date and time, or
+/// both, and each form is computed from the other when needed.
+/// See https://www.sqlite.org/lang_datefunc.html
+#[derive(Clone, Default)]
+struct DateTime {
+    jd: i64,
+    y: i64,
+    month: i64,
+    d: i64,
+    h: i64,
+    min: i64,
+    s: f64,
+    /// The time zone offset in minutes, until it is applied to `jd`.
+    tz: i64,
+    valid_jd: bool,
+    valid_ymd: bool,
+    valid_hms: bool,
+    /// `s` holds a bare number, which modifiers like `unixepoch` interpret.
+    raw_s: bool,
+    use_subsec: bool,
+    is_utc: bool,
+    is_local: bool,
+    is_error: bool,
+    /// The days past the end of the month after adding months or years,
+    /// which the `floor` modifier takes back.
+    n_floor: i64,
+}
+
+/// Call one of the date and time functions, which return NULL for any time
+/// value or modifier they do not understand.
+pub(crate) fn call(name: &str, args: &[ColumnType]) -> ColumnType {
+    match name {
+        "strftime" => {
+            let (Some(format), Some(mut p)) = (args[0].to_text(), DateTime::parse(&args[1..]))
+            else {
+                return ColumnType::Null;
+            };
+            p.strftime(&format)
+                .map_or(ColumnType::Null, ColumnType::String)
+        }
+        "timediff" => match (DateTime::parse(&args[..1]), DateTime::parse(&args[1..])) {
+            (Some(a), Some(b)) => ColumnType::String(timediff(a, b)),
+            _ => ColumnType::Null,
+        },
+        _ => {
+            let Some(mut p) = DateTime::parse(args) else {
+                return ColumnType::Null;
+            };
+            match name {
+                "julianday" => ColumnType::Float(p.jd as f64 / DAY_MS as f64),
+                "unixepoch" if p.use_subsec => {
+                    ColumnType::Float((p.jd - UNIX_EPOCH_JD) as f64 / 1000.0)
+                }
+                "unixepoch" => ColumnType::Int64(p.jd / 1000 - UNIX_EPOCH_JD / 1000),
+                "date" => {
+                    p.compute_ymd();
+                    ColumnType::String(p.date_text())
+                }
+                "time" => {
+                    p.compute_hms();
+                    ColumnType::String(p.time_text())
+                }
+                _ => {
+                    p.compute_ymd_hms();
+                    ColumnType::String(format!("{} {}", p.date_text(), p.time_text()))
+                }
+            }
+        }
+    }
+}
+
+impl DateTime {
+    fn now() -> DateTime {
+        let ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        DateTime {
+            jd: ms + UNIX_EPOCH_JD,
+            valid_jd: true,
+            ..DateTime::default()
+        }
+    }
+
+    fn error() -> DateTime {
+        DateTime {
+            is_error: true,
+            ..DateTime::default()
+        }
+    }
+
+    /// A time value followed by modifiers, or None if any is invalid. With
+    /// no arguments at all the time is now.
+    fn parse(args: &[ColumnType]) -> Option<DateTime> {
+        let Some(value) = args.first() else {
+            return Some(DateTime::now());
+        };
+        let mut p = DateTime::default();
+        match value {
+            ColumnType::Null => return None,
+            ColumnType::String(_) | ColumnType::Blob(_) => {
+                if !p.parse_time_value(&value.to_text()?) {
+                    return None;
+                }
+            }
+            v => p.set_raw_number(v.to_f64()?),
+        }
+        for (i, modifier) in args.iter().enumerate().skip(1) {
+            if !p.apply_modifier(&modifier.to_text()?, i) {
+                return None;
+            }
+        }
+        p.compute_jd();
+        if p.is_error || !(0..=MAX_JD).contains(&p.jd) {
+            return None;
+        }
+        // a lone YYYY-MM-DD like 2023-02-31 is normalized to 2023-03-03
+        if args.len() == 1 && p.valid_ymd && p.d > 28 {
+            p.valid_ymd = false;
+        }
+        Some(p)
+    }
+
+    fn parse_time_value(&mut self, s: &str) -> bool {
+        if self.parse_ymd(s.as_bytes()) || self.parse_hms(s.as_bytes()) {
+            return true;
+        }
+        if s.eq_ignore_ascii_case("now") {
+            *self = DateTime::now();
+            return true;
+        }
+        if let Some(r) = parse_number(s).and_then(|n| n.to_f64()) {
+            self.set_raw_number(r);
+            return true;
+        }
+        if s.eq_ignore_ascii_case("subsec") || s.eq_ignore_ascii_case("subsecond") {
+            *self = DateTime::now();
+            self.use_subsec = true;
+            return true;
+        }
+        false
+    }
+
+    /// A bare number, a julian day unless a modifier says otherwise.
+    fn set_raw_number(&mut self, r: f64) {
+        self.s = r;
+        self.raw_s = true;
+        if (0.0..5373484.5).contains(&r) {
+            self.jd = (r * DAY_MS as f64 + 0.5) as i64;
+            self.valid_jd = true;
+        }
+    }
+
+    /// `[-]YYYY-MM-DD`, optionally followed by a time.
+    fn parse_ymd(&mut self, s: &[u8]) -> bool {
+        let (negative, s) = match s.first() {
+            Some(b'-') => (true, &s[1..]),
+            _ => (false, s),
+        };
+        let [y, month, d] = get_digits(s, b"40f-21a-21d")[..] else {
+            return false;
+        };
+        let mut rest = &s[10..];
+        while rest
+            .first()
+            .is_some_and(|c| c.is_ascii_whitespace() || *c == b'T')
+        {
+            rest = &rest[1..];
+        }
+        if !self.parse_hms(rest) {
+            if !rest.is_empty() {
+                return false;
+            }
+            self.valid_hms = false;
+        }
+        self.valid_jd = false;
+        self.valid_ymd = true;
+        self.y = if negative { -y } else { y };
+        self.month = month;
+        self.d = d;
+        self.compute_floor();
+        if self.tz != 0 {
+            self.compute_jd();
+        }
+        true
+    }
+
+    /// `HH:MM[:SS[.FFF]]`, optionally followed by a time zone.
+    fn parse_hms(&mut self, s: &[u8]) -> bool {
+        let [h, min] = get_digits(s, b"20c:20e")[..] else {
+            return false;
+        };
+        let mut rest = &s[5..];
+        let mut seconds = 0.0;
+        if rest.first() == Some(&b':') {
+            rest = &rest[1..];
+            let [whole] = get_digits(rest, b"20e")[..] else {
+                return false;
+            };
+            rest = &rest[2..];
+            seconds = whole as f64;
+            if rest.first() == Some(&b'.') && rest.get(1).is_some_and(u8::is_ascii_digit) {
+                rest = &rest[1..];
+                let (mut fraction, mut scale) = (0.0, 1.0);
+                while let Some(c) = rest.first().filter(|c| c.is_ascii_digit()) {
+                    fraction = fraction * 10.0 + (c - b'0') as f64;
+                    scale *= 10.0;
+                    rest = &rest[1..];
+                }
+                // truncated, so that it never rounds up to the next second
+                seconds += (fraction / scale).min(0.999);
+            }
+        }
+        self.valid_jd = false;
+        self.raw_s = false;
+        self.valid_hms = true;
+        self.h = h;
+        self.min = min;
+        self.s = seconds;
+        self.parse_timezone(rest)
+    }
+
+    /// `[+-]HH:MM` or `Z` after a time, or nothing.
+    fn parse_timezone(&mut self, s: &[u8]) -> bool {
+        let s = skip_spaces(s);
+        self.tz = 0;
+        let rest = match s.first() {
+            None => return true,
+            Some(b'Z' | b'z') => {
+                self.is_local = false;
+                self.is_utc = true;
+                &s[1..]
+            }
+            Some(c @ (b'+' | b'-')) => {
+                let sign = if *c == b'-' { -1 } else { 1 };
+                let [h, min] = get_digits(&s[1..], b"20b:20e")[..] else {
+                    return false;
+                };
+                self.tz = sign * (min + h * 60);
+                &s[6..]
+            }
+            Some(_) => return false,
+        };
+        skip_spaces(rest).is_empty()
+    }
+
+    fn compute_jd(&mut self) {
+        if self.valid_jd {
+            return;
+        }
+        let (mut y, mut month, d) = match self.valid_ymd {
+            true => (self.y, self.month, self.d),
+            false => (2000, 1, 1),
+        };
+        if !(-4713..=9999).contains(&y) || self.raw_s {
+            *self = DateTime::error();
+            return;
+        }
+        if month <= 2 {
+            y -= 1;
+            month += 12;
+        }
+        let a = (y + 4800) / 100;
+        let b = 38 - a + a / 4;
+        let x1 = 36525 * (y + 4716) / 100;
+        let x2 = 306001 * (month + 1) / 10000;
+        self.jd = (((x1 + x2 + d + b) as f64 - 1524.5) * DAY_MS as f64) as i64;
+        self.valid_jd = true;
+        if self.valid_hms {
+            self.jd += self.h * 3600000 + self.min * 60000 + (self.s * 1000.0 + 0.5) as i64;
+            if self.tz != 0 {
+                self.jd -= self.tz * 60000;
+                self.valid_ymd = false;
+                self.valid_hms = false;
+                self.tz = 0;
+                self.is_utc = true;
+                self.is_local = false;
+            }
+        }
+    }
+
+    fn compute_ymd(&mut self) {
+        if self.valid_ymd {
+            return;
+        }
+        if !self.valid_jd {
+            (self.y, self.month, self.d) = (2000, 1, 1);
+        } else if !(0..=MAX_JD).contains(&self.jd) {
+            *self = DateTime::error();
+            return;
+        } else {
+            let z = (self.jd + DAY_MS / 2) / DAY_MS;
+            let a = ((z as f64 - 1867216.25) / 36524.25) as i64;
+            let a = z + 1 + a - a / 4;
+            let b = a + 1524;
+            let c = ((b as f64 - 122.1) / 365.25) as i64;
+            let d = (36525 * (c & 32767)) / 100;
+            let e = ((b - d) as f64 / 30.6001) as i64;
+            let x1 = (30.6001 * e as f64) as i64;
+            self.d = b - d - x1;
+            self.month = if e < 14 { e - 1 } else { e - 13 };
+            self.y = if self.month > 2 { c - 4716 } else { c - 4715 };
+        }
+        self.valid_ymd = true;
+    }
+
+    fn compute_hms(&mut self) {
+        if self.valid_hms {
+            return;
+        }
+        self.compute_jd();
+        let day_ms = (self.jd + DAY_MS / 2) % DAY_MS;
+        self.s = (day_ms % 60000) as f64 / 1000.0;
+        let day_min = day_ms / 60000;
+        self.min = day_min % 60;
+        self.h = day_min / 60;
+        self.raw_s = false;
+        self.valid_hms = true;
+    }
+
+    fn compute_ymd_hms(&mut self) {
+        self.compute_ymd();
+        self.compute_hms();
+    }
+
+    /// Forget the calendar form, leaving only the julian day.
+    fn clear_ymd_hms_tz(&mut self) {
+        self.valid_ymd = false;
+        self.valid_hms = false;
+        self.tz = 0;
+    }
+
+    fn compute_floor(&mut self) {
+        self.n_floor = if self.d <= 28 || (1 << self.month) & 0x15aa != 0 {
+            0
+        } else if self.month != 2 {
+            (self.d == 31) as i64
+        } else if self.y % 4 != 0 || (self.y % 100 == 0 && self.y % 400 != 0) {
+            self.d - 28
+        } else {
+            self.d - 29
+        };
+    }
+
+    /// Convert from UTC to local time. Times outside 1970-2037 are moved
+    /// into that range by whole four-year cycles to find the offset.
+    fn convert_to_localtime(&mut self) {
+        self.compute_jd();
+        let (t, year_diff) = if !(2108667600 * 100000..=2130141456 * 100000).contains(&self.jd) {
+            let mut x = self.clone();
+            x.compute_ymd_hms();
+            let year_diff = (2000 + x.y % 4) - x.y;
+            x.y += year_diff;
+            x.valid_jd = false;
+            x.compute_jd();
+            (x.jd / 1000 - UNIX_EPOCH_JD / 1000, year_diff)
+        } else {
+            (self.jd / 1000 - UNIX_EPOCH_JD / 1000, 0)
+        };
+
+        let mut local = DateTime {
+            jd: (t + local_offset(t)) * 1000 + UNIX_EPOCH_JD,
+            valid_jd: true,
+            ..DateTime::default()
+        };
+        local.compute_ymd_hms();
+        self.y = local.y - year_diff;
+        self.month = local.month;
+        self.d = local.d;
+        self.h = local.h;
+        self.min = local.min;
+        self.s = local.s + (self.jd % 1000) as f64 * 0.001;
+        self.valid_ymd = true;
+        self.valid_hms = true;
+        self.valid_jd = false;
+        self.raw_s = false;
+        self.tz = 0;
+        self.is_error = false;
+    }
+
+    /// Apply the modifier that is argument `index` of the function call.
+    fn apply_modifier(&mut self, modifier: &str, index: usize) -> bool {
+        let z = modifier.to_ascii_lowercase();
+        match z.as_bytes().first() {
+            Some(b'a') if z == "auto" => {
+                // a julian day if it can be one, otherwise a unix time
+                if index > 1 {
+                    return false;
+                }
+                if !self.raw_s || self.valid_jd {
+                    self.raw_s = false;
+                    return true;
+                }
+                if !(-210866760000.0..=253402300799.0).contains(&self.s) {
+                    return false;
+                }
+                let r = self.s * 1000.0 + UNIX_EPOCH_JD as f64;
+                self.clear_ymd_hms_tz();
+                self.jd = (r + 0.5) as i64;
+                self.valid_jd = true;
+                self.raw_s = false;
+                true
+            }
+            Some(b'c') if z == "ceiling" => {
+                // rolling over into the next month is the default anyway
+                self.compute_jd();
+                self.clear_ymd_hms_tz();
+                self.n_floor = 0;
+                true
+            }
+            Some(b'f') if z == "floor" => {
+                self.compute_jd();
+                self.jd -= self.n_floor * DAY_MS;
+                self.clear_ymd_hms_tz();
+                true
+            }
+            Some(b'j') if z == "julianday" => {
+                if index > 1 || !(self.valid_jd && self.raw_s) {
+                    return false;
+                }
+                self.raw_s = false;
+                true
+            }
+            Some(b'l') if z == "localtime" => {
+                if !self.is_local {
+                    self.convert_to_localtime();
+                }
+                self.is_utc = false;
+                self.is_local = true;
+                true
+            }
+            Some(b'u') if z == "unixepoch" && self.raw_s => {
+                if index > 1 {
+                    return false;
+                }
+                let r = self.s * 1000.0 + UNIX_EPOCH_JD as f64;
+                if !(0.0..(MAX_JD + 1) as f64).contains(&r) {
+                    return false;
+                }
+                self.clear_ymd_hms_tz();
+                self.jd = (r + 0.5) as i64;
+                self.valid_jd = true;
+                self.raw_s = false;
+                true
+            }
+            Some(b'u') if z == "utc" => {
+                if !self.is_utc {
+                    self.convert_to_utc();
+                }
+                true
+            }
+            Some(b'w') if z.starts_with("weekday ") => {
+                let n = parse_number(&z[8..]).and_then(|n| n.to_f64());
+                let Some(n) = n.filter(|r| (0.0..7.0).contains(r) && r.fract() == 0.0) else {
+                    return false;
+                };
+                self.compute_ymd_hms();
+                self.tz = 0;
+                self.valid_jd = false;
+                self.compute_jd();
+                let mut day = ((self.jd + 129600000) / DAY_MS) % 7;
+                if day > n as i64 {
+                    day -= 7;
+                }
+                self.jd += (n as i64 - day) * DAY_MS;
+                self.clear_ymd_hms_tz();
+                true
+            }
+            Some(b's') => {
+                let Some(unit) = z.strip_prefix("start of ") else {
+                    if z == "subsec" || z == "subsecond" {
+                        self.use_subsec = true;
+                        return true;
+                    }
+                    return false;
+                };
+                if !self.valid_jd && !self.valid_ymd && !self.valid_hms {
+                    return false;
+                }
+                self.compute_ymd();
+                self.valid_hms = true;
+                (self.h, self.min, self.s) = (0, 0, 0.0);
+                self.raw_s = false;
+                self.tz = 0;
+                self.valid_jd = false;
+                match unit {
+                    "month" => self.d = 1,
+                    "year" => (self.month, self.d) = (1, 1),
+                    "day" => {}
+                    _ => return false,
+                }
+                true
+            }
+            Some(b'+' | b'-' | b'0'..=b'9') => self.apply_offset(&z),
+            _ => false,
+        }
+    }
+
+    /// Convert from local time to UTC by finding the UTC time whose local
+    /// time this is.
+    fn convert_to_utc(&mut self) {
+        self.compute_jd();
+        let original = self.jd;
+        let mut guess = original;
+        let mut error = 0;
+        for _ in 0..4 {
+            guess -= error;
+            let mut local = DateTime {
+                jd: guess,
+                valid_jd: true,
+                ..DateTime::default()
+            };
+            local.convert_to_localtime();
+            local.compute_jd();
+            error = local.jd - original;
+            if error == 0 {
+                break;
+            }
+        }
+        *self = DateTime {
+            jd: guess,
+            valid_jd: true,
+            is_utc: true,
+            ..DateTime::default()
+        };
+    }
+
+    /// The modifiers `±NNN units`, `±HH:MM[:SS[.FFF]]` and
+    /// `±YYYY-MM-DD[ HH:MM[:SS[.FFF]]]`.
+    fn apply_offset(&mut self, z: &str) -> bool {
+        let mut s = z.as_bytes();
+        let sign = s[0];
+        let mut n = 1;
+        while n < s.len() {
+            let c = s[n];
+            if c == b':' || c.is_ascii_whitespace() {
+                break;
+            }
+            if c == b'-'
+                && ((n == 5 && get_digits(&s[1..], b"40f").len() == 1)
+                    || (n == 6 && get_digits(&s[1..], b"50f").len() == 1))
+            {
+                break;
+            }
+            n += 1;
+        }
+        let Some(r) = parse_number(&z[..n]).and_then(|n| n.to_f64()) else {
+            return false;
+        };
+
+        let mut time = s;
+        if s.get(n) == Some(&b'-') {
+            // years, months and days, with months 0-11 and days 0-30
+            if sign != b'+' && sign != b'-' {
+                return false;
+            }
+            let format: &[u8] = if n == 5 {
+                b"40f-20a-20d"
+            } else {
+                b"50f-20a-20d"
+            };
+            let [y, month, mut d] = get_digits(&s[1..], format)[..] else {
+                return false;
+            };
+            if n == 6 {
+                s = &s[1..];
+            }
+            if month >= 12 || d >= 31 {
+                return false;
+            }
+            self.compute_ymd_hms();
+            self.valid_jd = false;
+            if sign == b'-' {
+                self.y -= y;
+                self.month -= month;
+                d = -d;
+            } else {
+                self.y += y;
+                self.month += month;
+            }
+            self.normalize_month();
+            self.compute_floor();
+            self.compute_jd();
+            self.valid_hms = false;
+            self.valid_ymd = false;
+            self.jd += d * DAY_MS;
+            match s.get(11) {
+                None => return true,
+                Some(c)
+                    if c.is_ascii_whitespace()
+                        && get_digits(s.get(12..).unwrap_or_default(), b"20c:20e").len() == 2 =>
+                {
+                    time = &s[12..];
+                    n = 2;
+                }
+                _ => return false,
+            }
+        }
+
+        if time.get(n) == Some(&b':') {
+            if !time[0].is_ascii_digit() {
+                time = &time[1..];
+            }
+            let mut tx = DateTime::default();
+            if !tx.parse_hms(time) {
+                return false;
+            }
+            tx.compute_jd();
+            tx.jd -= DAY_MS / 2;
+            tx.jd -= tx.jd / DAY_MS * DAY_MS;
+            if sign == b'-' {
+                tx.jd = -tx.jd;
+            }
+            self.compute_jd();
+            self.clear_ymd_hms_tz();
+            self.jd += tx.jd;
+            return true;
+        }
+
+        let unit = z[n..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+        if !(3..=10).contains(&unit.len()) {
+            return false;
+        }
+        let unit = unit.strip_suffix('s').unwrap_or(unit);
+        self.compute_jd();
+        let rounder = if r < 0.0 { -0.5 } else { 0.5 };
+        self.n_floor = 0;
+        let found = UNITS
+            .iter()
+            .find(|(name, limit, _)| *name == unit && r > -limit && r < *limit);
+        let applied = if let Some((name, _, seconds)) = found {
+            let mut r = r;
+            if *name == "month" || *name == "year" {
+                self.compute_ymd_hms();
+                if *name == "month" {
+                    self.month += r as i64;
+                    self.normalize_month();
+                } else {
+                    self.y += r as i64;
+                }
+                self.compute_floor();
+                self.valid_jd = false;
+                r -= r.trunc();
+            }
+            self.compute_jd();
+            self.jd += (r * 1000.0 * seconds + rounder) as i64;
+            true
+        } else {
+            false
+        };
+        self.clear_ymd_hms_tz();
+        applied
+    }
+
+    /// Bring the month back into 1-12, carrying into the year.
+    fn normalize_month(&mut self) {
+        let x = if self.month > 0 {
+            (self.month - 1) / 12
+        } else {
+            (self.month - 12) / 12
+        };
+        self.y += x;
+        self.month -= x * 12;
+    }
+
+    fn date_text(&self) -> String {
+        let sign = if self.y < 0 { "-" } else { "" };
+        format!("{sign}{:04}-{:02}-{:02}", self.y.abs(), self.month, self.d)
+    }
+
+    fn time_text(&self) -> String {
+        if self.use_subsec {
+            let ms = (self.s * 1000.0 + 0.5) as i64;
+            format!(
+                "{:02}:{:02}:{:02}.{:03}",
+                self.h,
+                self.min,
+                ms / 1000,
+                ms % 1000
+            )
+        } else {
+            format!("{:02}:{:02}:{:02}", self.h, self.min, self.s as i64)
+        }
+    }
+
+    fn days_after_jan01(&self) -> i64 {
+        let mut jan01 = self.clone();
+        jan01.valid_jd = false;
+        (jan01.month, jan01.d) = (1, 1);
+        jan01.compute_jd();
+        (self.jd - jan01.jd + DAY_MS / 2) / DAY_MS
+    }
+
+    fn days_after_monday(&self) -> i64 {
+        ((self.jd + DAY_MS / 2) / DAY_MS) % 7
+    }
+
+    fn days_after_sunday(&self) -> i64 {
+        ((self.jd + 129600000) / DAY_MS) % 7
+    }
+
+    /// The Thursday of the ISO 8601 week, which decides its year.
+    fn thursday(&self) -> DateTime {
+        let mut y = self.clone();
+        y.jd += (3 - self.days_after_monday()) * DAY_MS;
+        y.valid_ymd = false;
+        y.compute_ymd();
+        y
+    }
+
+    /// None for an unknown substitution.
+    fn strftime(&mut self, format: &str) -> Option<String> {
+        self.compute_jd();
+        self.compute_ymd_hms();
+        let hour12 = if self.h % 12 == 0 { 12 } else { self.h % 12 };
+
+        let mut out = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let text = match chars.next()? {
+                'd' => format!("{:02}", self.d),
+                'e' => format!("{:2}", self.d),
+                'f' => printf("%06.3f", &[ColumnType::Float(self.s.min(59.999))]).ok()?,
+                'F' => self.date_text(),
+                'G' => format!("{:04}", self.thursday().y),
+                'g' => format!("{:02}", self.thursday().y % 100),
+                'H' => format!("{:02}", self.h),
+                'k' => format!("{:2}", self.h),
+                'I' => format!("{hour12:02}"),
+                'l' => format!("{hour12:2}"),
+                'j' => format!("{:03}", self.days_after_jan01() + 1),
+                'J' => printf(
+                    "%.16g",
+                    &[ColumnType::Float(self.jd as f64 / DAY_MS as f64)],
+                )
+                .ok()?,
+                'm' => format!("{:02}", self.month),
+                'M' => format!("{:02}", self.min),
+                'p' => if self.h >= 12 { "PM" } else { "AM" }.to_string(),
+                'P' => if self.h >= 12 { "pm" } else { "am" }.to_string(),
+                'R' => format!("{:02}:{:02}", self.h, self.min),
+                's' if self.use_subsec => printf(
+                    "%.3f",
+                    &[ColumnType::Float((self.jd - UNIX_EPOCH_JD) as f64 / 1000.0)],
+                )
+                .ok()?,
+                's' => (self.jd / 1000 - UNIX_EPOCH_JD / 1000).to_string(),
+                'S' => format!("{:02}", self.s as i64),
+                'T' => format!("{:02}:{:02}:{:02}", self.h, self.min, self.s as i64),
+                'u' => match self.days_after_sunday() {
+                    0 => "7".to_string(),
+                    d => d.to_string(),
+                },
+                'w' => self.days_after_sunday().to_string(),
+                'U' => format!(
+                    "{:02}",
+                    (self.days_after_jan01() - self.days_after_sunday() + 7) / 7
+                ),
+                'V' => format!("{:02}", self.thursday().days_after_jan01() / 7 + 1),
+                'W' => format!(
+                    "{:02}",
+                    (self.days_after_jan01() - self.days_after_monday() + 7) / 7
+                ),
+                'Y' => format!("{:04}", self.y),
+                '%' => "%".to_string(),
+                _ => return None,
+            };
+            out.push_str(&text);
+        }
+        Some(out)
+    }
+}
+
+/// timediff(A, B): the time to add to B to reach A, as
+/// `±YYYY-MM-DD HH:MM:SS.SSS`, counting whole months on the calendar.
+fn timediff(mut d1: DateTime, mut d2: DateTime) -> String {
+    d1.compute_ymd_hms();
+    d2.compute_ymd_hms();
+    let recompute = |d: &mut DateTime| {
+        d.valid_jd = false;
+        d.compute_jd();
+    };
+
+    let sign = if d1.jd >= d2.jd { '+' } else { '-' };
+    let mut years = if sign == '+' {
+        d1.y - d2.y
+    } else {
+        d2.y - d1.y
+    };
+    if years != 0 {
+        d2.y = d1.y;
+        recompute(&mut d2);
+    }
+    let mut months = if sign == '+' {
+        d1.month - d2.month
+    } else {
+        d2.month - d1.month
+    };
+    if months < 0 {
+        years -= 1;
+        months += 12;
+    }
+    if months != 0 {
+        d2.month = d1.month;
+        recompute(&mut d2);
+    }
+    while (sign == '+' && d1.jd < d2.jd) || (sign == '-' && d1.jd > d2.jd) {
+        months -= 1;
+        if months < 0 {
+            months = 11;
+            years -= 1;
+        }
+        if sign == '+' {
+            d2.month -= 1;
+            if d2.month < 1 {
+                d2.month = 12;
+                d2.y -= 1;
+            }
+        } else {
+            d2.month += 1;
+            if d2.month > 12 {
+                d2.month = 1;
+                d2.y += 1;
+            }
+        }
+        recompute(&mut d2);
+    }
+
+    // the remainder, as a time after 0000-01-01 00:00:00
+    let remainder = if sign == '+' {
+        d1.jd - d2.jd
+    } else {
+        d2.jd - d1.jd
+    };
+    let mut d = DateTime {
+        jd: remainder + 148699540800000,
+        valid_jd: true,
+        ..DateTime::default()
+    };
+    d.compute_ymd_hms();
+    let seconds = printf("%06.3f", &[ColumnType::Float(d.s)]).unwrap_or_default();
+    format!(
+        "{sign}{years:04}-{months:02}-{:02} {:02}:{:02}:{seconds}",
+        d.d - 1,
+        d.h,
+        d.min
+    )
+}
+
+/// Read fixed-width fields as described by `format`: for each field, the
+/// number of digits, the minimum value, a letter for the maximum value and
+/// the character that must follow it, if any. Returns the fields read
+/// before the first that does not match.
+fn get_digits(s: &[u8], format: &[u8]) -> Vec<i64> {
+    const MAX: [i64; 6] = [12, 14, 24, 31, 59, 14712];
+    let mut values = vec![];
+    let mut pos = 0;
+    for spec in format.chunks(4) {
+        let min = (spec[1] - b'0') as i64;
+        let max = MAX[(spec[2] - b'a') as usize];
+        let mut value = 0;
+        for _ in 0..spec[0] - b'0' {
+            match s.get(pos) {
+                Some(c) if c.is_ascii_digit() => value = value * 10 + (c - b'0') as i64,
+                _ => return values,
+            }
+            pos += 1;
+        }
+        if value < min || value > max || spec.get(3).is_some_and(|c| s.get(pos) != Some(c)) {
+            return values;
+        }
+        values.push(value);
+        pos += 1;
+    }
+    values
+}
+
+fn skip_spaces(s: &[u8]) -> &[u8] {
+    let start = s
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(s.len());
+    &s[start..]
+}
+
+/// The local time zone: the offset from UTC in seconds before the first
+/// transition, then each transition time with the offset it starts.
+struct Zone {
+    initial: i64,
+    transitions: Vec<(i64, i64)>,
+}
+
+/// The offset of local time from UTC in seconds at unix time `t`, from the
+/// zoneinfo file named by `TZ` or /etc/localtime. Without one it is UTC.
+fn local_offset(t: i64) -> i64 {
+    static ZONE: OnceLock<Zone> = OnceLock::new();
+    let zone = ZONE.get_or_init(|| {
+        let path = match std::env::var("TZ") {
+            Ok(tz) if tz.trim_start_matches(':').starts_with('/') => {
+                tz.trim_start_matches(':').to_string()
+            }
+            Ok(tz) => format!("/usr/share/zoneinfo/{}", tz.trim_start_matches(':')),
+            Err(_) => "/etc/localtime".to_string(),
+        };
+        std::fs::read(path)
+            .ok()
+            .and_then(|data| parse_tzif(&data))
+            .unwrap_or(Zone {
+                initial: 0,
+                transitions: vec![],
+            })
+    });
+    match zone.transitions.partition_point(|(at, _)| *at <= t) {
+        0 => zone.initial,
+        i => zone.transitions[i - 1].1,
+    }
+}
+
+/// Parse the transitions of a TZif file, preferring the 64-bit data of
+/// version 2 and later. See RFC 8536.
+fn parse_tzif(data: &[u8]) -> Option<Zone> {
+    let counts = |data: &[u8]| -> Option<[usize; 6]> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let mut counts = [0; 6];
+        for (i, count) in counts.iter_mut().enumerate() {
+            let bytes = data.get(20 + i * 4..24 + i * 4)?;
+            *count = u32::from_be_bytes(bytes.try_into().ok()?) as usize;
+        }
+        Some(counts)
+    };
+
+    let mut header = counts(data)?;
+    let version = data[4];
+    let mut data = &data[44..];
+    let mut time_size = 4;
+    if version >= b'2' {
+        let [isut, isstd, leap, times, types, chars] = header;
+        data = data.get(times * 5 + types * 6 + chars + leap * 8 + isstd + isut..)?;
+        header = counts(data)?;
+        data = &data[44..];
+        time_size = 8;
+    }
+
+    let [_, _, _, times, types, _] = header;
+    let indices_at = times * time_size;
+    let types_at = indices_at + times;
+    let offset = |i: usize| -> Option<i64> {
+        let bytes = data.get(types_at + i * 6..types_at + i * 6 + 4)?;
+        (i < types).then(|| i32::from_be_bytes(bytes.try_into().unwrap()) as i64)
+    };
+    let transitions = (0..times)
+        .map(|i| {
+            let bytes = data.get(i * time_size..(i + 1) * time_size)?;
+            let at = match time_size {
+                8 => i64::from_be_bytes(bytes.try_into().ok()?),
+                _ => i32::from_be_bytes(bytes.try_into().ok()?) as i64,
+            };
+            Some((at, offset(*data.get(indices_at + i)? as usize)?))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(Zone {
+        initial: offset(0)?,
+        transitions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_text(name: &str, args: &[&str]) -> String {
+        let args = args
+            .iter()
+            .map(|a| ColumnType::String(a.to_string()))
+            .collect::<Vec<_>>();
+        call(name, &args).to_string()
+    }
+
+    #[test]
+    fn test_time_values() {
+        assert_eq!(
+            call_text("datetime", &["2024-02-29T13:45:10.5"]),
+            "2024-02-29 13:45:10"
+        );
+        assert_eq!(call_text("date", &["2023-02-31"]), "2023-03-03");
+        assert_eq!(
+            call_text("datetime", &["2024-01-01 10:00-02:30"]),
+            "2024-01-01 12:30:00"
+        );
+        assert_eq!(call_text("time", &["12:30"]), "12:30:00");
+        assert_eq!(call_text("julianday", &["-4713-11-24 12:00:00"]), "0.0");
+        assert_eq!(call_text("date", &["-4713-11-24 11:59:59"]), "");
+        assert_eq!(call_text("date", &["2024-13-01"]), "");
+        assert_eq!(
+            call(
+                "datetime",
+                &[
+                    ColumnType::Int64(1700000000),
+                    ColumnType::String("unixepoch".into())
+                ]
+            )
+            .to_string(),
+            "2023-11-14 22:13:20"
+        );
+    }
+
+    #[test]
+    fn test_modifiers() {
+        assert_eq!(call_text("date", &["2024-01-31", "+1 month"]), "2024-03-02");
+        assert_eq!(
+            call_text("date", &["2024-01-31", "+1 month", "floor"]),
+            "2024-02-29"
+        );
+        assert_eq!(call_text("date", &["2024-02-29", "-1 year"]), "2023-03-01");
+        assert_eq!(
+            call_text("date", &["2024-03-15", "start of month", "weekday 0"]),
+            "2024-03-03"
+        );
+        assert_eq!(
+            call_text("datetime", &["2024-01-01", "-0001-02-03 04:05"]),
+            "2022-10-28 19:55:00"
+        );
+        assert_eq!(
+            call_text("datetime", &["2024-01-01", "+01:30:15.25", "subsec"]),
+            "2024-01-01 01:30:15.250"
+        );
+        assert_eq!(call_text("date", &["2024-01-01", "+1 fortnight"]), "");
+        assert_eq!(
+            call_text("unixepoch", &["1700000000", "auto"]),
+            "1700000000"
+        );
+    }
+
+    #[test]
+    fn test_strftime_and_timediff() {
+        assert_eq!(
+            call_text(
+                "strftime",
+                &["%Y %j %U %W %V %G %u %w %I%p %J", "2021-01-03 15:00"]
+            ),
+            "2021 003 01 00 53 2020 7 0 03PM 2459218.125"
+        );
+        assert_eq!(call_text("strftime", &["%Q", "2021-01-03"]), "");
+        assert_eq!(
+            call_text("timediff", &["2024-03-01", "2023-01-31 12:00"]),
+            "+0001-00-29 12:00:00.000"
+        );
+        assert_eq!(
+            call_text("timediff", &["2023-01-31 12:00", "2024-03-01"]),
+            "-0001-01-00 12:00:00.000"
+        );
+    }
+}
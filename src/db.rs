@@ -1,7 +1,7 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use std::io::{prelude::*, BufReader};
 
-use crate::{page::PageInfo, util};
+use crate::{btree, page::PageInfo, table::TableInfo, util};
 
 pub fn open_db(
     reader: &mut BufReader<impl Read>,
@@ -9,22 +9,34 @@ pub fn open_db(
 ) -> Result<DbInfo, anyhow::Error> {
     reader.read_exact(file_header).context("read file header")?;
 
-    // SAFETY: per spec: https://www.sqlite.org/fileformat.html#magic_header_string
-    let magic = unsafe { std::str::from_utf8_unchecked(&file_header[0..16]) };
-    assert_eq!(magic, "SQLite format 3\0");
+    // per spec: https://www.sqlite.org/fileformat.html#magic_header_string
+    if &file_header[0..16] != b"SQLite format 3\0" {
+        bail!("file is not a database");
+    }
 
     let page_size = u16::from_be_bytes([file_header[16], file_header[17]]);
     let write_version = u8::from_be_bytes([file_header[18]]);
     let read_version = u8::from_be_bytes([file_header[19]]);
     let per_page_reserved_space = u8::from_be_bytes([file_header[20]]);
 
+    // a power of two from 512 to 32768, or 1 for 65536; and SQLite will not
+    // have less than 480 usable bytes a page
+    let size = if page_size == 1 {
+        65536
+    } else {
+        page_size as usize
+    };
+    if !size.is_power_of_two() || size < 512 || size - (per_page_reserved_space as usize) < 480 {
+        bail!("file is not a database");
+    }
+
     // check but don't store these next values
     let max_embedded_payload = u8::from_be_bytes([file_header[21]]);
-    assert_eq!(max_embedded_payload, 64);
     let min_embedded_payload = u8::from_be_bytes([file_header[22]]);
-    assert_eq!(min_embedded_payload, 32);
     let leaf_payload = u8::from_be_bytes([file_header[23]]);
-    assert_eq!(leaf_payload, 32);
+    if (max_embedded_payload, min_embedded_payload, leaf_payload) != (64, 32, 32) {
+        bail!("file is not a database");
+    }
 
     let mut file_header = &file_header[24..];
 
@@ -70,20 +82,179 @@ pub fn open_db(
 }
 
 impl DbInfo {
-    pub fn read_page(
-        &self,
-        reader: &mut BufReader<impl Read>,
-        file_header: Option<[u8; 100]>,
-    ) -> Result<PageInfo, anyhow::Error> {
-        let (mut buf, page_start) = if let Some(buf) = file_header {
-            (Vec::from(buf), 100)
-        } else {
-            (Vec::with_capacity(self.page_size as usize), 0)
+    /// The page size in bytes; the header stores 65536 as the value 1.
+    pub fn page_size(&self) -> usize {
+        match self.page_size {
+            1 => 65536,
+            n => n as usize,
+        }
+    }
+
+    /// The "usable size" of a page, i.e. the page size less the reserved
+    /// space at the end of each page.
+    pub fn usable_size(&self) -> usize {
+        self.page_size() - self.per_page_reserved_space as usize
+    }
+
+    /// The size of the database in pages as the header gives it, None if it
+    /// may be stale: versions of SQLite before 3.7.0 left it unchanged, which
+    /// shows as a version-valid-for number behind the change counter.
+    pub fn db_size(&self) -> Option<u32> {
+        (self.in_header_db_size != 0 && self.version_valid_for == self.file_change_counter)
+            .then_some(self.in_header_db_size)
+    }
+
+    /// The number of pages on the freelist.
+    pub fn freelist_count(&self) -> u32 {
+        self.num_freelist_pages
+    }
+
+    /// The first trunk page of the freelist, 0 if there is none.
+    pub fn freelist_trunk(&self) -> u32 {
+        self.first_freelist_trunk_page
+    }
+
+    /// Whether the file is in auto-vacuum or incremental-vacuum mode, and
+    /// so has pointer map pages.
+    pub fn auto_vacuum(&self) -> bool {
+        self.largest_root_page != 0
+    }
+
+    /// The name of the text encoding, as `PRAGMA encoding` gives it.
+    pub fn encoding(&self) -> &'static str {
+        match self.text_encoding {
+            2 => "UTF-16le",
+            3 => "UTF-16be",
+            _ => "UTF-8",
+        }
+    }
+
+    /// The user version; SQLite reads the header fields as signed.
+    pub fn user_version(&self) -> i32 {
+        self.user_version as i32
+    }
+
+    /// The application ID.
+    pub fn application_id(&self) -> i32 {
+        self.app_id as i32
+    }
+
+    /// The schema cookie, bumped whenever the schema changes.
+    pub fn schema_version(&self) -> i32 {
+        self.schema_cookie as i32
+    }
+}
+
+/// An open database: the parsed header, the raw file content and the schema
+/// read from the `sqlite_schema` table rooted at page 1.
+#[derive(Debug)]
+pub struct Database {
+    pub info: DbInfo,
+    data: Vec<u8>,
+    pub schema: Vec<TableInfo>,
+    /// The path of the file the database was read from, empty if unknown.
+    pub path: String,
+    /// The primary key of each WITHOUT ROWID table, as the index the rows
+    /// are stored in; these have no entry of their own in the schema.
+    primary_keys: Vec<TableInfo>,
+}
+
+impl Database {
+    pub fn open(reader: &mut BufReader<impl Read>) -> Result<Database, anyhow::Error> {
+        let mut file_header = [0; 100];
+        let info = open_db(reader, &mut file_header).context("open_db")?;
+
+        let mut data = Vec::from(file_header);
+        reader.read_to_end(&mut data).context("read database")?;
+
+        let mut db = Database {
+            info,
+            data,
+            schema: vec![],
+            path: String::new(),
+            primary_keys: vec![],
         };
 
-        reader.read_to_end(&mut buf)?;
+        let mut schema = vec![];
+        btree::scan_table(&db, 1, &mut |_, record| {
+            schema.push(TableInfo::new(record)?);
+            Ok(true)
+        })
+        .context("read schema")?;
+        db.primary_keys = schema
+            .iter()
+            .filter(|t| t.typ == "table" && t.table().is_ok_and(|t| t.without_rowid))
+            .map(TableInfo::primary_key_of)
+            .collect();
+        db.schema = schema;
+
+        Ok(db)
+    }
+
+    /// Number of pages in the file, as derived from the file size.
+    pub fn page_count(&self) -> u32 {
+        (self.data.len() / self.info.page_size()) as u32
+    }
+
+    /// Number of pages in the database: the header's count when it can be
+    /// trusted, otherwise as derived from the file size.
+    pub fn size(&self) -> u32 {
+        self.info.db_size().unwrap_or_else(|| self.page_count())
+    }
+
+    /// The raw bytes of page `page_num` (1-based, as stored in the file).
+    pub fn raw_page(&self, page_num: u32) -> Result<&[u8], anyhow::Error> {
+        if page_num == 0 || page_num > self.page_count() {
+            bail!("page {page_num} out of range");
+        }
+
+        let page_size = self.info.page_size();
+        let start = (page_num as usize - 1) * page_size;
+        Ok(&self.data[start..start + page_size])
+    }
+
+    /// Read and parse the b-tree page `page_num`.
+    pub fn read_page(&self, page_num: u32) -> Result<PageInfo, anyhow::Error> {
+        // the first page carries the 100 byte file header before its b-tree header
+        let page_start = if page_num == 1 { 100 } else { 0 };
+        PageInfo::read(self, page_num, page_start)
+    }
+
+    /// Look up a table (or other schema object) by name, case-insensitively.
+    pub fn find_table(&self, name: &str) -> Option<&TableInfo> {
+        self.schema
+            .iter()
+            .find(|t| t.typ == "table" && t.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The views, in the order they were created.
+    pub fn views(&self) -> impl Iterator<Item = &TableInfo> {
+        self.schema.iter().filter(|t| t.typ == "view")
+    }
+
+    /// The primary key of a WITHOUT ROWID table, as an index.
+    pub fn primary_key(&self, table: &str) -> Option<&TableInfo> {
+        self.primary_keys
+            .iter()
+            .find(|t| t.table_name.eq_ignore_ascii_case(table))
+    }
 
-        PageInfo::read(buf, page_start)
+    /// Look up an index by name, case-insensitively, including the primary
+    /// keys of WITHOUT ROWID tables.
+    pub fn find_index(&self, name: &str) -> Option<&TableInfo> {
+        self.schema
+            .iter()
+            .filter(|t| t.typ == "index")
+            .chain(&self.primary_keys)
+            .find(|t| t.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The indexes on a table that were created with CREATE INDEX, leaving
+    /// out the automatic ones for UNIQUE and PRIMARY KEY constraints.
+    pub fn indexes<'a>(&'a self, table: &'a str) -> impl Iterator<Item = &'a TableInfo> {
+        self.schema.iter().filter(move |t| {
+            t.typ == "index" && t.table_name.eq_ignore_ascii_case(table) && !t.sql.is_empty()
+        })
     }
 }
 
@@ -134,3 +305,54 @@ pub struct DbInfo {
     /// https://www.sqlite.org/c3ref/c_source_id.html
     version_num: u32,
 }
+
+/// Databases for the tests of other modules to run against.
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    /// Open a database from the bytes of its file.
+    pub(crate) fn open_bytes(data: &[u8]) -> Database {
+        Database::open(&mut BufReader::new(data)).unwrap()
+    }
+
+    /// Open `sample.db`, the database most tests read.
+    pub(crate) fn open_sample() -> Database {
+        open_bytes(&std::fs::read("sample.db").unwrap())
+    }
+
+    /// Open `fixtures.db`, built by `fixtures.sql` for what `sample.db`
+    /// lacks.
+    pub(crate) fn open_fixtures() -> Database {
+        open_bytes(&std::fs::read("fixtures.db").unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_checks_header() {
+        let sample = std::fs::read("sample.db").unwrap();
+        let open = |at: usize, bytes: &[u8]| {
+            let mut data = sample.clone();
+            data[at..at + bytes.len()].copy_from_slice(bytes);
+            Database::open(&mut BufReader::new(&data[..])).map(|_| ())
+        };
+        assert!(open(0, &[]).is_ok());
+        for (at, bytes) in [
+            (0, &b"SQLite format 2"[..]),
+            // page sizes that are not powers of two from 512 to 65536
+            (16, &[0x03, 0x00]),
+            (16, &[0x01, 0x00]),
+            (16, &[0x00, 0x00]),
+            // too few usable bytes in a page
+            (16, &[0x02, 0x00, 1, 1, 33]),
+            (21, &[65]),
+        ] {
+            let error = open(at, bytes).unwrap_err();
+            assert_eq!(error.root_cause().to_string(), "file is not a database");
+        }
+    }
+}
@@ -0,0 +1,4745 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+
+use anyhow::{anyhow, bail};
+
+use crate::ast::*;
+use crate::btree;
+use crate::collation::{CollatedKeys, Collation, Collations};
+use crate::convert::Affinity;
+use crate::db::Database;
+use crate::explain::{self, QueryPlan};
+use crate::functions;
+use crate::planner::{self, Access, Planner};
+use crate::stats::Statistics;
+use crate::table::{ColumnType, TableInfo};
+use crate::value::ValueKey;
+use crate::vdbe::Program;
+use crate::window::{Bound, Partition};
+
+mod codegen;
+mod integrity;
+mod join;
+mod pragma;
+mod vm;
+
+use join::{ColumnRefs, Ordered};
+
+pub type Row = Vec<ColumnType>;
+
+/// An output row together with its ORDER BY sort keys.
+type KeyedRow = (Row, Row);
+
+/// The result columns and rows of a single select, with the collations of
+/// the ORDER BY keys.
+type CoreOutput = (Vec<ColumnName>, Vec<KeyedRow>, Vec<Collation>);
+
+/// The values of the window function calls for one output row, keyed by the
+/// address of the call's AST node.
+type WindowValues = Vec<(usize, ColumnType)>;
+
+/// The rows produced by a statement, with the name of each column.
+#[derive(Debug)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Row>,
+}
+
+/// The name under which a column of a FROM clause source can be referenced.
+#[derive(Debug, Clone)]
+struct ColumnName {
+    /// The table name or alias qualifying the column.
+    table: Option<String>,
+    name: String,
+    /// Hidden columns such as `rowid` are not expanded by `*`.
+    hidden: bool,
+    /// The affinity of a table column, used in comparisons; a column of a
+    /// view or subquery has that of its expression.
+    affinity: Option<Affinity>,
+    /// The collation of a table column, BINARY unless declared otherwise;
+    /// a column of a view or subquery has that of its expression. A declared
+    /// collation that is not registered is kept by name, an error only where
+    /// the column is compared.
+    collation: Option<Result<Collation, String>>,
+}
+
+impl ColumnName {
+    /// The collation of the column, failing if it is not registered.
+    fn collation(&self) -> Result<Option<Collation>, anyhow::Error> {
+        match &self.collation {
+            Some(Err(name)) => bail!("no such collation sequence: {name}"),
+            Some(Ok(c)) => Ok(Some(c.clone())),
+            None => Ok(None),
+        }
+    }
+
+    fn matches(&self, table: Option<&str>, name: &str) -> bool {
+        let table_matches = match (table, &self.table) {
+            (None, _) => true,
+            (Some(t), Some(own)) => t.eq_ignore_ascii_case(own),
+            (Some(_), None) => false,
+        };
+        if !table_matches {
+            return false;
+        }
+        if self.name.eq_ignore_ascii_case(name) {
+            return true;
+        }
+        // the hidden rowid column also answers to its other names
+        self.hidden
+            && ["rowid", "oid", "_rowid_"]
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+}
+
+/// The rows produced by a FROM clause.
+struct Relation {
+    columns: Vec<ColumnName>,
+    rows: Vec<Row>,
+}
+
+/// Name resolution context for evaluating an expression against one row.
+/// Subqueries chain to the environment of the enclosing query through
+/// `outer`, which is how correlated column references are resolved.
+#[derive(Clone, Copy)]
+struct Env<'a> {
+    columns: &'a [ColumnName],
+    row: &'a [ColumnType],
+    /// Result column aliases, usable in WHERE, GROUP BY, HAVING and ORDER BY.
+    aliases: &'a [(String, &'a Expr)],
+    /// The rows of the current group, when evaluating an aggregate query.
+    group: Option<&'a [Row]>,
+    /// Common table expressions declared by the WITH clause of this scope.
+    ctes: &'a [Cte<'a>],
+    /// The values of the window function calls for the current output row.
+    window: &'a [(usize, ColumnType)],
+    outer: Option<&'a Env<'a>>,
+}
+
+impl<'a> Env<'a> {
+    /// An environment with no columns of its own, inside `outer`.
+    fn new(outer: Option<&'a Env<'a>>) -> Env<'a> {
+        Env {
+            columns: &[],
+            row: &[],
+            aliases: &[],
+            group: None,
+            ctes: &[],
+            window: &[],
+            outer,
+        }
+    }
+
+    fn with_row(&self, row: &'a [ColumnType]) -> Env<'a> {
+        Env {
+            row,
+            group: None,
+            ..*self
+        }
+    }
+}
+
+/// A common table expression bound to the scope of the select declaring it.
+struct Cte<'a> {
+    def: &'a CommonTableExpr,
+    /// The result columns, once known.
+    columns: RefCell<Option<Vec<ColumnName>>>,
+    state: RefCell<CteState>,
+    /// The most rows the select about to read it needs, when that is known:
+    /// a recursive CTE then stops there rather than run to its end.
+    wanted: Cell<Option<usize>>,
+}
+
+impl<'a> Cte<'a> {
+    fn new(def: &'a CommonTableExpr) -> Self {
+        Cte {
+            def,
+            columns: RefCell::new(None),
+            state: RefCell::new(CteState::Pending),
+            wanted: Cell::new(None),
+        }
+    }
+}
+
+enum CteState {
+    Pending,
+    /// Being computed; a non-recursive reference now would never terminate.
+    Computing,
+    /// The current row of a recursive CTE, which its recursive select reads.
+    Working(Rc<Vec<Row>>),
+    Done(Rc<Vec<Row>>),
+}
+
+/// The state of the walk over a statement for EXPLAIN QUERY PLAN.
+struct PlanContext<'s> {
+    plan: QueryPlan,
+    /// The CTEs in scope, innermost last.
+    ctes: Vec<PlanCte<'s>>,
+    /// The number of subqueries seen so far, which numbers them.
+    subqueries: usize,
+    /// The columns of the enclosing queries, innermost last, which a
+    /// correlated subquery can look up rows by.
+    scopes: Vec<Vec<ColumnName>>,
+}
+
+struct PlanCte<'s> {
+    def: &'s CommonTableExpr,
+    /// The end of the CTEs declared by the same WITH clause, which are the
+    /// ones its select can see besides those of enclosing scopes.
+    scope_end: usize,
+    /// How many times the declaring select runs.
+    loops: f64,
+    /// The estimated rows, once the steps computing the CTE were added.
+    rows: Option<f64>,
+}
+
+/// A table read by a query.
+struct TableSource<'d> {
+    /// None for `sqlite_schema`, which has no entry in the schema.
+    table: Option<&'d TableInfo>,
+    root_page: u32,
+    /// The columns of the table, then the hidden rowid.
+    columns: Vec<ColumnName>,
+    /// Whether each column, not counting the rowid, is an alias for the
+    /// rowid.
+    is_rowid: Vec<bool>,
+    /// The column of each field of the records, which leave out the VIRTUAL
+    /// generated columns and put the primary key of a WITHOUT ROWID table
+    /// first.
+    stored: Vec<usize>,
+    /// The DEFAULT of each column, which records written before the column
+    /// was added lack.
+    defaults: Vec<Option<Expr>>,
+    /// The VIRTUAL generated columns with their expressions, each after
+    /// those it refers to; they are computed as the rows are read.
+    generated: Vec<(usize, Expr)>,
+    /// How a WITHOUT ROWID table stores its rows; such a table has no
+    /// hidden rowid column.
+    layout: Option<Layout>,
+}
+
+/// How a WITHOUT ROWID table stores its rows: as the entries of an index
+/// b-tree in the order of the primary key, whose columns come first.
+struct Layout {
+    /// The collation of each column of the primary key, and whether it is
+    /// in descending order.
+    key: Vec<(Collation, bool)>,
+}
+
+impl Layout {
+    /// How the primary key of a record compares with `key`.
+    fn compare_key(&self, record: &Row, key: &[ColumnType]) -> Ordering {
+        for ((value, key), (collation, desc)) in record.iter().zip(key).zip(&self.key) {
+            let o = collation.compare(value, key);
+            if o != Ordering::Equal {
+                return if *desc { o.reverse() } else { o };
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+/// Results of uncorrelated subqueries, computed once per statement.
+enum Materialized {
+    Rows(Rc<ResultSet>),
+    /// The right-hand side of `IN (SELECT ...)`: the distinct values, and
+    /// whether a NULL was among them.
+    Set(Rc<HashSet<ValueKey>>, bool),
+}
+
+pub struct Executor<'d> {
+    db: &'d Database,
+    /// Keyed by the address of the subquery's AST node, which is stable
+    /// while a statement executes.
+    materialized: RefCell<HashMap<usize, Materialized>>,
+    correlated: RefCell<HashMap<usize, bool>>,
+    /// `PRAGMA case_sensitive_like`, which lasts beyond the statement.
+    case_sensitive_like: Cell<bool>,
+    collations: Collations,
+    /// The values of the parameters of the statement being executed.
+    bindings: RefCell<Vec<ColumnType>>,
+    /// The statistics left by ANALYZE, if any.
+    stats: Statistics,
+}
+
+const AGGREGATES: [&str; 7] = ["avg", "count", "group_concat", "max", "min", "sum", "total"];
+
+fn is_aggregate(name: &str, argc: usize) -> bool {
+    match name {
+        // with several arguments these are the scalar min() and max()
+        "min" | "max" => argc == 1,
+        _ => AGGREGATES.contains(&name),
+    }
+}
+
+/// Whether an expression uses an aggregate function, other than as a
+/// window function.
+fn contains_aggregate(expr: &Expr) -> bool {
+    let mut found = false;
+    expr.walk(&mut |e| {
+        if let Expr::Function {
+            name,
+            args,
+            over: None,
+            ..
+        } = e
+        {
+            found |= is_aggregate(name, args.len());
+        }
+    });
+    found
+}
+
+/// The last call of min() or max() of one argument among `exprs`, in the
+/// order SQLite takes them in: that of the result columns, ORDER BY and
+/// HAVING.
+fn last_min_max<'e>(exprs: impl IntoIterator<Item = &'e Expr>) -> Option<&'e Expr> {
+    let mut last = None;
+    for expr in exprs {
+        expr.walk(&mut |e| {
+            if let Expr::Function {
+                name,
+                args,
+                over: None,
+                ..
+            } = e
+            {
+                if args.len() == 1 && (name == "min" || name == "max") {
+                    last = Some(e);
+                }
+            }
+        });
+    }
+    last
+}
+
+/// Functions that can only be used as window functions.
+const WINDOW_FUNCTIONS: [&str; 11] = [
+    "cume_dist",
+    "dense_rank",
+    "first_value",
+    "lag",
+    "last_value",
+    "lead",
+    "nth_value",
+    "ntile",
+    "percent_rank",
+    "rank",
+    "row_number",
+];
+
+/// A window with any named window it builds on resolved.
+struct Window<'a> {
+    partition_by: &'a [Expr],
+    order_by: &'a [OrderingTerm],
+    frame: Option<&'a Frame>,
+}
+
+/// The frame used when a window does not specify one: with an ORDER BY it
+/// ends at the current row's last peer, without one it is the whole
+/// partition.
+const DEFAULT_FRAME: Frame = Frame {
+    units: FrameUnits::Range,
+    start: FrameBound::UnboundedPreceding,
+    end: FrameBound::CurrentRow,
+    exclude: FrameExclude::NoOthers,
+};
+
+impl<'d> Executor<'d> {
+    pub fn new(db: &'d Database) -> Self {
+        Executor {
+            db,
+            materialized: RefCell::new(HashMap::new()),
+            correlated: RefCell::new(HashMap::new()),
+            case_sensitive_like: Cell::new(false),
+            collations: Collations::default(),
+            bindings: RefCell::new(vec![]),
+            // statistics that cannot be read only make for worse plans
+            stats: Statistics::load(db).unwrap_or_default(),
+        }
+    }
+
+    /// The planner, with what it knows about the database.
+    fn planner(&self) -> Planner<'_> {
+        Planner {
+            db: self.db,
+            stats: &self.stats,
+            collations: &self.collations,
+            case_sensitive_like: self.case_sensitive_like.get(),
+        }
+    }
+
+    /// Register a collating sequence that `COLLATE name` and the schema can
+    /// refer to, for databases made by applications with their own.
+    pub fn register_collation(
+        &mut self,
+        name: &str,
+        compare: impl Fn(&str, &str) -> Ordering + 'static,
+    ) {
+        self.collations.register(name, compare);
+    }
+
+    pub fn execute(&self, statement: &Statement) -> Result<ResultSet, anyhow::Error> {
+        // subquery results are only valid for one execution, with one set
+        // of bindings
+        self.materialized.borrow_mut().clear();
+        self.correlated.borrow_mut().clear();
+        *self.bindings.borrow_mut() = (1..=statement.parameter_count())
+            .map(|i| statement.binding(i))
+            .collect();
+        if statement.explain == Some(Explain::QueryPlan) {
+            let plan = self.query_plan(statement)?;
+            return Ok(ResultSet {
+                columns: ["id", "parent", "notused", "detail"]
+                    .map(String::from)
+                    .to_vec(),
+                rows: plan
+                    .steps
+                    .into_iter()
+                    .map(|step| {
+                        vec![
+                            ColumnType::Int64(step.id as i64),
+                            ColumnType::Int64(step.parent as i64),
+                            ColumnType::Int64(0),
+                            ColumnType::String(step.detail),
+                        ]
+                    })
+                    .collect(),
+            });
+        }
+        if statement.explain == Some(Explain::Program) {
+            let program = self.program(statement)?;
+            return Ok(ResultSet {
+                columns: ["addr", "opcode", "p1", "p2", "p3", "p4", "p5", "comment"]
+                    .map(String::from)
+                    .to_vec(),
+                rows: program
+                    .insns
+                    .iter()
+                    .enumerate()
+                    .map(|(addr, insn)| {
+                        vec![
+                            ColumnType::Int64(addr as i64),
+                            ColumnType::String(insn.opcode.to_string()),
+                            ColumnType::Int64(insn.p1),
+                            ColumnType::Int64(insn.p2),
+                            ColumnType::Int64(insn.p3),
+                            ColumnType::String(insn.p4.to_string()),
+                            ColumnType::Int64(insn.p5 as i64),
+                            ColumnType::String(insn.comment()),
+                        ]
+                    })
+                    .collect(),
+            });
+        }
+        match &statement.kind {
+            // selects the compiler does not handle yet are evaluated by
+            // walking the tree
+            StatementKind::Select(select) => match self.compile(select) {
+                Ok(program) => self.run_program(&program),
+                Err(e) if e.is::<codegen::NotCompiled>() => {
+                    let views = self.views()?;
+                    let ctes = views.iter().map(Cte::new).collect::<Vec<_>>();
+                    let scope = Env {
+                        ctes: &ctes,
+                        ..Env::new(None)
+                    };
+                    self.run_select(select, Some(&scope))
+                }
+                Err(e) => Err(e),
+            },
+            StatementKind::Pragma(pragma) => self.pragma(pragma),
+            _ => bail!("only SELECT statements can be executed"),
+        }
+    }
+
+    /// The bytecode program a statement compiles to, for `EXPLAIN`.
+    pub fn program(&self, statement: &Statement) -> Result<Program, anyhow::Error> {
+        match &statement.kind {
+            StatementKind::Select(select) => self.compile(select),
+            _ => bail!("only SELECT statements are compiled to bytecode"),
+        }
+    }
+
+    /// The views of the database, as CTEs in a scope around each
+    /// statement: like a CTE, a view is computed where it is used, and a CTE
+    /// of the same name hides it.
+    fn views(&self) -> Result<Vec<CommonTableExpr>, anyhow::Error> {
+        self.db
+            .views()
+            .map(|info| {
+                let view = info.view()?;
+                Ok(CommonTableExpr {
+                    name: view.name,
+                    columns: view.columns,
+                    materialized: None,
+                    select: view.select,
+                })
+            })
+            .collect()
+    }
+
+    fn run_select(&self, select: &Select, outer: Option<&Env>) -> Result<ResultSet, anyhow::Error> {
+        let (columns, rows) = self.select_rows(select, outer)?;
+        Ok(ResultSet {
+            columns: columns.into_iter().map(|c| c.name).collect(),
+            rows,
+        })
+    }
+
+    /// The result columns and rows of a select. The columns of a compound
+    /// select are those of its first select.
+    fn select_rows(
+        &self,
+        select: &Select,
+        outer: Option<&Env>,
+    ) -> Result<(Vec<ColumnName>, Vec<Row>), anyhow::Error> {
+        let ctes = match &select.with {
+            Some(with) => with.ctes.iter().map(Cte::new).collect(),
+            None => vec![],
+        };
+        let scope = Env {
+            ctes: &ctes,
+            ..Env::new(outer)
+        };
+        let outer = if ctes.is_empty() { outer } else { Some(&scope) };
+
+        let (offset, limit) = self.limit_offset(select, outer)?;
+        if let (Some(limit), Some(name)) = (limit, takes_rows_in_order(select)) {
+            if let Some((cte, _)) = find_cte(outer, name) {
+                cte.wanted.set(Some(offset.saturating_add(limit)));
+            }
+        }
+
+        let (columns, rows) = if select.compound.is_empty() {
+            let (columns, mut output, collations) =
+                self.run_core(&select.body, &select.order_by, outer)?;
+            if !select.order_by.is_empty() {
+                output.sort_by(|(_, a), (_, b)| compare_keys(a, b, &select.order_by, &collations));
+            }
+            (
+                columns,
+                output.into_iter().map(|(values, _)| values).collect(),
+            )
+        } else {
+            let (mut names, mut rows) = self.run_compound(&compound_parts(select), outer)?;
+            if !select.order_by.is_empty() {
+                let positions = compound_order_by(&select.order_by, &names)?;
+                let collations = compound_collations(&names)?;
+                let collations =
+                    self.order_by_collations(&select.order_by, &positions, &collations)?;
+                rows.sort_by(|a, b| {
+                    let a = positions.iter().map(|i| a[*i].clone()).collect::<Vec<_>>();
+                    let b = positions.iter().map(|i| b[*i].clone()).collect::<Vec<_>>();
+                    compare_keys(&a, &b, &select.order_by, &collations)
+                });
+            }
+            (names.swap_remove(0), rows)
+        };
+
+        let rows = rows.into_iter().skip(offset);
+        let rows = match limit {
+            Some(limit) => rows.take(limit).collect(),
+            None => rows.collect(),
+        };
+
+        Ok((columns, rows))
+    }
+
+    /// The OFFSET and LIMIT of a select; a negative LIMIT means no limit.
+    fn limit_offset(
+        &self,
+        select: &Select,
+        outer: Option<&Env>,
+    ) -> Result<(usize, Option<usize>), anyhow::Error> {
+        let env = Env::new(outer);
+        let offset = match &select.offset {
+            Some(e) => limit_integer(self.eval(e, &env)?)?.max(0) as usize,
+            None => 0,
+        };
+        let limit = match &select.limit {
+            Some(e) => limit_integer(self.eval(e, &env)?)?,
+            None => -1,
+        };
+        Ok((offset, usize::try_from(limit).ok()))
+    }
+
+    /// Evaluate the selects of a compound select and combine their rows,
+    /// left to right. Returns the result columns of every select; the first
+    /// select's are the result columns of the whole.
+    fn run_compound(
+        &self,
+        parts: &[(Option<CompoundOp>, &SelectCore)],
+        outer: Option<&Env>,
+    ) -> Result<(Vec<Vec<ColumnName>>, Vec<Row>), anyhow::Error> {
+        let mut names: Vec<Vec<ColumnName>> = vec![];
+        let mut outputs = vec![];
+        for (op, core) in parts {
+            let (core_names, output, _) = self.run_core(core, &[], outer)?;
+            if let (Some(op), Some(first)) = (op, names.first()) {
+                if core_names.len() != first.len() {
+                    bail!(
+                        "SELECTs to the left and right of {} do not have the same number of result columns",
+                        op.name()
+                    );
+                }
+            }
+            names.push(core_names);
+            outputs.push(output.into_iter().map(|(values, _)| values));
+        }
+
+        // rows are told apart under the collations of the columns
+        let collations = compound_collations(&names)?;
+        let mut rows: Vec<Row> = vec![];
+        for ((op, _), next) in parts.iter().zip(outputs) {
+            rows = match op {
+                None | Some(CompoundOp::UnionAll) => {
+                    rows.extend(next);
+                    rows
+                }
+                Some(CompoundOp::Union) => {
+                    rows.extend(next);
+                    distinct_sorted(rows, &collations)
+                }
+                Some(op @ (CompoundOp::Intersect | CompoundOp::Except)) => {
+                    let mut keys = CollatedKeys::new(collations.clone());
+                    let right = next.map(|row| keys.key(&row)).collect::<HashSet<_>>();
+                    let keep = *op == CompoundOp::Intersect;
+                    distinct_sorted(rows, &collations)
+                        .into_iter()
+                        .filter(|row| right.contains(&keys.key(row)) == keep)
+                        .collect()
+                }
+            };
+        }
+
+        Ok((names, rows))
+    }
+
+    /// Evaluate a single select, without the ORDER BY and LIMIT that apply to
+    /// the whole (possibly compound) statement. Each output row comes with
+    /// its sort keys for `order_by`.
+    fn run_core(
+        &self,
+        core: &SelectCore,
+        order_by: &[OrderingTerm],
+        outer: Option<&Env>,
+    ) -> Result<CoreOutput, anyhow::Error> {
+        let relation = match &core.from {
+            Some(from) => {
+                let refs = ColumnRefs::of(core, order_by);
+                self.eval_from(from, core.where_clause.as_ref(), &refs, outer)?
+            }
+            None => Relation {
+                columns: vec![],
+                rows: vec![vec![]],
+            },
+        };
+
+        let (names, exprs) = self.result_columns(core, &relation.columns)?;
+        let aliases = core
+            .columns
+            .iter()
+            .filter_map(|c| match c {
+                ResultColumn::Expr {
+                    expr,
+                    alias: Some(alias),
+                    ..
+                } => Some((alias.clone(), expr)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let env = Env {
+            columns: &relation.columns,
+            aliases: &aliases,
+            ..Env::new(outer)
+        };
+        // a view or subquery reading these compares them as their
+        // expressions would be
+        let mut columns = vec![];
+        for (name, expr) in names.into_iter().zip(&exprs) {
+            columns.push(ColumnName {
+                table: None,
+                name,
+                hidden: false,
+                affinity: self.affinity(expr, &env),
+                collation: self.collation(expr, &env)?.map(|(c, _)| Ok(c)),
+            });
+        }
+
+        let mut rows = vec![];
+        for row in relation.rows {
+            if let Some(cond) = &core.where_clause {
+                if self.eval(cond, &env.with_row(&row))?.truthy() != Some(true) {
+                    continue;
+                }
+            }
+            rows.push(row);
+        }
+
+        let group_by = group_by_terms(&core.group_by, &exprs)?;
+        let is_aggregate = !core.group_by.is_empty()
+            || exprs.iter().any(|e| contains_aggregate(e))
+            || core.having.as_ref().is_some_and(contains_aggregate)
+            || order_by.iter().any(|t| contains_aggregate(&t.expr));
+
+        // the environment of each output row: a row, or a group of rows
+        let null_row = vec![ColumnType::Null; relation.columns.len()];
+        let groups;
+        let mut envs = vec![];
+        if is_aggregate {
+            groups = self.group_rows(&group_by, rows, &env)?;
+            let min_max = last_min_max(
+                exprs
+                    .iter()
+                    .map(|e| &**e)
+                    .chain(order_by.iter().map(|t| &t.expr))
+                    .chain(&core.having),
+            );
+            for group in &groups {
+                let group_env = Env {
+                    row: self.bare_row(min_max, group, &env)?.unwrap_or(&null_row),
+                    group: Some(group),
+                    ..env
+                };
+                if let Some(having) = &core.having {
+                    if self.eval(having, &group_env)?.truthy() != Some(true) {
+                        continue;
+                    }
+                }
+                envs.push(group_env);
+            }
+        } else {
+            envs.extend(rows.iter().map(|row| env.with_row(row)));
+        }
+
+        // window functions see all output rows; the rows come out in the
+        // order of the first window
+        let (windows, order) = self.eval_windows(core, &exprs, order_by, &envs, &env)?;
+
+        // each output row with its ORDER BY sort keys
+        let mut output: Vec<KeyedRow> = vec![];
+        for i in order {
+            let env = Env {
+                window: &windows[i],
+                ..envs[i]
+            };
+            let values = exprs
+                .iter()
+                .map(|e| self.eval(e, &env))
+                .collect::<Result<Row, _>>()?;
+            let mut keys = vec![];
+            for (i, term) in order_by.iter().enumerate() {
+                keys.push(match order_by_position(i, &term.expr, values.len())? {
+                    Some(i) => values[i].clone(),
+                    None => self.eval(&term.expr, &env)?,
+                });
+            }
+            output.push((values, keys));
+        }
+
+        if core.distinct {
+            let collations = exprs
+                .iter()
+                .map(|e| self.collation_or_binary(e, &env))
+                .collect::<Result<_, _>>()?;
+            let mut keys = CollatedKeys::new(collations);
+            let mut seen = HashSet::new();
+            output.retain(|(values, _)| seen.insert(keys.key(values)));
+        }
+
+        let mut collations = vec![];
+        for (i, term) in order_by.iter().enumerate() {
+            collations.push(match order_by_position(i, &term.expr, exprs.len())? {
+                Some(i) => match &term.expr {
+                    Expr::Collate { .. } => self.collation_or_binary(&term.expr, &env)?,
+                    _ => self.collation_or_binary(&exprs[i], &env)?,
+                },
+                None => self.collation_or_binary(&term.expr, &env)?,
+            });
+        }
+
+        Ok((columns, output, collations))
+    }
+
+    /// Expand `*` and `table.*` and name each result column.
+    fn result_columns<'s>(
+        &self,
+        core: &'s SelectCore,
+        columns: &[ColumnName],
+    ) -> Result<(Vec<String>, Vec<ExprRef<'s>>), anyhow::Error> {
+        let mut names = vec![];
+        let mut exprs = vec![];
+
+        for column in &core.columns {
+            match column {
+                ResultColumn::Star | ResultColumn::TableStar(_) => {
+                    let table = match column {
+                        ResultColumn::TableStar(t) => Some(t.as_str()),
+                        _ => None,
+                    };
+                    // `*` has each column a USING clause names once
+                    let skipped = match (table, &core.from) {
+                        (None, Some(from)) => join::using_duplicates(from, columns),
+                        _ => vec![false; columns.len()],
+                    };
+                    let before = names.len();
+                    for (c, _) in columns
+                        .iter()
+                        .zip(skipped)
+                        .filter(|(c, skipped)| !c.hidden && !skipped)
+                    {
+                        if let Some(t) = table {
+                            if !c
+                                .table
+                                .as_deref()
+                                .is_some_and(|own| own.eq_ignore_ascii_case(t))
+                            {
+                                continue;
+                            }
+                        }
+                        names.push(c.name.clone());
+                        exprs.push(ExprRef::Owned(Expr::Column {
+                            table: c.table.clone(),
+                            name: c.name.clone(),
+                        }));
+                    }
+                    if names.len() == before {
+                        match table {
+                            Some(t) => bail!("no such table: {t}"),
+                            None if core.from.is_none() => bail!("no tables specified"),
+                            None => {}
+                        }
+                    }
+                }
+                ResultColumn::Expr { expr, alias, text } => {
+                    names.push(match (alias, expr) {
+                        (Some(alias), _) => alias.clone(),
+                        (None, Expr::Column { name, .. }) => name.clone(),
+                        (None, _) => text.clone(),
+                    });
+                    exprs.push(ExprRef::Borrowed(expr));
+                }
+            }
+        }
+
+        Ok((names, exprs))
+    }
+
+    /// Split rows into groups with equal GROUP BY values, in the order of
+    /// those values. Without GROUP BY all rows form a single group, even if
+    /// there are none. Each term is an expression and the one whose
+    /// collation it is compared with, as [`group_by_terms`] gives them.
+    fn group_rows(
+        &self,
+        group_by: &[(&Expr, &Expr)],
+        rows: Vec<Row>,
+        env: &Env,
+    ) -> Result<Vec<Vec<Row>>, anyhow::Error> {
+        if group_by.is_empty() {
+            return Ok(vec![rows]);
+        }
+
+        let collations = group_by
+            .iter()
+            .map(|(_, collated)| self.collation_or_binary(collated, env))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut keys = CollatedKeys::new(collations.clone());
+        let mut groups: Vec<(Row, Vec<Row>)> = vec![];
+        let mut index: HashMap<Vec<ValueKey>, usize> = HashMap::new();
+        for row in rows {
+            let values = group_by
+                .iter()
+                .map(|(e, _)| self.eval(e, &env.with_row(&row)))
+                .collect::<Result<Row, _>>()?;
+            let key = keys.key(&values);
+            match index.get(&key) {
+                Some(&i) => groups[i].1.push(row),
+                None => {
+                    index.insert(key, groups.len());
+                    groups.push((values, vec![row]));
+                }
+            }
+        }
+
+        groups.sort_by(|(a, _), (b, _)| compare_rows(a, b, &collations));
+
+        Ok(groups.into_iter().map(|(_, rows)| rows).collect())
+    }
+
+    /// The row of a group that the columns outside of aggregate calls take
+    /// their values from. As in SQLite, that is the last row for which
+    /// `min_max`, the last min() or max() call of the query, took a new
+    /// value, or without one the first row.
+    fn bare_row<'r>(
+        &self,
+        min_max: Option<&Expr>,
+        group: &'r [Row],
+        env: &Env,
+    ) -> Result<Option<&'r Row>, anyhow::Error> {
+        let Some(Expr::Function {
+            name, args, filter, ..
+        }) = min_max
+        else {
+            return Ok(group.first());
+        };
+        let collation = self.collation_or_binary(&args[0], env)?;
+        let wanted = match name == "max" {
+            true => Ordering::Greater,
+            false => Ordering::Less,
+        };
+        let (mut best, mut found) = (None, None);
+        for row in group {
+            let env = env.with_row(row);
+            // the rows a FILTER clause leaves out are not stepped
+            if let Some(filter) = filter {
+                if self.eval(filter, &env)?.truthy() != Some(true) {
+                    continue;
+                }
+            }
+            let value = self.eval(&args[0], &env)?;
+            // a NULL before any value is taken like one
+            let new = match &best {
+                None => true,
+                Some(_) if value.is_null() => false,
+                Some(best) => collation.compare(&value, best) == wanted,
+            };
+            if new {
+                found = Some(row);
+                if !value.is_null() {
+                    best = Some(value);
+                }
+            }
+        }
+        Ok(found.or(group.first()))
+    }
+
+    /// Evaluate a FROM clause. `where_clause` is given when `from` is the
+    /// whole FROM clause, so that its terms can narrow down the rows read;
+    /// `refs` are the columns the select needs of its tables.
+    fn eval_from(
+        &self,
+        from: &FromClause,
+        where_clause: Option<&Expr>,
+        refs: &ColumnRefs,
+        outer: Option<&Env>,
+    ) -> Result<Relation, anyhow::Error> {
+        match from {
+            FromClause::Table { name, alias } => {
+                let table = alias.as_deref().unwrap_or(name);
+                let Some((cte, scope)) = find_cte(outer, name) else {
+                    return self.eval_joins(from, where_clause, refs, outer);
+                };
+                let rows = self.cte_rows(cte, scope)?;
+                let columns = cte
+                    .columns
+                    .borrow()
+                    .iter()
+                    .flatten()
+                    .map(|c| ColumnName {
+                        table: Some(table.to_string()),
+                        ..c.clone()
+                    })
+                    .collect();
+                Ok(Relation {
+                    columns,
+                    rows: rows.as_ref().clone(),
+                })
+            }
+            FromClause::Subquery { select, alias } => {
+                let (columns, rows) = self.select_rows(select, outer)?;
+                Ok(Relation {
+                    columns: columns
+                        .into_iter()
+                        .map(|c| ColumnName {
+                            table: alias.clone(),
+                            ..c
+                        })
+                        .collect(),
+                    rows,
+                })
+            }
+            FromClause::Function { .. } | FromClause::Join { .. } => {
+                self.eval_joins(from, where_clause, refs, outer)
+            }
+        }
+    }
+
+    /// The rows of a common table expression, computed on first use. `scope`
+    /// is the environment of the select that declares it.
+    fn cte_rows(&self, cte: &Cte, scope: &Env) -> Result<Rc<Vec<Row>>, anyhow::Error> {
+        let wanted = cte.wanted.take();
+        match &*cte.state.borrow() {
+            CteState::Working(rows) | CteState::Done(rows) => return Ok(rows.clone()),
+            CteState::Computing => bail!("circular reference: {}", cte.def.name),
+            CteState::Pending => {}
+        }
+        *cte.state.borrow_mut() = CteState::Computing;
+
+        let select = &cte.def.select;
+        let (rows, cut_short) = match recursive_split(select, &cte.def.name) {
+            Some(split) => self.run_recursive(cte, split, scope, wanted)?,
+            None => {
+                let (columns, rows) = self.select_rows(select, Some(scope))?;
+                *cte.columns.borrow_mut() = Some(cte_columns(cte.def, columns)?);
+                (rows, false)
+            }
+        };
+
+        let rows = Rc::new(rows);
+        *cte.state.borrow_mut() = match cte.def.materialized {
+            // a NOT MATERIALIZED CTE is computed again wherever it is used,
+            // and so are rows only some of which one select wanted
+            Some(false) => CteState::Pending,
+            _ if cut_short => CteState::Pending,
+            _ => CteState::Done(rows.clone()),
+        };
+        Ok(rows)
+    }
+
+    /// Evaluate a recursive CTE: the selects before `split` produce the
+    /// initial rows, which go into a queue. Each row taken from the queue is
+    /// output and becomes the sole content of the CTE while the recursive
+    /// selects run, and their rows are added to the queue in turn. An ORDER
+    /// BY on the CTE orders the queue, and LIMIT stops the recursion, as
+    /// does having the rows `wanted`; whether the latter cut it short is
+    /// returned with the rows.
+    fn run_recursive(
+        &self,
+        cte: &Cte,
+        split: usize,
+        scope: &Env,
+        wanted: Option<usize>,
+    ) -> Result<(Vec<Row>, bool), anyhow::Error> {
+        let select = &cte.def.select;
+        let parts = compound_parts(select);
+        let (select_names, initial) = self.run_compound(&parts[..split], Some(scope))?;
+        let columns = cte_columns(cte.def, select_names[0].clone())?;
+        let width = columns.len();
+        *cte.columns.borrow_mut() = Some(columns);
+
+        let union = parts[split..]
+            .iter()
+            .any(|(op, _)| *op == Some(CompoundOp::Union));
+        let positions = compound_order_by(&select.order_by, &select_names)?;
+        let column_collations = compound_collations(&select_names)?;
+        let collations =
+            self.order_by_collations(&select.order_by, &positions, &column_collations)?;
+        let (mut offset, limit) = self.limit_offset(select, Some(scope))?;
+        let cut_short = wanted.is_some_and(|w| limit.map_or(true, |l| w < l));
+        let limit = if cut_short { wanted } else { limit };
+
+        let mut seen = HashSet::new();
+        let mut seen_keys = CollatedKeys::new(column_collations);
+        let mut queue: VecDeque<KeyedRow> = VecDeque::new();
+        let mut enqueue = |queue: &mut VecDeque<KeyedRow>, row: Row| {
+            if union && !seen.insert(seen_keys.key(&row)) {
+                return;
+            }
+            let keys = positions.iter().map(|i| row[*i].clone()).collect::<Row>();
+            // rows with equal keys stay in the order they were produced
+            let at = queue.partition_point(|(_, k)| {
+                compare_keys(k, &keys, &select.order_by, &collations) != Ordering::Greater
+            });
+            queue.insert(at, (row, keys));
+        };
+        for row in initial {
+            enqueue(&mut queue, row);
+        }
+
+        let mut rows = vec![];
+        if limit == Some(0) {
+            return Ok((rows, cut_short));
+        }
+        let mut stopped = false;
+        while let Some((row, _)) = queue.pop_front() {
+            if offset > 0 {
+                offset -= 1;
+            } else {
+                rows.push(row.clone());
+                if Some(rows.len()) == limit {
+                    stopped = true;
+                    break;
+                }
+            }
+
+            *cte.state.borrow_mut() = CteState::Working(Rc::new(vec![row]));
+            for (_, core) in &parts[split..] {
+                let (core_names, output, _) = self.run_core(core, &[], Some(scope))?;
+                if core_names.len() != width {
+                    bail!(
+                        "SELECTs to the left and right of {} do not have the same number of result columns",
+                        parts[split].0.map_or("UNION", CompoundOp::name)
+                    );
+                }
+                for (row, _) in output {
+                    enqueue(&mut queue, row);
+                }
+            }
+        }
+        *cte.state.borrow_mut() = CteState::Computing;
+
+        Ok((rows, cut_short && stopped))
+    }
+
+    /// The b-tree and columns of a table, with the hidden rowid column last
+    /// unless it is a WITHOUT ROWID table.
+    fn table_source(&self, name: &str, alias: &str) -> Result<TableSource<'d>, anyhow::Error> {
+        let column = |name: String, affinity, collation| ColumnName {
+            table: Some(alias.to_string()),
+            name,
+            hidden: false,
+            affinity: Some(affinity),
+            collation: Some(collation),
+        };
+        let mut generated = vec![];
+        let mut defaults = vec![];
+        let (table, root_page, mut columns, is_rowid, stored, layout) = if is_schema_table(name) {
+            let names = ["type", "name", "tbl_name", "rootpage", "sql"];
+            (
+                None,
+                1,
+                names
+                    .iter()
+                    .map(|n| {
+                        let affinity = match *n {
+                            "rootpage" => Affinity::Integer,
+                            _ => Affinity::Text,
+                        };
+                        column(n.to_string(), affinity, Ok(Collation::Binary))
+                    })
+                    .collect::<Vec<_>>(),
+                vec![false; names.len()],
+                (0..names.len()).collect(),
+                None,
+            )
+        } else {
+            let table = self
+                .db
+                .find_table(name)
+                .ok_or_else(|| anyhow!("no such table: {name}"))?;
+            let schema = table.table()?;
+            let stored = schema
+                .record_columns()
+                .unwrap_or_else(|| schema.stored_columns());
+            let layout = match (schema.record_columns(), self.db.primary_key(name)) {
+                (Some(columns), Some(primary_key)) => {
+                    let mut key = vec![];
+                    for (indexed, &i) in primary_key.index()?.columns.iter().zip(&columns) {
+                        let collation = indexed.collation.as_ref();
+                        let collation = match collation.or(schema.columns[i].collation.as_ref()) {
+                            Some(name) => self.collations.find(name)?,
+                            None => Collation::Binary,
+                        };
+                        key.push((collation, indexed.desc));
+                    }
+                    Some(Layout { key })
+                }
+                _ => None,
+            };
+            // an INTEGER PRIMARY KEY of a WITHOUT ROWID table is no alias
+            let is_rowid = schema
+                .columns
+                .iter()
+                .map(|c| layout.is_none() && c.is_rowid_alias())
+                .collect();
+            let mut columns = vec![];
+            let mut virtual_columns = vec![];
+            for c in schema.columns {
+                let affinity = Affinity::of_column(c.type_name.as_deref());
+                let collation = match &c.collation {
+                    Some(name) => self.collations.find(name).map_err(|_| name.clone()),
+                    None => Ok(Collation::Binary),
+                };
+                if let Some(g) = c.generated.filter(|g| !g.stored) {
+                    virtual_columns.push((columns.len(), g.expr));
+                }
+                defaults.push(c.default);
+                columns.push(column(c.name, affinity, collation));
+            }
+            // a generated column can refer to others, which come first
+            while !virtual_columns.is_empty() {
+                let ready = virtual_columns.iter().position(|(_, expr)| {
+                    let mut refers = false;
+                    expr.walk(&mut |e| {
+                        if let Expr::Column { name, .. } = e {
+                            refers |= virtual_columns
+                                .iter()
+                                .any(|(i, _)| columns[*i].name.eq_ignore_ascii_case(name));
+                        }
+                    });
+                    !refers
+                });
+                let Some(k) = ready else {
+                    let (i, _) = &virtual_columns[0];
+                    bail!("generated column loop on \"{}\"", columns[*i].name);
+                };
+                generated.push(virtual_columns.remove(k));
+            }
+            (
+                Some(table),
+                table.root_page,
+                columns,
+                is_rowid,
+                stored,
+                layout,
+            )
+        };
+        if layout.is_none() {
+            columns.push(ColumnName {
+                table: Some(alias.to_string()),
+                name: "rowid".to_string(),
+                hidden: true,
+                affinity: Some(Affinity::Integer),
+                collation: Some(Ok(Collation::Binary)),
+            });
+        }
+        Ok(TableSource {
+            table,
+            root_page,
+            columns,
+            is_rowid,
+            stored,
+            defaults,
+            generated,
+            layout,
+        })
+    }
+
+    /// Read the rows of a table the way `access` says, appending the rowid
+    /// as a hidden column. The keys of the access are computed in `env`.
+    fn read_table(
+        &self,
+        source: &TableSource,
+        access: &Access,
+        env: &Env,
+    ) -> Result<Vec<Row>, anyhow::Error> {
+        let TableSource {
+            root_page,
+            columns,
+            is_rowid,
+            stored,
+            defaults,
+            generated,
+            layout,
+            ..
+        } = source;
+        let affinities = columns[..is_rowid.len()]
+            .iter()
+            .map(|c| c.affinity.unwrap_or(Affinity::Blob))
+            .collect::<Vec<_>>();
+
+        let mut rows = vec![];
+        // the fields of a record are put in the order of the columns, and
+        // the generated columns computed, unless the row comes from an index
+        // entry; a row of a WITHOUT ROWID table has no rowid
+        let in_order = layout.is_none() && generated.is_empty();
+        let constant = Env::new(None);
+        let mut add_row = |row_id: Option<i64>, mut record: Row, from_table: bool| {
+            // a record written before columns were added lacks them
+            let missing = match from_table {
+                true => stored.get(record.len()..).unwrap_or_default(),
+                false => &[],
+            };
+            if from_table && !in_order {
+                let mut row = vec![ColumnType::Null; affinities.len()];
+                for (value, &column) in record.into_iter().zip(stored) {
+                    row[column] = value;
+                }
+                record = row;
+            }
+            record.resize(affinities.len(), ColumnType::Null);
+            for &column in missing {
+                if let Some(default) = defaults.get(column).and_then(|d| d.as_ref()) {
+                    let value = self.eval(default, &constant)?;
+                    record[column] = value.apply_affinity(affinities[column]);
+                }
+            }
+            for ((value, is_rowid), affinity) in record.iter_mut().zip(is_rowid).zip(&affinities) {
+                *value = match row_id {
+                    Some(row_id) if *is_rowid => ColumnType::Int64(row_id),
+                    _ => std::mem::replace(value, ColumnType::Null).read_with_affinity(*affinity),
+                };
+            }
+            if from_table {
+                for (column, expr) in generated {
+                    let env = Env {
+                        columns,
+                        row: &record,
+                        ..Env::new(None)
+                    };
+                    record[*column] = self.eval(expr, &env)?.apply_affinity(affinities[*column]);
+                }
+            }
+            if let Some(row_id) = row_id {
+                record.push(ColumnType::Int64(row_id));
+            }
+            rows.push(record);
+            Ok::<_, anyhow::Error>(())
+        };
+        // the entries of the other indexes of a WITHOUT ROWID table end
+        // with the primary key, which finds the row
+        let uncovered = match access {
+            Access::IndexSeek(seek) if seek.covering.is_none() => Some(seek.index),
+            Access::IndexRange(range) if range.covering.is_none() => Some(range.index),
+            _ => None,
+        };
+        let mut key_at = vec![];
+        if let (Some(layout), Some(index), Some(table)) = (layout, uncovered, source.table) {
+            let entry = planner::index_columns(&table.table()?, index).unwrap_or_default();
+            for column in &stored[..layout.key.len()] {
+                let Some(i) = entry.iter().position(|c| c == column) else {
+                    bail!("index {} has entries without the primary key", index.name);
+                };
+                key_at.push(i);
+            }
+        }
+        // the row of an index entry, made from the entry alone when the
+        // index covers the query; the rowid is the last column of an entry
+        let mut add_entry = |index: &TableInfo,
+                             covering: Option<&Vec<usize>>,
+                             entry: Row|
+         -> Result<(), anyhow::Error> {
+            let row_id = match (layout, entry.last().and_then(|v| v.as_integer())) {
+                (Some(_), _) => None,
+                (None, Some(row_id)) => Some(row_id),
+                (None, None) => bail!("index {} has an entry without a rowid", index.name),
+            };
+            match (covering, layout, row_id) {
+                // the primary key of a WITHOUT ROWID table holds its rows,
+                // which may be short
+                (Some(_), Some(_), _) if index.primary_key => add_row(None, entry, true)?,
+                (Some(columns), _, _) => {
+                    let mut record = vec![ColumnType::Null; is_rowid.len()];
+                    for (value, &column) in entry.into_iter().zip(columns) {
+                        record[column] = value;
+                    }
+                    add_row(row_id, record, false)?;
+                }
+                (None, Some(layout), _) => {
+                    let key = key_at
+                        .iter()
+                        .map(|&i| entry.get(i).cloned().unwrap_or(ColumnType::Null))
+                        .collect::<Vec<_>>();
+                    let mut found = None;
+                    btree::scan_index(
+                        self.db,
+                        *root_page,
+                        &|record| layout.compare_key(record, &key) == Ordering::Less,
+                        &mut |record| {
+                            if layout.compare_key(&record, &key) == Ordering::Equal {
+                                found = Some(record);
+                            }
+                            Ok(false)
+                        },
+                    )?;
+                    if let Some(record) = found {
+                        add_row(None, record, true)?;
+                    }
+                }
+                (None, None, Some(row_id)) => {
+                    if let Some(record) = btree::find_row(self.db, *root_page, row_id)? {
+                        add_row(Some(row_id), record, true)?;
+                    }
+                }
+                (None, None, None) => unreachable!("a table with rowids has a rowid"),
+            }
+            Ok(())
+        };
+        // the distinct values of a key in order; NULL is not equal to
+        // anything
+        let values = |key: &planner::SeekKey,
+                      collation: &Collation|
+         -> Result<Vec<ColumnType>, anyhow::Error> {
+            let mut values = vec![];
+            for value in &key.values {
+                let value = with_affinity(self.eval(value, env)?, key.affinity);
+                if !value.is_null() {
+                    values.push(value);
+                }
+            }
+            values.sort_by(|a, b| collation.compare(a, b));
+            values.dedup_by(|a, b| collation.compare(a, b) == Ordering::Equal);
+            Ok(values)
+        };
+        match access {
+            Access::Scan => match layout {
+                Some(_) => {
+                    btree::scan_index(self.db, *root_page, &|_| false, &mut |record| {
+                        add_row(None, record, true)?;
+                        Ok(true)
+                    })?;
+                }
+                None => {
+                    btree::scan_table(self.db, *root_page, &mut |row_id, record| {
+                        add_row(Some(row_id), record, true)?;
+                        Ok(true)
+                    })?;
+                }
+            },
+            Access::RowidEq(seek) => {
+                let mut row_ids = values(seek, &Collation::Binary)?
+                    .into_iter()
+                    .filter_map(|v| match v {
+                        ColumnType::Float(f) if f.fract() == 0.0 && f.abs() < 9.2e18 => {
+                            Some(f as i64)
+                        }
+                        v => v.as_integer(),
+                    })
+                    .collect::<Vec<_>>();
+                row_ids.sort_unstable();
+                row_ids.dedup();
+                for row_id in row_ids {
+                    if let Some(record) = btree::find_row(self.db, *root_page, row_id)? {
+                        add_row(Some(row_id), record, true)?;
+                    }
+                }
+            }
+            Access::RowidRange { lower, upper } => {
+                // integers come before text and blobs, and a real bound
+                // takes in the integers on either side of it; the WHERE
+                // clause settles which rows of the range it holds for
+                let (mut start, mut end) = (i64::MIN, i64::MAX);
+                if let Some((k, _)) = lower {
+                    match values(k, &Collation::Binary)?.pop() {
+                        Some(ColumnType::Float(f)) => start = f.floor() as i64,
+                        Some(v) => match v.as_integer() {
+                            Some(i) => start = i,
+                            None => return Ok(rows),
+                        },
+                        None => return Ok(rows),
+                    }
+                }
+                if let Some((k, _)) = upper {
+                    match values(k, &Collation::Binary)?.pop() {
+                        Some(ColumnType::Float(f)) => end = f.ceil() as i64,
+                        Some(v) => end = v.as_integer().unwrap_or(i64::MAX),
+                        None => return Ok(rows),
+                    }
+                }
+                btree::scan_table_from(self.db, *root_page, start, &mut |row_id, record| {
+                    if row_id > end {
+                        return Ok(false);
+                    }
+                    add_row(Some(row_id), record, true)?;
+                    Ok(true)
+                })?;
+            }
+            Access::IndexSeek(seek) => {
+                // each combination of the values of the keys, in index order
+                let mut lookups = vec![vec![]];
+                for (i, (k, collation)) in seek.eq.iter().zip(&seek.collations).enumerate() {
+                    let mut values = values(k, collation)?;
+                    if seek.desc[i] {
+                        values.reverse();
+                    }
+                    lookups = lookups
+                        .into_iter()
+                        .flat_map(|eq: Vec<ColumnType>| {
+                            values.iter().map(move |v| {
+                                let mut eq = eq.clone();
+                                eq.push(v.clone());
+                                eq
+                            })
+                        })
+                        .collect();
+                }
+                let mut bounds = vec![];
+                for bound in [&seek.lower, &seek.upper] {
+                    bounds.push(match bound {
+                        Some((k, _)) => match values(k, &Collation::Binary)?.pop() {
+                            Some(v) => Some(v),
+                            None => return Ok(rows),
+                        },
+                        None => None,
+                    });
+                }
+                for eq in lookups {
+                    let position = |entry: &Row| {
+                        seek.position(entry, &eq, bounds[0].as_ref(), bounds[1].as_ref())
+                    };
+                    btree::scan_index(
+                        self.db,
+                        seek.index.root_page,
+                        &|entry| position(entry) == Ordering::Less,
+                        &mut |entry| {
+                            if position(&entry) == Ordering::Greater {
+                                return Ok(false);
+                            }
+                            add_entry(seek.index, seek.covering.as_ref(), entry)?;
+                            Ok(true)
+                        },
+                    )?;
+                }
+            }
+            Access::IndexRange(range) => {
+                let key = |entry: &Row| entry.first().cloned().unwrap_or(ColumnType::Null);
+                for blob in [false, true] {
+                    btree::scan_index(
+                        self.db,
+                        range.index.root_page,
+                        &|entry| range.position(&key(entry), blob) == Ordering::Less,
+                        &mut |entry| {
+                            if range.position(&key(&entry), blob) == Ordering::Greater {
+                                return Ok(false);
+                            }
+                            add_entry(range.index, range.covering.as_ref(), entry)?;
+                            Ok(true)
+                        },
+                    )?;
+                }
+            }
+            Access::IndexScan(scan) => {
+                btree::scan_index(self.db, scan.index.root_page, &|_| false, &mut |entry| {
+                    add_entry(scan.index, Some(&scan.columns), entry)?;
+                    Ok(true)
+                })?;
+            }
+            Access::MultiIndexOr(branches) => {
+                // as in SQLite, each side gives the rows it holds for, less
+                // those given before; the rowid is the last column of a
+                // row, and a WITHOUT ROWID table has its primary key
+                let mut seen = HashSet::new();
+                let key = |row: &Row| match layout {
+                    Some(layout) => stored[..layout.key.len()]
+                        .iter()
+                        .map(|&i| row[i].key())
+                        .collect(),
+                    None => row.last().map(|v| v.key()).into_iter().collect::<Vec<_>>(),
+                };
+                for (side, branch) in branches {
+                    for row in self.read_table(source, &branch.access, env)? {
+                        let env = Env {
+                            columns,
+                            row: &row,
+                            ..Env::new(Some(env))
+                        };
+                        if self.eval(side, &env)?.truthy() == Some(true) && seen.insert(key(&row)) {
+                            rows.push(row);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(rows)
+    }
+
+    /// Describe how a statement is run, for `EXPLAIN QUERY PLAN`, with the
+    /// planner's estimate of the rows read.
+    pub fn query_plan(&self, statement: &Statement) -> Result<QueryPlan, anyhow::Error> {
+        let views = self.views()?;
+        let mut cx = PlanContext {
+            plan: QueryPlan::default(),
+            ctes: views
+                .iter()
+                .map(|def| PlanCte {
+                    def,
+                    scope_end: views.len(),
+                    loops: 1.0,
+                    rows: None,
+                })
+                .collect(),
+            subqueries: 0,
+            scopes: vec![],
+        };
+        if let StatementKind::Select(select) = &statement.kind {
+            self.plan_select(select, 0, 1.0, &mut cx)?;
+        }
+        Ok(cx.plan)
+    }
+
+    /// Add the steps of a select under `parent`, returning the estimated
+    /// number of rows it produces. The select runs `loops` times.
+    fn plan_select<'s>(
+        &self,
+        select: &'s Select,
+        parent: usize,
+        loops: f64,
+        cx: &mut PlanContext<'s>,
+    ) -> Result<f64, anyhow::Error> {
+        let bound = cx.ctes.len();
+        let defs = select.with.iter().flat_map(|w| &w.ctes).collect::<Vec<_>>();
+        let scope_end = bound + defs.len();
+        cx.ctes.extend(defs.into_iter().map(|def| PlanCte {
+            def,
+            scope_end,
+            loops,
+            rows: None,
+        }));
+
+        let mut rows = if select.compound.is_empty() {
+            self.plan_core(&select.body, &select.order_by, parent, loops, cx)?
+        } else {
+            let compound = cx.plan.add(parent, "COMPOUND QUERY");
+            let mut rows = 0.0;
+            for (op, core) in compound_parts(select) {
+                let detail = match op {
+                    None => "LEFT-MOST SUBQUERY",
+                    Some(CompoundOp::UnionAll) => "UNION ALL",
+                    Some(CompoundOp::Union) => "UNION USING TEMP B-TREE",
+                    Some(CompoundOp::Intersect) => "INTERSECT USING TEMP B-TREE",
+                    Some(CompoundOp::Except) => "EXCEPT USING TEMP B-TREE",
+                };
+                let id = cx.plan.add(compound, detail);
+                let core_rows = self.plan_core(core, &[], id, loops, cx)?;
+                rows = match op {
+                    Some(CompoundOp::Intersect) => f64::min(rows, core_rows),
+                    Some(CompoundOp::Except) => rows,
+                    _ => rows + core_rows,
+                };
+            }
+            if !select.order_by.is_empty() {
+                plan_sort(cx, parent, "ORDER BY", rows * loops);
+            }
+            rows
+        };
+        cx.ctes.truncate(bound);
+
+        if let Some(Expr::Literal(limit)) = &select.limit {
+            if let Some(limit) = limit.as_integer().filter(|n| *n >= 0) {
+                rows = rows.min(limit as f64);
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Add the steps of a single select, as run by [`Self::run_core`].
+    fn plan_core<'s>(
+        &self,
+        core: &'s SelectCore,
+        order_by: &'s [OrderingTerm],
+        parent: usize,
+        loops: f64,
+        cx: &mut PlanContext<'s>,
+    ) -> Result<f64, anyhow::Error> {
+        let (mut rows, columns, scan_order) = match &core.from {
+            Some(from) => {
+                let refs = ColumnRefs::of(core, order_by);
+                self.plan_from(from, core.where_clause.as_ref(), &refs, parent, loops, cx)?
+            }
+            None => {
+                cx.plan.add(parent, "SCAN CONSTANT ROW");
+                (1.0, vec![], vec![])
+            }
+        };
+
+        // the GROUP BY, DISTINCT and ORDER BY terms that are columns of the
+        // FROM clause, to see whether the rows already come in their order
+        let mut results = vec![];
+        let mut aliases = vec![];
+        for c in &core.columns {
+            if let ResultColumn::Expr { expr, alias, .. } = c {
+                results.push(expr);
+                aliases.extend(alias.as_ref().map(|a| (a.clone(), expr)));
+            }
+        }
+        // with a `*` the positions of the result columns are not known yet
+        if results.len() < core.columns.len() {
+            results.clear();
+        }
+        let env = Env {
+            columns: &columns,
+            aliases: &aliases,
+            ..Env::new(None)
+        };
+        let sort_columns = |terms: &mut dyn Iterator<Item = (&Expr, bool)>| {
+            let terms = terms.map(|(term, desc)| {
+                let (column, collation) = self.sort_column(term, &results, &env)?;
+                Some((column, collation, desc))
+            });
+            terms.collect::<Option<Vec<_>>>().unwrap_or_default()
+        };
+        let group_terms = sort_columns(&mut core.group_by.iter().map(|e| (e, false)));
+        let order_terms = sort_columns(&mut order_by.iter().map(|t| (&t.expr, t.desc)));
+        let result_terms = sort_columns(&mut results.iter().map(|e| (&**e, false)));
+        let mut window = false;
+        for expr in results
+            .iter()
+            .map(|e| &**e)
+            .chain(order_by.iter().map(|t| &t.expr))
+        {
+            expr.walk(&mut |e| window |= matches!(e, Expr::Function { over: Some(_), .. }));
+        }
+
+        let mut exprs: Vec<&Expr> = vec![];
+        for c in &core.columns {
+            if let ResultColumn::Expr { expr, .. } = c {
+                exprs.push(expr);
+            }
+        }
+        exprs.extend(core.where_clause.iter());
+        exprs.extend(core.group_by.iter());
+        exprs.extend(core.having.iter());
+        exprs.extend(order_by.iter().map(|t| &t.expr));
+        if let Some(from) = &core.from {
+            join_conditions(from, &mut exprs);
+        }
+        let mut subqueries = vec![];
+        for expr in &exprs {
+            expr.walk(&mut |e| match e {
+                Expr::Exists(select) | Expr::Subquery(select) => {
+                    subqueries.push((select.as_ref(), "SCALAR"))
+                }
+                Expr::InSelect { select, .. } => subqueries.push((select.as_ref(), "LIST")),
+                _ => {}
+            });
+        }
+        cx.scopes.push(columns);
+        for (select, kind) in subqueries {
+            // a correlated subquery runs again for every row
+            let correlated = self.plan_is_correlated(select, cx)?;
+            cx.subqueries += 1;
+            let detail = format!(
+                "{}{kind} SUBQUERY {}",
+                if correlated { "CORRELATED " } else { "" },
+                cx.subqueries
+            );
+            let id = cx.plan.add(parent, detail);
+            let loops = if correlated { loops * rows } else { loops };
+            self.plan_select(select, id, loops, cx)?;
+        }
+        cx.scopes.pop();
+
+        let is_aggregate = !core.group_by.is_empty()
+            || exprs.iter().any(|e| contains_aggregate(e))
+            || core.columns.iter().any(|c| match c {
+                ResultColumn::Expr { expr, .. } => contains_aggregate(expr),
+                _ => false,
+            });
+        // as in SQLite, an ORDER BY of the GROUP BY terms is done by the
+        // grouping, which goes in its direction, and so is one of just the
+        // result columns of a DISTINCT select, done as a GROUP BY
+        let same_terms = |terms: &[SortColumn]| {
+            !terms.is_empty()
+                && terms.len() == order_terms.len()
+                && terms
+                    .iter()
+                    .zip(&order_terms)
+                    .all(|(a, b)| a.0 == b.0 && a.1 == b.1)
+        };
+        let mut ordered = !window && in_order(&scan_order, &order_terms, false);
+        if !core.group_by.is_empty() {
+            let grouped = match same_terms(&group_terms) {
+                true => ordered,
+                false => in_order(&scan_order, &group_terms, true),
+            };
+            if !grouped {
+                plan_sort(cx, parent, "GROUP BY", rows * loops);
+            }
+            ordered = same_terms(&group_terms);
+        } else if is_aggregate {
+            rows = 1.0;
+        }
+        if core.distinct && !is_aggregate {
+            let distinct_order = same_terms(&result_terms) && order_by.iter().all(|t| !t.desc);
+            let grouped = match distinct_order {
+                true => ordered,
+                false => in_order(&scan_order, &result_terms, true),
+            };
+            if !grouped {
+                plan_sort(cx, parent, "DISTINCT", rows * loops);
+            }
+            ordered |= distinct_order;
+        } else if core.distinct {
+            plan_sort(cx, parent, "DISTINCT", rows * loops);
+        }
+        if !order_by.is_empty() && rows > 1.0 && !ordered {
+            plan_sort(cx, parent, "ORDER BY", rows * loops);
+        }
+        Ok(rows)
+    }
+
+    /// Add the steps computing the CTE at `index` of the ones in scope,
+    /// unless it was already computed. Returns its estimated rows.
+    fn plan_cte(
+        &self,
+        index: usize,
+        parent: usize,
+        cx: &mut PlanContext,
+    ) -> Result<f64, anyhow::Error> {
+        let cte = &cx.ctes[index];
+        if let Some(rows) = cte.rows {
+            return Ok(rows);
+        }
+        let (def, loops) = (cte.def, cte.loops);
+        let id = cx.plan.add(parent, format!("MATERIALIZE {}", def.name));
+
+        // the CTE's select only sees the CTEs of its own scope; while it is
+        // computed, the CTE itself holds the current row of the recursion
+        let hidden = cx.ctes.split_off(cx.ctes[index].scope_end);
+        cx.ctes[index].rows = Some(1.0);
+        let rows = match recursive_split(&def.select, &def.name) {
+            Some(split) => {
+                let parts = compound_parts(&def.select);
+                let setup = cx.plan.add(id, "SETUP");
+                let mut rows = 0.0;
+                for (_, core) in &parts[..split] {
+                    rows += self.plan_core(core, &[], setup, loops, cx)?;
+                }
+                let step = cx.plan.add(id, "RECURSIVE STEP");
+                for (_, core) in &parts[split..] {
+                    self.plan_core(core, &[], step, loops * rows, cx)?;
+                }
+                rows
+            }
+            None => self.plan_select(&def.select, id, loops, cx)?,
+        };
+        cx.ctes.extend(hidden);
+
+        // a NOT MATERIALIZED CTE is computed again wherever it is used
+        cx.ctes[index].rows = match def.materialized {
+            Some(false) => None,
+            _ => Some(rows),
+        };
+        Ok(rows)
+    }
+
+    /// Whether a subquery refers to the queries around it, as
+    /// [`Self::is_correlated`] but before anything runs.
+    fn plan_is_correlated(&self, select: &Select, cx: &PlanContext) -> Result<bool, anyhow::Error> {
+        let mut ctes = self.plan_cte_names(cx)?;
+        self.refers_outside(select, &mut vec![], &mut ctes)
+    }
+
+    /// The column names of the CTEs in scope while planning.
+    fn plan_cte_names(&self, cx: &PlanContext) -> Result<CteNames, anyhow::Error> {
+        let mut ctes = vec![];
+        for cte in &cx.ctes {
+            let names = self.cte_names(cte.def, &mut ctes)?;
+            ctes.push((cte.def.name.clone(), names));
+        }
+        Ok(ctes)
+    }
+
+    fn lookup(
+        &self,
+        env: &Env,
+        table: Option<&str>,
+        name: &str,
+    ) -> Result<ColumnType, anyhow::Error> {
+        let mut env = Some(env);
+        while let Some(e) = env {
+            // real columns take precedence over the hidden rowid
+            let found = e
+                .columns
+                .iter()
+                .position(|c| !c.hidden && c.matches(table, name))
+                .or_else(|| e.columns.iter().position(|c| c.matches(table, name)));
+            if let Some(i) = found {
+                return Ok(e.row.get(i).cloned().unwrap_or(ColumnType::Null));
+            }
+            if table.is_none() {
+                if let Some((_, expr)) =
+                    e.aliases.iter().find(|(a, _)| a.eq_ignore_ascii_case(name))
+                {
+                    // evaluate without aliases so `SELECT x AS x` cannot recurse
+                    return self.eval(expr, &Env { aliases: &[], ..*e });
+                }
+            }
+            env = e.outer;
+        }
+
+        match table {
+            Some(t) => bail!("no such column: {t}.{name}"),
+            None => bail!("no such column: {name}"),
+        }
+    }
+
+    fn eval(&self, expr: &Expr, env: &Env) -> Result<ColumnType, anyhow::Error> {
+        match expr {
+            Expr::Literal(v) => Ok(v.clone()),
+            Expr::Column { table, name } => self.lookup(env, table.as_deref(), name),
+            Expr::Unary { op, expr } => {
+                let v = self.eval(expr, env)?;
+                Ok(match op {
+                    UnaryOp::Plus => v,
+                    UnaryOp::Neg => match v.to_numeric() {
+                        ColumnType::Null => ColumnType::Null,
+                        ColumnType::Float(f) => ColumnType::Float(-f),
+                        n => {
+                            let n = n.as_integer().unwrap_or_default();
+                            match n.checked_neg() {
+                                Some(n) => ColumnType::Int64(n),
+                                None => ColumnType::Float(-(n as f64)),
+                            }
+                        }
+                    },
+                    UnaryOp::Not => bool_value(v.truthy().map(|b| !b)),
+                    UnaryOp::BitNot => match v.to_i64() {
+                        Some(n) => ColumnType::Int64(!n),
+                        None => ColumnType::Null,
+                    },
+                })
+            }
+            Expr::Binary { op, left, right } => self.eval_binary(*op, left, right, env),
+            Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => {
+                let (v, low_value, collation) = self.eval_compared(expr, low, env)?;
+                let ge = compare(&v, &low_value, &collation).map(|o| o != Ordering::Less);
+                let (v, high_value, collation) = self.eval_compared(expr, high, env)?;
+                let le = compare(&v, &high_value, &collation).map(|o| o != Ordering::Greater);
+                let result = and3(ge, le);
+                Ok(bool_value(if *negated {
+                    result.map(|b| !b)
+                } else {
+                    result
+                }))
+            }
+            Expr::InList {
+                expr,
+                negated,
+                list,
+            } => {
+                if list.is_empty() {
+                    return Ok(bool_value(Some(*negated)));
+                }
+                // the values of the list have no affinity of their own, and
+                // are compared with the collation of the left operand
+                let affinity = self.affinity(expr, env);
+                let collation = self.collation_or_binary(expr, env)?;
+                let v = self.eval(expr, env)?;
+                if v.is_null() {
+                    return Ok(ColumnType::Null);
+                }
+                let v = with_affinity(v, affinity);
+                let mut saw_null = false;
+                for item in list {
+                    let item = with_affinity(self.eval(item, env)?, affinity);
+                    match compare(&v, &item, &collation) {
+                        Some(Ordering::Equal) => return Ok(bool_value(Some(!*negated))),
+                        None => saw_null = true,
+                        _ => {}
+                    }
+                }
+                Ok(in_result(false, saw_null, *negated))
+            }
+            Expr::InSelect {
+                expr,
+                negated,
+                select,
+            } => {
+                let v = self.eval(expr, env)?;
+                let (set, has_null) = self.subquery_set(select, env)?;
+                if set.is_empty() && !has_null {
+                    return Ok(bool_value(Some(*negated)));
+                }
+                if v.is_null() {
+                    return Ok(ColumnType::Null);
+                }
+                Ok(in_result(set.contains(&v.key()), has_null, *negated))
+            }
+            Expr::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                for (when, then) in branches {
+                    let matched = match operand {
+                        // `CASE x WHEN y` compares like `x = y`
+                        Some(operand) => {
+                            let (v, w, collation) = self.eval_compared(operand, when, env)?;
+                            compare(&v, &w, &collation) == Some(Ordering::Equal)
+                        }
+                        None => self.eval(when, env)?.truthy() == Some(true),
+                    };
+                    if matched {
+                        return self.eval(then, env);
+                    }
+                }
+                match else_result {
+                    Some(e) => self.eval(e, env),
+                    None => Ok(ColumnType::Null),
+                }
+            }
+            Expr::Collate { expr, .. } => self.eval(expr, env),
+            Expr::Parameter(i) => Ok(self
+                .bindings
+                .borrow()
+                .get(i - 1)
+                .cloned()
+                .unwrap_or(ColumnType::Null)),
+            Expr::Cast { expr, type_name } => Ok(self
+                .eval(expr, env)?
+                .cast(Affinity::from_type_name(type_name))),
+            Expr::Exists(select) => {
+                let result = self.subquery_rows(select, env)?;
+                Ok(bool_value(Some(!result.rows.is_empty())))
+            }
+            Expr::Subquery(select) => {
+                let result = self.subquery_rows(select, env)?;
+                if result.columns.len() != 1 {
+                    bail!(
+                        "sub-select returns {} columns - expected 1",
+                        result.columns.len()
+                    );
+                }
+                Ok(result
+                    .rows
+                    .first()
+                    .map(|row| row[0].clone())
+                    .unwrap_or(ColumnType::Null))
+            }
+            Expr::Function {
+                name,
+                args,
+                distinct,
+                star,
+                filter,
+                over,
+            } => {
+                if over.is_some() || WINDOW_FUNCTIONS.contains(&name.as_str()) {
+                    let id = expr as *const Expr as usize;
+                    return match env.window.iter().find(|(call, _)| *call == id) {
+                        Some((_, value)) => Ok(value.clone()),
+                        None => bail!("misuse of window function {name}()"),
+                    };
+                }
+                if is_aggregate(name, args.len()) {
+                    let Some(group) = env.group else {
+                        bail!("misuse of aggregate function {name}()");
+                    };
+                    let filtered;
+                    let group = match filter {
+                        Some(filter) => {
+                            filtered = self.filter_rows(filter, group, env)?;
+                            &filtered[..]
+                        }
+                        None => group,
+                    };
+                    return self.eval_aggregate(name, args, *distinct, *star, group, env);
+                }
+                if filter.is_some() {
+                    bail!("FILTER may not be used with non-aggregate {name}()");
+                }
+
+                // coalesce(), ifnull() and iif() only evaluate the arguments they need
+                functions::check_arity(name, args.len())?;
+                match name.as_str() {
+                    "coalesce" | "ifnull" => {
+                        for arg in args {
+                            let value = self.eval(arg, env)?;
+                            if !value.is_null() {
+                                return Ok(value);
+                            }
+                        }
+                        Ok(ColumnType::Null)
+                    }
+                    "iif" => match self.eval(&args[0], env)?.truthy() {
+                        Some(true) => self.eval(&args[1], env),
+                        _ => match args.get(2) {
+                            Some(e) => self.eval(e, env),
+                            None => Ok(ColumnType::Null),
+                        },
+                    },
+                    _ => {
+                        let values = args
+                            .iter()
+                            .map(|e| self.eval(e, env))
+                            .collect::<Result<Row, _>>()?;
+                        self.call_function(name, &values)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Call a scalar function other than those evaluating their arguments
+    /// lazily.
+    fn call_function(&self, name: &str, args: &[ColumnType]) -> Result<ColumnType, anyhow::Error> {
+        if name == "like" {
+            return functions::like(args, self.case_sensitive_like.get());
+        }
+        functions::call(name, args)
+    }
+
+    /// Evaluate the operands of a comparison, converted by the affinity the
+    /// comparison takes from them, and the collation to compare them with.
+    fn eval_compared(
+        &self,
+        left: &Expr,
+        right: &Expr,
+        env: &Env,
+    ) -> Result<(ColumnType, ColumnType, Collation), anyhow::Error> {
+        let (affinity, collation) = self.comparison(left, right, env)?;
+        Ok((
+            with_affinity(self.eval(left, env)?, affinity),
+            with_affinity(self.eval(right, env)?, affinity),
+            collation,
+        ))
+    }
+
+    /// The affinity applied to both sides of a comparison and the collating
+    /// sequence it uses.
+    fn comparison(
+        &self,
+        left: &Expr,
+        right: &Expr,
+        env: &Env,
+    ) -> Result<(Option<Affinity>, Collation), anyhow::Error> {
+        let affinity =
+            Affinity::for_comparison(self.affinity(left, env), self.affinity(right, env));
+        // an explicit COLLATE on either side wins over a column's collation
+        let collation = match (self.collation(left, env)?, self.collation(right, env)?) {
+            (Some((c, true)), _) | (_, Some((c, true))) => c,
+            (Some((c, _)), _) | (_, Some((c, _))) => c,
+            _ => Collation::Binary,
+        };
+        Ok((affinity, collation))
+    }
+
+    /// Resolve a column reference the way lookup() does, passing the column
+    /// it names to `column`, or the expression of the result column alias
+    /// it names to `alias`.
+    fn resolve_column<T>(
+        &self,
+        env: &Env,
+        table: Option<&str>,
+        name: &str,
+        column: impl Fn(&ColumnName) -> T,
+        alias: impl Fn(&Expr, &Env) -> T,
+    ) -> Option<T> {
+        let mut env = Some(env);
+        while let Some(e) = env {
+            let found = e
+                .columns
+                .iter()
+                .find(|c| !c.hidden && c.matches(table, name))
+                .or_else(|| e.columns.iter().find(|c| c.matches(table, name)));
+            if let Some(c) = found {
+                return Some(column(c));
+            }
+            if table.is_none() {
+                if let Some((_, expr)) =
+                    e.aliases.iter().find(|(a, _)| a.eq_ignore_ascii_case(name))
+                {
+                    return Some(alias(expr, &Env { aliases: &[], ..*e }));
+                }
+            }
+            env = e.outer;
+        }
+        None
+    }
+
+    /// The affinity of an expression: that of the column it names or the
+    /// type it is cast to. Other expressions have none.
+    fn affinity(&self, expr: &Expr, env: &Env) -> Option<Affinity> {
+        match expr {
+            Expr::Column { table, name } => self
+                .resolve_column(
+                    env,
+                    table.as_deref(),
+                    name,
+                    |c| c.affinity,
+                    |e, env| self.affinity(e, env),
+                )
+                .flatten(),
+            Expr::Cast { type_name, .. } => Some(Affinity::from_type_name(type_name)),
+            Expr::Collate { expr, .. } => self.affinity(expr, env),
+            _ => None,
+        }
+    }
+
+    /// The collation of an expression, if it has one, and whether it was
+    /// given by COLLATE rather than taken from a column's declaration.
+    fn collation(
+        &self,
+        expr: &Expr,
+        env: &Env,
+    ) -> Result<Option<(Collation, bool)>, anyhow::Error> {
+        match expr {
+            Expr::Collate { collation, .. } => Ok(Some((self.collations.find(collation)?, true))),
+            Expr::Column { table, name } => self
+                .resolve_column(
+                    env,
+                    table.as_deref(),
+                    name,
+                    |c| Ok(c.collation()?.map(|c| (c, false))),
+                    |e, env| self.collation(e, env),
+                )
+                .unwrap_or(Ok(None)),
+            Expr::Cast { expr, .. }
+            | Expr::Unary {
+                op: UnaryOp::Plus,
+                expr,
+            } => self.collation(expr, env),
+            // an operator takes on an explicit collation of its operands
+            Expr::Binary { left, right, .. } => {
+                for e in [left, right] {
+                    if let Some((c, true)) = self.collation(e, env)? {
+                        return Ok(Some((c, true)));
+                    }
+                }
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The collations of the ORDER BY terms of a compound select: that of a
+    /// COLLATE, or else that of the result column at the term's position.
+    fn order_by_collations(
+        &self,
+        terms: &[OrderingTerm],
+        positions: &[usize],
+        columns: &[Collation],
+    ) -> Result<Vec<Collation>, anyhow::Error> {
+        terms
+            .iter()
+            .zip(positions)
+            .map(|(t, &i)| match &t.expr {
+                Expr::Collate { collation, .. } => self.collations.find(collation),
+                _ => Ok(columns[i].clone()),
+            })
+            .collect()
+    }
+
+    /// The column of the FROM clause a GROUP BY, ORDER BY or DISTINCT term
+    /// sorts by, with the collation it compares with, if it is one. A term
+    /// can name one of the `results` columns by position or by alias.
+    fn sort_column(&self, term: &Expr, results: &[&Expr], env: &Env) -> Option<(usize, Collation)> {
+        let expr = match term_position("", 0, term, results.len()) {
+            Ok(Some(n)) => results[n],
+            _ => term,
+        };
+        let collated = if let Expr::Collate { .. } = term {
+            term
+        } else {
+            expr
+        };
+        let collation = self.collation(collated, env).ok()?.map(|(c, _)| c);
+        let Expr::Column { table, name } = expr.skip_collate() else {
+            return None;
+        };
+        let columns = env.columns;
+        let found = columns
+            .iter()
+            .position(|c| !c.hidden && c.matches(table.as_deref(), name))
+            .or_else(|| {
+                columns
+                    .iter()
+                    .position(|c| c.matches(table.as_deref(), name))
+            });
+        match found {
+            Some(i) => Some((i, collation.unwrap_or_default())),
+            None => {
+                let (_, expr) = env
+                    .aliases
+                    .iter()
+                    .find(|(a, _)| table.is_none() && a.eq_ignore_ascii_case(name))?;
+                let (i, own) = self.sort_column(
+                    expr,
+                    &[],
+                    &Env {
+                        aliases: &[],
+                        ..*env
+                    },
+                )?;
+                Some((i, collation.unwrap_or(own)))
+            }
+        }
+    }
+
+    /// The collation of an expression, or BINARY if it has none.
+    fn collation_or_binary(&self, expr: &Expr, env: &Env) -> Result<Collation, anyhow::Error> {
+        Ok(self
+            .collation(expr, env)?
+            .map(|(c, _)| c)
+            .unwrap_or_default())
+    }
+
+    fn eval_binary(
+        &self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        env: &Env,
+    ) -> Result<ColumnType, anyhow::Error> {
+        // AND and OR short-circuit, with three-valued logic for NULL
+        match op {
+            BinaryOp::And => {
+                let l = self.eval(left, env)?.truthy();
+                if l == Some(false) {
+                    return Ok(bool_value(Some(false)));
+                }
+                let r = self.eval(right, env)?.truthy();
+                return Ok(bool_value(and3(l, r)));
+            }
+            BinaryOp::Or => {
+                let l = self.eval(left, env)?.truthy();
+                if l == Some(true) {
+                    return Ok(bool_value(Some(true)));
+                }
+                let r = self.eval(right, env)?.truthy();
+                return Ok(bool_value(or3(l, r)));
+            }
+            _ => {}
+        }
+
+        let (l, r, collation) = match op {
+            BinaryOp::Eq
+            | BinaryOp::NotEq
+            | BinaryOp::Lt
+            | BinaryOp::LtEq
+            | BinaryOp::Gt
+            | BinaryOp::GtEq
+            | BinaryOp::Is
+            | BinaryOp::IsNot => self.eval_compared(left, right, env)?,
+            _ => (
+                self.eval(left, env)?,
+                self.eval(right, env)?,
+                Collation::Binary,
+            ),
+        };
+
+        Ok(match op {
+            BinaryOp::Eq => bool_value(compare(&l, &r, &collation).map(|o| o == Ordering::Equal)),
+            BinaryOp::NotEq => {
+                bool_value(compare(&l, &r, &collation).map(|o| o != Ordering::Equal))
+            }
+            BinaryOp::Lt => bool_value(compare(&l, &r, &collation).map(|o| o == Ordering::Less)),
+            BinaryOp::LtEq => {
+                bool_value(compare(&l, &r, &collation).map(|o| o != Ordering::Greater))
+            }
+            BinaryOp::Gt => bool_value(compare(&l, &r, &collation).map(|o| o == Ordering::Greater)),
+            BinaryOp::GtEq => bool_value(compare(&l, &r, &collation).map(|o| o != Ordering::Less)),
+            BinaryOp::Is => bool_value(Some(collation.compare(&l, &r) == Ordering::Equal)),
+            BinaryOp::IsNot => bool_value(Some(collation.compare(&l, &r) != Ordering::Equal)),
+            _ => binary_value(op, &l, &r),
+        })
+    }
+
+    /// The rows of a group that pass the FILTER clause of an aggregate call.
+    fn filter_rows(
+        &self,
+        filter: &Expr,
+        group: &[Row],
+        env: &Env,
+    ) -> Result<Vec<Row>, anyhow::Error> {
+        let mut rows = vec![];
+        for row in group {
+            if self.eval(filter, &env.with_row(row))?.truthy() == Some(true) {
+                rows.push(row.clone());
+            }
+        }
+        Ok(rows)
+    }
+
+    fn eval_aggregate(
+        &self,
+        name: &str,
+        args: &[Expr],
+        distinct: bool,
+        star: bool,
+        group: &[Row],
+        env: &Env,
+    ) -> Result<ColumnType, anyhow::Error> {
+        if star || (name == "count" && args.is_empty()) {
+            return Ok(ColumnType::Int64(group.len() as i64));
+        }
+        let Some(arg) = args.first() else {
+            bail!("wrong number of arguments to function {name}()");
+        };
+
+        let collation = self.collation_or_binary(arg, env)?;
+        let mut keys = CollatedKeys::new(vec![collation.clone()]);
+        let mut values = vec![];
+        let mut seen = HashSet::new();
+        for row in group {
+            let v = self.eval(arg, &env.with_row(row))?;
+            if v.is_null() || (distinct && !seen.insert(keys.key(std::slice::from_ref(&v)))) {
+                continue;
+            }
+            values.push(v);
+        }
+
+        let separator = match args.get(1) {
+            Some(e) => Some(self.eval(e, &env.with_row(group.last().unwrap_or(&vec![])))?),
+            None => None,
+        };
+        aggregate(name, values, separator, &collation)
+    }
+
+    /// Evaluate the window function calls in the result columns and ORDER
+    /// BY of a select, given the environment of each output row. Returns the
+    /// values of the calls for each output row, and the order in which the
+    /// rows are output.
+    fn eval_windows(
+        &self,
+        core: &SelectCore,
+        exprs: &[ExprRef],
+        order_by: &[OrderingTerm],
+        envs: &[Env],
+        env: &Env,
+    ) -> Result<(Vec<WindowValues>, Vec<usize>), anyhow::Error> {
+        let mut calls = vec![];
+        for expr in exprs
+            .iter()
+            .map(|e| &**e)
+            .chain(order_by.iter().map(|t| &t.expr))
+        {
+            expr.walk(&mut |e| {
+                if let Expr::Function { over: Some(_), .. } = e {
+                    calls.push(e);
+                }
+            });
+        }
+
+        let mut values = vec![vec![]; envs.len()];
+        let mut order = None;
+        for call in calls {
+            let Expr::Function {
+                name,
+                args,
+                distinct,
+                star,
+                filter,
+                over: Some(over),
+            } = call
+            else {
+                unreachable!("not a window function call");
+            };
+            if WINDOW_FUNCTIONS.contains(&name.as_str()) {
+                if filter.is_some() {
+                    bail!("FILTER clause may only be used with aggregate window functions");
+                }
+            } else if !is_aggregate(name, args.len()) {
+                bail!("{name}() may not be used as a window function");
+            }
+            if *distinct {
+                bail!("DISTINCT is not supported for window functions");
+            }
+            let window = resolve_window(over, &core.windows)?;
+            let partition_collations = window
+                .partition_by
+                .iter()
+                .map(|e| self.collation_or_binary(e, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            let collations = window
+                .order_by
+                .iter()
+                .map(|t| self.collation_or_binary(&t.expr, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            let arg_collation = match args.first() {
+                Some(arg) => self.collation_or_binary(arg, env)?,
+                None => Collation::Binary,
+            };
+
+            // sort the rows by partition, then by the window's ORDER BY
+            let mut partitions = vec![];
+            let mut keys = vec![];
+            let mut arg_values = vec![];
+            let mut passes = vec![];
+            for env in envs {
+                let eval_all = |exprs: &mut dyn Iterator<Item = &Expr>| {
+                    exprs.map(|e| self.eval(e, env)).collect::<Result<Row, _>>()
+                };
+                partitions.push(eval_all(&mut window.partition_by.iter())?);
+                keys.push(eval_all(&mut window.order_by.iter().map(|t| &t.expr))?);
+                arg_values.push(eval_all(&mut args.iter())?);
+                passes.push(match filter {
+                    Some(filter) => self.eval(filter, env)?.truthy() == Some(true),
+                    None => true,
+                });
+            }
+            let mut sorted = (0..envs.len()).collect::<Vec<_>>();
+            sorted.sort_by(|a, b| {
+                compare_rows(&partitions[*a], &partitions[*b], &partition_collations)
+                    .then_with(|| compare_keys(&keys[*a], &keys[*b], window.order_by, &collations))
+            });
+
+            let frame = window.frame.unwrap_or(&DEFAULT_FRAME);
+            let bound = |bound: &FrameBound| -> Result<Bound, anyhow::Error> {
+                Ok(match bound {
+                    FrameBound::UnboundedPreceding => Bound::UnboundedPreceding,
+                    FrameBound::Preceding(e) => Bound::Preceding(self.eval(e, env)?),
+                    FrameBound::CurrentRow => Bound::CurrentRow,
+                    FrameBound::Following(e) => Bound::Following(self.eval(e, env)?),
+                    FrameBound::UnboundedFollowing => Bound::UnboundedFollowing,
+                })
+            };
+            let (start, end) = (bound(&frame.start)?, bound(&frame.end)?);
+
+            let id = call as *const Expr as usize;
+            let same_partition = |a: &usize, b: &usize| {
+                compare_rows(&partitions[*a], &partitions[*b], &partition_collations)
+                    == Ordering::Equal
+            };
+            for members in sorted.chunk_by(same_partition) {
+                let member_keys = members.iter().map(|k| keys[*k].clone()).collect::<Vec<_>>();
+                let partition = Partition::new(&member_keys, window.order_by, &collations);
+                for (i, k) in members.iter().enumerate() {
+                    let args = &arg_values[*k];
+                    let arg = |j: usize| args.get(j).cloned().unwrap_or(ColumnType::Null);
+                    let frame_values = || -> Result<Vec<ColumnType>, anyhow::Error> {
+                        Ok(partition
+                            .frame(i, frame, &start, &end)?
+                            .into_iter()
+                            .filter(|j| passes[members[*j]])
+                            .map(|j| arg_values[members[j]].first().cloned())
+                            .map(|v| v.unwrap_or(ColumnType::Null))
+                            .collect())
+                    };
+
+                    let argc = match name.as_str() {
+                        "row_number" | "rank" | "dense_rank" | "percent_rank" | "cume_dist" => {
+                            0..=0
+                        }
+                        "ntile" | "first_value" | "last_value" => 1..=1,
+                        "nth_value" => 2..=2,
+                        "lag" | "lead" => 1..=3,
+                        _ => 0..=usize::MAX,
+                    };
+                    if !argc.contains(&args.len()) {
+                        bail!("wrong number of arguments to function {name}()");
+                    }
+
+                    let value = match name.as_str() {
+                        "row_number" => ColumnType::Int64(i as i64 + 1),
+                        "rank" => ColumnType::Int64(partition.rank(i)),
+                        "dense_rank" => ColumnType::Int64(partition.dense_rank(i)),
+                        "percent_rank" => ColumnType::Float(partition.percent_rank(i)),
+                        "cume_dist" => ColumnType::Float(partition.cume_dist(i)),
+                        "ntile" => match arg(0).to_i64().filter(|n| *n > 0) {
+                            Some(n) => ColumnType::Int64(partition.ntile(i, n as usize)),
+                            None => bail!("argument of ntile must be a positive integer"),
+                        },
+                        "lag" | "lead" => {
+                            let offset = if args.len() > 1 {
+                                arg(1).to_i64()
+                            } else {
+                                Some(1)
+                            };
+                            // an offset out of reach gives the default
+                            let target = offset.and_then(|n| {
+                                let n = if name == "lag" { n.checked_neg()? } else { n };
+                                (i as i64).checked_add(n)
+                            });
+                            match target.filter(|t| (0..members.len() as i64).contains(t)) {
+                                Some(t) => arg_values[members[t as usize]][0].clone(),
+                                None => arg(2),
+                            }
+                        }
+                        "first_value" => frame_values()?
+                            .into_iter()
+                            .next()
+                            .unwrap_or(ColumnType::Null),
+                        "last_value" => frame_values()?.pop().unwrap_or(ColumnType::Null),
+                        "nth_value" => {
+                            let Some(n) = arg(1).to_i64().filter(|n| *n > 0) else {
+                                bail!("second argument to nth_value must be a positive integer");
+                            };
+                            frame_values()?
+                                .into_iter()
+                                .nth(n as usize - 1)
+                                .unwrap_or(ColumnType::Null)
+                        }
+                        _ if *star || args.is_empty() => {
+                            let frame = partition.frame(i, frame, &start, &end)?;
+                            let rows = frame.into_iter().filter(|j| passes[members[*j]]);
+                            ColumnType::Int64(rows.count() as i64)
+                        }
+                        _ => {
+                            let frame_values = frame_values()?;
+                            let values = frame_values.into_iter().filter(|v| !v.is_null());
+                            let separator = args.get(1).cloned();
+                            aggregate(name, values.collect(), separator, &arg_collation)?
+                        }
+                    };
+                    values[*k].push((id, value));
+                }
+            }
+
+            if order.is_none() {
+                order = Some(sorted);
+            }
+        }
+
+        Ok((values, order.unwrap_or_else(|| (0..envs.len()).collect())))
+    }
+
+    /// The rows of a subquery evaluated for the current row; an uncorrelated
+    /// subquery is only run once.
+    fn subquery_rows(&self, select: &Select, env: &Env) -> Result<Rc<ResultSet>, anyhow::Error> {
+        let id = select as *const Select as usize;
+        if !self.is_correlated(select, env)? {
+            if let Some(Materialized::Rows(rows)) = self.materialized.borrow().get(&id) {
+                return Ok(rows.clone());
+            }
+            let rows = Rc::new(self.run_select(select, Some(env))?);
+            self.materialized
+                .borrow_mut()
+                .insert(id, Materialized::Rows(rows.clone()));
+            return Ok(rows);
+        }
+        Ok(Rc::new(self.run_select(select, Some(env))?))
+    }
+
+    /// The values of a single column subquery as a hash set for IN; an
+    /// uncorrelated subquery is only run and hashed once.
+    fn subquery_set(
+        &self,
+        select: &Select,
+        env: &Env,
+    ) -> Result<(Rc<HashSet<ValueKey>>, bool), anyhow::Error> {
+        let id = select as *const Select as usize;
+        let correlated = self.is_correlated(select, env)?;
+        if !correlated {
+            if let Some(Materialized::Set(set, has_null)) = self.materialized.borrow().get(&id) {
+                return Ok((set.clone(), *has_null));
+            }
+        }
+
+        let result = self.run_select(select, Some(env))?;
+        if result.columns.len() != 1 {
+            bail!(
+                "sub-select returns {} columns - expected 1",
+                result.columns.len()
+            );
+        }
+        let has_null = result.rows.iter().any(|row| row[0].is_null());
+        let set = Rc::new(
+            result
+                .rows
+                .iter()
+                .filter(|row| !row[0].is_null())
+                .map(|row| row[0].key())
+                .collect::<HashSet<_>>(),
+        );
+
+        if !correlated {
+            self.materialized
+                .borrow_mut()
+                .insert(id, Materialized::Set(set.clone(), has_null));
+        }
+        Ok((set, has_null))
+    }
+
+    /// Whether a subquery refers to columns of an enclosing query, in which
+    /// case it must be re-evaluated for every row of that query.
+    fn is_correlated(&self, select: &Select, env: &Env) -> Result<bool, anyhow::Error> {
+        let id = select as *const Select as usize;
+        if let Some(correlated) = self.correlated.borrow().get(&id) {
+            return Ok(*correlated);
+        }
+
+        // the CTEs in scope, outermost first so that inner ones shadow them
+        let mut chain = vec![];
+        let mut e = Some(env);
+        while let Some(x) = e {
+            chain.push(x);
+            e = x.outer;
+        }
+        let mut ctes = vec![];
+        for x in chain.into_iter().rev() {
+            for cte in x.ctes {
+                let names = match cte.columns.borrow().clone() {
+                    Some(columns) => columns,
+                    None => self.cte_names(cte.def, &mut ctes)?,
+                };
+                ctes.push((cte.def.name.clone(), names));
+            }
+        }
+
+        let mut scopes = vec![];
+        let correlated = self.refers_outside(select, &mut scopes, &mut ctes)?;
+        self.correlated.borrow_mut().insert(id, correlated);
+        Ok(correlated)
+    }
+
+    /// The columns of a CTE, without running it.
+    fn cte_names(
+        &self,
+        def: &CommonTableExpr,
+        ctes: &mut CteNames,
+    ) -> Result<Vec<ColumnName>, anyhow::Error> {
+        let columns = self.output_columns(&def.select, ctes)?;
+        if def.columns.is_empty() {
+            return Ok(columns);
+        }
+        // the names declared, with the types of the columns of the select
+        // where there are as many
+        Ok(def
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, name)| ColumnName {
+                name: name.clone(),
+                ..columns.get(i).cloned().unwrap_or(ColumnName {
+                    table: None,
+                    name: String::new(),
+                    hidden: false,
+                    affinity: None,
+                    collation: None,
+                })
+            })
+            .collect())
+    }
+
+    /// Add the CTEs declared by a WITH clause to `ctes`. They can refer to
+    /// each other in any order, so all names are bound before their columns
+    /// are worked out.
+    fn bind_ctes(&self, with: Option<&With>, ctes: &mut CteNames) -> Result<(), anyhow::Error> {
+        let bound = ctes.len();
+        let defs = with.iter().flat_map(|w| &w.ctes).collect::<Vec<_>>();
+        ctes.extend(defs.iter().map(|def| (def.name.clone(), vec![])));
+        for (i, def) in defs.into_iter().enumerate() {
+            ctes[bound + i].1 = self.cte_names(def, ctes)?;
+        }
+        Ok(())
+    }
+
+    /// Check every column reference in `select` against the names visible in
+    /// `scopes` plus its own FROM clause; anything else must come from an
+    /// enclosing query.
+    fn refers_outside(
+        &self,
+        select: &Select,
+        scopes: &mut Vec<Vec<ColumnName>>,
+        ctes: &mut CteNames,
+    ) -> Result<bool, anyhow::Error> {
+        let bound = ctes.len();
+        let result = self.select_refers_outside(select, scopes, ctes);
+        ctes.truncate(bound);
+        result
+    }
+
+    fn select_refers_outside(
+        &self,
+        select: &Select,
+        scopes: &mut Vec<Vec<ColumnName>>,
+        ctes: &mut CteNames,
+    ) -> Result<bool, anyhow::Error> {
+        self.bind_ctes(select.with.as_ref(), ctes)?;
+        for def in select.with.iter().flat_map(|w| &w.ctes) {
+            if self.refers_outside(&def.select, scopes, ctes)? {
+                return Ok(true);
+            }
+        }
+
+        for (i, (_, core)) in compound_parts(select).into_iter().enumerate() {
+            let mut from_subqueries = vec![];
+            let mut local = vec![];
+            if let Some(from) = &core.from {
+                self.source_columns(from, &mut local, &mut from_subqueries, ctes)?;
+            }
+            // result column aliases are visible too
+            for c in &core.columns {
+                if let ResultColumn::Expr {
+                    alias: Some(alias), ..
+                } = c
+                {
+                    local.push(ColumnName {
+                        table: None,
+                        name: alias.clone(),
+                        hidden: false,
+                        affinity: None,
+                        collation: None,
+                    });
+                }
+            }
+
+            // derived tables see the enclosing scopes but not their siblings
+            for sub in from_subqueries {
+                if self.refers_outside(sub, scopes, ctes)? {
+                    return Ok(true);
+                }
+            }
+
+            let mut exprs: Vec<&Expr> = vec![];
+            for c in &core.columns {
+                if let ResultColumn::Expr { expr, .. } = c {
+                    exprs.push(expr);
+                }
+            }
+            exprs.extend(core.where_clause.iter());
+            exprs.extend(core.group_by.iter());
+            exprs.extend(core.having.iter());
+            for (_, window) in &core.windows {
+                exprs.extend(window.partition_by.iter());
+                exprs.extend(window.order_by.iter().map(|t| &t.expr));
+            }
+            if i == 0 {
+                exprs.extend(select.order_by.iter().map(|t| &t.expr));
+                exprs.extend(select.limit.iter());
+                exprs.extend(select.offset.iter());
+            }
+            if let Some(from) = &core.from {
+                join_conditions(from, &mut exprs);
+            }
+
+            scopes.push(local);
+            let mut result = false;
+            for expr in exprs {
+                if self.expr_refers_outside(expr, scopes, ctes)? {
+                    result = true;
+                    break;
+                }
+            }
+            scopes.pop();
+            if result {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn expr_refers_outside(
+        &self,
+        expr: &Expr,
+        scopes: &mut Vec<Vec<ColumnName>>,
+        ctes: &mut CteNames,
+    ) -> Result<bool, anyhow::Error> {
+        let mut columns = vec![];
+        let mut subqueries = vec![];
+        expr.walk(&mut |e| match e {
+            Expr::Column { table, name } => columns.push((table.as_deref(), name.as_str())),
+            Expr::InSelect { select, .. } | Expr::Exists(select) | Expr::Subquery(select) => {
+                subqueries.push(select.as_ref())
+            }
+            _ => {}
+        });
+
+        for (table, name) in columns {
+            if !scopes
+                .iter()
+                .any(|scope| scope.iter().any(|c| c.matches(table, name)))
+            {
+                return Ok(true);
+            }
+        }
+        for sub in subqueries {
+            if self.refers_outside(sub, scopes, ctes)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The columns a FROM clause makes visible, without reading any rows.
+    fn source_columns<'s>(
+        &self,
+        from: &'s FromClause,
+        columns: &mut Vec<ColumnName>,
+        subqueries: &mut Vec<&'s Select>,
+        ctes: &mut CteNames,
+    ) -> Result<(), anyhow::Error> {
+        match from {
+            FromClause::Table { name, alias } => {
+                let table = Some(alias.clone().unwrap_or_else(|| name.clone()));
+                if let Some((_, names)) = ctes
+                    .iter()
+                    .rev()
+                    .find(|(cte, _)| cte.eq_ignore_ascii_case(name))
+                {
+                    for column in names {
+                        columns.push(ColumnName {
+                            table: table.clone(),
+                            ..column.clone()
+                        });
+                    }
+                    return Ok(());
+                }
+                let alias = alias.as_deref().unwrap_or(name);
+                columns.extend(self.table_source(name, alias)?.columns);
+            }
+            FromClause::Subquery { select, alias } => {
+                subqueries.push(select);
+                for column in self.output_columns(select, ctes)? {
+                    columns.push(ColumnName {
+                        table: alias.clone(),
+                        ..column
+                    });
+                }
+            }
+            FromClause::Function { name, args, alias } => {
+                let table = alias.as_deref().unwrap_or(name);
+                columns.extend(pragma::function_columns(name, args.len(), table)?);
+            }
+            FromClause::Join { left, right, .. } => {
+                self.source_columns(left, columns, subqueries, ctes)?;
+                self.source_columns(right, columns, subqueries, ctes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The result columns of a select, without running it: their names,
+    /// and the types of those that are columns of its FROM clause.
+    fn output_columns(
+        &self,
+        select: &Select,
+        ctes: &mut CteNames,
+    ) -> Result<Vec<ColumnName>, anyhow::Error> {
+        let bound = ctes.len();
+        let columns = (|| {
+            self.bind_ctes(select.with.as_ref(), ctes)?;
+            let mut columns = vec![];
+            if let Some(from) = &select.body.from {
+                self.source_columns(from, &mut columns, &mut vec![], ctes)?;
+            }
+            let (names, exprs) = self.result_columns(&select.body, &columns)?;
+            let env = Env {
+                columns: &columns,
+                ..Env::new(None)
+            };
+            names
+                .into_iter()
+                .zip(&exprs)
+                .map(|(name, expr)| {
+                    Ok(ColumnName {
+                        table: None,
+                        name,
+                        hidden: false,
+                        affinity: self.affinity(expr, &env),
+                        collation: self.collation(expr, &env)?.map(|(c, _)| Ok(c)),
+                    })
+                })
+                .collect()
+        })();
+        ctes.truncate(bound);
+        columns
+    }
+}
+
+/// A result column expression: either from the statement, or made up when
+/// expanding `*`.
+enum ExprRef<'a> {
+    Borrowed(&'a Expr),
+    Owned(Expr),
+}
+
+impl std::ops::Deref for ExprRef<'_> {
+    type Target = Expr;
+
+    fn deref(&self) -> &Expr {
+        match self {
+            ExprRef::Borrowed(e) => e,
+            ExprRef::Owned(e) => e,
+        }
+    }
+}
+
+/// The result columns of the CTEs in scope, innermost last.
+type CteNames = Vec<(String, Vec<ColumnName>)>;
+
+/// The CTE called `name` visible from `env`, with the environment of the
+/// select declaring it.
+fn find_cte<'e>(env: Option<&'e Env<'e>>, name: &str) -> Option<(&'e Cte<'e>, &'e Env<'e>)> {
+    let mut env = env;
+    while let Some(e) = env {
+        if let Some(cte) = e
+            .ctes
+            .iter()
+            .find(|c| c.def.name.eq_ignore_ascii_case(name))
+        {
+            return Some((cte, e));
+        }
+        env = e.outer;
+    }
+    None
+}
+
+/// The selects of a possibly compound select, each with the operator
+/// joining it to the ones before.
+fn compound_parts(select: &Select) -> Vec<(Option<CompoundOp>, &SelectCore)> {
+    std::iter::once((None, &select.body))
+        .chain(select.compound.iter().map(|(op, core)| (Some(*op), core)))
+        .collect()
+}
+
+/// The table or CTE a select reads, if its rows are the first rows of that
+/// in order, one for each, so that it needs no more of them than its
+/// OFFSET and LIMIT take.
+fn takes_rows_in_order(select: &Select) -> Option<&str> {
+    let core = &select.body;
+    let plain = select.compound.is_empty()
+        && select.order_by.is_empty()
+        && !core.distinct
+        && core.where_clause.is_none()
+        && core.group_by.is_empty()
+        && core.having.is_none();
+    let per_row = core.columns.iter().all(|c| match c {
+        ResultColumn::Expr { expr, .. } => {
+            let mut window = false;
+            expr.walk(&mut |e| window |= matches!(e, Expr::Function { over: Some(_), .. }));
+            !window && !contains_aggregate(expr)
+        }
+        _ => true,
+    });
+    match &core.from {
+        Some(FromClause::Table { name, .. }) if plain && per_row => Some(name),
+        _ => None,
+    }
+}
+
+/// For a CTE whose select is a compound select, the index of the first
+/// select that reads the CTE itself, which makes it recursive.
+fn recursive_split(select: &Select, name: &str) -> Option<usize> {
+    compound_parts(select)
+        .iter()
+        .position(|(_, core)| core.from.as_ref().is_some_and(|f| reads_table(f, name)))
+        .filter(|i| *i > 0)
+}
+
+fn reads_table(from: &FromClause, table: &str) -> bool {
+    match from {
+        FromClause::Table { name, .. } => name.eq_ignore_ascii_case(table),
+        FromClause::Subquery { .. } | FromClause::Function { .. } => false,
+        FromClause::Join { left, right, .. } => {
+            reads_table(left, table) || reads_table(right, table)
+        }
+    }
+}
+
+/// The columns of a CTE, given its select's result columns, renamed as the
+/// CTE declares.
+fn cte_columns(
+    def: &CommonTableExpr,
+    mut columns: Vec<ColumnName>,
+) -> Result<Vec<ColumnName>, anyhow::Error> {
+    if def.columns.is_empty() {
+        return Ok(columns);
+    }
+    if def.columns.len() != columns.len() {
+        bail!(
+            "table {} has {} values for {} columns",
+            def.name,
+            columns.len(),
+            def.columns.len()
+        );
+    }
+    for (column, name) in columns.iter_mut().zip(&def.columns) {
+        column.name = name.clone();
+    }
+    Ok(columns)
+}
+
+/// The collation each result column of a compound select compares with:
+/// that of the leftmost select whose column has one.
+fn compound_collations(columns: &[Vec<ColumnName>]) -> Result<Vec<Collation>, anyhow::Error> {
+    let width = columns.first().map_or(0, Vec::len);
+    (0..width)
+        .map(|i| {
+            let column = columns
+                .iter()
+                .find_map(|c| c.get(i).filter(|c| c.collation.is_some()));
+            Ok(match column {
+                Some(c) => c.collation()?.unwrap_or_default(),
+                None => Collation::Binary,
+            })
+        })
+        .collect()
+}
+
+/// Resolve the ORDER BY terms of a compound select to result column
+/// positions. A term is a column number, or the name of a result column of
+/// any of the selects.
+fn compound_order_by(
+    order_by: &[OrderingTerm],
+    names: &[Vec<ColumnName>],
+) -> Result<Vec<usize>, anyhow::Error> {
+    let num_columns = names.first().map_or(0, |n| n.len());
+    order_by
+        .iter()
+        .enumerate()
+        .map(|(i, term)| {
+            if let Some(n) = order_by_position(i, &term.expr, num_columns)? {
+                return Ok(n);
+            }
+            let found = match term.expr.skip_collate() {
+                Expr::Column { name, .. } => names
+                    .iter()
+                    .find_map(|names| names.iter().position(|n| n.name.eq_ignore_ascii_case(name))),
+                _ => None,
+            };
+            found.ok_or_else(|| {
+                anyhow!(
+                    "{} ORDER BY term does not match any column in the result set",
+                    ordinal(i + 1)
+                )
+            })
+        })
+        .collect()
+}
+
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{n}{suffix}")
+}
+
+/// Remove duplicate rows, leaving the rest in ascending order, with the
+/// values of each column compared under its collation. Of rows that are
+/// equal but not identical, such as 2 and 2.0, the last one is kept, as in
+/// SQLite.
+fn distinct_sorted(mut rows: Vec<Row>, collations: &[Collation]) -> Vec<Row> {
+    let cmp = |a: &Row, b: &Row| compare_rows(a, b, collations);
+    rows.sort_by(cmp);
+
+    let mut distinct: Vec<Row> = vec![];
+    for row in rows {
+        match distinct.last_mut() {
+            Some(last) if cmp(last, &row) == Ordering::Equal => *last = row,
+            _ => distinct.push(row),
+        }
+    }
+    distinct
+}
+
+/// Resolve the window of a window function call against the named windows
+/// of the WINDOW clause.
+fn resolve_window<'a>(
+    over: &'a Over,
+    windows: &'a [(String, WindowDef)],
+) -> Result<Window<'a>, anyhow::Error> {
+    match over {
+        Over::Named(name) => {
+            let i = named_window(name, windows)?;
+            resolve_window_def(&windows[i].1, &windows[..i])
+        }
+        Over::Spec(def) => resolve_window_def(def, windows),
+    }
+}
+
+/// Resolve a window definition, which can build on one of the `earlier`
+/// named windows.
+fn resolve_window_def<'a>(
+    def: &'a WindowDef,
+    earlier: &'a [(String, WindowDef)],
+) -> Result<Window<'a>, anyhow::Error> {
+    let Some(base) = &def.base else {
+        return Ok(Window {
+            partition_by: &def.partition_by,
+            order_by: &def.order_by,
+            frame: def.frame.as_ref(),
+        });
+    };
+
+    let i = named_window(base, earlier)?;
+    let base_window = resolve_window_def(&earlier[i].1, &earlier[..i])?;
+    if !def.partition_by.is_empty() {
+        bail!("cannot override PARTITION clause of window: {base}");
+    }
+    if !def.order_by.is_empty() && !base_window.order_by.is_empty() {
+        bail!("cannot override ORDER BY clause of window: {base}");
+    }
+    if base_window.frame.is_some() {
+        bail!("cannot override frame specification of window: {base}");
+    }
+    Ok(Window {
+        partition_by: base_window.partition_by,
+        order_by: if def.order_by.is_empty() {
+            base_window.order_by
+        } else {
+            &def.order_by
+        },
+        frame: def.frame.as_ref(),
+    })
+}
+
+fn named_window(name: &str, windows: &[(String, WindowDef)]) -> Result<usize, anyhow::Error> {
+    windows
+        .iter()
+        .position(|(n, _)| n.eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow!("no such window: {name}"))
+}
+
+/// Compare rows column by column, as for sorting groups and distinct rows.
+/// Compare two rows value by value, each with its collation.
+fn compare_rows(a: &[ColumnType], b: &[ColumnType], collations: &[Collation]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .zip(collations)
+        .map(|((x, y), collation)| collation.compare(x, y))
+        .find(|o| *o != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+fn is_schema_table(name: &str) -> bool {
+    ["sqlite_schema", "sqlite_master"]
+        .iter()
+        .any(|n| n.eq_ignore_ascii_case(name))
+}
+
+/// The expressions of a FROM clause: the ON conditions of its joins and the
+/// arguments of its table-valued functions.
+fn join_conditions<'a>(from: &'a FromClause, exprs: &mut Vec<&'a Expr>) {
+    match from {
+        FromClause::Join {
+            left,
+            right,
+            constraint,
+            ..
+        } => {
+            join_conditions(left, exprs);
+            join_conditions(right, exprs);
+            if let Some(JoinConstraint::On(cond)) = constraint {
+                exprs.push(cond);
+            }
+        }
+        FromClause::Function { args, .. } => exprs.extend(args),
+        _ => {}
+    }
+}
+
+/// A GROUP BY, ORDER BY or DISTINCT term that is a column of the FROM
+/// clause: its position, its collation and whether it is descending.
+type SortColumn = (usize, Collation, bool);
+
+/// Whether rows that come in `order` are already in the order of `terms`.
+/// With `grouped`, as for GROUP BY, they only need rows with the same terms
+/// next to each other, so the terms may be in any order and direction.
+/// Otherwise the whole order may be read backwards, as SQLite can.
+fn in_order(order: &[Ordered], terms: &[SortColumn], grouped: bool) -> bool {
+    let matches = |o: &Ordered, t: &SortColumn| o.columns.contains(&t.0) && o.collation == t.1;
+    // a column fixed by an equality is in order wherever it is
+    let rest = terms
+        .iter()
+        .filter(|t| !order.iter().any(|o| o.fixed && matches(o, t)))
+        .collect::<Vec<_>>();
+    let columns = order
+        .iter()
+        .filter(|o| !o.fixed)
+        .take(rest.len())
+        .collect::<Vec<_>>();
+    if terms.is_empty() || columns.len() < rest.len() {
+        return false;
+    }
+    if grouped {
+        return columns
+            .iter()
+            .all(|o| rest.iter().filter(|t| matches(o, t)).count() == 1);
+    }
+    let reverse = rest
+        .first()
+        .zip(columns.first())
+        .map(|(t, o)| t.2 != o.desc);
+    rest.iter()
+        .zip(&columns)
+        .all(|(t, o)| matches(o, t) && Some(t.2 != o.desc) == reverse)
+}
+
+/// Add a `USE TEMP B-TREE FOR ...` step sorting about `rows` rows.
+fn plan_sort(cx: &mut PlanContext, parent: usize, purpose: &str, rows: f64) {
+    cx.plan
+        .add(parent, format!("USE TEMP B-TREE FOR {purpose}"));
+    cx.plan.add_cost(
+        rows,
+        format!("The {purpose} sorts about {}", explain::rows(rows)),
+    );
+}
+
+/// `ORDER BY 2` refers to the second result column. `index` is the position
+/// of the term in the ORDER BY clause.
+fn order_by_position(
+    index: usize,
+    expr: &Expr,
+    num_columns: usize,
+) -> Result<Option<usize>, anyhow::Error> {
+    term_position("ORDER", index, expr, num_columns)
+}
+
+/// The value of a LIMIT or OFFSET, which as in SQLite must be an integer
+/// once taken as a number.
+fn limit_integer(value: ColumnType) -> Result<i64, anyhow::Error> {
+    match value.apply_affinity(Affinity::Numeric) {
+        ColumnType::Float(f) if f.fract() == 0.0 && f.abs() < 9.2e18 => Ok(f as i64),
+        v => match v.as_integer() {
+            Some(n) => Ok(n),
+            None => bail!("datatype mismatch"),
+        },
+    }
+}
+
+/// The result column an integer ORDER BY or GROUP BY term names, counting
+/// from 0; `clause` is the first word of the clause, for errors.
+fn term_position(
+    clause: &str,
+    index: usize,
+    expr: &Expr,
+    num_columns: usize,
+) -> Result<Option<usize>, anyhow::Error> {
+    if let Expr::Literal(v) = expr.skip_collate() {
+        if let Some(n) = v.as_integer() {
+            if n < 1 || n as usize > num_columns {
+                bail!(
+                    "{} {clause} BY term out of range - should be between 1 and {num_columns}",
+                    ordinal(index + 1)
+                );
+            }
+            return Ok(Some(n as usize - 1));
+        }
+    }
+    Ok(None)
+}
+
+/// The GROUP BY terms, each as the expression to group by and the one
+/// whose collation to compare with. An integer term stands for the result
+/// column it names, under its own COLLATE if it has one. A row cannot be
+/// grouped by an aggregate.
+fn group_by_terms<'e>(
+    group_by: &'e [Expr],
+    exprs: &'e [ExprRef],
+) -> Result<Vec<(&'e Expr, &'e Expr)>, anyhow::Error> {
+    let mut terms = vec![];
+    for (i, term) in group_by.iter().enumerate() {
+        let (expr, collated) = match term_position("GROUP", i, term, exprs.len())? {
+            None => (term, term),
+            Some(n) if matches!(term, Expr::Collate { .. }) => (&*exprs[n], term),
+            Some(n) => (&*exprs[n], &*exprs[n]),
+        };
+        if contains_aggregate(expr) {
+            bail!("aggregate functions are not allowed in the GROUP BY clause");
+        }
+        terms.push((expr, collated));
+    }
+    Ok(terms)
+}
+
+/// Compute an aggregate function over the non-NULL values of its argument.
+/// `separator` is the second argument of group_concat(), and min() and
+/// max() compare with the argument's collation.
+fn aggregate(
+    name: &str,
+    values: Vec<ColumnType>,
+    separator: Option<ColumnType>,
+    collation: &Collation,
+) -> Result<ColumnType, anyhow::Error> {
+    Ok(match name {
+        "count" => ColumnType::Int64(values.len() as i64),
+        "min" => values
+            .into_iter()
+            .min_by(|a, b| collation.compare(a, b))
+            .unwrap_or(ColumnType::Null),
+        // the first of equal values, as min_by() gives for min()
+        "max" => values
+            .into_iter()
+            .reduce(|max, v| match collation.compare(&v, &max) {
+                Ordering::Greater => v,
+                _ => max,
+            })
+            .unwrap_or(ColumnType::Null),
+        "sum" | "total" | "avg" => {
+            // sum() is exact only while every value is an integer
+            let integers = values
+                .iter()
+                .map(|v| v.to_exact_integer())
+                .collect::<Option<Vec<_>>>();
+            let numbers = values.iter().map(|v| v.to_numeric()).collect::<Vec<_>>();
+            if name == "sum" && numbers.is_empty() {
+                ColumnType::Null
+            } else if let (Some(integers), "sum") = (integers, name) {
+                let mut sum = 0i64;
+                for n in integers {
+                    sum = sum
+                        .checked_add(n)
+                        .ok_or_else(|| anyhow!("integer overflow"))?;
+                }
+                ColumnType::Int64(sum)
+            } else {
+                let sum = numbers.iter().filter_map(|n| n.to_f64()).sum::<f64>();
+                match name {
+                    "avg" if numbers.is_empty() => ColumnType::Null,
+                    "avg" => ColumnType::Float(sum / numbers.len() as f64),
+                    _ => ColumnType::Float(sum),
+                }
+            }
+        }
+        "group_concat" => {
+            if values.is_empty() {
+                return Ok(ColumnType::Null);
+            }
+            let separator = match separator {
+                Some(v) => v.to_text().unwrap_or_default(),
+                None => ",".to_string(),
+            };
+            ColumnType::String(
+                values
+                    .iter()
+                    .map(|v| v.to_text().unwrap_or_default())
+                    .collect::<Vec<_>>()
+                    .join(&separator),
+            )
+        }
+        _ => unreachable!("not an aggregate: {name}"),
+    })
+}
+
+/// Compare rows of ORDER BY keys, with the text of each key compared
+/// under the collation at the same position, BINARY if there is none.
+pub(crate) fn compare_keys(
+    a: &[ColumnType],
+    b: &[ColumnType],
+    terms: &[OrderingTerm],
+    collations: &[Collation],
+) -> Ordering {
+    for (i, ((x, y), term)) in a.iter().zip(b).zip(terms).enumerate() {
+        let collation = collations.get(i).unwrap_or(&Collation::Binary);
+        let o = compare_key(x, y, term.desc, term.nulls_first, collation);
+        if o != Ordering::Equal {
+            return o;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Compare one sort key of two rows.
+fn compare_key(
+    x: &ColumnType,
+    y: &ColumnType,
+    desc: bool,
+    nulls_first: Option<bool>,
+    collation: &Collation,
+) -> Ordering {
+    // NULLs sort first in ascending order unless told otherwise
+    let nulls_first = nulls_first.unwrap_or(!desc);
+    match (x.is_null(), y.is_null()) {
+        (true, true) => Ordering::Equal,
+        (true, false) if nulls_first => Ordering::Less,
+        (true, false) => Ordering::Greater,
+        (false, true) if nulls_first => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        _ if desc => collation.compare(y, x),
+        _ => collation.compare(x, y),
+    }
+}
+
+/// A value with the affinity of a comparison applied, if there is one.
+fn with_affinity(v: ColumnType, affinity: Option<Affinity>) -> ColumnType {
+    match affinity {
+        Some(affinity) => v.apply_affinity(affinity),
+        None => v,
+    }
+}
+
+/// Compare for `=`, `<` and friends, where NULL compares as unknown.
+fn compare(a: &ColumnType, b: &ColumnType, collation: &Collation) -> Option<Ordering> {
+    if a.is_null() || b.is_null() {
+        return None;
+    }
+    Some(collation.compare(a, b))
+}
+
+/// The value of an operator other than a comparison, AND or OR.
+fn binary_value(op: BinaryOp, l: &ColumnType, r: &ColumnType) -> ColumnType {
+    match op {
+        BinaryOp::Concat => match (l.to_text(), r.to_text()) {
+            (Some(a), Some(b)) => ColumnType::String(a + &b),
+            _ => ColumnType::Null,
+        },
+        BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::ShiftLeft | BinaryOp::ShiftRight => {
+            let (Some(a), Some(b)) = (l.to_i64(), r.to_i64()) else {
+                return ColumnType::Null;
+            };
+            ColumnType::Int64(match op {
+                BinaryOp::BitAnd => a & b,
+                BinaryOp::BitOr => a | b,
+                BinaryOp::ShiftLeft => shift_left(a, b),
+                _ => shift_left(a, b.saturating_neg()),
+            })
+        }
+        _ => arithmetic(op, l, r),
+    }
+}
+
+fn or3(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match (a, b) {
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (Some(false), Some(false)) => Some(false),
+        _ => None,
+    }
+}
+
+fn and3(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match (a, b) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }
+}
+
+/// A PRAGMA's boolean setting: `yes`, `on`, `true`, or a nonzero number.
+fn is_true(value: &str) -> bool {
+    ["yes", "on", "true", "full", "extra"]
+        .iter()
+        .any(|s| value.eq_ignore_ascii_case(s))
+        || ColumnType::String(value.to_string())
+            .to_numeric()
+            .to_i64()
+            .is_some_and(|n| n != 0)
+}
+
+fn bool_value(b: Option<bool>) -> ColumnType {
+    match b {
+        Some(b) => ColumnType::Int64(b as i64),
+        None => ColumnType::Null,
+    }
+}
+
+/// The result of `IN`: true if found, otherwise NULL if the candidates
+/// included a NULL, otherwise false.
+fn in_result(found: bool, saw_null: bool, negated: bool) -> ColumnType {
+    let result = if found {
+        Some(true)
+    } else if saw_null {
+        None
+    } else {
+        Some(false)
+    };
+    bool_value(if negated { result.map(|b| !b) } else { result })
+}
+
+fn shift_left(a: i64, b: i64) -> i64 {
+    match b {
+        b if b >= 64 => 0,
+        b if b <= -64 => {
+            if a < 0 {
+                -1
+            } else {
+                0
+            }
+        }
+        b if b >= 0 => a << b,
+        b => a >> -b,
+    }
+}
+
+/// `+ - * / %` with SQLite's rules: integer arithmetic that overflows falls
+/// back to floating point, and division by zero is NULL.
+pub(crate) fn arithmetic(op: BinaryOp, l: &ColumnType, r: &ColumnType) -> ColumnType {
+    let (l, r) = (l.to_numeric(), r.to_numeric());
+    if l.is_null() || r.is_null() {
+        return ColumnType::Null;
+    }
+
+    if let (Some(a), Some(b)) = (l.as_integer(), r.as_integer()) {
+        let result = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Sub => a.checked_sub(b),
+            BinaryOp::Mul => a.checked_mul(b),
+            BinaryOp::Div | BinaryOp::Rem if b == 0 => return ColumnType::Null,
+            BinaryOp::Div => a.checked_div(b),
+            BinaryOp::Rem => Some(a.wrapping_rem(b)),
+            _ => unreachable!("not an arithmetic operator: {op:?}"),
+        };
+        if let Some(n) = result {
+            return ColumnType::Int64(n);
+        }
+    }
+
+    let (a, b) = (
+        l.to_f64().unwrap_or_default(),
+        r.to_f64().unwrap_or_default(),
+    );
+    let result = match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div if b == 0.0 => return ColumnType::Null,
+        BinaryOp::Div => a / b,
+        BinaryOp::Rem if b as i64 == 0 => return ColumnType::Null,
+        BinaryOp::Rem => a % b,
+        _ => unreachable!("not an arithmetic operator: {op:?}"),
+    };
+    ColumnType::Float(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{open_fixtures, open_sample};
+    use crate::parser;
+
+    fn query(sql: &str) -> Vec<String> {
+        run(&open_sample(), sql)
+    }
+
+    fn run(db: &Database, sql: &str) -> Vec<String> {
+        let statement = parser::parse(sql).unwrap();
+        let result = Executor::new(db).execute(&statement).unwrap();
+        result
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join("|")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_in_subquery() {
+        let rows = query(
+            "SELECT name FROM oranges WHERE id IN (SELECT id FROM apples WHERE color != 'Red')",
+        );
+        assert_eq!(rows, vec!["Mandarin", "Tangerine", "Clementine"]);
+
+        let rows = query("SELECT id FROM oranges WHERE id NOT IN (SELECT id FROM apples)");
+        assert_eq!(rows, vec!["5", "6"]);
+    }
+
+    #[test]
+    fn test_correlated_exists() {
+        let rows = query(
+            "SELECT o.name FROM oranges o WHERE NOT EXISTS (SELECT 1 FROM apples a WHERE a.id = o.id)",
+        );
+        assert_eq!(rows, vec!["Valencia Orange", "Navel Orange"]);
+    }
+
+    #[test]
+    fn test_scalar_subqueries() {
+        let rows = query(
+            "SELECT a.name, (SELECT o.name FROM oranges o WHERE o.id = a.id + 1) FROM apples a \
+             WHERE a.id > (SELECT count(*) FROM apples) - 2",
+        );
+        assert_eq!(
+            rows,
+            vec!["Honeycrisp|Clementine", "Golden Delicious|Valencia Orange"]
+        );
+
+        let rows = query("SELECT (SELECT name FROM apples WHERE id > 10)");
+        assert_eq!(rows, vec![""]);
+    }
+
+    #[test]
+    fn test_derived_table() {
+        let rows = query(
+            "SELECT t.n, t.c FROM (SELECT name AS n, length_free AS c FROM \
+             (SELECT name, id * 2 AS length_free FROM apples)) AS t WHERE t.c > 4",
+        );
+        assert_eq!(rows, vec!["Honeycrisp|6", "Golden Delicious|8"]);
+    }
+
+    #[test]
+    fn test_group_by_positions() {
+        let rows = query("SELECT color, count(*) FROM apples GROUP BY 1");
+        assert_eq!(
+            rows,
+            vec!["Blush Red|1", "Light Green|1", "Red|1", "Yellow|1"]
+        );
+        let rows = query("SELECT upper(color), count(*) FROM apples GROUP BY 1 COLLATE NOCASE");
+        assert_eq!(rows.len(), 4);
+
+        let db = open_sample();
+        let error = |sql: &str| {
+            let statement = parser::parse(sql).unwrap();
+            Executor::new(&db)
+                .execute(&statement)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("SELECT color, count(*) FROM apples GROUP BY 3"),
+            "1st GROUP BY term out of range - should be between 1 and 2"
+        );
+        assert_eq!(
+            error("SELECT color FROM apples GROUP BY color, 0"),
+            "2nd GROUP BY term out of range - should be between 1 and 1"
+        );
+        assert_eq!(
+            error("SELECT color, count(*) FROM apples GROUP BY 2"),
+            "aggregate functions are not allowed in the GROUP BY clause"
+        );
+        assert_eq!(
+            error("SELECT color FROM apples GROUP BY count(*)"),
+            "aggregate functions are not allowed in the GROUP BY clause"
+        );
+    }
+
+    #[test]
+    fn test_bare_columns() {
+        // the columns outside of aggregates come from the first row of a
+        // group, or from the row of the last min() or max()
+        let rows = query(
+            "SELECT c, count(*) FROM (SELECT 'OPS' c UNION ALL SELECT 'ops' UNION ALL SELECT 'Ops') \
+             GROUP BY c COLLATE NOCASE",
+        );
+        assert_eq!(rows, vec!["OPS|3"]);
+        let rows = query(
+            "SELECT c, n, max(n) FROM (SELECT 'a' c, 2 n UNION ALL SELECT 'A', 1) \
+             GROUP BY c COLLATE NOCASE",
+        );
+        assert_eq!(rows, vec!["a|2|2"]);
+        let rows = query(
+            "SELECT c, min(n) FROM (SELECT 'OPS' c, 2 n UNION ALL SELECT 'ops', 1 \
+             UNION ALL SELECT 'Ops', 3) GROUP BY c COLLATE NOCASE",
+        );
+        assert_eq!(rows, vec!["ops|1"]);
+        assert_eq!(
+            query("SELECT name, count(*) FROM apples"),
+            vec!["Granny Smith|4"]
+        );
+        assert_eq!(
+            query("SELECT name, max(color), min(id) FROM apples"),
+            vec!["Granny Smith|Yellow|1"]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_filter() {
+        // the bare columns come from the last row the filtered max() took
+        assert_eq!(
+            query(
+                "SELECT name, max(id) FILTER (WHERE color <> 'Yellow'), \
+                 count(*) FILTER (WHERE id > 1) FROM apples"
+            ),
+            vec!["Honeycrisp|3|3"]
+        );
+        // or from the first row when it takes none
+        assert_eq!(
+            query("SELECT name, min(id) FILTER (WHERE 0) FROM apples"),
+            vec!["Granny Smith|"]
+        );
+        assert_eq!(
+            query(
+                "SELECT color, count(*) FILTER (WHERE id % 2 = 0), \
+                 group_concat(name) FILTER (WHERE id > 2) FROM apples GROUP BY color ORDER BY color"
+            ),
+            vec![
+                "Blush Red|0|Honeycrisp",
+                "Light Green|0|",
+                "Red|1|",
+                "Yellow|1|Golden Delicious"
+            ]
+        );
+        assert_eq!(
+            query(
+                "SELECT sum(id) FILTER (WHERE id % 2 = 0) OVER (ORDER BY id), \
+                 count(*) FILTER (WHERE id > 3) OVER () FROM oranges"
+            ),
+            vec!["|3", "2|3", "2|3", "6|3", "6|3", "12|3"]
+        );
+        // FILTER is still a column alias when no parenthesis follows
+        assert_eq!(query("SELECT count(*) filter FROM apples"), vec!["4"]);
+
+        let db = open_sample();
+        let error = |sql: &str| {
+            let statement = parser::parse(sql).unwrap();
+            Executor::new(&db)
+                .execute(&statement)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("SELECT abs(id) FILTER (WHERE 1) FROM apples"),
+            "FILTER may not be used with non-aggregate abs()"
+        );
+        assert_eq!(
+            error("SELECT row_number() FILTER (WHERE 1) OVER () FROM apples"),
+            "FILTER clause may only be used with aggregate window functions"
+        );
+        assert_eq!(
+            error("SELECT max(id) FILTER (WHERE max(id) > 1) FROM apples"),
+            "misuse of aggregate function max()"
+        );
+    }
+
+    #[test]
+    fn test_generated_columns() {
+        let db = open_fixtures();
+        // twice is computed, and b and label come after it in the table
+        // but straight after a in the record
+        assert_eq!(
+            run(&db, "SELECT * FROM g"),
+            vec!["1|3|6|x|x:6", "2|||y|", "3|5|10||"]
+        );
+        assert_eq!(
+            run(&db, "SELECT id, label FROM g WHERE twice > 6"),
+            vec!["3|"]
+        );
+        assert_eq!(
+            run(&db, "SELECT b FROM g ORDER BY twice DESC"),
+            vec!["", "x", "y"]
+        );
+        assert_eq!(run(&db, "PRAGMA integrity_check(g)"), vec!["ok"]);
+    }
+
+    #[test]
+    fn test_added_column_defaults() {
+        let db = open_fixtures();
+        // the records of rows 1 and 2 end before the added columns
+        assert_eq!(
+            run(&db, "SELECT * FROM d"),
+            vec!["1|old|5|none|", "2|older|5|none|", "3|new|7|some|z"]
+        );
+        assert_eq!(run(&db, "SELECT id FROM d WHERE n = 5"), vec!["1", "2"]);
+        // DISTINCT is not compiled, so this walks the tree instead
+        assert_eq!(
+            run(&db, "SELECT DISTINCT n, s FROM d"),
+            vec!["5|none", "7|some"]
+        );
+        assert_eq!(
+            run(&db, "SELECT count(*) FROM d WHERE z IS NULL"),
+            vec!["2"]
+        );
+        assert_eq!(run(&db, "PRAGMA integrity_check(d)"), vec!["ok"]);
+
+        // and so are those of a WITHOUT ROWID table, scanned or sought
+        assert_eq!(run(&db, "SELECT * FROM dw"), vec!["1|2|9", "2|3|4"]);
+        assert_eq!(run(&db, "SELECT * FROM dw WHERE k = 1"), vec!["1|2|9"]);
+        assert_eq!(run(&db, "SELECT z FROM dw WHERE k > 0"), vec!["9", "4"]);
+    }
+
+    #[test]
+    fn test_views() {
+        let db = open_fixtures();
+        assert_eq!(
+            run(&db, "SELECT id, b FROM mv WHERE id > 2"),
+            vec!["3|xyz", "4|ABD"]
+        );
+        // the columns of the view compare as those of its table
+        assert_eq!(run(&db, "SELECT id FROM mv WHERE a = '10'"), vec!["1", "2"]);
+        assert_eq!(
+            run(&db, "SELECT * FROM mv WHERE b = 'ABC'"),
+            vec!["1|10|abc", "2|10|Abc"]
+        );
+        // and so do those of subqueries and CTEs
+        assert_eq!(
+            run(
+                &db,
+                "SELECT x.id FROM (SELECT id, b FROM m) x WHERE x.b = 'XYZ'"
+            ),
+            vec!["3"]
+        );
+        assert_eq!(
+            run(
+                &db,
+                "WITH c(p, q) AS (SELECT a, b FROM m) SELECT q FROM c WHERE p = '9'"
+            ),
+            vec!["xyz"]
+        );
+        // but not those of expressions
+        assert_eq!(
+            run(
+                &db,
+                "SELECT * FROM (SELECT a + 0 AS a FROM m) WHERE a = '10'"
+            )
+            .len(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_correlation_detection() {
+        let db = open_sample();
+        let executor = Executor::new(&db);
+
+        let StatementKind::Select(select) = parser::parse(
+            "SELECT (SELECT count(*) FROM oranges), \
+             (SELECT count(*) FROM oranges o WHERE o.id = apples.id) FROM apples",
+        )
+        .unwrap()
+        .kind
+        else {
+            panic!("expected SELECT");
+        };
+        let subquery = |i: usize| match &select.body.columns[i] {
+            ResultColumn::Expr {
+                expr: Expr::Subquery(s),
+                ..
+            } => s,
+            _ => panic!("expected a subquery"),
+        };
+        let env = Env::new(None);
+        assert!(!executor.is_correlated(subquery(0), &env).unwrap());
+        assert!(executor.is_correlated(subquery(1), &env).unwrap());
+    }
+
+    #[test]
+    fn test_common_table_expressions() {
+        let rows = query(
+            "WITH red(n) AS (SELECT name FROM apples WHERE color IN ('Red', 'Blush Red')), \
+             fuji AS NOT MATERIALIZED (SELECT n FROM red WHERE n = 'Fuji') \
+             SELECT n FROM red WHERE n NOT IN (SELECT n FROM fuji)",
+        );
+        assert_eq!(rows, vec!["Honeycrisp"]);
+
+        // a CTE shadows a table of the same name
+        let rows = query("WITH apples AS (SELECT 1 AS id) SELECT count(*) FROM apples");
+        assert_eq!(rows, vec!["1"]);
+    }
+
+    #[test]
+    fn test_recursive_cte() {
+        let rows = query(
+            "WITH RECURSIVE cnt(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM cnt LIMIT 5) \
+             SELECT group_concat(x) FROM cnt",
+        );
+        assert_eq!(rows, vec!["1,2,3,4,5"]);
+
+        // UNION stops the recursion once no new rows are produced
+        let rows = query(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION SELECT x % 3 + 1 FROM c) SELECT x FROM c",
+        );
+        assert_eq!(rows, vec!["1", "2", "3"]);
+
+        // ORDER BY turns the queue into a priority queue
+        let rows = query(
+            "WITH RECURSIVE t(id, depth) AS (SELECT 1, 0 UNION ALL \
+             SELECT o.id, t.depth + 1 FROM t JOIN oranges o ON o.id IN (t.id * 2, t.id * 2 + 1) \
+             ORDER BY 2 DESC) SELECT group_concat(id) FROM t",
+        );
+        assert_eq!(rows, vec!["1,2,4,5,3,6"]);
+
+        // the LIMIT of the select reading it stops an endless recursion
+        let counter = "WITH RECURSIVE r(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM r)";
+        let rows = query(&format!("{counter} SELECT x FROM r LIMIT 3"));
+        assert_eq!(rows, vec!["1", "2", "3"]);
+        let rows = query(&format!("{counter} SELECT x * 10 FROM r LIMIT 2 OFFSET 4"));
+        assert_eq!(rows, vec!["50", "60"]);
+    }
+
+    #[test]
+    fn test_union() {
+        let rows = query("SELECT id FROM oranges WHERE id > 4 UNION SELECT id FROM apples");
+        assert_eq!(rows, vec!["1", "2", "3", "4", "5", "6"]);
+
+        let rows = query(
+            "SELECT name FROM apples WHERE id = 1 UNION ALL SELECT name FROM oranges \
+             WHERE id < 3 ORDER BY name DESC LIMIT 2",
+        );
+        assert_eq!(rows, vec!["Tangelo", "Mandarin"]);
+    }
+
+    #[test]
+    fn test_intersect_except() {
+        let rows = query("SELECT id FROM oranges EXCEPT SELECT id FROM apples WHERE id > 2");
+        assert_eq!(rows, vec!["1", "2", "5", "6"]);
+
+        // operators associate to the left
+        let rows = query(
+            "SELECT id FROM apples UNION ALL SELECT 9 INTERSECT SELECT id FROM oranges \
+             UNION SELECT 0 ORDER BY 1 DESC",
+        );
+        assert_eq!(rows, vec!["4", "3", "2", "1", "0"]);
+
+        // of equal rows the last one is kept
+        assert_eq!(query("SELECT 2 UNION SELECT 2.0"), vec!["2.0"]);
+        assert_eq!(query("SELECT 2.0 INTERSECT SELECT 2"), vec!["2.0"]);
+    }
+
+    #[test]
+    fn test_compound_errors() {
+        let db = open_sample();
+        let error = |sql: &str| {
+            let statement = parser::parse(sql).unwrap();
+            Executor::new(&db)
+                .execute(&statement)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("SELECT id FROM apples EXCEPT SELECT id, name FROM oranges"),
+            "SELECTs to the left and right of EXCEPT do not have the same number of result columns"
+        );
+        assert_eq!(
+            error("SELECT id AS a FROM apples UNION SELECT id AS b FROM oranges ORDER BY a, c"),
+            "2nd ORDER BY term does not match any column in the result set"
+        );
+    }
+
+    #[test]
+    fn test_window_functions() {
+        let rows = query(
+            "SELECT name, row_number() OVER w, lag(id) OVER w, \
+             sum(id) OVER (w ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING) \
+             FROM oranges WINDOW w AS (ORDER BY id % 3, id)",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                "Tangerine|1||9",
+                "Navel Orange|2|3|10",
+                "Mandarin|3|6|11",
+                "Clementine|4|1|7",
+                "Tangelo|5|4|11",
+                "Valencia Orange|6|2|7",
+            ]
+        );
+
+        let rows = query(
+            "SELECT id, rank() OVER w, dense_rank() OVER w, ntile(4) OVER w, \
+             group_concat(id) OVER (w GROUPS CURRENT ROW EXCLUDE CURRENT ROW) \
+             FROM oranges WINDOW w AS (ORDER BY id % 3) ORDER BY id",
+        );
+        assert_eq!(
+            rows,
+            vec![
+                "1|3|2|2|4",
+                "2|5|3|3|5",
+                "3|1|1|1|6",
+                "4|3|2|2|1",
+                "5|5|3|4|2",
+                "6|1|1|1|3"
+            ]
+        );
+
+        // an offset does not move a text key, so the frame is the peers
+        assert_eq!(
+            query(
+                "SELECT sum(id) OVER (ORDER BY substr(name, 1, 1) RANGE 1 PRECEDING) \
+                 FROM oranges ORDER BY id"
+            ),
+            vec!["1", "5", "5", "4", "5", "6"]
+        );
+
+        // an offset that cannot be reached gives the default
+        assert_eq!(
+            query(
+                "SELECT lag(id, -9223372036854775808, 'd') OVER (), \
+                 lead(id, 9223372036854775807, 'e') OVER () FROM oranges LIMIT 1"
+            ),
+            vec!["d|e"]
+        );
+    }
+
+    #[test]
+    fn test_window_over_groups() {
+        // rows come out in the order of the first window
+        let rows = query(
+            "SELECT id % 3 AS k, count(*), sum(count(*)) OVER (ORDER BY id % 3 DESC), \
+             first_value(max(id)) OVER (ORDER BY id % 3) FROM oranges GROUP BY k",
+        );
+        assert_eq!(rows, vec!["2|2|2|6", "1|2|4|6", "0|2|6|6"]);
+    }
+
+    #[test]
+    fn test_scalar_functions() {
+        let rows = query(
+            "SELECT upper(substr(name, 1, 3)), printf('%05.1f', length(name) * 1.5), \
+             coalesce(NULL, typeof(id)) FROM apples WHERE id < 3",
+        );
+        assert_eq!(rows, vec!["GRA|018.0|integer", "FUJ|006.0|integer"]);
+        // unused arguments of coalesce() and iif() are not evaluated
+        assert_eq!(
+            query("SELECT coalesce(1, abs(-9223372036854775808)), iif(0, abs(-9223372036854775808), 2)"),
+            vec!["1|2"]
+        );
+    }
+
+    #[test]
+    fn test_pattern_matching() {
+        assert_eq!(
+            query("SELECT name FROM apples WHERE name LIKE '%SMITH' OR color GLOB '*[Yy]ellow'"),
+            vec!["Granny Smith", "Golden Delicious"]
+        );
+        assert_eq!(
+            query(
+                "SELECT name FROM apples WHERE name REGEXP '^\\w+ \\w+$' AND name NOT GLOB 'Gr*'"
+            ),
+            vec!["Golden Delicious"]
+        );
+
+        // the pragma holds for the statements that follow
+        let db = open_sample();
+        let executor = Executor::new(&db);
+        let like = |sql: &str| {
+            let mut rows = vec![];
+            for statement in parser::parse_statements(sql).unwrap() {
+                rows.extend(executor.execute(&statement).unwrap().rows);
+            }
+            rows
+        };
+        assert_eq!(
+            like("SELECT 'ABC' LIKE 'a%'"),
+            vec![vec![ColumnType::Int64(1)]]
+        );
+        assert_eq!(
+            like("PRAGMA case_sensitive_like = ON; SELECT 'ABC' LIKE 'a%'"),
+            vec![vec![ColumnType::Int64(0)]]
+        );
+        assert_eq!(
+            like("SELECT 'ABC' LIKE 'A%'"),
+            vec![vec![ColumnType::Int64(1)]]
+        );
+    }
+
+    #[test]
+    fn test_case_cast_affinity() {
+        assert_eq!(
+            query(
+                "SELECT name, CASE WHEN id < 2 THEN 'first' WHEN id < 4 THEN 'middle' END, \
+                 CASE color WHEN 'Red' THEN 'r' ELSE 'other' END FROM apples"
+            ),
+            vec![
+                "Granny Smith|first|other",
+                "Fuji|middle|r",
+                "Honeycrisp|middle|other",
+                "Golden Delicious||other",
+            ]
+        );
+        assert_eq!(
+            query(
+                "SELECT CAST('12abc' AS INTEGER), CAST(7 AS TEXT) || 'x', CAST('1e3' AS NUMERIC)"
+            ),
+            vec!["12|7x|1000"]
+        );
+        // the rowid has integer affinity, so text compares as a number; the
+        // text column turns the number into text
+        assert_eq!(
+            query("SELECT name FROM apples WHERE id = '3' OR name = 1"),
+            vec!["Honeycrisp"]
+        );
+        assert_eq!(
+            query("SELECT name FROM apples WHERE +id = '3'"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_collations() {
+        assert_eq!(
+            query("SELECT count(*) FROM apples WHERE name = 'FUJI' COLLATE NOCASE"),
+            vec!["1"]
+        );
+        assert_eq!(
+            query("SELECT count(*) FROM apples GROUP BY substr(color, -3) COLLATE NOCASE"),
+            vec!["1", "1", "2"]
+        );
+
+        let db = open_sample();
+        let mut executor = Executor::new(&db);
+        executor.register_collation("length", |a, b| a.len().cmp(&b.len()));
+        let sql = "SELECT name FROM apples ORDER BY name COLLATE length DESC";
+        let result = executor.execute(&parser::parse(sql).unwrap()).unwrap();
+        let names = result
+            .rows
+            .iter()
+            .map(|r| r[0].to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec!["Golden Delicious", "Granny Smith", "Honeycrisp", "Fuji"]
+        );
+        let sql = "SELECT name FROM apples ORDER BY name COLLATE unknown";
+        assert!(executor.execute(&parser::parse(sql).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_sum() {
+        let db = open_fixtures();
+        // a value that is not an integer, '9x' here, makes the sum a real
+        assert_eq!(
+            run(
+                &db,
+                "SELECT sum(a), (SELECT sum(a) FROM m WHERE id < 4) FROM m"
+            ),
+            vec!["38.0|29"]
+        );
+        assert_eq!(
+            run(&db, "SELECT sum(a) OVER (ORDER BY id) FROM m"),
+            vec!["10", "20", "29", "38.0"]
+        );
+        assert_eq!(
+            run(&db, "SELECT sum(' 7 '), sum('1.0'), sum(x'3132')"),
+            vec!["7|1.0|12.0"]
+        );
+        let executor = Executor::new(&db);
+        let Ok(StatementKind::Select(select)) =
+            parser::parse("SELECT sum(a) FROM m").map(|s| s.kind)
+        else {
+            unreachable!()
+        };
+        let program = executor.compile(&select).unwrap();
+        let result = executor.run_program(&program).unwrap();
+        assert_eq!(result.rows, vec![vec![ColumnType::Float(38.0)]]);
+    }
+
+    #[test]
+    fn test_declared_collations() {
+        let db = open_fixtures();
+        assert_eq!(
+            run(&db, "SELECT count(*) FROM (SELECT b FROM m) GROUP BY b"),
+            vec!["2", "1", "1"]
+        );
+        assert_eq!(
+            run(
+                &db,
+                "SELECT id, count(*) OVER (PARTITION BY b) FROM m ORDER BY id"
+            ),
+            vec!["1|2", "2|2", "3|1", "4|1"]
+        );
+        // max and min keep the first of equal values
+        assert_eq!(
+            run(&db, "SELECT max(b), min(b) FROM m WHERE id < 3"),
+            vec!["abc|abc"]
+        );
+
+        // a compound select compares with the collation of the left-most
+        // select that has one for the column
+        assert_eq!(
+            run(&db, "SELECT b FROM m UNION SELECT 'ABC'"),
+            vec!["ABC", "ABD", "xyz"]
+        );
+        assert_eq!(
+            run(&db, "SELECT 'ABC' UNION SELECT b FROM m"),
+            vec!["Abc", "ABD", "xyz"]
+        );
+        assert_eq!(
+            run(&db, "SELECT b FROM m UNION ALL SELECT 'ABC' ORDER BY 1"),
+            vec!["abc", "Abc", "ABC", "ABD", "xyz"]
+        );
+        assert_eq!(
+            run(&db, "SELECT b FROM m INTERSECT SELECT 'ABC'"),
+            vec!["Abc"]
+        );
+        assert_eq!(
+            run(
+                &db,
+                "WITH RECURSIVE c(x) AS (SELECT b FROM m WHERE id = 1 UNION SELECT 'ABC' FROM c) \
+                 SELECT x FROM c"
+            ),
+            vec!["abc"]
+        );
+
+        // a collation that is not registered is an error only where used
+        assert_eq!(run(&db, "SELECT b FROM x"), vec!["r"]);
+        // nor can an index with one be used
+        assert_eq!(run(&db, "SELECT b FROM x WHERE b = 'r'"), vec!["r"]);
+        let executor = Executor::new(&db);
+        let sql = "SELECT a FROM x ORDER BY a";
+        let err = executor.execute(&parser::parse(sql).unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "no such collation sequence: rev");
+    }
+
+    #[test]
+    fn test_bound_parameters() {
+        let db = open_sample();
+        let executor = Executor::new(&db);
+        let mut statement = parser::parse(
+            "SELECT name FROM apples WHERE id = (SELECT max(id) FROM apples WHERE id <= :id)",
+        )
+        .unwrap();
+        let mut run = |id: i64| {
+            statement.bind(":id", ColumnType::Int64(id)).unwrap();
+            let result = executor.execute(&statement).unwrap();
+            result
+                .rows
+                .iter()
+                .map(|r| r[0].to_string())
+                .collect::<Vec<_>>()
+        };
+        // the subquery is evaluated again for each execution
+        assert_eq!(run(2), vec!["Fuji"]);
+        assert_eq!(run(3), vec!["Honeycrisp"]);
+        statement.clear_bindings();
+        assert!(executor.execute(&statement).unwrap().rows.is_empty());
+    }
+
+    #[test]
+    fn test_explain_query_plan() {
+        assert_eq!(
+            query("EXPLAIN QUERY PLAN SELECT color, count(*) FROM apples GROUP BY color"),
+            vec!["1|0|0|SCAN apples", "2|0|0|USE TEMP B-TREE FOR GROUP BY"]
+        );
+
+        let db = open_sample();
+        let executor = Executor::new(&db);
+        let sql = "EXPLAIN QUERY PLAN WITH c AS (SELECT id FROM oranges) \
+                   SELECT name FROM apples a \
+                   WHERE EXISTS (SELECT 1 FROM c WHERE c.id = a.id) \
+                   UNION SELECT 1 ORDER BY 1";
+        let plan = executor.query_plan(&parser::parse(sql).unwrap()).unwrap();
+        assert_eq!(
+            plan.to_string(),
+            "QUERY PLAN\n\
+             |--COMPOUND QUERY\n\
+             |  |--LEFT-MOST SUBQUERY\n\
+             |  |  |--SCAN a\n\
+             |  |  `--CORRELATED SCALAR SUBQUERY 1\n\
+             |  |     |--MATERIALIZE c\n\
+             |  |     |  `--SCAN oranges\n\
+             |  |     `--SCAN c\n\
+             |  `--UNION USING TEMP B-TREE\n\
+             |     `--SCAN CONSTANT ROW\n\
+             `--USE TEMP B-TREE FOR ORDER BY\n"
+        );
+        // the CTE is computed once, but scanned for every apple
+        assert!(plan.cost_summary().contains(
+            "SCAN c reads about 6 rows, once for each of about 4 rows of the outer query."
+        ));
+
+        // the statistics of a WITHOUT ROWID table's primary key are under
+        // the table's name, and its descending column can be sought
+        let db = open_fixtures();
+        assert_eq!(
+            run(
+                &db,
+                "EXPLAIN QUERY PLAN SELECT * FROM wr2 WHERE b = 'x' AND a = 2"
+            ),
+            vec!["1|0|0|SEARCH wr2 USING PRIMARY KEY (b=? AND a=?)"]
+        );
+        assert_eq!(
+            run(&db, "SELECT * FROM wr2 WHERE b = 'x' AND a = 2"),
+            vec!["2|x|20"]
+        );
+        assert_eq!(
+            run(&db, "SELECT * FROM wr2 WHERE b > 'w'"),
+            vec!["1|y|30", "2|y|40", "1|x|10", "2|x|20"]
+        );
+        assert_eq!(
+            run(&db, "SELECT * FROM wr2 WHERE b >= 'x' AND b < 'y'"),
+            vec!["1|x|10", "2|x|20"]
+        );
+        assert_eq!(
+            run(&db, "SELECT * FROM wr2 WHERE b IN ('x', 'y') AND a > 1"),
+            vec!["2|y|40", "2|x|20"]
+        );
+
+        // rows that the scan gives in order need no sorting
+        let plan = |sql: &str| run(&db, &format!("EXPLAIN QUERY PLAN {sql}"));
+        assert_eq!(
+            plan("SELECT c, count(*) FROM wr2 GROUP BY c ORDER BY c DESC"),
+            vec!["1|0|0|SCAN wr2 USING COVERING INDEX wr2c"]
+        );
+        assert_eq!(
+            plan("SELECT DISTINCT c FROM wr2"),
+            vec!["1|0|0|SCAN wr2 USING COVERING INDEX wr2c"]
+        );
+        assert_eq!(
+            plan("SELECT * FROM wr2 WHERE b = 'x' ORDER BY a DESC"),
+            vec!["1|0|0|SEARCH wr2 USING PRIMARY KEY (b=?)"]
+        );
+        assert_eq!(
+            query("EXPLAIN QUERY PLAN SELECT * FROM apples ORDER BY id DESC"),
+            vec!["1|0|0|SCAN apples"]
+        );
+        // unless they are grouped in another order, or are in order of
+        // another collation
+        assert_eq!(
+            plan("SELECT c, count(*) FROM wr2 GROUP BY c, c"),
+            vec![
+                "1|0|0|SCAN wr2 USING COVERING INDEX wr2c",
+                "2|0|0|USE TEMP B-TREE FOR GROUP BY"
+            ]
+        );
+        assert_eq!(
+            plan("SELECT c FROM wr2 ORDER BY c COLLATE NOCASE"),
+            vec![
+                "1|0|0|SCAN wr2 USING COVERING INDEX wr2c",
+                "2|0|0|USE TEMP B-TREE FOR ORDER BY"
+            ]
+        );
+        // a GROUP BY sorted in the direction of the ORDER BY does for both
+        assert_eq!(
+            query(
+                "EXPLAIN QUERY PLAN SELECT color, count(*) FROM apples \
+                 GROUP BY color ORDER BY color DESC"
+            ),
+            vec!["1|0|0|SCAN apples", "2|0|0|USE TEMP B-TREE FOR GROUP BY"]
+        );
+        let statement = parser::parse("EXPLAIN QUERY PLAN SELECT c, count(*) FROM wr2 GROUP BY c");
+        let plan = Executor::new(&db).query_plan(&statement.unwrap()).unwrap();
+        assert!(!plan.cost_summary().contains("sorts"));
+    }
+
+    #[test]
+    fn test_join_order() {
+        assert_eq!(
+            query("EXPLAIN QUERY PLAN SELECT * FROM apples WHERE id = 2"),
+            vec!["1|0|0|SEARCH apples USING INTEGER PRIMARY KEY (rowid=?)"]
+        );
+        // the oranges are looked up by rowid for each apple that passes the
+        // filter, whichever comes first in the FROM clause
+        let sql = "SELECT a.name, o.name FROM oranges o, apples a \
+                   WHERE o.id = a.id AND a.name LIKE 'G%'";
+        assert_eq!(
+            query(&format!("EXPLAIN QUERY PLAN {sql}")),
+            vec![
+                "1|0|0|SCAN a",
+                "2|0|0|SEARCH o USING INTEGER PRIMARY KEY (rowid=?)"
+            ]
+        );
+        assert_eq!(
+            query(sql),
+            vec!["Granny Smith|Mandarin", "Golden Delicious|Clementine"]
+        );
+        // a LEFT JOIN keeps the apples without an orange
+        assert_eq!(
+            query(
+                "SELECT a.id, o.name FROM apples a \
+                 LEFT JOIN oranges o ON o.id = a.id + 3 ORDER BY a.id"
+            ),
+            vec!["1|Clementine", "2|Valencia Orange", "3|Navel Orange", "4|"]
+        );
+        // `*` has a column of USING once, but `o.*` has all of its columns
+        assert_eq!(
+            query("SELECT * FROM apples a JOIN oranges o USING (id) WHERE id < 3"),
+            vec![
+                "1|Granny Smith|Light Green|Mandarin|great for snacking",
+                "2|Fuji|Red|Tangelo|sweet and tart"
+            ]
+        );
+        assert_eq!(
+            query("SELECT o.* FROM apples a JOIN oranges o USING (id) WHERE id = 1"),
+            vec!["1|Mandarin|great for snacking"]
+        );
+    }
+
+    #[test]
+    fn test_in_and_or_lookups() {
+        // `x = a OR x = b` is looked up like `x IN (a, b)`, in rowid order
+        let sql = "SELECT name FROM apples WHERE id = 4 OR id = '2'";
+        assert_eq!(
+            query(&format!("EXPLAIN QUERY PLAN {sql}")),
+            vec!["1|0|0|SEARCH apples USING INTEGER PRIMARY KEY (rowid=?)"]
+        );
+        assert_eq!(query(sql), vec!["Fuji", "Golden Delicious"]);
+        // each side of an OR is looked up, and a row is found only once
+        let sql = "SELECT name FROM apples WHERE id IN (3, 1, 3) OR id = 4";
+        assert_eq!(
+            query(&format!("EXPLAIN QUERY PLAN {sql}")),
+            vec![
+                "1|0|0|MULTI-INDEX OR",
+                "2|1|0|INDEX 1",
+                "3|2|0|SEARCH apples USING INTEGER PRIMARY KEY (rowid=?)",
+                "4|1|0|INDEX 2",
+                "5|4|0|SEARCH apples USING INTEGER PRIMARY KEY (rowid=?)"
+            ]
+        );
+        assert_eq!(
+            query(sql),
+            vec!["Granny Smith", "Honeycrisp", "Golden Delicious"]
+        );
+        assert_eq!(
+            query("SELECT name FROM apples WHERE (id = 2 AND color = 'Yellow') OR id IN (4, 2)"),
+            vec!["Fuji", "Golden Delicious"]
+        );
+    }
+
+    #[test]
+    fn test_rowid_ranges() {
+        let sql = "SELECT name FROM apples WHERE id BETWEEN 2 AND 3";
+        assert_eq!(
+            query(&format!("EXPLAIN QUERY PLAN {sql}")),
+            vec!["1|0|0|SEARCH apples USING INTEGER PRIMARY KEY (rowid>? AND rowid<?)"]
+        );
+        assert_eq!(query(sql), vec!["Fuji", "Honeycrisp"]);
+        assert_eq!(
+            query("EXPLAIN QUERY PLAN SELECT name FROM apples WHERE id < 3"),
+            vec!["1|0|0|SEARCH apples USING INTEGER PRIMARY KEY (rowid<?)"]
+        );
+        // reals fall between the rowids, text comes after them all
+        assert_eq!(
+            query("SELECT name FROM apples WHERE id > 1.5 AND id <= 3.5"),
+            vec!["Fuji", "Honeycrisp"]
+        );
+        assert_eq!(
+            query("SELECT id FROM apples WHERE id >= '3'"),
+            vec!["3", "4"]
+        );
+        assert_eq!(query("SELECT id FROM apples WHERE id > 'x'").len(), 0);
+        assert_eq!(query("SELECT id FROM apples WHERE id < x'00'").len(), 4);
+        assert_eq!(query("SELECT id FROM apples WHERE id > NULL").len(), 0);
+        assert_eq!(
+            query("SELECT id FROM apples WHERE id > 9223372036854775807").len(),
+            0
+        );
+
+        // the program seeks the same rows
+        let db = open_sample();
+        let executor = Executor::new(&db);
+        for sql in [
+            "SELECT name FROM apples WHERE id > 1.5 AND id < 3.5",
+            "SELECT id FROM apples WHERE id >= '3'",
+            "SELECT id FROM apples WHERE id > 2.0 AND id <= 'x'",
+            "SELECT id FROM apples WHERE id < 2.5",
+        ] {
+            let Ok(StatementKind::Select(select)) = parser::parse(sql).map(|s| s.kind) else {
+                panic!("not a select: {sql}");
+            };
+            let program = executor.compile(&select).unwrap();
+            let compiled = executor.run_program(&program).unwrap();
+            let walked = executor.run_select(&select, None).unwrap();
+            assert_eq!(compiled.rows, walked.rows, "{sql}");
+        }
+
+        // negative rowids, and those of nine-byte varints
+        let db = open_fixtures();
+        let sql = "SELECT name FROM ids WHERE id BETWEEN -5 AND 0";
+        assert_eq!(
+            run(&db, &format!("EXPLAIN QUERY PLAN {sql}")),
+            vec!["1|0|0|SEARCH ids USING INTEGER PRIMARY KEY (rowid>? AND rowid<?)"]
+        );
+        assert_eq!(run(&db, sql), vec!["minus five", "minus one", "zero"]);
+        assert_eq!(
+            run(&db, "SELECT id FROM ids WHERE id < -5 OR id >= 7"),
+            vec!["-9223372036854775808", "7", "9223372036854775807"]
+        );
+        let Ok(StatementKind::Select(select)) = parser::parse(sql).map(|s| s.kind) else {
+            unreachable!();
+        };
+        let executor = Executor::new(&db);
+        let program = executor.compile(&select).unwrap();
+        assert_eq!(executor.run_program(&program).unwrap().rows.len(), 3);
+    }
+
+    #[test]
+    fn test_pragmas() {
+        assert_eq!(
+            query("PRAGMA table_info(apples)"),
+            vec!["0|id|INTEGER|0||1", "1|name|TEXT|0||0", "2|color|TEXT|0||0"]
+        );
+        assert_eq!(query("PRAGMA index_list('apples')").len(), 0);
+        assert_eq!(query("PRAGMA table_info(nosuch)").len(), 0);
+        // the table-valued functions are called for each row they join
+        assert_eq!(
+            query(
+                "SELECT m.name, p.name FROM sqlite_master m \
+                 JOIN pragma_table_info(m.name) p WHERE p.pk OR m.name = 'sqlite_sequence'"
+            ),
+            vec![
+                "apples|id",
+                "sqlite_sequence|name",
+                "sqlite_sequence|seq",
+                "oranges|id"
+            ]
+        );
+        // even written before the tables they refer to
+        assert_eq!(
+            query(
+                "SELECT p.name, p.arg FROM pragma_table_info(m.name) p, sqlite_master m \
+                 WHERE m.name = 'oranges' AND p.cid = 2"
+            ),
+            vec!["description|oranges"]
+        );
+        assert!(query("SELECT name FROM pragma_collation_list").contains(&"NOCASE".to_string()));
+        assert_eq!(
+            query("SELECT narg FROM pragma_function_list WHERE name = 'substr'"),
+            vec!["2", "3"]
+        );
+        assert_eq!(query("PRAGMA page_size"), vec!["4096"]);
+        assert_eq!(query("PRAGMA main.page_count"), vec!["4"]);
+        assert_eq!(query("PRAGMA temp.page_count"), vec!["0"]);
+        assert_eq!(query("PRAGMA encoding"), vec!["UTF-8"]);
+        assert_eq!(
+            query("SELECT schema_version, freelist_count FROM pragma_schema_version, pragma_freelist_count"),
+            vec!["2|0"]
+        );
+        assert_eq!(query("PRAGMA page_size = 1024").len(), 0);
+        let db = open_sample();
+        let error = |sql: &str| {
+            let statement = parser::parse(sql).unwrap();
+            Executor::new(&db)
+                .execute(&statement)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("PRAGMA user_version = 3"),
+            "attempt to write a readonly database"
+        );
+        assert_eq!(
+            error("SELECT * FROM pragma_user_version('main')"),
+            "too many arguments on pragma_user_version() - max 0"
+        );
+
+        // the columns of a view have the types of the table columns they
+        // are, through other views
+        let db = open_fixtures();
+        assert_eq!(
+            run(&db, "PRAGMA table_info(mv2)"),
+            vec!["0|x|TEXT|0||0", "1|y|INT|0||0", "2|z||0||0"]
+        );
+        // a WITHOUT ROWID table stands for its primary key
+        assert_eq!(run(&db, "PRAGMA index_info(dw)"), vec!["0|0|k"]);
+        assert_eq!(
+            run(&db, "PRAGMA index_xinfo(dw)"),
+            vec!["0|0|k|0|BINARY|1", "1|1|a|0|BINARY|0", "2|2|z|0|BINARY|0"]
+        );
+    }
+
+    #[test]
+    fn test_integrity_check() {
+        assert_eq!(query("PRAGMA integrity_check"), vec!["ok"]);
+        assert_eq!(query("PRAGMA quick_check(apples)"), vec!["ok"]);
+        assert_eq!(query("PRAGMA temp.integrity_check"), vec!["ok"]);
+        assert_eq!(
+            query("SELECT * FROM pragma_integrity_check('oranges')"),
+            vec!["ok"]
+        );
+        let db = open_sample();
+        let statement = parser::parse("PRAGMA integrity_check(nosuch)").unwrap();
+        let error = Executor::new(&db).execute(&statement).unwrap_err();
+        assert_eq!(error.to_string(), "no such table: nosuch");
+
+        // an error that is not damage to the file is reported as itself
+        let db = open_fixtures();
+        assert_eq!(run(&db, "PRAGMA integrity_check(m)"), vec!["ok"]);
+        let statement = parser::parse("PRAGMA integrity_check").unwrap();
+        let error = Executor::new(&db).execute(&statement).unwrap_err();
+        assert_eq!(error.to_string(), "no such collation sequence: rev");
+
+        // a table that cannot be read is passed over
+        let mut data = std::fs::read("sample.db").unwrap();
+        for page in [2, 4] {
+            data[(page - 1) * 4096] = 0;
+        }
+        let db = crate::db::testing::open_bytes(&data);
+        assert_eq!(
+            run(&db, "PRAGMA integrity_check"),
+            vec![
+                "*** in database main ***\n\
+                 Tree 4 page 4: btreeInitPage() returns error code 11\n\
+                 Tree 2 page 2: btreeInitPage() returns error code 11"
+            ]
+        );
+        // the tables are checked in the order of SQLite's hash table
+        let names = [
+            "sqlite_master",
+            "apples",
+            "oranges",
+            "pears",
+            "v",
+            "figs",
+            "plums",
+            "limes",
+        ];
+        let order = integrity::hash_order(&names).into_iter().map(|i| names[i]);
+        assert_eq!(
+            order.collect::<Vec<_>>(),
+            [
+                "limes",
+                "plums",
+                "figs",
+                "v",
+                "sqlite_master",
+                "apples",
+                "oranges",
+                "pears"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bytecode_program() {
+        // the program gives the rows the tree-walking evaluator does
+        let compare = |db: &Database, sql: &str| {
+            let executor = Executor::new(db);
+            let Ok(StatementKind::Select(select)) = parser::parse(sql).map(|s| s.kind) else {
+                panic!("not a select: {sql}");
+            };
+            let program = executor.compile(&select).unwrap();
+            let compiled = executor.run_program(&program).unwrap();
+            let walked = executor.run_select(&select, None).unwrap();
+            assert_eq!(compiled.columns, walked.columns, "{sql}");
+            assert_eq!(compiled.rows, walked.rows, "{sql}");
+        };
+        let db = open_sample();
+        for sql in [
+            "SELECT * FROM apples WHERE id BETWEEN 2 AND 3",
+            "SELECT name, color IN ('Red', NULL) FROM apples ORDER BY color DESC, 1",
+            "SELECT CASE id WHEN 1 THEN 'one' ELSE upper(name) END FROM apples LIMIT 2 OFFSET 1",
+            "SELECT count(*), max(name), sum(id) + 1 FROM apples WHERE name LIKE '%e%'",
+            "SELECT coalesce(NULL, id) AS c, NULL AND 0, 1 IS NOT NULL FROM apples ORDER BY c DESC",
+            "SELECT name, count(*) FROM apples",
+            "SELECT name, min(color), max(id) FROM apples WHERE id > 1",
+            "SELECT * FROM apples WHERE id = 2",
+            "SELECT name FROM apples WHERE id IN (3, '1', 3.0, 2.5, NULL, 9)",
+            "SELECT color, count(*), max(name) FROM apples GROUP BY color",
+            "SELECT color, id FROM apples GROUP BY 1 HAVING count(*) > 1 ORDER BY id DESC",
+            "SELECT length(color) AS n, min(id) FROM apples GROUP BY n LIMIT 1 OFFSET 1",
+            "SELECT DISTINCT length(color) FROM apples ORDER BY 1 DESC",
+            "SELECT DISTINCT count(*) FROM apples GROUP BY color",
+            "SELECT name FROM apples LIMIT 1 + 1 OFFSET '1'",
+        ] {
+            compare(&db, sql);
+        }
+        let fixtures = open_fixtures();
+        for sql in [
+            "SELECT id, c FROM lk WHERE a = 2 AND b IN ('X', 'y')",
+            "SELECT id, c FROM lk WHERE a IN (3, 1, 2, NULL, 2.0)",
+            "SELECT id, c FROM lk WHERE a < 3",
+            "SELECT id, c FROM lk WHERE a >= 2 AND a < 3",
+            "SELECT id, c FROM lk WHERE a = '2' AND b > 'x'",
+            "SELECT * FROM lw WHERE v > 5",
+            "SELECT * FROM lw WHERE v IN (5, 6)",
+            "SELECT * FROM lw WHERE k = 'a' AND j > 1",
+            "SELECT * FROM wr2 WHERE b < 'y'",
+            "SELECT * FROM dw WHERE k = 2",
+            "SELECT b, count(*) FROM m GROUP BY b",
+            "SELECT DISTINCT b FROM m",
+        ] {
+            compare(&fixtures, sql);
+        }
+        let executor = Executor::new(&db);
+        let mut statement = parser::parse("SELECT name FROM apples LIMIT ?").unwrap();
+        statement.bind(1, ColumnType::Int64(2)).unwrap();
+        assert_eq!(executor.execute(&statement).unwrap().rows.len(), 2);
+        statement
+            .bind(1, ColumnType::String("two".to_string()))
+            .unwrap();
+        let error = executor.execute(&statement).unwrap_err();
+        assert_eq!(error.to_string(), "datatype mismatch");
+
+        assert_eq!(
+            query("EXPLAIN SELECT name FROM apples WHERE id > 2"),
+            vec![
+                "0|Init|0|11|0||0|Start at 11",
+                "1|OpenRead|0|2|0|3|0|root=2 iDb=0",
+                "2|Integer|2|1|0||0|r[1]=2",
+                "3|SeekGT|0|10|1||0|key=r[1]; pk",
+                "4|Rowid|0|2|0||0|r[2]=rowid",
+                "5|Integer|2|3|0||0|r[3]=2",
+                "6|Le|3|9|2|BINARY|84|if r[2]<=r[3] goto 9",
+                "7|Column|0|1|4||0|r[4]= cursor 0 column 1",
+                "8|ResultRow|4|1|0||0|output=r[4]",
+                "9|Next|0|4|0||0|",
+                "10|Halt|0|0|0||0|",
+                "11|Transaction|0|0|0||0|",
+                "12|Goto|0|1|0||0|",
+            ]
+        );
+        assert_eq!(
+            query("EXPLAIN SELECT name FROM apples WHERE id = 2"),
+            vec![
+                "0|Init|0|12|0||0|Start at 12",
+                "1|OpenRead|0|2|0|3|0|root=2 iDb=0",
+                "2|Integer|2|1|0||0|r[1]=2",
+                "3|Affinity|1|1|0|D|0|affinity(r[1])",
+                "4|IsNull|1|11|0||0|if r[1]==NULL goto 11",
+                "5|SeekRowid|0|11|1||0|intkey=r[1]",
+                "6|Rowid|0|2|0||0|r[2]=rowid",
+                "7|Integer|2|3|0||0|r[3]=2",
+                "8|Ne|3|11|2|BINARY|84|if r[2]!=r[3] goto 11",
+                "9|Column|0|1|4||0|r[4]= cursor 0 column 1",
+                "10|ResultRow|4|1|0||0|output=r[4]",
+                "11|Halt|0|0|0||0|",
+                "12|Transaction|0|0|0||0|",
+                "13|Goto|0|1|0||0|",
+            ]
+        );
+
+        // what the compiler does not handle yet is walked instead, but any
+        // other error in compiling is the error of the select
+        let compile = |sql: &str| {
+            let Ok(StatementKind::Select(select)) = parser::parse(sql).map(|s| s.kind) else {
+                panic!("not a select: {sql}");
+            };
+            executor.compile(&select).unwrap_err()
+        };
+        let sql = "SELECT a.name FROM apples a JOIN oranges o ON o.id = a.id";
+        assert!(compile(sql).is::<codegen::NotCompiled>());
+        assert_eq!(query(sql).len(), 4);
+        let sql = "SELECT a.id FROM apples";
+        assert!(!compile(sql).is::<codegen::NotCompiled>());
+        let error = executor.execute(&parser::parse(sql).unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "no such column: a.id");
+    }
+}
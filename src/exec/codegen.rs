@@ -0,0 +1,1841 @@
+//! Compiles selects into programs for the register machine in `vm.rs`.
+
+use super::*;
+use crate::planner::{IndexSeek, SeekKey};
+use crate::vdbe::{self, KeyInfo, Opcode, Program, JUMP_IF_NULL, NULL_EQ, P4};
+
+/// The cursor of the table a program reads.
+const TABLE: i64 = 0;
+/// The cursor of the index, when the table is read through one.
+const INDEX: i64 = 1;
+/// The cursor of the sorter for ORDER BY.
+const SORTER: i64 = 2;
+/// The cursor of the sorter for GROUP BY.
+const GROUPER: i64 = 3;
+/// The cursor of the set of the rows DISTINCT has output.
+const DISTINCT: i64 = 4;
+/// The cursor of the sorter of the values of the first `IN (...)` key of a
+/// lookup; those of the other keys follow it.
+const KEYS: i64 = 5;
+
+/// What the compiler does not handle yet: the executor evaluates such a
+/// select by walking the tree instead. Any other error from compiling is an
+/// error of the select.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub(super) struct NotCompiled(String);
+
+/// Like `bail!`, returning a [`NotCompiled`] error.
+macro_rules! not_compiled {
+    ($($arg:tt)*) => {
+        return Err(NotCompiled(format!($($arg)*)).into())
+    };
+}
+
+/// The state of compiling one select.
+struct Compiler<'a, 'd> {
+    executor: &'a Executor<'d>,
+    program: Program,
+    env: Env<'a>,
+    /// Whether each table column is an alias for the rowid.
+    is_rowid: &'a [bool],
+    /// The table column of each field of the records of the table.
+    stored: &'a [usize],
+    /// The default value of each table column, for records that lack it.
+    defaults: Vec<P4>,
+    /// When the rows are read from a covering index alone: the table
+    /// column of each column of the index.
+    covering: Option<Vec<usize>>,
+    /// Whether result column aliases can be referred to; not inside the
+    /// expression of an alias.
+    aliases: bool,
+    /// Outside of the loop over the table: the registers holding the
+    /// values of the columns of the row, by column.
+    saved: Option<HashMap<usize, i64>>,
+    /// The accumulator of each aggregate function call, by the address of
+    /// its AST node.
+    accumulators: Vec<(usize, i64)>,
+    /// While stepping the aggregates: the call of min() or max() whose row
+    /// the saved columns come from, and the register its step sets to 1
+    /// when the row is not that one.
+    min_max: Option<(usize, i64)>,
+}
+
+impl<'d> Executor<'d> {
+    /// Compile a select into a program. For now only selects reading at
+    /// most one table, without compound selects, CTEs, subqueries or
+    /// window functions, can be compiled; anything else is a
+    /// [`NotCompiled`] error.
+    pub(super) fn compile(&self, select: &Select) -> Result<Program, anyhow::Error> {
+        if select.with.is_some() {
+            not_compiled!("WITH is not compiled to bytecode yet");
+        }
+        if !select.compound.is_empty() {
+            not_compiled!("compound selects are not compiled to bytecode yet");
+        }
+        let core = &select.body;
+        let source = match &core.from {
+            None => None,
+            Some(FromClause::Table { name, .. })
+                if self.db.views().any(|v| v.name.eq_ignore_ascii_case(name)) =>
+            {
+                not_compiled!("views are not compiled to bytecode yet")
+            }
+            Some(FromClause::Table { name, alias }) => {
+                let alias = alias.as_deref().unwrap_or(name);
+                let refs = ColumnRefs::of(core, &select.order_by);
+                Some(self.single_table(name, alias, core.where_clause.as_ref(), &refs)?)
+            }
+            Some(_) => not_compiled!(
+                "joins, subqueries and table-valued functions in FROM are not compiled to bytecode yet"
+            ),
+        };
+        if source
+            .as_ref()
+            .is_some_and(|(s, _)| !s.generated.is_empty())
+        {
+            not_compiled!("VIRTUAL generated columns are not compiled to bytecode yet");
+        }
+        let columns = source.as_ref().map_or(&[][..], |(s, _)| &s.columns);
+        let (names, exprs) = self.result_columns(core, columns)?;
+        let aliases = core
+            .columns
+            .iter()
+            .filter_map(|c| match c {
+                ResultColumn::Expr {
+                    expr,
+                    alias: Some(alias),
+                    ..
+                } => Some((alias.clone(), expr)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let env = Env {
+            columns,
+            aliases: &aliases,
+            ..Env::new(None)
+        };
+        // the entries of an index are never short, unlike the records of a
+        // table, which a WITHOUT ROWID table is read from as an index
+        let mut defaults = vec![];
+        if let Some((source, _)) = source.as_ref().filter(|(source, access)| match access {
+            Access::IndexScan(_) => false,
+            Access::IndexRange(range) => range.covering.is_none(),
+            Access::IndexSeek(seek) => seek.covering.is_none() || holds_rows(source, seek),
+            _ => true,
+        }) {
+            for (default, column) in source.defaults.iter().zip(&source.columns) {
+                defaults.push(match default {
+                    Some(default) => {
+                        let value = self.eval(
+                            default,
+                            &Env {
+                                columns: &[],
+                                ..env
+                            },
+                        )?;
+                        P4::Mem(value.apply_affinity(column.affinity.unwrap_or(Affinity::Blob)))
+                    }
+                    None => P4::None,
+                });
+            }
+        }
+
+        let mut c = Compiler {
+            executor: self,
+            program: Program {
+                columns: names,
+                ..Program::default()
+            },
+            env,
+            is_rowid: source.as_ref().map_or(&[][..], |(s, _)| &s.is_rowid),
+            stored: source.as_ref().map_or(&[][..], |(s, _)| &s.stored),
+            defaults,
+            covering: source.as_ref().and_then(|(source, access)| match access {
+                // a WITHOUT ROWID table is read from its primary key index
+                Access::Scan => source.layout.as_ref().map(|_| source.stored.clone()),
+                Access::IndexSeek(seek) if holds_rows(source, seek) => Some(source.stored.clone()),
+                Access::IndexSeek(seek) => seek.covering.clone(),
+                Access::IndexScan(scan) => Some(scan.columns.clone()),
+                Access::IndexRange(range) => range.covering.clone(),
+                _ => None,
+            }),
+            aliases: true,
+            saved: None,
+            accumulators: vec![],
+            min_max: None,
+        };
+        let init = c.emit(Opcode::Init, 0, 0, 0);
+
+        let is_aggregate = !core.group_by.is_empty()
+            || exprs.iter().any(|e| contains_aggregate(e))
+            || core.having.as_ref().is_some_and(contains_aggregate)
+            || select.order_by.iter().any(|t| contains_aggregate(&t.expr));
+        let end = if is_aggregate {
+            c.aggregate_query(source.as_ref(), select, &exprs)?
+        } else {
+            c.simple_query(source.as_ref(), select, &exprs)?
+        };
+
+        for jump in end {
+            c.patch(jump);
+        }
+        c.emit(Opcode::Halt, 0, 0, 0);
+        c.patch(init);
+        c.emit(Opcode::Transaction, 0, 0, 0);
+        c.emit(Opcode::Goto, 0, 1, 0);
+        Ok(c.program)
+    }
+}
+
+/// Whether a seek reads the primary key of a WITHOUT ROWID table, whose
+/// entries are the rows.
+fn holds_rows(source: &TableSource, seek: &IndexSeek) -> bool {
+    source.layout.is_some() && seek.index.primary_key
+}
+
+/// The value of a LIMIT or OFFSET that is an integer literal, which is
+/// known when compiling; any other is computed when the program runs.
+fn constant_limit(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::Literal(v) => v.as_integer(),
+        _ => None,
+    }
+}
+
+/// The instructions around the body of the loop over the rows of a table.
+#[derive(Default)]
+struct Loop {
+    /// The address of the body, where each row starts.
+    top: usize,
+    /// Jumps to patch to the end of the loop, or of the lookup when there
+    /// are several, once it is known.
+    exits: Vec<usize>,
+    /// For an index range read in two passes: the register counting the
+    /// passes, and the blob bounds for the second one.
+    second_pass: Option<(i64, i64, Vec<u8>, Vec<u8>)>,
+    /// The cursor stepped to the next row: the index when reading one.
+    /// There is none when a lookup finds a single row.
+    cursor: Option<i64>,
+    /// For an index that is not covering: the jumps taken when the row of
+    /// an index entry is missing, which go to the next entry.
+    seek: Vec<usize>,
+    /// The loops over the values of the `IN (...)` keys of a lookup, from
+    /// the outermost; the lookup is made for each combination of them.
+    keys: Vec<KeyLoop>,
+    /// Jumps to patch past all the lookups, for keys that find nothing.
+    done: Vec<usize>,
+}
+
+/// The loop over the values of an `IN (...)` key, in the order of the
+/// index, without NULLs or repeats.
+struct KeyLoop {
+    cursor: i64,
+    /// The address where each value starts.
+    top: usize,
+    /// The jump taken when a value repeats the one before.
+    repeated: usize,
+}
+
+/// The aggregate calls of an aggregate query, and the columns of the row
+/// that it uses outside of them.
+struct Group<'e> {
+    calls: Vec<&'e Expr>,
+    columns: Vec<usize>,
+    /// The call of min() or max() whose row the columns come from.
+    min_max: Option<&'e Expr>,
+}
+
+impl Group<'_> {
+    /// Allocate the accumulators of the calls, the registers the columns
+    /// are saved in, and, if there are columns, the register telling
+    /// whether the row is not one to save them from.
+    fn registers(&self, c: &mut Compiler) -> (i64, i64, Option<i64>) {
+        let accumulators = c.alloc(self.calls.len() as i64);
+        let saved = c.alloc(self.columns.len() as i64);
+        let skip_saving = (!self.columns.is_empty()).then(|| c.alloc(1));
+        c.min_max = self
+            .min_max
+            .zip(skip_saving)
+            .map(|(call, register)| (call as *const Expr as usize, register));
+        (accumulators, saved, skip_saving)
+    }
+
+    /// Start a group: clear the accumulators and the saved columns, which
+    /// come after them.
+    fn reset(&self, c: &mut Compiler, accumulators: i64, skip_saving: Option<i64>) {
+        let registers = (self.calls.len() + self.columns.len()) as i64;
+        if registers > 0 {
+            c.emit(Opcode::Null, 0, accumulators, accumulators + registers - 1);
+        }
+        if let Some(register) = skip_saving {
+            c.emit(Opcode::Integer, 0, register, 0);
+        }
+    }
+
+    /// Take a row into the group: step the calls, and save the columns
+    /// from the first row, or from the row min() or max() takes.
+    fn step(
+        &self,
+        c: &mut Compiler,
+        accumulators: i64,
+        saved: i64,
+        skip_saving: Option<i64>,
+    ) -> Result<(), anyhow::Error> {
+        for (i, call) in self.calls.iter().enumerate() {
+            let accumulator = accumulators + i as i64;
+            c.accumulators
+                .push((*call as *const Expr as usize, accumulator));
+            c.aggregate_step(call, accumulator)?;
+        }
+        if let Some(register) = skip_saving {
+            let skip = c.emit(Opcode::If, register, 0, 0);
+            for (i, column) in self.columns.iter().enumerate() {
+                c.column_value(*column, saved + i as i64);
+            }
+            c.patch(skip);
+            // the rows that the FILTER clause of the min() or max() call
+            // leaves out do not set the register, so only the first of them
+            // is saved before the call takes a value
+            let filtered = matches!(
+                self.min_max,
+                Some(Expr::Function {
+                    filter: Some(_),
+                    ..
+                })
+            );
+            if self.min_max.is_none() || filtered {
+                c.emit(Opcode::Integer, 1, register, 0);
+            }
+        }
+        Ok(())
+    }
+
+    /// End the group: compute the values of the calls, and make the saved
+    /// columns the values of the columns.
+    fn finish(&self, c: &mut Compiler, accumulators: i64, saved: i64) {
+        for (i, call) in self.calls.iter().enumerate() {
+            let Expr::Function { name, args, .. } = call else {
+                unreachable!("aggregate calls are functions");
+            };
+            let p4 = P4::Function(name.clone(), args.len());
+            c.emit_p4(
+                Opcode::AggFinal,
+                accumulators + i as i64,
+                args.len() as i64,
+                0,
+                p4,
+            );
+        }
+        c.saved = Some(
+            self.columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| (*column, saved + i as i64))
+                .collect(),
+        );
+    }
+}
+
+impl Compiler<'_, '_> {
+    /// Compile a query without aggregates: its rows are output in the loop,
+    /// or collected in a sorter and output after it. Returns the jumps to
+    /// the end of the program.
+    fn simple_query(
+        &mut self,
+        source: Option<&(TableSource, Access)>,
+        select: &Select,
+        exprs: &[ExprRef],
+    ) -> Result<Vec<usize>, anyhow::Error> {
+        let mut end = vec![];
+        self.open_output(select, exprs, &mut end)?;
+        // in a sorted query LIMIT and OFFSET apply when reading the sorter
+        let limit = self.limit_registers(select, &mut end)?;
+
+        let scan = self.begin_loop(source)?;
+        let mut next = vec![];
+        if let Some(cond) = &select.body.where_clause {
+            self.jump_if_false(cond, &mut next)?;
+        }
+        self.result_row(select, exprs, limit, &mut next, &mut end)?;
+        self.end_loop(source, scan, next);
+        self.sorted_output(select, exprs, limit, &mut end);
+        Ok(end)
+    }
+
+    /// Compile a query with aggregate functions or GROUP BY. Without GROUP
+    /// BY it outputs one row after the loop. With it, the rows are sorted
+    /// into groups after the loop, and the row of each group is output once
+    /// the rows of the next one start.
+    fn aggregate_query(
+        &mut self,
+        source: Option<&(TableSource, Access)>,
+        select: &Select,
+        exprs: &[ExprRef],
+    ) -> Result<Vec<usize>, anyhow::Error> {
+        let core = &select.body;
+        let mut end = vec![];
+        self.open_output(select, exprs, &mut end)?;
+        let limit = self.limit_registers(select, &mut end)?;
+
+        // the aggregate calls of the result columns, ORDER BY and HAVING,
+        // and the columns of the row that they use outside of them
+        let outputs = exprs
+            .iter()
+            .map(|e| &**e)
+            .chain(select.order_by.iter().map(|t| &t.expr))
+            .chain(&core.having)
+            .collect::<Vec<_>>();
+        let mut calls: Vec<&Expr> = vec![];
+        let mut references = vec![];
+        for e in &outputs {
+            e.walk(&mut |e| match e {
+                Expr::Function {
+                    name, args, over, ..
+                } if over.is_none() && is_aggregate(name, args.len()) => calls.push(e),
+                Expr::Column { .. } => references.push(e),
+                _ => {}
+            });
+        }
+        let mut in_calls = HashSet::new();
+        for call in &calls {
+            call.walk(&mut |e| {
+                in_calls.insert(e as *const Expr as usize);
+            });
+        }
+        let (mut columns, mut inputs) = (vec![], vec![]);
+        for e in references {
+            if let Expr::Column { table, name } = e {
+                if let Some(i) = self.find_column(table.as_deref(), name) {
+                    inputs.push(i);
+                    if !in_calls.contains(&(e as *const Expr as usize)) {
+                        columns.push(i);
+                    }
+                }
+            }
+        }
+        for columns in [&mut columns, &mut inputs] {
+            columns.sort_unstable();
+            columns.dedup();
+        }
+        // as in SQLite, the columns are saved from the last row a min() or
+        // max() call took its value from, or else from the first row
+        let min_max = last_min_max(outputs.iter().copied());
+        let group = Group {
+            calls,
+            columns,
+            min_max,
+        };
+
+        let group_by = group_by_terms(&core.group_by, exprs)?;
+        if group_by.is_empty() {
+            let (accumulators, saved, skip_saving) = group.registers(self);
+            group.reset(self, accumulators, skip_saving);
+            let scan = self.begin_loop(source)?;
+            let mut next = vec![];
+            if let Some(cond) = &core.where_clause {
+                self.jump_if_false(cond, &mut next)?;
+            }
+            group.step(self, accumulators, saved, skip_saving)?;
+            self.end_loop(source, scan, next);
+            let mut skip = vec![];
+            group.finish(self, accumulators, saved);
+            self.group_row(select, exprs, limit, &mut skip, &mut end)?;
+            end.extend(skip);
+            self.sorted_output(select, exprs, limit, &mut end);
+            return Ok(end);
+        }
+
+        // the rows are sorted by their GROUP BY values, followed by those
+        // values again and the columns the query uses, which the sorter
+        // gives back
+        let width = group_by.len() as i64;
+        let mut keys = vec![];
+        for (_, collated) in &group_by {
+            keys.push(self.key_info(collated, false, None)?);
+        }
+        self.emit_p4(Opcode::SorterOpen, GROUPER, width, 0, P4::KeyInfo(keys));
+        let scan = self.begin_loop(source)?;
+        let mut next = vec![];
+        if let Some(cond) = &core.where_clause {
+            self.jump_if_false(cond, &mut next)?;
+        }
+        let count = width + inputs.len() as i64;
+        let base = self.alloc(width + count);
+        for (i, (expr, _)) in group_by.iter().enumerate() {
+            self.expr(expr, base + i as i64)?;
+            self.emit(Opcode::Copy, base + i as i64, base + width + i as i64, 0);
+        }
+        for (i, column) in inputs.iter().enumerate() {
+            self.column(*column, base + 2 * width + i as i64);
+        }
+        self.emit(Opcode::SorterInsert, GROUPER, base, width + count);
+        self.end_loop(source, scan, next);
+
+        let row = self.alloc(count);
+        let previous = self.alloc(width);
+        let (accumulators, saved, skip_saving) = group.registers(self);
+        let (started, back) = (self.alloc(1), self.alloc(1));
+        let sort = self.emit(Opcode::SorterSort, GROUPER, 0, 0);
+        self.emit(Opcode::Integer, 0, started, 0);
+        let top = self.here();
+        self.emit(Opcode::SorterData, GROUPER, row, count);
+        // a row whose values differ from those before starts a new group,
+        // after the row of the one before is output
+        let first = self.emit(Opcode::IfNot, started, 0, 0);
+        let mut changed = vec![];
+        for (i, (_, collated)) in group_by.iter().enumerate() {
+            let collation = (
+                self.collation_name(collated),
+                self.executor.collation_or_binary(collated, &self.env)?,
+            );
+            let (l, r) = (row + i as i64, previous + i as i64);
+            changed.push(self.compare_with(Opcode::Ne, l, r, None, collation, NULL_EQ));
+        }
+        let same = self.emit(Opcode::Goto, 0, 0, 0);
+        for jump in changed {
+            self.patch(jump);
+        }
+        let output = self.emit(Opcode::Gosub, back, 0, 0);
+        self.patch(first);
+        group.reset(self, accumulators, skip_saving);
+        for i in 0..width {
+            self.emit(Opcode::Copy, row + i, previous + i, 0);
+        }
+        self.emit(Opcode::Integer, 1, started, 0);
+        self.patch(same);
+        self.saved = Some(
+            inputs
+                .iter()
+                .enumerate()
+                .map(|(i, column)| (*column, row + width + i as i64))
+                .collect(),
+        );
+        group.step(self, accumulators, saved, skip_saving)?;
+        self.emit(Opcode::SorterNext, GROUPER, top as i64, 0);
+        let last = self.emit(Opcode::Gosub, back, 0, 0);
+        let done = self.emit(Opcode::Goto, 0, 0, 0);
+
+        // the subroutine outputting the row of a group
+        self.patch(output);
+        self.patch(last);
+        let mut skip = vec![];
+        group.finish(self, accumulators, saved);
+        self.group_row(select, exprs, limit, &mut skip, &mut end)?;
+        for jump in skip {
+            self.patch(jump);
+        }
+        self.emit(Opcode::Return, back, 0, 0);
+        self.patch(done);
+        self.patch(sort);
+        self.sorted_output(select, exprs, limit, &mut end);
+        Ok(end)
+    }
+
+    /// The result row of a group, unless HAVING leaves it out.
+    fn group_row(
+        &mut self,
+        select: &Select,
+        exprs: &[ExprRef],
+        limit: Option<i64>,
+        next: &mut Vec<usize>,
+        end: &mut Vec<usize>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(having) = &select.body.having {
+            self.jump_if_false(having, next)?;
+        }
+        self.result_row(select, exprs, limit, next, end)
+    }
+
+    /// Open the sorter for ORDER BY and the set for DISTINCT, if the query
+    /// has them.
+    fn open_output(
+        &mut self,
+        select: &Select,
+        exprs: &[ExprRef],
+        end: &mut Vec<usize>,
+    ) -> Result<(), anyhow::Error> {
+        if select.limit.as_ref().and_then(constant_limit) == Some(0) {
+            end.push(self.emit(Opcode::Goto, 0, 0, 0));
+        }
+        if !select.order_by.is_empty() {
+            let keys = self.sort_keys(&select.order_by, exprs)?;
+            let count = keys.len() as i64;
+            self.emit_p4(Opcode::SorterOpen, SORTER, count, 0, P4::KeyInfo(keys));
+        }
+        if select.body.distinct {
+            // rows are told apart under the collations of the columns
+            let mut keys = vec![];
+            for e in exprs {
+                keys.push(self.key_info(e, false, None)?);
+            }
+            let count = keys.len() as i64;
+            self.emit_p4(Opcode::OpenEphemeral, DISTINCT, count, 0, P4::KeyInfo(keys));
+        }
+        Ok(())
+    }
+
+    /// Compute a result row, and output it, or add it to the sorter of
+    /// ORDER BY; a row DISTINCT has seen before goes to one of `next`.
+    fn result_row(
+        &mut self,
+        select: &Select,
+        exprs: &[ExprRef],
+        limit: Option<i64>,
+        next: &mut Vec<usize>,
+        end: &mut Vec<usize>,
+    ) -> Result<(), anyhow::Error> {
+        let order_by = &select.order_by;
+        let width = exprs.len() as i64;
+        // the sort keys, then the result values
+        let keys = order_by.len() as i64;
+        let base = self.alloc(keys + width);
+        let out = base + keys;
+        for (i, e) in exprs.iter().enumerate() {
+            self.expr(e, out + i as i64)?;
+        }
+        if select.body.distinct {
+            let count = P4::Int32(width as i32);
+            next.push(self.emit_p4(Opcode::Found, DISTINCT, 0, out, count));
+            self.emit(Opcode::IdxInsert, DISTINCT, out, width);
+        }
+        if order_by.is_empty() {
+            self.output(out, width, limit, next, end);
+            return Ok(());
+        }
+        for (i, term) in order_by.iter().enumerate() {
+            let key = base + i as i64;
+            match order_by_position(i, &term.expr, exprs.len())? {
+                Some(column) => {
+                    self.emit(Opcode::Copy, out + column as i64, key, 0);
+                }
+                None => self.expr(&term.expr, key)?,
+            }
+        }
+        self.emit(Opcode::SorterInsert, SORTER, base, keys + width);
+        Ok(())
+    }
+
+    /// Output the rows of the sorter of ORDER BY, if the query has one.
+    fn sorted_output(
+        &mut self,
+        select: &Select,
+        exprs: &[ExprRef],
+        limit: Option<i64>,
+        end: &mut Vec<usize>,
+    ) {
+        if select.order_by.is_empty() {
+            return;
+        }
+        let width = exprs.len() as i64;
+        let sort = self.emit(Opcode::SorterSort, SORTER, 0, 0);
+        let top = self.here();
+        let out = self.alloc(width);
+        self.emit(Opcode::SorterData, SORTER, out, width);
+        let mut next = vec![];
+        self.output(out, width, limit, &mut next, end);
+        for jump in next {
+            self.patch(jump);
+        }
+        self.emit(Opcode::SorterNext, SORTER, top as i64, 0);
+        self.patch(sort);
+    }
+
+    fn aggregate_step(&mut self, call: &Expr, accumulator: i64) -> Result<(), anyhow::Error> {
+        let Expr::Function {
+            name,
+            args,
+            distinct,
+            star,
+            filter,
+            ..
+        } = call
+        else {
+            unreachable!("aggregate calls are functions");
+        };
+        if *distinct {
+            not_compiled!("DISTINCT aggregates are not compiled to bytecode yet");
+        }
+        // the rows the FILTER clause leaves out skip the step
+        let mut skip = vec![];
+        if let Some(filter) = filter {
+            self.jump_if_false(filter, &mut skip)?;
+        }
+        if *star || (name == "count" && args.is_empty()) {
+            let p4 = P4::Function(name.clone(), 0);
+            self.emit_p4(Opcode::AggStep, 0, 0, accumulator, p4);
+        } else {
+            let [arg] = &args[..] else {
+                not_compiled!("aggregates of several arguments are not compiled to bytecode yet");
+            };
+            let register = self.alloc(1);
+            self.expr(arg, register)?;
+            let collation = self.executor.collation(arg, &self.env)?;
+            let (collation_name, collation) = match collation {
+                Some((collation, _)) => (self.collation_name(arg), collation),
+                None => ("BINARY".to_string(), Collation::Binary),
+            };
+            let hit = match self.min_max {
+                Some((id, hit)) if id == call as *const Expr as usize => hit,
+                _ => 0,
+            };
+            self.emit_p4(
+                Opcode::CollSeq,
+                hit,
+                0,
+                0,
+                P4::Collation(collation_name, collation),
+            );
+            let p4 = P4::Function(name.clone(), 1);
+            let step = self.emit_p4(Opcode::AggStep, 0, register, accumulator, p4);
+            self.program.insns[step].p5 = 1;
+        }
+        for jump in skip {
+            self.patch(jump);
+        }
+        Ok(())
+    }
+
+    /// Registers for LIMIT and OFFSET, if they limit anything; the LIMIT
+    /// register is followed by the OFFSET one. A LIMIT or OFFSET that is
+    /// not an integer literal is computed, as in SQLite, before the table
+    /// is read, and a LIMIT that comes to 0 ends the program at once.
+    fn limit_registers(
+        &mut self,
+        select: &Select,
+        end: &mut Vec<usize>,
+    ) -> Result<Option<i64>, anyhow::Error> {
+        // a negative LIMIT is no limit, and a negative OFFSET none
+        let limit = select
+            .limit
+            .as_ref()
+            .filter(|e| constant_limit(e).map_or(true, |n| n >= 0));
+        let offset = select
+            .offset
+            .as_ref()
+            .filter(|e| constant_limit(e).map_or(true, |n| n > 0));
+        if limit.is_none() && offset.is_none() {
+            return Ok(None);
+        }
+        let registers = self.alloc(2);
+        // like the select, they cannot refer to its columns
+        let env = self.env;
+        self.env = Env {
+            columns: &[],
+            aliases: &[],
+            ..env
+        };
+        let loaded = self
+            .limit_value(limit, -1, registers)
+            .and_then(|()| self.limit_value(offset, 0, registers + 1));
+        self.env = env;
+        loaded?;
+        if limit.is_some_and(|e| constant_limit(e).is_none()) {
+            end.push(self.emit(Opcode::IfNot, registers, 0, 0));
+        }
+        Ok(Some(registers))
+    }
+
+    /// Put the value of a LIMIT or OFFSET, or else `default`, in `target`.
+    fn limit_value(
+        &mut self,
+        expr: Option<&Expr>,
+        default: i64,
+        target: i64,
+    ) -> Result<(), anyhow::Error> {
+        match expr {
+            None => self.load_integer(default, target),
+            Some(e) => match constant_limit(e) {
+                Some(n) => self.load_integer(n, target),
+                None => {
+                    self.expr(e, target)?;
+                    self.emit(Opcode::MustBeInt, target, 0, 0);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Output a result row, after skipping the rows of OFFSET, and stop
+    /// after the rows of LIMIT.
+    fn output(
+        &mut self,
+        out: i64,
+        width: i64,
+        limit: Option<i64>,
+        next: &mut Vec<usize>,
+        end: &mut Vec<usize>,
+    ) {
+        if let Some(limit) = limit {
+            next.push(self.emit(Opcode::IfPos, limit + 1, 0, 1));
+        }
+        self.emit(Opcode::ResultRow, out, width, 0);
+        if let Some(limit) = limit {
+            end.push(self.emit(Opcode::DecrJumpZero, limit, 0, 0));
+        }
+    }
+
+    /// Open the cursors and start the loop over the rows of the table; a
+    /// select without FROM has a single row.
+    fn begin_loop(
+        &mut self,
+        source: Option<&(TableSource, Access)>,
+    ) -> Result<Loop, anyhow::Error> {
+        let Some((source, access)) = source else {
+            return Ok(Loop {
+                top: self.here(),
+                ..Loop::default()
+            });
+        };
+        let width = source.is_rowid.len() as i32;
+        if self.covering.is_none() {
+            // a WITHOUT ROWID table is found by its primary key
+            let p4 = match source.layout {
+                Some(_) => P4::KeyInfo(self.primary_key_keys(source)?),
+                None => P4::Int32(width),
+            };
+            self.emit_p4(Opcode::OpenRead, TABLE, source.root_page as i64, 0, p4);
+        }
+        match access {
+            Access::RowidEq(key) => {
+                // the row of each distinct rowid, in order
+                let mut scan = Loop::default();
+                let register = self.alloc(1);
+                let binary = KeyInfo {
+                    collation_name: None,
+                    collation: Collation::Binary,
+                    desc: false,
+                    nulls_first: None,
+                };
+                if let Some(cursor) = self.key_values(key, register, binary, KEYS, &mut scan)? {
+                    self.key_loop(cursor, register, (None, Collation::Binary), &mut scan);
+                }
+                scan.top = self.here();
+                scan.exits
+                    .push(self.emit(Opcode::SeekRowid, TABLE, 0, register));
+                Ok(scan)
+            }
+            Access::RowidRange { lower, upper } => {
+                // seek to the lower bound, or start at the first row, and
+                // stop past the upper bound
+                let start = match lower {
+                    Some((key, inclusive)) => {
+                        let register = self.alloc(1);
+                        self.expr(key.values[0], register)?;
+                        let opcode = if *inclusive {
+                            Opcode::SeekGE
+                        } else {
+                            Opcode::SeekGT
+                        };
+                        self.emit(opcode, TABLE, 0, register)
+                    }
+                    None => self.emit(Opcode::Rewind, TABLE, 0, 0),
+                };
+                let mut exits = vec![start];
+                let end = match upper {
+                    Some((key, inclusive)) => {
+                        let register = self.alloc(1);
+                        self.expr(key.values[0], register)?;
+                        Some((register, key.affinity, *inclusive))
+                    }
+                    None => None,
+                };
+                let top = self.here();
+                if let Some((register, affinity, inclusive)) = end {
+                    let row_id = self.alloc(1);
+                    self.emit(Opcode::Rowid, TABLE, row_id, 0);
+                    let opcode = if inclusive { Opcode::Gt } else { Opcode::Ge };
+                    let binary = ("BINARY".to_string(), Collation::Binary);
+                    exits.push(self.compare_with(
+                        opcode,
+                        row_id,
+                        register,
+                        affinity,
+                        binary,
+                        JUMP_IF_NULL,
+                    ));
+                }
+                Ok(Loop {
+                    top,
+                    exits,
+                    cursor: Some(TABLE),
+                    ..Loop::default()
+                })
+            }
+            Access::IndexSeek(seek) => self.index_seek(source, seek),
+            Access::MultiIndexOr(_) => not_compiled!("OR lookups are not compiled to bytecode yet"),
+            Access::Scan if source.layout.is_some() => {
+                let keys = self.primary_key_keys(source)?;
+                let root = source.root_page as i64;
+                self.emit_p4(Opcode::OpenRead, INDEX, root, 0, P4::KeyInfo(keys));
+                let rewind = self.emit(Opcode::Rewind, INDEX, 0, 0);
+                Ok(Loop {
+                    top: self.here(),
+                    exits: vec![rewind],
+                    cursor: Some(INDEX),
+                    ..Loop::default()
+                })
+            }
+            Access::Scan => {
+                let rewind = self.emit(Opcode::Rewind, TABLE, 0, 0);
+                Ok(Loop {
+                    top: self.here(),
+                    exits: vec![rewind],
+                    cursor: Some(TABLE),
+                    ..Loop::default()
+                })
+            }
+            Access::IndexScan(scan) => {
+                let keys = self.index_keys(source, scan.index)?;
+                let root = scan.index.root_page as i64;
+                self.emit_p4(Opcode::OpenRead, INDEX, root, 0, P4::KeyInfo(keys));
+                let rewind = self.emit(Opcode::Rewind, INDEX, 0, 0);
+                Ok(Loop {
+                    top: self.here(),
+                    exits: vec![rewind],
+                    cursor: Some(INDEX),
+                    ..Loop::default()
+                })
+            }
+            Access::IndexRange(range) => {
+                // the text between the bounds, then the blobs between them
+                let (Ok(low), Ok(high)) = (
+                    String::from_utf8(range.low.clone()),
+                    String::from_utf8(range.high.clone()),
+                ) else {
+                    not_compiled!(
+                        "index ranges that are not UTF-8 are not compiled to bytecode yet"
+                    );
+                };
+                let keys = self.index_keys(source, range.index)?;
+                let root = range.index.root_page as i64;
+                self.emit_p4(Opcode::OpenRead, INDEX, root, 0, P4::KeyInfo(keys));
+                let passes = self.alloc(1);
+                self.emit(Opcode::Integer, 1, passes, 0);
+                let bounds = self.alloc(2);
+                self.emit_p4(Opcode::String8, 0, bounds, 0, P4::Text(low));
+                self.emit_p4(Opcode::String8, 0, bounds + 1, 0, P4::Text(high));
+                let seek = self.emit_p4(Opcode::SeekGE, INDEX, 0, bounds, P4::Int32(1));
+                let top = self.here();
+                let past = self.emit_p4(Opcode::IdxGE, INDEX, 0, bounds + 1, P4::Int32(1));
+                // a covering index has all the columns, the rest need the row
+                let missing = match self.covering {
+                    None => self.seek_row(source, range.index)?,
+                    Some(_) => vec![],
+                };
+                Ok(Loop {
+                    top,
+                    exits: vec![seek, past],
+                    second_pass: Some((passes, bounds, range.low.clone(), range.high.clone())),
+                    cursor: Some(INDEX),
+                    seek: missing,
+                    ..Loop::default()
+                })
+            }
+        }
+    }
+
+    /// Start the loop over the entries of an index that a seek finds: for
+    /// each combination of the values of its `eq` keys, those between its
+    /// bounds. As in the tree walker, a NULL key or bound finds nothing,
+    /// and NULL is within no bound.
+    fn index_seek(
+        &mut self,
+        source: &TableSource,
+        seek: &IndexSeek,
+    ) -> Result<Loop, anyhow::Error> {
+        let keys = self.index_keys(source, seek.index)?;
+        let root = seek.index.root_page as i64;
+        self.emit_p4(Opcode::OpenRead, INDEX, root, 0, P4::KeyInfo(keys.clone()));
+        let mut scan = Loop {
+            cursor: Some(INDEX),
+            ..Loop::default()
+        };
+
+        // the key sought starts with the values of the `eq` keys, which
+        // those of `IN (...)` take in turn
+        let n = seek.eq.len();
+        let start = self.alloc(n as i64 + 1);
+        let mut loops = vec![];
+        for (i, key) in seek.eq.iter().enumerate() {
+            let cursor = KEYS + loops.len() as i64;
+            let target = start + i as i64;
+            if let Some(cursor) =
+                self.key_values(key, target, keys[i].clone(), cursor, &mut scan)?
+            {
+                loops.push((cursor, target, i));
+            }
+        }
+
+        // the bound the entries start from and the one they end at, in the
+        // order of the index
+        let ranged = seek.lower.is_some() || seek.upper.is_some();
+        let desc = seek.desc.get(n).copied().unwrap_or_default();
+        let (first, last) = match desc {
+            true => (&seek.upper, &seek.lower),
+            false => (&seek.lower, &seek.upper),
+        };
+        if let Some((key, _)) = first {
+            self.bound(key, start + n as i64, &mut scan)?;
+        }
+        if first.is_none() && ranged && !desc {
+            // past the NULLs, which come first
+            self.emit(Opcode::Null, 0, start + n as i64, 0);
+        }
+        // a descending range ends before the NULLs, which come last
+        let end = (last.is_some() || (ranged && desc)).then(|| self.alloc(n as i64 + 1));
+        if let Some(end) = end {
+            match last {
+                Some((key, _)) => self.bound(key, end + n as i64, &mut scan)?,
+                None => {
+                    self.emit(Opcode::Null, 0, end + n as i64, 0);
+                }
+            }
+        }
+
+        for (cursor, target, i) in loops {
+            let collation = (keys[i].collation_name.clone(), keys[i].collation.clone());
+            self.key_loop(cursor, target, collation, &mut scan);
+        }
+        if let Some(end) = end {
+            for i in 0..n as i64 {
+                self.emit(Opcode::Copy, start + i, end + i, 0);
+            }
+        }
+        let key = |count: usize| P4::Int32(count as i32);
+        let found = match first {
+            Some((_, true)) => self.emit_p4(Opcode::SeekGE, INDEX, 0, start, key(n + 1)),
+            Some((_, false)) => self.emit_p4(Opcode::SeekGT, INDEX, 0, start, key(n + 1)),
+            None if ranged && !desc => self.emit_p4(Opcode::SeekGT, INDEX, 0, start, key(n + 1)),
+            None if n == 0 => self.emit(Opcode::Rewind, INDEX, 0, 0),
+            None => self.emit_p4(Opcode::SeekGE, INDEX, 0, start, key(n)),
+        };
+        scan.exits.push(found);
+        scan.top = self.here();
+        let past = match (last, end) {
+            (Some((_, true)), Some(end)) => {
+                Some(self.emit_p4(Opcode::IdxGT, INDEX, 0, end, key(n + 1)))
+            }
+            (_, Some(end)) => Some(self.emit_p4(Opcode::IdxGE, INDEX, 0, end, key(n + 1))),
+            (_, None) if n > 0 => Some(self.emit_p4(Opcode::IdxGT, INDEX, 0, start, key(n))),
+            (_, None) => None,
+        };
+        scan.exits.extend(past);
+        if self.covering.is_none() {
+            scan.seek = self.seek_row(source, seek.index)?;
+        }
+        Ok(scan)
+    }
+
+    /// Put the value of a bound of a seek in `target`, with its affinity.
+    fn bound(&mut self, key: &SeekKey, target: i64, scan: &mut Loop) -> Result<(), anyhow::Error> {
+        self.expr(key.values[0], target)?;
+        self.affinity(key.affinity, target);
+        scan.done.push(self.emit(Opcode::IsNull, target, 0, 0));
+        Ok(())
+    }
+
+    /// Put the value of a lookup key in `target`, with its affinity. A key
+    /// of several values has them added to a sorter opened as `cursor`, in
+    /// the order of `order`, and returns it for [`Self::key_loop`] to take
+    /// them in turn. NULLs find nothing.
+    fn key_values(
+        &mut self,
+        key: &SeekKey,
+        target: i64,
+        order: KeyInfo,
+        cursor: i64,
+        scan: &mut Loop,
+    ) -> Result<Option<i64>, anyhow::Error> {
+        if let [value] = &key.values[..] {
+            self.expr(value, target)?;
+            self.affinity(key.affinity, target);
+            scan.done.push(self.emit(Opcode::IsNull, target, 0, 0));
+            return Ok(None);
+        }
+        self.emit_p4(Opcode::SorterOpen, cursor, 1, 0, P4::KeyInfo(vec![order]));
+        // the value, as its sort key and then its value
+        let value = self.alloc(2);
+        for e in &key.values {
+            self.expr(e, value)?;
+            self.affinity(key.affinity, value);
+            let null = self.emit(Opcode::IsNull, value, 0, 0);
+            self.emit(Opcode::Copy, value, value + 1, 0);
+            self.emit(Opcode::SorterInsert, cursor, value, 2);
+            self.patch(null);
+        }
+        Ok(Some(cursor))
+    }
+
+    /// Start the loop over the values [`Self::key_values`] added to the
+    /// sorter `cursor`, putting each in `target` and skipping those equal
+    /// to the one before under `collation`.
+    fn key_loop(
+        &mut self,
+        cursor: i64,
+        target: i64,
+        (name, collation): (Option<String>, Collation),
+        scan: &mut Loop,
+    ) {
+        scan.done.push(self.emit(Opcode::SorterSort, cursor, 0, 0));
+        let previous = self.alloc(1);
+        self.emit(Opcode::Null, 0, previous, 0);
+        let top = self.here();
+        self.emit(Opcode::SorterData, cursor, target, 1);
+        let name = name.unwrap_or_else(|| "BINARY".to_string());
+        let repeated = self.compare_with(Opcode::Eq, target, previous, None, (name, collation), 0);
+        self.emit(Opcode::Copy, target, previous, 0);
+        scan.keys.push(KeyLoop {
+            cursor,
+            top,
+            repeated,
+        });
+    }
+
+    /// Apply the affinity of a comparison to the value in `target`.
+    fn affinity(&mut self, affinity: Option<Affinity>, target: i64) {
+        if let Some(affinity) = affinity {
+            let code = vdbe::affinity_code(affinity) as u8 as char;
+            self.emit_p4(Opcode::Affinity, target, 1, 0, P4::Text(code.to_string()));
+        }
+    }
+
+    /// Move the table cursor to the row of the entry of the index cursor,
+    /// returning the jumps taken if it is missing. The entries of the
+    /// indexes of a WITHOUT ROWID table end with its primary key.
+    fn seek_row(
+        &mut self,
+        source: &TableSource,
+        index: &TableInfo,
+    ) -> Result<Vec<usize>, anyhow::Error> {
+        let (Some(layout), Some(table)) = (&source.layout, source.table) else {
+            let row_id = self.alloc(1);
+            self.emit(Opcode::IdxRowid, INDEX, row_id, 0);
+            return Ok(vec![self.emit(Opcode::SeekRowid, TABLE, 0, row_id)]);
+        };
+        let entry = planner::index_columns(&table.table()?, index).unwrap_or_default();
+        let count = layout.key.len() as i64;
+        let key = self.alloc(count);
+        let stored = self.stored;
+        for (i, column) in stored[..layout.key.len()].iter().enumerate() {
+            let Some(at) = entry.iter().position(|c| c == column) else {
+                bail!("index {} has entries without the primary key", index.name);
+            };
+            self.emit(Opcode::Column, INDEX, at as i64, key + i as i64);
+        }
+        let count = P4::Int32(count as i32);
+        Ok(vec![
+            self.emit_p4(Opcode::SeekGE, TABLE, 0, key, count.clone()),
+            self.emit_p4(Opcode::IdxGT, TABLE, 0, key, count),
+        ])
+    }
+
+    /// The key order of the primary key of a WITHOUT ROWID table, whose
+    /// b-tree holds the rows.
+    fn primary_key_keys(&self, source: &TableSource) -> Result<Vec<KeyInfo>, anyhow::Error> {
+        let Some(table) = source.table else {
+            bail!("only a table has a primary key");
+        };
+        let Some(primary_key) = self.executor.db.primary_key(&table.name) else {
+            bail!("no primary key on {}", table.name);
+        };
+        self.index_keys(source, primary_key)
+    }
+
+    /// The key order of the entries of an index of the table: that of its
+    /// columns, then the rowid, or the rest of the primary key of a WITHOUT
+    /// ROWID table. As in SQLite, BINARY is left unnamed.
+    fn index_keys(
+        &self,
+        source: &TableSource,
+        index: &TableInfo,
+    ) -> Result<Vec<KeyInfo>, anyhow::Error> {
+        let Some(table) = source.table else {
+            bail!("no index on {}", index.table_name);
+        };
+        let schema = table.table()?;
+        let columns = &schema.columns;
+        let definition = index.index()?;
+        let rest = schema.primary_key.iter().filter(|k| {
+            schema.without_rowid
+                && !definition
+                    .columns
+                    .iter()
+                    .any(|c| c.name.eq_ignore_ascii_case(&k.name))
+        });
+        let mut keys = vec![];
+        for indexed in definition.columns.iter().chain(rest) {
+            let declared = columns
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(&indexed.name))
+                .and_then(|c| c.collation.as_ref());
+            let name = indexed.collation.as_ref().or(declared);
+            let collation = match name {
+                Some(name) => self.executor.collations.find(name)?,
+                None => Collation::Binary,
+            };
+            keys.push(KeyInfo {
+                collation_name: name
+                    .filter(|name| !name.eq_ignore_ascii_case("BINARY"))
+                    .map(|name| name.to_ascii_uppercase()),
+                collation,
+                desc: indexed.desc,
+                nulls_first: None,
+            });
+        }
+        if !schema.without_rowid {
+            keys.push(KeyInfo {
+                collation_name: None,
+                collation: Collation::Binary,
+                desc: false,
+                nulls_first: None,
+            });
+        }
+        Ok(keys)
+    }
+
+    /// End the loop over the rows; `next` are the jumps to the next row.
+    fn end_loop(&mut self, source: Option<&(TableSource, Access)>, scan: Loop, next: Vec<usize>) {
+        for jump in next {
+            self.patch(jump);
+        }
+        if source.is_none() {
+            return;
+        }
+        for jump in scan.seek {
+            self.patch(jump);
+        }
+        if let Some(cursor) = scan.cursor {
+            self.emit(Opcode::Next, cursor, scan.top as i64, 0);
+        }
+        for jump in scan.exits {
+            self.patch(jump);
+        }
+        // the next value of each key, from the innermost
+        for key in scan.keys.into_iter().rev() {
+            self.patch(key.repeated);
+            self.emit(Opcode::SorterNext, key.cursor, key.top as i64, 0);
+        }
+        if let Some((passes, bounds, low, high)) = scan.second_pass {
+            let done = self.emit(Opcode::IfNot, passes, 0, 0);
+            self.emit(Opcode::Integer, 0, passes, 0);
+            self.emit_p4(Opcode::Blob, low.len() as i64, bounds, 0, P4::Blob(low));
+            self.emit_p4(
+                Opcode::Blob,
+                high.len() as i64,
+                bounds + 1,
+                0,
+                P4::Blob(high),
+            );
+            // back to the seek, which is just before the top of the loop
+            self.emit(Opcode::Goto, 0, scan.top as i64 - 1, 0);
+            self.patch(done);
+        }
+        for jump in scan.done {
+            self.patch(jump);
+        }
+    }
+
+    /// The key order of the sorter: that of the ORDER BY terms, with the
+    /// collations run_core() gives them.
+    fn sort_keys(
+        &mut self,
+        order_by: &[OrderingTerm],
+        exprs: &[ExprRef],
+    ) -> Result<Vec<KeyInfo>, anyhow::Error> {
+        let mut keys = vec![];
+        for (i, term) in order_by.iter().enumerate() {
+            let expr = match order_by_position(i, &term.expr, exprs.len())? {
+                Some(i) => match &term.expr {
+                    Expr::Collate { .. } => &term.expr,
+                    _ => &exprs[i],
+                },
+                None => &term.expr,
+            };
+            keys.push(self.key_info(expr, term.desc, term.nulls_first)?);
+        }
+        Ok(keys)
+    }
+
+    /// The key order of a sorter or set for the values of `expr`, with its
+    /// collation.
+    fn key_info(
+        &self,
+        expr: &Expr,
+        desc: bool,
+        nulls_first: Option<bool>,
+    ) -> Result<KeyInfo, anyhow::Error> {
+        let collation = self.executor.collation(expr, &self.env)?;
+        Ok(KeyInfo {
+            collation_name: collation.as_ref().map(|_| self.collation_name(expr)),
+            collation: collation.map(|(c, _)| c).unwrap_or_default(),
+            desc,
+            nulls_first,
+        })
+    }
+
+    /// The name of the collation of an expression, for listings.
+    fn collation_name(&self, expr: &Expr) -> String {
+        let mut name = None;
+        expr.walk(&mut |e| {
+            if let Expr::Collate { collation, .. } = e {
+                name.get_or_insert_with(|| collation.to_ascii_uppercase());
+            }
+        });
+        if let Some(name) = name {
+            return name;
+        }
+        match self.executor.collation(expr, &self.env) {
+            Ok(Some((Collation::NoCase, _))) => "NOCASE",
+            Ok(Some((Collation::RTrim, _))) => "RTRIM",
+            Ok(Some((Collation::Custom(_), _))) => "CUSTOM",
+            _ => "BINARY",
+        }
+        .to_string()
+    }
+
+    /// Jump to one of `jumps`, patched later, unless `expr` is true.
+    fn jump_if_false(&mut self, expr: &Expr, jumps: &mut Vec<usize>) -> Result<(), anyhow::Error> {
+        match expr {
+            Expr::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } => {
+                self.jump_if_false(left, jumps)?;
+                self.jump_if_false(right, jumps)
+            }
+            Expr::Binary { op, left, right } if comparison(*op).is_some() => {
+                // jump when the opposite comparison holds, or on NULL
+                let (opcode, null_eq) = comparison(negate(*op)).unwrap();
+                let (l, r) = (self.alloc(1), self.alloc(1));
+                self.expr(left, l)?;
+                self.expr(right, r)?;
+                let flags = if null_eq { NULL_EQ } else { JUMP_IF_NULL };
+                jumps.push(self.compare(opcode, left, right, l, r, flags)?);
+                Ok(())
+            }
+            _ => {
+                let register = self.alloc(1);
+                self.expr(expr, register)?;
+                jumps.push(self.emit(Opcode::IfNot, register, 0, 1));
+                Ok(())
+            }
+        }
+    }
+
+    /// Emit a comparison of the values of `left` and `right` in registers
+    /// `l` and `r`, jumping when it holds, with the affinity and collation
+    /// it takes from the expressions. Returns its address.
+    fn compare(
+        &mut self,
+        opcode: Opcode,
+        left: &Expr,
+        right: &Expr,
+        l: i64,
+        r: i64,
+        flags: u16,
+    ) -> Result<usize, anyhow::Error> {
+        let affinity = Affinity::for_comparison(
+            self.executor.affinity(left, &self.env),
+            self.executor.affinity(right, &self.env),
+        );
+        // an explicit COLLATE on either side wins over a column's collation
+        let collation = match (
+            self.executor.collation(left, &self.env)?,
+            self.executor.collation(right, &self.env)?,
+        ) {
+            (Some((c, true)), _) => (self.collation_name(left), c),
+            (_, Some((c, true))) => (self.collation_name(right), c),
+            (Some((c, _)), _) => (self.collation_name(left), c),
+            (_, Some((c, _))) => (self.collation_name(right), c),
+            _ => ("BINARY".to_string(), Collation::Binary),
+        };
+        Ok(self.compare_with(opcode, l, r, affinity, collation, flags))
+    }
+
+    fn compare_with(
+        &mut self,
+        opcode: Opcode,
+        l: i64,
+        r: i64,
+        affinity: Option<Affinity>,
+        (name, collation): (String, Collation),
+        flags: u16,
+    ) -> usize {
+        let at = self.emit_p4(opcode, r, 0, l, P4::Collation(name, collation));
+        self.program.insns[at].p5 = affinity.map_or(0, vdbe::affinity_code) | flags;
+        at
+    }
+
+    /// Emit the instructions putting the value of `expr` in `target`.
+    fn expr(&mut self, expr: &Expr, target: i64) -> Result<(), anyhow::Error> {
+        match expr {
+            Expr::Literal(v) => self.load(v, target),
+            Expr::Column { table, name } => match self.find_column(table.as_deref(), name) {
+                Some(i) => match &self.saved {
+                    Some(saved) => {
+                        let Some(register) = saved.get(&i) else {
+                            not_compiled!("column {name} is not available after the loop");
+                        };
+                        self.emit(Opcode::Copy, *register, target, 0);
+                    }
+                    None => self.column(i, target),
+                },
+                None => {
+                    let alias = match table {
+                        None if self.aliases => self
+                            .env
+                            .aliases
+                            .iter()
+                            .find(|(a, _)| a.eq_ignore_ascii_case(name))
+                            .map(|(_, e)| *e),
+                        _ => None,
+                    };
+                    let Some(alias) = alias else {
+                        match table {
+                            Some(t) => bail!("no such column: {t}.{name}"),
+                            None => bail!("no such column: {name}"),
+                        }
+                    };
+                    self.aliases = false;
+                    let result = self.expr(alias, target);
+                    self.aliases = true;
+                    result?;
+                }
+            },
+            Expr::Parameter(i) => {
+                self.emit(Opcode::Variable, *i as i64, target, 0);
+            }
+            Expr::Unary { op, expr } => match op {
+                UnaryOp::Plus => self.expr(expr, target)?,
+                UnaryOp::Neg => {
+                    let operand = self.alloc(1);
+                    self.expr(expr, operand)?;
+                    let zero = self.alloc(1);
+                    self.emit(Opcode::Integer, 0, zero, 0);
+                    self.emit(Opcode::Subtract, operand, zero, target);
+                }
+                UnaryOp::Not | UnaryOp::BitNot => {
+                    let operand = self.alloc(1);
+                    self.expr(expr, operand)?;
+                    let opcode = match op {
+                        UnaryOp::Not => Opcode::Not,
+                        _ => Opcode::BitNot,
+                    };
+                    self.emit(opcode, operand, target, 0);
+                }
+            },
+            Expr::Binary { op, left, right } => self.binary(*op, left, right, target)?,
+            Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => {
+                let (v, bounds) = (self.alloc(1), self.alloc(2));
+                self.expr(expr, v)?;
+                self.expr(low, bounds)?;
+                self.expr(high, bounds + 1)?;
+                let results = self.alloc(2);
+                for (i, (bound, opcode)) in [(low, Opcode::Ge), (high, Opcode::Le)]
+                    .into_iter()
+                    .enumerate()
+                {
+                    let result = results + i as i64;
+                    self.emit(Opcode::Integer, 1, result, 0);
+                    let at = self.compare(opcode, expr, bound, v, bounds + i as i64, 0)?;
+                    self.program.insns[at].p2 = at as i64 + 2;
+                    self.emit(Opcode::ZeroOrNull, v, result, bounds + i as i64);
+                }
+                self.emit(Opcode::And, results, results + 1, target);
+                if *negated {
+                    self.emit(Opcode::Not, target, target, 0);
+                }
+            }
+            Expr::InList {
+                expr,
+                negated,
+                list,
+            } => self.in_list(expr, *negated, list, target)?,
+            Expr::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                let value = match operand {
+                    Some(operand) => {
+                        let register = self.alloc(1);
+                        self.expr(operand, register)?;
+                        Some((operand, register))
+                    }
+                    None => None,
+                };
+                let mut end = vec![];
+                for (when, then) in branches {
+                    let register = self.alloc(1);
+                    self.expr(when, register)?;
+                    let skip = match value {
+                        // `CASE x WHEN y` compares like `x = y`
+                        Some((operand, v)) => {
+                            let matched =
+                                self.compare(Opcode::Eq, operand, when, v, register, 0)?;
+                            let skip = self.emit(Opcode::Goto, 0, 0, 0);
+                            self.patch(matched);
+                            skip
+                        }
+                        None => self.emit(Opcode::IfNot, register, 0, 1),
+                    };
+                    self.expr(then, target)?;
+                    end.push(self.emit(Opcode::Goto, 0, 0, 0));
+                    self.patch(skip);
+                }
+                match else_result {
+                    Some(e) => self.expr(e, target)?,
+                    None => {
+                        self.emit(Opcode::Null, 0, target, 0);
+                    }
+                }
+                for jump in end {
+                    self.patch(jump);
+                }
+            }
+            Expr::Collate { expr, .. } => self.expr(expr, target)?,
+            Expr::Cast { expr, type_name } => {
+                self.expr(expr, target)?;
+                let affinity = vdbe::affinity_code(Affinity::from_type_name(type_name));
+                self.emit(Opcode::Cast, target, affinity as i64, 0);
+            }
+            Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. } => {
+                not_compiled!("subqueries are not compiled to bytecode yet")
+            }
+            Expr::Function {
+                name,
+                args,
+                filter,
+                over,
+                ..
+            } => {
+                if over.is_some() || WINDOW_FUNCTIONS.contains(&name.as_str()) {
+                    not_compiled!("window functions are not compiled to bytecode yet");
+                }
+                if is_aggregate(name, args.len()) {
+                    let id = expr as *const Expr as usize;
+                    let Some((_, accumulator)) = self
+                        .accumulators
+                        .iter()
+                        .find(|(call, _)| *call == id)
+                        .copied()
+                    else {
+                        bail!("misuse of aggregate function {name}()");
+                    };
+                    self.emit(Opcode::Copy, accumulator, target, 0);
+                    return Ok(());
+                }
+                if filter.is_some() {
+                    bail!("FILTER may not be used with non-aggregate {name}()");
+                }
+                functions::check_arity(name, args.len())?;
+                self.function(name, args, target)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, name: &str, args: &[Expr], target: i64) -> Result<(), anyhow::Error> {
+        match name {
+            // coalesce(), ifnull() and iif() only evaluate the arguments they need
+            "coalesce" | "ifnull" => {
+                let mut end = vec![];
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        end.push(self.emit(Opcode::NotNull, target, 0, 0));
+                    }
+                    self.expr(arg, target)?;
+                }
+                for jump in end {
+                    self.patch(jump);
+                }
+            }
+            "iif" => {
+                let condition = self.alloc(1);
+                self.expr(&args[0], condition)?;
+                let otherwise = self.emit(Opcode::IfNot, condition, 0, 1);
+                self.expr(&args[1], target)?;
+                let end = self.emit(Opcode::Goto, 0, 0, 0);
+                self.patch(otherwise);
+                match args.get(2) {
+                    Some(e) => self.expr(e, target)?,
+                    None => {
+                        self.emit(Opcode::Null, 0, target, 0);
+                    }
+                }
+                self.patch(end);
+            }
+            _ => {
+                let first = self.alloc(args.len() as i64);
+                for (i, arg) in args.iter().enumerate() {
+                    self.expr(arg, first + i as i64)?;
+                }
+                let p4 = P4::Function(name.to_string(), args.len());
+                self.emit_p4(Opcode::Function, 0, first, target, p4);
+            }
+        }
+        Ok(())
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        target: i64,
+    ) -> Result<(), anyhow::Error> {
+        let (l, r) = (self.alloc(1), self.alloc(1));
+        self.expr(left, l)?;
+        match op {
+            // AND and OR only evaluate the right operand if they need to
+            BinaryOp::And | BinaryOp::Or => {
+                let (decided, value) = match op {
+                    BinaryOp::And => (Opcode::IfNot, 0),
+                    _ => (Opcode::If, 1),
+                };
+                let short = self.emit(decided, l, 0, 0);
+                self.expr(right, r)?;
+                let opcode = match op {
+                    BinaryOp::And => Opcode::And,
+                    _ => Opcode::Or,
+                };
+                self.emit(opcode, l, r, target);
+                let end = self.emit(Opcode::Goto, 0, 0, 0);
+                self.patch(short);
+                self.emit(Opcode::Integer, value, target, 0);
+                self.patch(end);
+                return Ok(());
+            }
+            _ => self.expr(right, r)?,
+        }
+
+        if let Some((opcode, null_eq)) = comparison(op) {
+            // 1 if the comparison holds, else 0, or NULL for unknown
+            self.emit(Opcode::Integer, 1, target, 0);
+            let flags = if null_eq { NULL_EQ } else { 0 };
+            let at = self.compare(opcode, left, right, l, r, flags)?;
+            self.program.insns[at].p2 = at as i64 + 2;
+            if null_eq {
+                self.emit(Opcode::Integer, 0, target, 0);
+            } else {
+                self.emit(Opcode::ZeroOrNull, l, target, r);
+            }
+            return Ok(());
+        }
+
+        let opcode = match op {
+            BinaryOp::Add => Opcode::Add,
+            BinaryOp::Sub => Opcode::Subtract,
+            BinaryOp::Mul => Opcode::Multiply,
+            BinaryOp::Div => Opcode::Divide,
+            BinaryOp::Rem => Opcode::Remainder,
+            BinaryOp::Concat => Opcode::Concat,
+            BinaryOp::BitAnd => Opcode::BitAnd,
+            BinaryOp::BitOr => Opcode::BitOr,
+            BinaryOp::ShiftLeft => Opcode::ShiftLeft,
+            BinaryOp::ShiftRight => Opcode::ShiftRight,
+            _ => unreachable!("comparisons, AND and OR are handled above"),
+        };
+        self.emit(opcode, r, l, target);
+        Ok(())
+    }
+
+    /// `expr IN (list)`: true if a value of the list equals the left
+    /// operand, else NULL if a comparison was unknown, else false.
+    fn in_list(
+        &mut self,
+        expr: &Expr,
+        negated: bool,
+        list: &[Expr],
+        target: i64,
+    ) -> Result<(), anyhow::Error> {
+        if list.is_empty() {
+            self.emit(Opcode::Integer, negated as i64, target, 0);
+            return Ok(());
+        }
+        // the values of the list have no affinity of their own, and are
+        // compared with the collation of the left operand
+        let affinity = self.executor.affinity(expr, &self.env);
+        let collation = (
+            self.collation_name(expr),
+            self.executor.collation_or_binary(expr, &self.env)?,
+        );
+        let (v, item, saw_null) = (self.alloc(1), self.alloc(1), self.alloc(1));
+        self.expr(expr, v)?;
+        let null = self.emit(Opcode::IsNull, v, 0, 0);
+        self.emit(Opcode::Integer, 0, saw_null, 0);
+        let mut found = vec![];
+        for e in list {
+            self.expr(e, item)?;
+            found.push(self.compare_with(Opcode::Eq, v, item, affinity, collation.clone(), 0));
+            let not_null = self.emit(Opcode::NotNull, item, 0, 0);
+            self.emit(Opcode::Integer, 1, saw_null, 0);
+            self.patch(not_null);
+        }
+        let unknown = self.emit(Opcode::If, saw_null, 0, 0);
+        self.emit(Opcode::Integer, negated as i64, target, 0);
+        let end = self.emit(Opcode::Goto, 0, 0, 0);
+        for jump in found {
+            self.patch(jump);
+        }
+        self.emit(Opcode::Integer, !negated as i64, target, 0);
+        let end2 = self.emit(Opcode::Goto, 0, 0, 0);
+        self.patch(null);
+        self.patch(unknown);
+        self.emit(Opcode::Null, 0, target, 0);
+        self.patch(end);
+        self.patch(end2);
+        Ok(())
+    }
+
+    /// The index of the column a reference names, as lookup() finds it.
+    fn find_column(&self, table: Option<&str>, name: &str) -> Option<usize> {
+        let columns = self.env.columns;
+        columns
+            .iter()
+            .position(|c| !c.hidden && c.matches(table, name))
+            .or_else(|| columns.iter().position(|c| c.matches(table, name)))
+    }
+
+    /// Read column `i` of the table, where the one after the table's
+    /// columns is the rowid.
+    fn column(&mut self, i: usize, target: i64) {
+        let is_rowid = self.is_rowid.get(i).copied().unwrap_or(true);
+        match &self.covering {
+            // the rowid ends every index entry
+            Some(_) if is_rowid => {
+                self.emit(Opcode::IdxRowid, INDEX, target, 0);
+                return;
+            }
+            Some(covering) => {
+                let k = covering.iter().position(|&c| c == i).unwrap_or_default();
+                let default = self.defaults.get(i).cloned().unwrap_or_default();
+                self.emit_p4(Opcode::Column, INDEX, k as i64, target, default);
+            }
+            None if is_rowid => {
+                self.emit(Opcode::Rowid, TABLE, target, 0);
+                return;
+            }
+            None => {
+                let field = self.stored.iter().position(|&c| c == i).unwrap_or(i);
+                let default = self.defaults.get(i).cloned().unwrap_or_default();
+                self.emit_p4(Opcode::Column, TABLE, field as i64, target, default);
+            }
+        }
+        if self.env.columns[i].affinity == Some(Affinity::Real) {
+            self.emit(Opcode::RealAffinity, target, 0, 0);
+        }
+    }
+
+    /// Put the value of column `i` in `target`, from the register it is
+    /// saved in, if it is.
+    fn column_value(&mut self, i: usize, target: i64) {
+        match self.saved.as_ref().and_then(|saved| saved.get(&i)) {
+            Some(&register) => {
+                self.emit(Opcode::Copy, register, target, 0);
+            }
+            None => self.column(i, target),
+        }
+    }
+
+    fn load(&mut self, v: &ColumnType, target: i64) {
+        match v {
+            ColumnType::Null => {
+                self.emit(Opcode::Null, 0, target, 0);
+            }
+            ColumnType::Float(x) => {
+                self.emit_p4(Opcode::Real, 0, target, 0, P4::Real(*x));
+            }
+            ColumnType::String(s) => {
+                self.emit_p4(Opcode::String8, 0, target, 0, P4::Text(s.clone()));
+            }
+            ColumnType::Blob(b) => {
+                self.emit_p4(Opcode::Blob, b.len() as i64, target, 0, P4::Blob(b.clone()));
+            }
+            v => self.load_integer(v.as_integer().unwrap_or_default(), target),
+        }
+    }
+
+    fn load_integer(&mut self, n: i64, target: i64) {
+        match i32::try_from(n) {
+            Ok(n) => self.emit(Opcode::Integer, n as i64, target, 0),
+            Err(_) => self.emit_p4(Opcode::Int64, 0, target, 0, P4::Int64(n)),
+        };
+    }
+
+    /// Allocate `n` consecutive registers, returning the first.
+    fn alloc(&mut self, n: i64) -> i64 {
+        let first = self.program.registers as i64 + 1;
+        self.program.registers += n as usize;
+        first
+    }
+
+    fn here(&self) -> usize {
+        self.program.insns.len()
+    }
+
+    fn emit(&mut self, opcode: Opcode, p1: i64, p2: i64, p3: i64) -> usize {
+        self.emit_p4(opcode, p1, p2, p3, P4::None)
+    }
+
+    fn emit_p4(&mut self, opcode: Opcode, p1: i64, p2: i64, p3: i64, p4: P4) -> usize {
+        if matches!(
+            opcode,
+            Opcode::OpenRead | Opcode::SorterOpen | Opcode::OpenEphemeral
+        ) {
+            self.program.cursors = self.program.cursors.max(p1 as usize + 1);
+        }
+        self.program.insns.push(vdbe::Insn {
+            opcode,
+            p1,
+            p2,
+            p3,
+            p4,
+            p5: 0,
+        });
+        self.program.insns.len() - 1
+    }
+
+    /// Make the jump at `at` go to the next instruction emitted.
+    fn patch(&mut self, at: usize) {
+        self.program.insns[at].p2 = self.here() as i64;
+    }
+}
+
+/// The opcode of a comparison operator, and whether NULLs compare equal.
+fn comparison(op: BinaryOp) -> Option<(Opcode, bool)> {
+    Some(match op {
+        BinaryOp::Eq => (Opcode::Eq, false),
+        BinaryOp::NotEq => (Opcode::Ne, false),
+        BinaryOp::Lt => (Opcode::Lt, false),
+        BinaryOp::LtEq => (Opcode::Le, false),
+        BinaryOp::Gt => (Opcode::Gt, false),
+        BinaryOp::GtEq => (Opcode::Ge, false),
+        BinaryOp::Is => (Opcode::Eq, true),
+        BinaryOp::IsNot => (Opcode::Ne, true),
+        _ => return None,
+    })
+}
+
+/// The comparison that holds exactly when `op` does not, for non-NULL
+/// operands.
+fn negate(op: BinaryOp) -> BinaryOp {
+    match op {
+        BinaryOp::Eq => BinaryOp::NotEq,
+        BinaryOp::NotEq => BinaryOp::Eq,
+        BinaryOp::Lt => BinaryOp::GtEq,
+        BinaryOp::LtEq => BinaryOp::Gt,
+        BinaryOp::Gt => BinaryOp::LtEq,
+        BinaryOp::GtEq => BinaryOp::Lt,
+        BinaryOp::Is => BinaryOp::IsNot,
+        BinaryOp::IsNot => BinaryOp::Is,
+        op => op,
+    }
+}
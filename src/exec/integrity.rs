@@ -0,0 +1,391 @@
+//! `PRAGMA integrity_check` and `quick_check`. The structure of the file is
+//! checked by [`crate::integrity`]; then the rows of each table are checked
+//! against the constraints of its columns, and, unless the check is quick,
+//! against the entries of its indexes.
+//! See https://www.sqlite.org/pragma.html#pragma_integrity_check
+
+use super::pragma::index_fields;
+use super::*;
+use crate::integrity;
+
+/// A table to check, with its indexes: whether each is UNIQUE and partial.
+struct Checked<'d> {
+    /// None for `sqlite_schema`.
+    table: Option<&'d TableInfo>,
+    indexes: Vec<(&'d TableInfo, bool, bool)>,
+}
+
+/// An index prepared for looking up the entry of each row.
+struct IndexCheck<'d> {
+    index: &'d TableInfo,
+    unique: bool,
+    /// The WHERE clause of a partial index.
+    partial: Option<Expr>,
+    /// The column of the row each field of an entry holds, None for the
+    /// rowid, with its collation and whether it is in descending order.
+    fields: Vec<(Option<usize>, Collation, bool)>,
+    /// How many of the fields are indexed columns.
+    keys: usize,
+    entries: Vec<Row>,
+}
+
+impl IndexCheck<'_> {
+    /// How an entry compares with another in index order, over the first
+    /// `n` fields of `key`; an entry lacking some of those is less.
+    fn compare(&self, entry: &Row, key: &[ColumnType], n: usize) -> Ordering {
+        for ((a, b), (_, collation, desc)) in entry.iter().zip(key).zip(&self.fields).take(n) {
+            let o = collation.compare(a, b);
+            if o != Ordering::Equal {
+                return if *desc { o.reverse() } else { o };
+            }
+        }
+        entry.len().min(n).cmp(&n)
+    }
+}
+
+impl<'d> Executor<'d> {
+    /// The rows of `PRAGMA integrity_check(arg)`, or of `quick_check`. A
+    /// number is the most problems to report, anything else the name of the
+    /// one table to check. With no problems the one row is "ok".
+    pub(super) fn integrity_check(
+        &self,
+        quick: bool,
+        arg: Option<&str>,
+    ) -> Result<Vec<Row>, anyhow::Error> {
+        let (max_errors, only) = match arg.map(|a| (a, a.parse::<i32>())) {
+            Some((_, Ok(n))) if n > 0 => (n as usize, None),
+            Some((_, Ok(_))) | None => (100, None),
+            Some((name, Err(_))) => (100, Some(name)),
+        };
+
+        // in the order SQLite keeps its tables in, with the schema itself
+        let listed = self
+            .db
+            .schema
+            .iter()
+            .filter(|t| t.typ == "table" || t.typ == "view")
+            .collect::<Vec<_>>();
+        let names = ["sqlite_master"]
+            .into_iter()
+            .chain(listed.iter().map(|t| t.name.as_str()))
+            .collect::<Vec<_>>();
+        let mut tables = hash_order(&names)
+            .into_iter()
+            .map(|i| i.checked_sub(1).map(|i| listed[i]))
+            .filter(|t| t.map_or(true, |t| t.typ == "table" && t.root_page != 0))
+            .collect::<Vec<_>>();
+        if let Some(name) = only {
+            tables.retain(|t| match t {
+                Some(t) => t.name.eq_ignore_ascii_case(name),
+                None => is_schema_table(name),
+            });
+            if tables.is_empty() {
+                bail!("no such table: {name}");
+            }
+        }
+        let mut checked = vec![];
+        let mut roots = vec![];
+        for table in tables {
+            let mut indexes = vec![];
+            roots.push(table.map_or(1, |t| t.root_page));
+            if let Some(table) = table {
+                // the primary key of a WITHOUT ROWID table holds its rows
+                for (index, unique, _, partial) in self.table_indexes(&table.table()?) {
+                    if !index.primary_key {
+                        indexes.push((index, unique, partial));
+                        roots.push(index.root_page);
+                    }
+                }
+            }
+            checked.push(Checked { table, indexes });
+        }
+
+        let report = integrity::check(self.db, &roots, max_errors, only.is_none());
+        let mut rows = vec![];
+        if !report.errors.is_empty() {
+            let errors = report.errors.join("\n");
+            rows.push(vec![ColumnType::String(format!(
+                "*** in database main ***\n{errors}"
+            ))]);
+        }
+        let limit = max_errors - report.errors.len();
+        let mut messages = vec![];
+
+        // each index has an entry for each row, unless it is partial
+        let mut first = 0;
+        for table in &checked {
+            for (k, (index, _, partial)) in table.indexes.iter().enumerate() {
+                if !partial && report.entries[first + 1 + k] != report.entries[first] {
+                    messages.push(format!("wrong # of entries in index {}", index.name));
+                }
+            }
+            first += 1 + table.indexes.len();
+        }
+        messages.truncate(limit);
+
+        // the rows of a table whose b-tree is damaged are not checked, nor
+        // are they looked up in a damaged index
+        let mut first = 0;
+        for table in &checked {
+            if messages.len() >= limit {
+                break;
+            }
+            let damaged = &report.damaged[first..first + 1 + table.indexes.len()];
+            first += damaged.len();
+            if !damaged[0] {
+                self.check_rows(table, &damaged[1..], quick, &mut messages, limit)?;
+            }
+        }
+
+        rows.extend(messages.into_iter().map(|m| vec![ColumnType::String(m)]));
+        if rows.is_empty() {
+            rows.push(vec![ColumnType::String("ok".to_string())]);
+        }
+        Ok(rows)
+    }
+
+    /// Check each row of a table: that its columns hold values their
+    /// constraints and affinities allow, and unless `quick`, that the rows
+    /// of a WITHOUT ROWID table are in order and each has its entry in the
+    /// indexes that are not `damaged`. Stops once there are `limit`
+    /// messages.
+    ///
+    /// A table or index that cannot be read is passed over; other errors,
+    /// such as an unknown collation, are errors of the check.
+    fn check_rows(
+        &self,
+        checked: &Checked<'d>,
+        damaged: &[bool],
+        quick: bool,
+        messages: &mut Vec<String>,
+        limit: usize,
+    ) -> Result<(), anyhow::Error> {
+        let name = checked.table.map_or("sqlite_master", |t| t.name.as_str());
+        let source = self.table_source(name, name)?;
+        let schema = checked.table.map(|t| t.table()).transpose()?;
+        let constant = Env::new(None);
+        let Ok(rows) = self.read_table(&source, &Access::Scan, &constant) else {
+            return Ok(());
+        };
+
+        let mut indexes = vec![];
+        for (&(index, unique, _), damaged) in checked.indexes.iter().zip(damaged) {
+            let Some(schema) = schema.as_ref().filter(|_| !quick) else {
+                break;
+            };
+            if *damaged {
+                continue;
+            }
+            let Some(fields) = index_fields(index, schema)? else {
+                continue;
+            };
+            let keys = fields.iter().filter(|f| f.key).count();
+            // an indexed expression has no column to look up the entry by
+            if fields[..keys].iter().any(|f| f.cid.is_none()) {
+                continue;
+            }
+            let fields = fields
+                .iter()
+                .map(|f| Ok((f.cid, self.collations.find(&f.collation)?, f.desc)))
+                .collect::<Result<Vec<_>, anyhow::Error>>()?;
+            let partial = match index.sql.is_empty() {
+                true => None,
+                false => index.index()?.where_clause,
+            };
+            let mut entries = vec![];
+            let scan = btree::scan_index(self.db, index.root_page, &|_| false, &mut |entry| {
+                entries.push(entry);
+                Ok(true)
+            });
+            if scan.is_err() {
+                continue;
+            }
+            indexes.push(IndexCheck {
+                index,
+                unique,
+                partial,
+                fields,
+                keys,
+                entries,
+            });
+        }
+
+        let not_null = |i: usize| schema.as_ref().is_some_and(|s| s.columns[i].not_null);
+        let is_virtual = |i: usize| schema.as_ref().is_some_and(|s| s.columns[i].is_virtual());
+        let mut previous_key: Option<Row> = None;
+        for (n, row) in rows.iter().enumerate() {
+            let mut found = vec![];
+            if let (Some(layout), false) = (&source.layout, quick) {
+                let key = source.stored[..layout.key.len()]
+                    .iter()
+                    .map(|&i| row[i].clone())
+                    .collect::<Row>();
+                if previous_key
+                    .as_ref()
+                    .is_some_and(|p| layout.compare_key(p, &key) != Ordering::Less)
+                {
+                    found.push(format!("row not in PRIMARY KEY order for {name}"));
+                }
+                previous_key = Some(key);
+            }
+
+            for (i, column) in source.columns[..source.is_rowid.len()].iter().enumerate() {
+                if source.is_rowid[i] || is_virtual(i) {
+                    continue;
+                }
+                let value = &row[i];
+                let numeric = !matches!(
+                    value,
+                    ColumnType::Null | ColumnType::String(_) | ColumnType::Blob(_)
+                );
+                let problem = match column.affinity {
+                    _ if value.is_null() => not_null(i).then_some("NULL"),
+                    Some(Affinity::Text) => numeric.then_some("NUMERIC"),
+                    Some(Affinity::Blob) | None => None,
+                    // text that would have been stored as a number
+                    Some(_) => match value {
+                        ColumnType::String(_) => {
+                            let converted = value.apply_affinity(Affinity::Numeric);
+                            (!matches!(converted, ColumnType::String(_))).then_some("TEXT")
+                        }
+                        _ => None,
+                    },
+                };
+                if let Some(problem) = problem {
+                    found.push(format!("{problem} value in {name}.{}", column.name));
+                }
+            }
+
+            let env = Env {
+                columns: &source.columns,
+                row,
+                ..constant
+            };
+            for check in &indexes {
+                if let Some(cond) = &check.partial {
+                    if self.eval(cond, &env)?.truthy() != Some(true) {
+                        continue;
+                    }
+                }
+                if let Some(problem) = check_entry(check, row, n + 1, &not_null) {
+                    found.push(problem);
+                }
+            }
+
+            for message in found {
+                if messages.len() >= limit {
+                    return Ok(());
+                }
+                messages.push(message);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The order of SQLite's hash table of the tables of a schema, into which
+/// `names` are put in turn: the indexes of the names as the table lists
+/// them. The list keeps the names of each bucket together, newest first,
+/// and a name starting a bucket goes to the front; the buckets are made
+/// anew as the table grows. See hash.c in the SQLite sources.
+pub(super) fn hash_order(names: &[&str]) -> Vec<usize> {
+    // case-insensitive for ASCII letters
+    let hash = |name: &str| {
+        name.bytes().fold(0u32, |h, c| {
+            h.wrapping_add((c & 0xdf) as u32).wrapping_mul(0x9e3779b1)
+        }) as usize
+    };
+    // each bucket's count and newest name
+    fn insert(list: &mut Vec<usize>, buckets: &mut [(usize, usize)], bucket: usize, i: usize) {
+        let head = buckets.get_mut(bucket).and_then(|(count, newest)| {
+            let head = (*count > 0).then_some(*newest);
+            *count += 1;
+            *newest = i;
+            head
+        });
+        let at = head.and_then(|h| list.iter().position(|&j| j == h));
+        list.insert(at.unwrap_or(0), i);
+    }
+    let mut list = vec![];
+    let mut buckets: Vec<(usize, usize)> = vec![];
+    for (i, name) in names.iter().enumerate() {
+        let count = i + 1;
+        // the buckets take at most 1024 bytes, SQLITE_MALLOC_SOFT_LIMIT
+        let size = (count * 3).min(64);
+        if count >= 5 && count > 2 * buckets.len() && size != buckets.len() {
+            buckets = vec![(0, 0); size];
+            for j in std::mem::take(&mut list) {
+                insert(&mut list, &mut buckets, hash(names[j]) % size, j);
+            }
+        }
+        let bucket = match buckets.len() {
+            0 => usize::MAX,
+            n => hash(name) % n,
+        };
+        insert(&mut list, &mut buckets, bucket, i);
+    }
+    list
+}
+
+/// The problem with the entry of the `n`th row in an index, if any: that
+/// there is none, that it has more fields than it should, that its values
+/// differ from the row's in a way the index's collations hide, or that it
+/// has the key of another entry of a UNIQUE index.
+fn check_entry(
+    check: &IndexCheck,
+    row: &Row,
+    n: usize,
+    not_null: &dyn Fn(usize) -> bool,
+) -> Option<String> {
+    let name = &check.index.name;
+    // the rowid is the last column of a row
+    let expected = check
+        .fields
+        .iter()
+        .map(|(cid, _, _)| cid.map_or_else(|| row[row.len() - 1].clone(), |i| row[i].clone()))
+        .collect::<Row>();
+    let len = expected.len();
+    let entries = &check.entries;
+    // a binary search that stops at the first match, like SQLite's, to
+    // find the same entry in an index whose entries are out of order
+    let (mut low, mut high) = (0, entries.len());
+    let i = loop {
+        if low == high {
+            return Some(format!("row {n} missing from index {name}"));
+        }
+        let mid = (low + high - 1) / 2;
+        match check.compare(&entries[mid], &expected, len) {
+            Ordering::Less => low = mid + 1,
+            Ordering::Greater => high = mid,
+            Ordering::Equal => break mid,
+        }
+    };
+    let entry = &entries[i];
+    let has_rowid = check.fields.last().is_some_and(|(cid, _, _)| cid.is_none());
+    if has_rowid && entry.len() != len {
+        return Some(format!(
+            "rowid not at end-of-record for row {n} of index {name}"
+        ));
+    }
+    let differs = check.fields[..check.keys]
+        .iter()
+        .zip(entry.iter().zip(&expected))
+        .any(|((_, collation, _), (a, b))| {
+            *collation != Collation::Binary && Collation::Binary.compare(a, b) != Ordering::Equal
+        });
+    if differs {
+        return Some(format!("row {n} values differ from index {name}"));
+    }
+    // NULLs are distinct from each other, so never duplicates
+    let has_null = check.fields[..check.keys]
+        .iter()
+        .zip(&expected)
+        .any(|((cid, _, _), v)| v.is_null() && cid.is_some_and(|c| !not_null(c)));
+    if check.unique && !has_null {
+        let next = entries.get(i + 1);
+        if next.is_some_and(|next| check.compare(next, entry, check.keys) != Ordering::Greater) {
+            return Some(format!("non-unique entry in index {name}"));
+        }
+    }
+    None
+}
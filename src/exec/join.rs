@@ -0,0 +1,1432 @@
+//! Reads the tables of a FROM clause: the planner picks the order of the
+//! tables and how to read each one given those read before it, and the
+//! rows are joined in nested loops.
+
+use super::*;
+use crate::planner::{conjuncts, disjuncts, log_est, Constraint, Disjunction, Plan, SeekKey};
+
+/// The most tables a FROM clause can join, as in SQLite.
+const MAX_ITEMS: usize = 64;
+
+/// The rows a table-valued function is guessed to give, as SQLite guesses
+/// for the pragma functions.
+const FUNCTION_ROWS: f64 = 20.0;
+
+/// Up to this many tables every order is tried; with more, the cheapest
+/// next table is taken at each step.
+const EXHAUSTIVE: usize = 5;
+
+/// The columns a select refers to, which tell whether an index holds all
+/// it needs of a table.
+#[derive(Default)]
+pub(super) struct ColumnRefs<'s> {
+    /// The references, with the table named, if any.
+    columns: Vec<(Option<&'s str>, &'s str)>,
+    /// The tables all of whose columns are needed, as by `*` or `t.*`;
+    /// None stands for every table.
+    all: Vec<Option<&'s str>>,
+}
+
+impl<'s> ColumnRefs<'s> {
+    /// The columns a select refers to outside of its subqueries; one with a
+    /// subquery could need any column, as a correlated subquery would.
+    pub fn of(core: &'s SelectCore, order_by: &'s [OrderingTerm]) -> Self {
+        let mut refs = ColumnRefs::default();
+        let mut exprs: Vec<&Expr> = vec![];
+        for c in &core.columns {
+            match c {
+                ResultColumn::Star => refs.all.push(None),
+                ResultColumn::TableStar(table) => refs.all.push(Some(table)),
+                ResultColumn::Expr { expr, .. } => exprs.push(expr),
+            }
+        }
+        exprs.extend(core.where_clause.iter());
+        exprs.extend(core.group_by.iter());
+        exprs.extend(core.having.iter());
+        exprs.extend(order_by.iter().map(|t| &t.expr));
+        if let Some(from) = &core.from {
+            join_conditions(from, &mut exprs);
+            using_columns(from, &mut refs.columns);
+        }
+        let mut visit = |e: &'s Expr| match e {
+            Expr::Column { table, name } => refs.columns.push((table.as_deref(), name)),
+            Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. } => refs.all.push(None),
+            _ => {}
+        };
+        for expr in exprs {
+            expr.walk(&mut visit);
+        }
+        for (_, window) in &core.windows {
+            window.walk(&mut visit);
+        }
+        refs
+    }
+
+    /// Whether each of the columns of the table known as `table` is needed.
+    fn needed(&self, table: &str, columns: &[ColumnName]) -> Vec<bool> {
+        if self
+            .all
+            .iter()
+            .any(|t| t.map_or(true, |t| t.eq_ignore_ascii_case(table)))
+        {
+            return vec![true; columns.len()];
+        }
+        columns
+            .iter()
+            .map(|c| self.columns.iter().any(|(t, name)| c.matches(*t, name)))
+            .collect()
+    }
+}
+
+/// The columns named by the USING clauses of a FROM clause.
+fn using_columns<'s>(from: &'s FromClause, columns: &mut Vec<(Option<&'s str>, &'s str)>) {
+    if let FromClause::Join {
+        left,
+        right,
+        constraint,
+        ..
+    } = from
+    {
+        using_columns(left, columns);
+        using_columns(right, columns);
+        if let Some(JoinConstraint::Using(names)) = constraint {
+            columns.extend(names.iter().map(|n| (None, n.as_str())));
+        }
+    }
+}
+
+/// The columns that `*` leaves out of the rows of a FROM clause: for each
+/// column a USING clause names, the one of the table on its right, which
+/// equals that of the tables on its left.
+pub(super) fn using_duplicates(from: &FromClause, columns: &[ColumnName]) -> Vec<bool> {
+    fn tables<'s>(from: &'s FromClause, names: &mut Vec<&'s str>) {
+        match from {
+            FromClause::Table { name, alias } | FromClause::Function { name, alias, .. } => {
+                names.push(alias.as_deref().unwrap_or(name));
+            }
+            FromClause::Subquery { alias, .. } => names.extend(alias.as_deref()),
+            FromClause::Join { left, right, .. } => {
+                tables(left, names);
+                tables(right, names);
+            }
+        }
+    }
+    fn mark(from: &FromClause, columns: &[ColumnName], skipped: &mut [bool]) {
+        let FromClause::Join {
+            left,
+            right,
+            constraint,
+            ..
+        } = from
+        else {
+            return;
+        };
+        mark(left, columns, skipped);
+        mark(right, columns, skipped);
+        let Some(JoinConstraint::Using(names)) = constraint else {
+            return;
+        };
+        let mut right_tables = vec![];
+        tables(right, &mut right_tables);
+        for name in names {
+            let found = columns.iter().enumerate().position(|(i, c)| {
+                !c.hidden
+                    && !skipped[i]
+                    && c.matches(None, name)
+                    && right_tables.iter().any(|t| {
+                        c.table
+                            .as_deref()
+                            .is_some_and(|own| own.eq_ignore_ascii_case(t))
+                    })
+            });
+            if let Some(i) = found {
+                skipped[i] = true;
+            }
+        }
+    }
+    let mut skipped = vec![false; columns.len()];
+    mark(from, columns, &mut skipped);
+    skipped
+}
+
+/// A column the rows of a FROM clause come in the order of, as the table
+/// read by its outermost loop gives them.
+pub(super) struct Ordered {
+    /// The positions among the columns of the FROM clause that hold it: a
+    /// rowid is also held by the column that is an alias for it.
+    pub columns: Vec<usize>,
+    pub collation: Collation,
+    pub desc: bool,
+    /// Whether an equality with a single value fixes it, so that it is
+    /// the same in every row.
+    pub fixed: bool,
+}
+
+/// A table, CTE or subquery of a FROM clause.
+struct Item<'s, 'd> {
+    /// The name or alias it is known by.
+    name: String,
+    /// How it is joined to the items before it.
+    kind: JoinKind,
+    constraint: Option<&'s JoinConstraint>,
+    /// Its columns are at `offset..offset + width` in the joined rows.
+    offset: usize,
+    width: usize,
+    source: Source<'s, 'd>,
+    /// For a table, whether the query refers to each of its columns.
+    needed: Vec<bool>,
+}
+
+enum Source<'s, 'd> {
+    Table(TableSource<'d>),
+    /// Rows computed before the join, and how many there are; when only
+    /// planning, just the estimate.
+    Rows(Vec<Row>, f64),
+    /// A table-valued function, called again for each row of the items its
+    /// arguments refer to. Those are in the mask `depends`, None if that is
+    /// not known.
+    Function {
+        name: &'s str,
+        args: &'s [Expr],
+        depends: Option<u64>,
+    },
+}
+
+/// The items of a FROM clause and the terms that apply to their rows.
+struct Joins<'s, 'd> {
+    items: Vec<Item<'s, 'd>>,
+    /// The columns of the joined rows: those of each item in turn.
+    columns: Vec<ColumnName>,
+    terms: Vec<Term<'s>>,
+}
+
+/// A term of the WHERE clause or of a join constraint.
+struct Term<'s> {
+    condition: Condition<'s>,
+    /// The items it refers to as a bit mask, or None if that is not known
+    /// because it has a subquery or names something other than a column of
+    /// the FROM clause or of an enclosing query.
+    items: Option<u64>,
+    /// For the ON or USING of a LEFT JOIN, the item joined: the term
+    /// decides whether a row of it matches rather than filtering rows.
+    left_join: Option<usize>,
+    /// Whether it is part of the WHERE clause, which is applied again to
+    /// the joined rows.
+    from_where: bool,
+}
+
+enum Condition<'s> {
+    Expr(&'s Expr),
+    /// The two columns of the joined rows that USING makes equal.
+    Using(usize, usize),
+}
+
+/// A table of the join, in the order it is read.
+struct Loop<'s> {
+    item: usize,
+    plan: Plan<'s>,
+    /// The terms deciding whether a row of a LEFT JOIN matches.
+    on: Vec<usize>,
+    /// The terms that can be checked once the item is read.
+    filters: Vec<usize>,
+    /// Whether the rows read do not depend on the items read before, in
+    /// which case they are read only once.
+    reusable: bool,
+    rows: Option<Vec<Row>>,
+}
+
+/// Costs and estimated rows of reading an item given the items read
+/// before it, keyed by the item and the mask of those items.
+type Estimates = HashMap<(usize, u64), (f64, f64)>;
+
+/// The search of every order of some items for the cheapest.
+struct OrderSearch<'a> {
+    /// The items to order.
+    free: &'a [usize],
+    estimates: &'a mut Estimates,
+    /// The order being tried, so far.
+    path: Vec<usize>,
+    /// The cost of the cheapest order found, and that order.
+    best: (f64, Vec<usize>),
+}
+
+/// What the terms tell about reading an item.
+struct ItemTerms<'s> {
+    /// The constraints on its columns.
+    constraints: Vec<Constraint<'s>>,
+    /// The OR terms whose sides put constraints on its columns.
+    ors: Vec<Disjunction<'s>>,
+    /// The terms concerning it alone, with their positions among the terms.
+    local: Vec<(usize, &'s Expr)>,
+}
+
+/// The sides of an OR as a single `IN` constraint, when each is an
+/// equality with the same column, compared the same way.
+fn in_list<'s>(branches: &[(&'s Expr, Vec<Constraint<'s>>)]) -> Option<Constraint<'s>> {
+    let mut merged: Option<Constraint> = None;
+    for (side, branch) in branches {
+        let (
+            [c],
+            Expr::Binary {
+                op: BinaryOp::Eq, ..
+            },
+        ) = (&branch[..], side)
+        else {
+            return None;
+        };
+        match &mut merged {
+            None => merged = Some(c.clone()),
+            Some(m) => {
+                if m.column != c.column
+                    || m.key.affinity != c.key.affinity
+                    || m.collation != c.collation
+                {
+                    return None;
+                }
+                m.key.values.extend(&c.key.values);
+                m.constant = None;
+            }
+        }
+    }
+    merged
+}
+
+fn bit(item: usize) -> u64 {
+    1 << item
+}
+
+/// The tables and subqueries of a FROM clause, left to right, each with
+/// how it is joined to the ones before it. A parenthesized join on the
+/// right of another join stays a single item.
+fn flatten<'s>(
+    from: &'s FromClause,
+    kind: JoinKind,
+    constraint: Option<&'s JoinConstraint>,
+    parts: &mut Vec<(&'s FromClause, JoinKind, Option<&'s JoinConstraint>)>,
+) {
+    match from {
+        FromClause::Join {
+            left,
+            right,
+            kind: right_kind,
+            constraint: right_constraint,
+        } => {
+            flatten(left, kind, constraint, parts);
+            parts.push((right, *right_kind, right_constraint.as_ref()));
+        }
+        from => parts.push((from, kind, constraint)),
+    }
+}
+
+/// The position of the column a reference names among `columns`, the way
+/// lookup() finds it.
+fn find_column(columns: &[ColumnName], table: Option<&str>, name: &str) -> Option<usize> {
+    columns
+        .iter()
+        .position(|c| !c.hidden && c.matches(table, name))
+        .or_else(|| columns.iter().position(|c| c.matches(table, name)))
+}
+
+/// The comparison `b op a` for `a op b`.
+fn flip(op: BinaryOp) -> BinaryOp {
+    match op {
+        BinaryOp::Lt => BinaryOp::Gt,
+        BinaryOp::LtEq => BinaryOp::GtEq,
+        BinaryOp::Gt => BinaryOp::Lt,
+        BinaryOp::GtEq => BinaryOp::LtEq,
+        op => op,
+    }
+}
+
+impl<'d> Executor<'d> {
+    /// Evaluate a FROM clause of tables, CTEs and subqueries joined
+    /// together. The terms of `where_clause` narrow down the rows read; it
+    /// still has to be applied to the joined rows.
+    pub(super) fn eval_joins(
+        &self,
+        from: &FromClause,
+        where_clause: Option<&Expr>,
+        refs: &ColumnRefs,
+        outer: Option<&Env>,
+    ) -> Result<Relation, anyhow::Error> {
+        let mut parts = vec![];
+        flatten(from, JoinKind::Inner, None, &mut parts);
+        let mut items = vec![];
+        for (part, kind, constraint) in parts {
+            let (name, columns, source) = match part {
+                FromClause::Table { name: table, alias } if find_cte(outer, table).is_none() => {
+                    let name = alias.as_deref().unwrap_or(table);
+                    let source = self.table_source(table, name)?;
+                    (
+                        name.to_string(),
+                        source.columns.clone(),
+                        Source::Table(source),
+                    )
+                }
+                FromClause::Function {
+                    name: function,
+                    args,
+                    alias,
+                } => {
+                    let name = alias.as_deref().unwrap_or(function);
+                    let columns = pragma::function_columns(function, args.len(), name)?;
+                    let source = Source::Function {
+                        name: function,
+                        args,
+                        depends: None,
+                    };
+                    (name.to_string(), columns, source)
+                }
+                _ => {
+                    let relation = self.eval_from(part, None, refs, outer)?;
+                    let count = relation.rows.len() as f64;
+                    let name = match part {
+                        FromClause::Table { name, alias }
+                        | FromClause::Function { name, alias, .. } => {
+                            alias.as_deref().unwrap_or(name)
+                        }
+                        FromClause::Subquery { alias, .. } => alias.as_deref().unwrap_or(""),
+                        FromClause::Join { .. } => "",
+                    };
+                    (
+                        name.to_string(),
+                        relation.columns,
+                        Source::Rows(relation.rows, count),
+                    )
+                }
+            };
+            let needed = refs.needed(&name, &columns);
+            items.push((
+                Item {
+                    name,
+                    kind,
+                    constraint,
+                    offset: 0,
+                    width: columns.len(),
+                    source,
+                    needed,
+                },
+                columns,
+            ));
+        }
+        let joins = self.joins(items, where_clause, outer)?;
+
+        let order = self.join_order(&joins, outer)?;
+        let mut loops = self.loops(&joins, &order, outer)?;
+        let mut rows = vec![];
+        let mut row = vec![ColumnType::Null; joins.columns.len()];
+        self.nested_loops(&joins, &mut loops, &mut row, outer, &mut rows)?;
+        Ok(Relation {
+            columns: joins.columns,
+            rows,
+        })
+    }
+
+    /// How a table named `alias` in the query is read by a select whose
+    /// FROM clause is that table alone.
+    pub(super) fn single_table<'s>(
+        &'s self,
+        name: &str,
+        alias: &str,
+        where_clause: Option<&'s Expr>,
+        refs: &ColumnRefs,
+    ) -> Result<(TableSource<'d>, Access<'s>), anyhow::Error> {
+        let source = self.table_source(name, alias)?;
+        let columns = source.columns.clone();
+        let item = Item {
+            name: alias.to_string(),
+            kind: JoinKind::Inner,
+            constraint: None,
+            offset: 0,
+            width: columns.len(),
+            needed: refs.needed(alias, &columns),
+            source: Source::Table(source),
+        };
+        let joins = self.joins(vec![(item, columns)], where_clause, None)?;
+        let (plan, _) = self.loop_plan(&joins, 0, 0, None)?;
+        let Some(Item {
+            source: Source::Table(source),
+            ..
+        }) = joins.items.into_iter().next()
+        else {
+            unreachable!("the item is a table");
+        };
+        Ok((source, plan.access))
+    }
+
+    /// Add the steps reading a FROM clause, as done by
+    /// [`Self::eval_joins`], returning the estimated number of rows it
+    /// produces and its columns.
+    pub(super) fn plan_from<'s>(
+        &self,
+        from: &'s FromClause,
+        where_clause: Option<&'s Expr>,
+        refs: &ColumnRefs,
+        parent: usize,
+        loops: f64,
+        cx: &mut PlanContext<'s>,
+    ) -> Result<(f64, Vec<ColumnName>, Vec<Ordered>), anyhow::Error> {
+        let mut parts = vec![];
+        flatten(from, JoinKind::Inner, None, &mut parts);
+        let mut items = vec![];
+        // the name each item is shown with, None for a nested join, whose
+        // tables are shown on their own
+        let mut shown = vec![];
+        for (part, kind, constraint) in parts {
+            let (name, columns, source) = match part {
+                FromClause::Table { name: table, alias }
+                    if !cx
+                        .ctes
+                        .iter()
+                        .any(|c| c.def.name.eq_ignore_ascii_case(table)) =>
+                {
+                    let name = alias.as_deref().unwrap_or(table);
+                    let source = self.table_source(table, name)?;
+                    shown.push(Some(name.to_string()));
+                    (
+                        name.to_string(),
+                        source.columns.clone(),
+                        Source::Table(source),
+                    )
+                }
+                FromClause::Table { name: table, alias } => {
+                    let i = cx
+                        .ctes
+                        .iter()
+                        .rposition(|c| c.def.name.eq_ignore_ascii_case(table))
+                        .expect("the CTE is in scope");
+                    let rows = self.plan_cte(i, parent, cx)?;
+                    let name = alias.as_deref().unwrap_or(table);
+                    shown.push(Some(name.to_string()));
+                    let columns = self.plan_columns(part, cx)?;
+                    (name.to_string(), columns, Source::Rows(vec![], rows))
+                }
+                FromClause::Subquery { select, alias } => {
+                    let name = match alias {
+                        Some(alias) => alias.clone(),
+                        None => format!("(subquery-{})", cx.subqueries + 1),
+                    };
+                    cx.subqueries += 1;
+                    let id = cx.plan.add(parent, format!("MATERIALIZE {name}"));
+                    let rows = self.plan_select(select, id, loops, cx)?;
+                    shown.push(Some(name));
+                    let columns = self.plan_columns(part, cx)?;
+                    let name = alias.clone().unwrap_or_default();
+                    (name, columns, Source::Rows(vec![], rows))
+                }
+                FromClause::Function {
+                    name: function,
+                    args,
+                    alias,
+                } => {
+                    let name = alias.as_deref().unwrap_or(function);
+                    shown.push(Some(name.to_string()));
+                    let columns = pragma::function_columns(function, args.len(), name)?;
+                    let source = Source::Function {
+                        name: function,
+                        args,
+                        depends: None,
+                    };
+                    (name.to_string(), columns, source)
+                }
+                FromClause::Join { .. } => {
+                    let (rows, columns, _) = self.plan_from(part, None, refs, parent, loops, cx)?;
+                    shown.push(None);
+                    (String::new(), columns, Source::Rows(vec![], rows))
+                }
+            };
+            let needed = refs.needed(&name, &columns);
+            items.push((
+                Item {
+                    name,
+                    kind,
+                    constraint,
+                    offset: 0,
+                    width: columns.len(),
+                    source,
+                    needed,
+                },
+                columns,
+            ));
+        }
+
+        let scopes = cx.scopes.clone();
+        with_scopes(&scopes, None, |outer| {
+            let joins = self.joins(items, where_clause, outer)?;
+            let order = self.join_order(&joins, outer)?;
+            let mut outer_rows = loops;
+            let mut bound = 0;
+            let mut scan_order = vec![];
+            for j in order {
+                let (plan, rows_out) = self.loop_plan(&joins, j, bound, outer)?;
+                // the rows of the join come in the order of its outermost loop
+                if let (0, Source::Table(source)) = (bound, &joins.items[j].source) {
+                    scan_order = self.access_order(source, &plan.access)?;
+                    for ordered in &mut scan_order {
+                        for column in &mut ordered.columns {
+                            *column += joins.items[j].offset;
+                        }
+                    }
+                }
+                bound |= bit(j);
+                let times = if outer_rows > 1.0 {
+                    format!(
+                        ", once for each of about {} of the outer query",
+                        explain::rows(outer_rows)
+                    )
+                } else {
+                    String::new()
+                };
+                let Some(name) = &shown[j] else {
+                    if let Source::Rows(_, rows) = joins.items[j].source {
+                        outer_rows *= rows;
+                    }
+                    continue;
+                };
+                let left_join = match joins.items[j].kind {
+                    JoinKind::Left => " LEFT-JOIN",
+                    _ => "",
+                };
+                // the costs are told for the rows the tables hold
+                let rows = match &joins.items[j].source {
+                    Source::Table(source) => {
+                        let table = source.table.map_or("sqlite_schema", |t| &t.name);
+                        let total = btree::count_rows(self.db, source.root_page)? as f64;
+                        let rows = plan.access.estimated_rows(total);
+                        let (index, covering) = match &plan.access {
+                            Access::Scan
+                            | Access::RowidEq(_)
+                            | Access::RowidRange { .. }
+                            | Access::MultiIndexOr(_) => ("", false),
+                            Access::IndexSeek(seek) => (&*seek.index.name, seek.covering.is_some()),
+                            Access::IndexRange(range) => {
+                                (&*range.index.name, range.covering.is_some())
+                            }
+                            Access::IndexScan(scan) => (&*scan.index.name, true),
+                        };
+                        let alone = if covering { " alone" } else { "" };
+                        let description = match &plan.access {
+                            Access::Scan => format!(
+                                "SCAN {name} reads all {} of {table}{times}",
+                                explain::rows(total)
+                            ),
+                            Access::RowidEq(_) => format!(
+                                "SEARCH {name} reads about {} of the {total} in {table} by rowid{times}",
+                                explain::rows(rows)
+                            ),
+                            Access::RowidRange { .. } => format!(
+                                "SEARCH {name} reads about {} of the {total} in {table} by rowid range{times}",
+                                explain::rows(rows)
+                            ),
+                            Access::IndexSeek(seek) if seek.index.primary_key => format!(
+                                "SEARCH {name} reads about {} of the {total} in {table} by primary key{times}",
+                                explain::rows(rows)
+                            ),
+                            Access::IndexSeek(_) | Access::IndexRange(_) => format!(
+                                "SEARCH {name} reads about {} of the {total} in {table} through index {index}{alone}{times}",
+                                explain::rows(rows)
+                            ),
+                            Access::IndexScan(_) => format!(
+                                "SCAN {name} reads all {} of {table} through index {index} alone{times}",
+                                explain::rows(total)
+                            ),
+                            Access::MultiIndexOr(branches) => format!(
+                                "SEARCH {name} reads about {} of the {total} in {table} through {} lookups, one for each side of an OR{times}",
+                                explain::rows(rows),
+                                branches.len()
+                            ),
+                        };
+                        let detail = plan.access.describe(name);
+                        let id = cx.plan.add(parent, format!("{detail}{left_join}"));
+                        if let Access::MultiIndexOr(branches) = &plan.access {
+                            for (i, (_, branch)) in branches.iter().enumerate() {
+                                let side = cx.plan.add(id, format!("INDEX {}", i + 1));
+                                cx.plan.add(side, branch.access.describe(name));
+                            }
+                        }
+                        cx.plan.add_cost(rows * outer_rows, description);
+                        rows
+                    }
+                    Source::Function { .. } => {
+                        cx.plan.add(
+                            parent,
+                            format!("SCAN {name} VIRTUAL TABLE INDEX 0:{left_join}"),
+                        );
+                        cx.plan.add_cost(
+                            FUNCTION_ROWS * outer_rows,
+                            format!(
+                                "SCAN {name} gives about {}{times}",
+                                explain::rows(FUNCTION_ROWS)
+                            ),
+                        );
+                        FUNCTION_ROWS
+                    }
+                    Source::Rows(_, rows) => {
+                        cx.plan.add(parent, format!("SCAN {name}{left_join}"));
+                        cx.plan.add_cost(
+                            rows * outer_rows,
+                            format!("SCAN {name} reads about {}{times}", explain::rows(*rows)),
+                        );
+                        *rows
+                    }
+                };
+                // the terms checked after reading the item keep some of its
+                // rows
+                outer_rows *= rows * (rows_out / plan.rows.max(1.0)).min(1.0);
+            }
+            Ok((outer_rows / loops, joins.columns, scan_order))
+        })
+    }
+
+    /// The columns of a table whose order `access` reads its rows in,
+    /// outermost first, as positions among the columns of the table.
+    fn access_order(
+        &self,
+        source: &TableSource,
+        access: &Access,
+    ) -> Result<Vec<Ordered>, anyhow::Error> {
+        let Some(table) = source.table else {
+            return Ok(vec![]);
+        };
+        let schema = table.table()?;
+        let rowid = || {
+            let mut columns = (0..source.is_rowid.len())
+                .filter(|&i| source.is_rowid[i])
+                .collect::<Vec<_>>();
+            columns.push(source.columns.len() - 1);
+            Ordered {
+                columns,
+                collation: Collation::Binary,
+                desc: false,
+                fixed: false,
+            }
+        };
+        let index_order = |index: &TableInfo, fixed: &dyn Fn(usize) -> bool| {
+            let keys = planner::index_keys(&self.collations, &schema, index)?;
+            let order = keys
+                .into_iter()
+                .enumerate()
+                .map(|(i, key)| match key.column {
+                    Some(column) => Ordered {
+                        columns: vec![column],
+                        collation: key.collation,
+                        desc: key.desc,
+                        fixed: fixed(i),
+                    },
+                    None => rowid(),
+                });
+            Ok::<_, anyhow::Error>(order.collect())
+        };
+        match access {
+            Access::Scan | Access::RowidRange { .. } if source.layout.is_none() => {
+                Ok(vec![rowid()])
+            }
+            Access::Scan => match self.db.primary_key(&table.name) {
+                Some(primary_key) => index_order(primary_key, &|_| false),
+                None => Ok(vec![]),
+            },
+            // each lookup is in index order, and they are made in index order
+            Access::IndexSeek(seek) => index_order(seek.index, &|i| {
+                seek.eq.get(i).is_some_and(|key| key.values.len() == 1)
+            }),
+            Access::IndexRange(range) => index_order(range.index, &|_| false),
+            Access::IndexScan(scan) => index_order(scan.index, &|_| false),
+            Access::RowidEq(_) | Access::RowidRange { .. } | Access::MultiIndexOr(_) => Ok(vec![]),
+        }
+    }
+
+    /// The columns of a CTE, subquery or join of a FROM clause, as known
+    /// before anything runs.
+    fn plan_columns(
+        &self,
+        part: &FromClause,
+        cx: &PlanContext,
+    ) -> Result<Vec<ColumnName>, anyhow::Error> {
+        let mut ctes = self.plan_cte_names(cx)?;
+        let mut columns = vec![];
+        self.source_columns(part, &mut columns, &mut vec![], &mut ctes)?;
+        Ok(columns)
+    }
+
+    /// Gather the items of a FROM clause, with their columns, and the terms
+    /// of the WHERE clause and the join constraints.
+    fn joins<'s>(
+        &self,
+        parts: Vec<(Item<'s, 'd>, Vec<ColumnName>)>,
+        where_clause: Option<&'s Expr>,
+        outer: Option<&Env>,
+    ) -> Result<Joins<'s, 'd>, anyhow::Error> {
+        if parts.len() > MAX_ITEMS {
+            bail!("at most {MAX_ITEMS} tables in a join");
+        }
+        let mut joins = Joins {
+            items: vec![],
+            columns: vec![],
+            terms: vec![],
+        };
+        for (mut item, columns) in parts {
+            item.offset = joins.columns.len();
+            joins.columns.extend(columns);
+            joins.items.push(item);
+        }
+
+        let mut exprs = vec![];
+        if let Some(where_clause) = where_clause {
+            let mut conjuncts = vec![];
+            planner::conjuncts(where_clause, &mut conjuncts);
+            exprs.extend(conjuncts.into_iter().map(|e| (e, None, true)));
+        }
+        for (j, item) in joins.items.iter().enumerate() {
+            let left_join = (item.kind == JoinKind::Left).then_some(j);
+            match item.constraint {
+                Some(JoinConstraint::On(on)) => {
+                    let mut conjuncts = vec![];
+                    planner::conjuncts(on, &mut conjuncts);
+                    exprs.extend(conjuncts.into_iter().map(|e| (e, left_join, false)));
+                }
+                // USING (a, b) is ON left.a = right.a AND left.b = right.b
+                Some(JoinConstraint::Using(names)) => {
+                    for name in names {
+                        let l = joins.columns[..item.offset]
+                            .iter()
+                            .position(|c| c.matches(None, name));
+                        let r = joins.columns[item.offset..item.offset + item.width]
+                            .iter()
+                            .position(|c| c.matches(None, name));
+                        let (Some(l), Some(r)) = (l, r) else {
+                            bail!(
+                                "cannot join using column {name} - column not present in both tables"
+                            );
+                        };
+                        let r = item.offset + r;
+                        let left = joins.item_of(l);
+                        joins.terms.push(Term {
+                            condition: Condition::Using(l, r),
+                            items: Some(bit(left) | bit(j)),
+                            left_join,
+                            from_where: false,
+                        });
+                    }
+                }
+                None => {}
+            }
+        }
+        // a function's arguments can refer to the items read before it
+        for j in 0..joins.items.len() {
+            let Source::Function { args, .. } = joins.items[j].source else {
+                continue;
+            };
+            let mut items = Some(0);
+            for arg in args {
+                items = items
+                    .zip(self.expr_items(&joins, arg, outer))
+                    .map(|(a, b)| a | b);
+            }
+            if let Source::Function { depends, .. } = &mut joins.items[j].source {
+                *depends = items.map(|m| m & !bit(j));
+            }
+        }
+        for (expr, left_join, from_where) in exprs {
+            let items = self.expr_items(&joins, expr, outer);
+            joins.terms.push(Term {
+                condition: Condition::Expr(expr),
+                items,
+                left_join,
+                from_where,
+            });
+        }
+
+        // the ON clause of a LEFT JOIN can only refer to the tables up to
+        // its own; that of an inner join is just more terms
+        for (j, item) in joins.items.iter().enumerate() {
+            let (JoinKind::Left, Some(JoinConstraint::On(on))) = (item.kind, item.constraint)
+            else {
+                continue;
+            };
+            let later = !(bit(j + 1) - 1);
+            if self.expr_items(&joins, on, outer).unwrap_or(0) & later != 0 {
+                bail!("ON clause references tables to its right");
+            }
+        }
+        Ok(joins)
+    }
+
+    /// The items an expression refers to, as in [`Term::items`].
+    fn expr_items(&self, joins: &Joins, expr: &Expr, outer: Option<&Env>) -> Option<u64> {
+        let mut items = Some(0);
+        expr.walk(&mut |e| match e {
+            Expr::Column { table, name } => {
+                match find_column(&joins.columns, table.as_deref(), name) {
+                    Some(i) => items = items.map(|m| m | bit(joins.item_of(i))),
+                    None => {
+                        let outside = outer.and_then(|env| {
+                            self.resolve_column(env, table.as_deref(), name, |_| true, |_, _| false)
+                        });
+                        if outside != Some(true) {
+                            items = None;
+                        }
+                    }
+                }
+            }
+            Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. } => items = None,
+            Expr::Function {
+                name, args, over, ..
+            } if over.is_some() || is_aggregate(name, args.len()) => items = None,
+            _ => {}
+        });
+        items
+    }
+
+    /// Choose the order to read the items in: the cheapest the planner
+    /// finds. The item of a LEFT or CROSS JOIN keeps its place, after all
+    /// the items before it and before all the items after it.
+    fn join_order(&self, joins: &Joins, outer: Option<&Env>) -> Result<Vec<usize>, anyhow::Error> {
+        let n = joins.items.len();
+        let mut estimates = Estimates::new();
+        let mut order = vec![];
+        let mut bound = 0;
+        let mut outer_rows = 1.0;
+        let mut start = 0;
+        while start < n {
+            let mut end = start + 1;
+            while end < n && !matches!(joins.items[end].kind, JoinKind::Left | JoinKind::Cross) {
+                end += 1;
+            }
+            let mut free = (start..end).collect::<Vec<_>>();
+            if start > 0 {
+                free.remove(0);
+                let (_, rows) = self.estimate(joins, start, bound, outer, &mut estimates)?;
+                order.push(start);
+                bound |= bit(start);
+                outer_rows *= rows;
+            }
+
+            let chosen = if free.len() <= EXHAUSTIVE {
+                let mut search = OrderSearch {
+                    free: &free,
+                    estimates: &mut estimates,
+                    path: vec![],
+                    best: (f64::INFINITY, free.clone()),
+                };
+                self.search_orders(joins, outer, &mut search, (bound, outer_rows, 0.0))?;
+                search.best.1
+            } else {
+                let mut chosen: Vec<usize> = vec![];
+                let (mut bound, mut outer_rows) = (bound, outer_rows);
+                while chosen.len() < free.len() {
+                    let mut best: Option<(f64, usize, f64)> = None;
+                    for &j in free.iter().filter(|j| !chosen.contains(j)) {
+                        let (cost, rows) = self.estimate(joins, j, bound, outer, &mut estimates)?;
+                        if best.map_or(true, |(c, _, _)| outer_rows * cost < c) {
+                            best = Some((outer_rows * cost, j, rows));
+                        }
+                    }
+                    let (_, j, rows) = best.expect("an item is left");
+                    chosen.push(j);
+                    bound |= bit(j);
+                    outer_rows *= rows;
+                }
+                chosen
+            };
+            for j in chosen {
+                let (_, rows) = self.estimate(joins, j, bound, outer, &mut estimates)?;
+                order.push(j);
+                bound |= bit(j);
+                outer_rows *= rows;
+            }
+            start = end;
+        }
+        Ok(order)
+    }
+
+    /// Try the orders of the free items that start with the search's path,
+    /// keeping the cheapest; the first found wins a tie. Costs are compared
+    /// in SQLite's LogEst units, so nearly equal ones tie. `state` is the
+    /// mask of the items read so far, the rows they produce and their cost.
+    fn search_orders(
+        &self,
+        joins: &Joins,
+        outer: Option<&Env>,
+        search: &mut OrderSearch,
+        state: (u64, f64, f64),
+    ) -> Result<(), anyhow::Error> {
+        let (bound, outer_rows, cost) = state;
+        if search.path.len() == search.free.len() {
+            if log_est(cost as u64) < log_est(search.best.0 as u64) {
+                search.best = (cost, search.path.clone());
+            }
+            return Ok(());
+        }
+        for &j in search.free {
+            if search.path.contains(&j) {
+                continue;
+            }
+            let (loop_cost, rows) = self.estimate(joins, j, bound, outer, search.estimates)?;
+            let cost = cost + outer_rows * loop_cost;
+            if log_est(cost as u64) >= log_est(search.best.0 as u64) {
+                continue;
+            }
+            search.path.push(j);
+            let state = (bound | bit(j), outer_rows * rows, cost);
+            self.search_orders(joins, outer, search, state)?;
+            search.path.pop();
+        }
+        Ok(())
+    }
+
+    fn estimate(
+        &self,
+        joins: &Joins,
+        j: usize,
+        bound: u64,
+        outer: Option<&Env>,
+        estimates: &mut Estimates,
+    ) -> Result<(f64, f64), anyhow::Error> {
+        if let Some(estimate) = estimates.get(&(j, bound)) {
+            return Ok(*estimate);
+        }
+        let (plan, rows) = self.loop_plan(joins, j, bound, outer)?;
+        estimates.insert((j, bound), (plan.cost, rows));
+        Ok((plan.cost, rows))
+    }
+
+    /// How to read item `j` after the items in the mask `bound`, with the
+    /// estimated number of its rows that pass the terms that can be checked
+    /// then.
+    fn loop_plan<'s>(
+        &'s self,
+        joins: &Joins<'s, 'd>,
+        j: usize,
+        bound: u64,
+        outer: Option<&Env>,
+    ) -> Result<(Plan<'s>, f64), anyhow::Error> {
+        let item = &joins.items[j];
+        let (plan, total) = match &item.source {
+            Source::Table(
+                source @ TableSource {
+                    table: Some(table), ..
+                },
+            ) => {
+                let terms = self.constraints(joins, j, bound, outer)?;
+                let planner = self.planner();
+                let needed = &item.needed[..source.is_rowid.len()];
+                let plan = planner.plan(
+                    table,
+                    &item.name,
+                    &terms.constraints,
+                    &terms.local,
+                    &terms.ors,
+                    needed,
+                )?;
+                (plan, planner.table_rows(table))
+            }
+            Source::Table(source) => {
+                let rows = btree::count_rows(self.db, source.root_page)? as f64;
+                (Plan::scan(rows), rows)
+            }
+            Source::Rows(_, rows) => (Plan::scan(*rows), *rows),
+            Source::Function { depends, .. } => {
+                let mut plan = Plan::scan(FUNCTION_ROWS);
+                // it is called with the values of the items its arguments
+                // refer to, so it cannot be read before them
+                if depends.is_some_and(|m| m & !bound != 0) {
+                    plan.cost = f64::INFINITY;
+                }
+                (plan, FUNCTION_ROWS)
+            }
+        };
+
+        // each term the access does not use still filters the rows, as
+        // SQLite's whereLoopOutputAdjust() guesses: a little for most, and
+        // an equality keeps at most a quarter of the table, or a half when
+        // comparing with -1, 0 or 1
+        let mut rows = plan.rows;
+        let mut reduce: f64 = 1.0;
+        for (t, term) in joins.terms.iter().enumerate() {
+            if !joins.applies(term, j, bound) || plan.terms.contains(&t) {
+                continue;
+            }
+            rows *= 0.93;
+            match term.condition {
+                Condition::Expr(Expr::Binary {
+                    op: BinaryOp::Eq | BinaryOp::Is,
+                    right,
+                    ..
+                }) => {
+                    let small = matches!(
+                        right.as_ref(),
+                        Expr::Literal(v) if v.as_integer().is_some_and(|n| (-1..=1).contains(&n))
+                    );
+                    reduce = reduce.max(if small { 2.0 } else { 4.0 });
+                }
+                Condition::Using(..) => reduce = reduce.max(4.0),
+                _ => {}
+            }
+        }
+        Ok((plan, rows.min(total / reduce)))
+    }
+
+    /// The constraints the terms put on the columns of item `j` that an
+    /// index or the rowid could look up, given the items in the mask
+    /// `bound`, and the terms concerning item `j` alone.
+    fn constraints<'s>(
+        &self,
+        joins: &Joins<'s, 'd>,
+        j: usize,
+        bound: u64,
+        outer: Option<&Env>,
+    ) -> Result<ItemTerms<'s>, anyhow::Error> {
+        let mut found = ItemTerms {
+            constraints: vec![],
+            ors: vec![],
+            local: vec![],
+        };
+        let item = &joins.items[j];
+        let Source::Table(source) = &item.source else {
+            return Ok(found);
+        };
+        let env = Env {
+            columns: &joins.columns,
+            ..Env::new(outer)
+        };
+        // the column of item `j` an operand names, None for the rowid
+        let column_of = |e: &Expr| {
+            let Expr::Column { table, name } = e.skip_collate() else {
+                return None;
+            };
+            let i = find_column(&joins.columns, table.as_deref(), name)?;
+            let i = i.checked_sub(item.offset).filter(|i| *i < item.width)?;
+            // the hidden rowid is last; an INTEGER PRIMARY KEY is an alias
+            Some((i < source.is_rowid.len() && !source.is_rowid[i]).then_some(i))
+        };
+        let independent =
+            |e: &Expr| matches!(self.expr_items(joins, e, outer), Some(m) if m & bit(j) == 0);
+
+        // the constraints an expression of term `t` puts on the columns
+        let constrain = |t: usize, expr: &'s Expr, out: &mut Vec<Constraint<'s>>| {
+            let mut add = |column, op, values: Vec<&'s Expr>, (affinity, collation)| {
+                // a value without column references is known in advance
+                let mut has_columns = false;
+                for value in &values {
+                    value.walk(&mut |e| has_columns |= matches!(e, Expr::Column { .. }));
+                }
+                let constant = match values[..] {
+                    [value] if !has_columns => self
+                        .eval(value, &env)
+                        .ok()
+                        .map(|v| with_affinity(v, affinity)),
+                    _ => None,
+                };
+                out.push(Constraint {
+                    term: t,
+                    column,
+                    op,
+                    key: SeekKey { values, affinity },
+                    collation,
+                    constant,
+                });
+            };
+            match expr {
+                Expr::Binary {
+                    op:
+                        op @ (BinaryOp::Eq
+                        | BinaryOp::Lt
+                        | BinaryOp::LtEq
+                        | BinaryOp::Gt
+                        | BinaryOp::GtEq),
+                    left,
+                    right,
+                } => {
+                    for (column, value, op) in [(left, right, *op), (right, left, flip(*op))] {
+                        if let (Some(column), true) = (column_of(column), independent(value)) {
+                            let comparison = self.comparison(left, right, &env)?;
+                            add(column, op, vec![&**value], comparison);
+                        }
+                    }
+                }
+                Expr::Between {
+                    expr: operand,
+                    negated: false,
+                    low,
+                    high,
+                } => {
+                    if let Some(column) = column_of(operand) {
+                        for (value, op) in [(low, BinaryOp::GtEq), (high, BinaryOp::LtEq)] {
+                            if independent(value) {
+                                let comparison = self.comparison(operand, value, &env)?;
+                                add(column, op, vec![&**value], comparison);
+                            }
+                        }
+                    }
+                }
+                // compared as eval() does, with the affinity and collation
+                // of the left operand
+                Expr::InList {
+                    expr: operand,
+                    negated: false,
+                    list,
+                } if !list.is_empty() => {
+                    if let (Some(column), true) = (column_of(operand), list.iter().all(independent))
+                    {
+                        let affinity = self.affinity(operand, &env);
+                        let collation = self.collation_or_binary(operand, &env)?;
+                        add(
+                            column,
+                            BinaryOp::Eq,
+                            list.iter().collect(),
+                            (affinity, collation),
+                        );
+                    }
+                }
+                _ => {}
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+
+        for (t, term) in joins.terms.iter().enumerate() {
+            let Condition::Expr(expr) = term.condition else {
+                continue;
+            };
+            // the rows of a LEFT JOIN are found by its ON clause alone
+            match term.left_join {
+                Some(i) if i != j => continue,
+                None if item.kind == JoinKind::Left => continue,
+                _ => {}
+            }
+            if !joins.applies(term, j, bound) {
+                continue;
+            }
+            if term.items == Some(bit(j)) {
+                found.local.push((t, expr));
+            }
+            let mut sides = vec![];
+            disjuncts(expr, &mut sides);
+            if sides.len() == 1 {
+                constrain(t, expr, &mut found.constraints)?;
+                continue;
+            }
+            let count = sides.len();
+            let mut branches = vec![];
+            for side in sides {
+                let mut terms = vec![];
+                conjuncts(side, &mut terms);
+                let mut branch = vec![];
+                for term in terms {
+                    constrain(t, term, &mut branch)?;
+                }
+                if branch.is_empty() {
+                    break;
+                }
+                branches.push((side, branch));
+            }
+            if branches.len() < count {
+                continue;
+            }
+            // `x = 1 OR x = 2` is `x IN (1, 2)`
+            match in_list(&branches) {
+                Some(c) => found.constraints.push(c),
+                None => found.ors.push(Disjunction { term: t, branches }),
+            }
+        }
+        Ok(found)
+    }
+
+    /// Plan each item in the chosen order, with the terms to check after
+    /// reading it.
+    fn loops<'s>(
+        &'s self,
+        joins: &Joins<'s, 'd>,
+        order: &[usize],
+        outer: Option<&Env>,
+    ) -> Result<Vec<Loop<'s>>, anyhow::Error> {
+        let depth_of = |item: usize| order.iter().position(|j| *j == item).unwrap_or(0);
+        let last = order.len() - 1;
+        let mut loops = vec![];
+        let mut bound = 0;
+        for (depth, &j) in order.iter().enumerate() {
+            let (plan, _) = self.loop_plan(joins, j, bound, outer)?;
+            let mut on = vec![];
+            let mut filters = vec![];
+            for (t, term) in joins.terms.iter().enumerate() {
+                if term.left_join.is_some() {
+                    if term.left_join == Some(j) {
+                        on.push(t);
+                    }
+                    continue;
+                }
+                // the WHERE clause is applied again after the join, so
+                // only terms that are understood are worth checking early
+                let at = match term.items {
+                    Some(items) => (0..joins.items.len())
+                        .filter(|i| items & bit(*i) != 0)
+                        .map(depth_of)
+                        .max()
+                        .unwrap_or(0),
+                    None if term.from_where => continue,
+                    None => last,
+                };
+                if at == depth {
+                    filters.push(t);
+                }
+            }
+            let reusable = plan
+                .terms
+                .iter()
+                .all(|t| joins.terms[*t].items == Some(bit(j)))
+                && !matches!(
+                    joins.items[j].source,
+                    Source::Function { depends, .. } if depends != Some(0)
+                );
+            loops.push(Loop {
+                item: j,
+                plan,
+                on,
+                filters,
+                reusable,
+                rows: None,
+            });
+            bound |= bit(j);
+        }
+        Ok(loops)
+    }
+
+    /// Join the rows of the items of `loops` to `row`, which holds the
+    /// columns of the items read before them, adding the joined rows to
+    /// `out`.
+    fn nested_loops(
+        &self,
+        joins: &Joins,
+        loops: &mut [Loop],
+        row: &mut Row,
+        outer: Option<&Env>,
+        out: &mut Vec<Row>,
+    ) -> Result<(), anyhow::Error> {
+        let Some((this, rest)) = loops.split_first_mut() else {
+            out.push(row.clone());
+            return Ok(());
+        };
+        let item = &joins.items[this.item];
+        let fresh;
+        let rows = if this.reusable {
+            if this.rows.is_none() {
+                this.rows = Some(self.read_item(joins, this, row, outer)?);
+            }
+            this.rows.as_ref().unwrap()
+        } else {
+            fresh = self.read_item(joins, this, row, outer)?;
+            &fresh
+        };
+
+        let columns = item.offset..item.offset + item.width;
+        let mut matched = false;
+        for values in rows {
+            row[columns.clone()].clone_from_slice(values);
+            if !self.all_hold(joins, &this.on, row, outer)? {
+                continue;
+            }
+            matched = true;
+            if self.all_hold(joins, &this.filters, row, outer)? {
+                self.nested_loops(joins, rest, row, outer, out)?;
+            }
+        }
+        // a LEFT JOIN without a match gives a row of NULLs
+        if !matched && item.kind == JoinKind::Left {
+            row[columns].fill(ColumnType::Null);
+            if self.all_hold(joins, &this.filters, row, outer)? {
+                self.nested_loops(joins, rest, row, outer, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn all_hold(
+        &self,
+        joins: &Joins,
+        terms: &[usize],
+        row: &[ColumnType],
+        outer: Option<&Env>,
+    ) -> Result<bool, anyhow::Error> {
+        let env = Env {
+            columns: &joins.columns,
+            row,
+            ..Env::new(outer)
+        };
+        for &t in terms {
+            let holds = match joins.terms[t].condition {
+                Condition::Expr(expr) => self.eval(expr, &env)?.truthy() == Some(true),
+                Condition::Using(l, r) => {
+                    row[l].sql_cmp(&row[r]) == Ordering::Equal && !row[l].is_null()
+                }
+            };
+            if !holds {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// The rows of the item of a loop, given the items read before it.
+    fn read_item(
+        &self,
+        joins: &Joins,
+        this: &Loop,
+        row: &[ColumnType],
+        outer: Option<&Env>,
+    ) -> Result<Vec<Row>, anyhow::Error> {
+        let env = Env {
+            columns: &joins.columns,
+            row,
+            ..Env::new(outer)
+        };
+        match &joins.items[this.item].source {
+            Source::Rows(rows, _) => Ok(rows.clone()),
+            Source::Table(source) => self.read_table(source, &this.plan.access, &env),
+            Source::Function { name, args, .. } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg, &env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.function_rows(name, &args)
+            }
+        }
+    }
+}
+
+/// Call `f` with `scopes`, the columns of enclosing queries with the
+/// innermost last, as environments without rows, so that references to
+/// them can be resolved.
+fn with_scopes<T>(
+    scopes: &[Vec<ColumnName>],
+    outer: Option<&Env>,
+    f: impl FnOnce(Option<&Env>) -> T,
+) -> T {
+    match scopes.split_first() {
+        None => f(outer),
+        Some((columns, inner)) => {
+            let env = Env {
+                columns,
+                ..Env::new(outer)
+            };
+            with_scopes(inner, Some(&env), f)
+        }
+    }
+}
+
+impl Joins<'_, '_> {
+    /// The item a column of the joined rows belongs to.
+    fn item_of(&self, column: usize) -> usize {
+        self.items
+            .iter()
+            .rposition(|item| item.offset <= column)
+            .unwrap_or(0)
+    }
+
+    /// Whether a term can be checked when item `j` is read after the items
+    /// in the mask `bound`, and concerns item `j`.
+    fn applies(&self, term: &Term, j: usize, bound: u64) -> bool {
+        match term.items {
+            Some(items) => items & bit(j) != 0 && items & !(bound | bit(j)) == 0,
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,888 @@
+//! PRAGMA statements, and the table-valued functions `pragma_<name>(...)`
+//! that give the same rows. Those describing the schema are worked out from
+//! the CREATE statements in `sqlite_schema`, and those describing the file
+//! from its header.
+//! See https://www.sqlite.org/pragma.html
+
+use super::*;
+
+/// The pragmas that give rows, with the names of their columns.
+const PRAGMAS: [(&str, &[&str]); 18] = [
+    ("application_id", &["application_id"]),
+    ("collation_list", &["seq", "name"]),
+    ("database_list", &["seq", "name", "file"]),
+    ("encoding", &["encoding"]),
+    (
+        "foreign_key_list",
+        &[
+            "id",
+            "seq",
+            "table",
+            "from",
+            "to",
+            "on_update",
+            "on_delete",
+            "match",
+        ],
+    ),
+    ("freelist_count", &["freelist_count"]),
+    (
+        "function_list",
+        &["name", "builtin", "type", "enc", "narg", "flags"],
+    ),
+    ("index_info", &["seqno", "cid", "name"]),
+    (
+        "index_list",
+        &["seq", "name", "unique", "origin", "partial"],
+    ),
+    (
+        "index_xinfo",
+        &["seqno", "cid", "name", "desc", "coll", "key"],
+    ),
+    ("integrity_check", &["integrity_check"]),
+    ("page_count", &["page_count"]),
+    ("page_size", &["page_size"]),
+    ("quick_check", &["quick_check"]),
+    ("schema_version", &["schema_version"]),
+    (
+        "table_info",
+        &["cid", "name", "type", "notnull", "dflt_value", "pk"],
+    ),
+    (
+        "table_xinfo",
+        &[
+            "cid",
+            "name",
+            "type",
+            "notnull",
+            "dflt_value",
+            "pk",
+            "hidden",
+        ],
+    ),
+    ("user_version", &["user_version"]),
+];
+
+/// The aggregate and window functions, with the fewest and most arguments
+/// each takes.
+const WINDOW_ARITY: [(&str, usize, usize); 18] = [
+    ("avg", 1, 1),
+    ("count", 0, 1),
+    ("cume_dist", 0, 0),
+    ("dense_rank", 0, 0),
+    ("first_value", 1, 1),
+    ("group_concat", 1, 2),
+    ("lag", 1, 3),
+    ("last_value", 1, 1),
+    ("lead", 1, 3),
+    ("max", 1, 1),
+    ("min", 1, 1),
+    ("nth_value", 2, 2),
+    ("ntile", 1, 1),
+    ("percent_rank", 0, 0),
+    ("rank", 0, 0),
+    ("row_number", 0, 0),
+    ("sum", 1, 1),
+    ("total", 1, 1),
+];
+
+/// SQLite's `SQLITE_DETERMINISTIC` and `SQLITE_INNOCUOUS` function flags.
+const DETERMINISTIC: i64 = 0x800;
+const INNOCUOUS: i64 = 0x200000;
+
+/// The pragmas whose functions take no arguments, so have no hidden `arg`
+/// and `schema` columns.
+const NO_ARGS: [&str; 8] = [
+    "application_id",
+    "collation_list",
+    "database_list",
+    "encoding",
+    "freelist_count",
+    "function_list",
+    "schema_version",
+    "user_version",
+];
+
+/// The pragmas whose functions take only the schema as an argument.
+const SCHEMA_ONLY: [&str; 2] = ["page_count", "page_size"];
+
+/// The header fields a pragma can set, which needs a database it can write.
+const WRITABLE: [&str; 3] = ["application_id", "schema_version", "user_version"];
+
+/// The text encodings `PRAGMA encoding` can be set to.
+const ENCODINGS: [&str; 8] = [
+    "UTF8", "UTF-8", "UTF16", "UTF-16", "UTF16le", "UTF-16le", "UTF16be", "UTF-16be",
+];
+
+/// The names of the columns of the rows a pragma gives, None if it gives
+/// none.
+fn pragma_columns(name: &str) -> Option<&'static [&'static str]> {
+    PRAGMAS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, columns)| *columns)
+}
+
+/// The pragma a table-valued function `pragma_<name>` gives the rows of,
+/// with the hidden columns holding the arguments it takes, if it is one.
+fn function_pragma(name: &str) -> Option<(&str, &'static [&'static str])> {
+    let pragma = name
+        .get(..7)?
+        .eq_ignore_ascii_case("pragma_")
+        .then(|| &name[7..])?;
+    pragma_columns(pragma)?;
+    let is = |names: &[&str]| names.iter().any(|n| n.eq_ignore_ascii_case(pragma));
+    let hidden: &[&str] = if is(&NO_ARGS) {
+        &[]
+    } else if is(&SCHEMA_ONLY) {
+        &["schema"]
+    } else {
+        &["arg", "schema"]
+    };
+    Some((pragma, hidden))
+}
+
+/// The columns of a table-valued function of a FROM clause called with
+/// `argc` arguments, known as `table`: those of the pragma's rows, then the
+/// hidden arguments.
+pub(super) fn function_columns(
+    name: &str,
+    argc: usize,
+    table: &str,
+) -> Result<Vec<ColumnName>, anyhow::Error> {
+    let Some((pragma, hidden)) = function_pragma(name) else {
+        bail!("no such table: {name}");
+    };
+    if argc > hidden.len() {
+        bail!("too many arguments on {name}() - max {}", hidden.len());
+    }
+    let visible = pragma_columns(pragma)
+        .into_iter()
+        .flatten()
+        .map(|c| (c, false));
+    let hidden = hidden.iter().map(|c| (c, true));
+    Ok(visible
+        .chain(hidden)
+        .map(|(name, hidden)| ColumnName {
+            table: Some(table.to_string()),
+            name: name.to_string(),
+            hidden,
+            affinity: None,
+            collation: None,
+        })
+        .collect())
+}
+
+fn int(n: impl Into<i64>) -> ColumnType {
+    ColumnType::Int64(n.into())
+}
+
+fn text(s: &str) -> ColumnType {
+    ColumnType::String(s.to_string())
+}
+
+fn text_or_null(s: Option<&str>) -> ColumnType {
+    s.map_or(ColumnType::Null, text)
+}
+
+impl<'d> Executor<'d> {
+    /// Run a PRAGMA. `case_sensitive_like` changes how LIKE compares, and
+    /// the pragmas of [`PRAGMAS`] give rows; like SQLite, unknown pragmas
+    /// are ignored.
+    pub(super) fn pragma(&self, pragma: &Pragma) -> Result<ResultSet, anyhow::Error> {
+        if pragma.name.eq_ignore_ascii_case("case_sensitive_like") {
+            if let Some(value) = &pragma.value {
+                self.case_sensitive_like.set(is_true(value));
+            }
+        }
+        let Some(columns) = pragma_columns(&pragma.name) else {
+            return Ok(ResultSet {
+                columns: vec![],
+                rows: vec![],
+            });
+        };
+        let rows = self.pragma_rows(
+            &pragma.name,
+            pragma.value.as_deref(),
+            pragma.schema.as_deref(),
+        )?;
+        Ok(ResultSet {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows,
+        })
+    }
+
+    /// The rows of a table-valued function of a FROM clause, given the
+    /// values of its arguments, with the hidden columns holding them.
+    pub(super) fn function_rows(
+        &self,
+        name: &str,
+        args: &[ColumnType],
+    ) -> Result<Vec<Row>, anyhow::Error> {
+        let Some((pragma, hidden)) = function_pragma(name) else {
+            bail!("no such table: {name}");
+        };
+        let value = |column| {
+            let i = hidden.iter().position(|c| *c == column)?;
+            args.get(i).and_then(|v| v.to_text())
+        };
+        let (arg, schema) = (value("arg"), value("schema"));
+        let mut rows = self.pragma_rows(pragma, arg.as_deref(), schema.as_deref())?;
+        for row in &mut rows {
+            row.extend((0..hidden.len()).map(|i| args.get(i).cloned().unwrap_or(ColumnType::Null)));
+        }
+        Ok(rows)
+    }
+
+    /// The rows of one of the pragmas of [`PRAGMAS`], given its argument
+    /// and the schema it looks in.
+    pub(super) fn pragma_rows(
+        &self,
+        name: &str,
+        arg: Option<&str>,
+        schema: Option<&str>,
+    ) -> Result<Vec<Row>, anyhow::Error> {
+        let temp = match schema {
+            None => false,
+            Some(s) if s.eq_ignore_ascii_case("main") => false,
+            Some(s) if s.eq_ignore_ascii_case("temp") => true,
+            Some(s) => bail!("unknown database {s}"),
+        };
+        let name = name.to_ascii_lowercase();
+        if arg.is_some() && WRITABLE.contains(&name.as_str()) {
+            bail!("attempt to write a readonly database");
+        }
+        let info = &self.db.info;
+        // the temporary schema is always empty, its header that of a new file
+        let header = |value: i64, empty: i64| Ok(vec![vec![int(if temp { empty } else { value })]]);
+        match (name.as_str(), arg) {
+            // the page size and encoding of a database can only be set
+            // before it has any content, so these are no-ops
+            ("encoding", Some(value)) => {
+                if !ENCODINGS.iter().any(|e| e.eq_ignore_ascii_case(value)) {
+                    bail!("unsupported encoding: {value}");
+                }
+                Ok(vec![])
+            }
+            ("page_size", Some(_)) => Ok(vec![]),
+            ("application_id", _) => header(info.application_id().into(), 0),
+            ("encoding", None) => Ok(vec![vec![text(info.encoding())]]),
+            ("freelist_count", _) => header(info.freelist_count().into(), 0),
+            ("page_count", _) => header(self.db.size().into(), 0),
+            ("page_size", None) => header(info.page_size() as i64, 4096),
+            ("schema_version", _) => header(info.schema_version().into(), 0),
+            ("user_version", _) => header(info.user_version().into(), 0),
+            ("integrity_check" | "quick_check", _) if temp => Ok(vec![vec![text("ok")]]),
+            ("integrity_check", _) => self.integrity_check(false, arg),
+            ("quick_check", _) => self.integrity_check(true, arg),
+            _ if temp => Ok(vec![]),
+            ("collation_list", _) => Ok(self
+                .collations
+                .names()
+                .iter()
+                .enumerate()
+                .map(|(i, name)| vec![int(i as i64), text(name)])
+                .collect()),
+            ("database_list", _) => Ok(vec![vec![int(0), text("main"), text(&self.db.path)]]),
+            ("function_list", _) => Ok(function_list()),
+            ("foreign_key_list", Some(table)) => self.foreign_key_list(table),
+            ("index_info" | "index_xinfo", Some(index)) => {
+                self.index_info(index, name == "index_xinfo")
+            }
+            ("index_list", Some(table)) => self.index_list(table),
+            ("table_info" | "table_xinfo", Some(table)) => {
+                self.table_info(table, name == "table_xinfo")
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// `PRAGMA table_info` and, with the generated columns too,
+    /// `table_xinfo`.
+    fn table_info(&self, name: &str, all: bool) -> Result<Vec<Row>, anyhow::Error> {
+        if is_schema_table(name) {
+            let columns = [
+                ("type", "TEXT"),
+                ("name", "TEXT"),
+                ("tbl_name", "TEXT"),
+                ("rootpage", "INT"),
+                ("sql", "TEXT"),
+            ];
+            return Ok(columns
+                .iter()
+                .enumerate()
+                .map(|(i, (name, typ))| {
+                    let mut row = vec![int(i as i64), text(name), text(typ)];
+                    row.extend([int(0), ColumnType::Null, int(0)]);
+                    if all {
+                        row.push(int(0));
+                    }
+                    row
+                })
+                .collect());
+        }
+        if let Some(view) = self.db.views().find(|v| v.name.eq_ignore_ascii_case(name)) {
+            return Ok(self
+                .view_columns(view)?
+                .into_iter()
+                .enumerate()
+                .map(|(i, (name, typ))| {
+                    let mut row = vec![int(i as i64), text(&name), text(&typ)];
+                    row.extend([int(0), ColumnType::Null, int(0)]);
+                    if all {
+                        row.push(int(0));
+                    }
+                    row
+                })
+                .collect());
+        }
+        let Some(info) = self.db.find_table(name) else {
+            return Ok(vec![]);
+        };
+        let table = info.table()?;
+        let key = table.key_columns();
+        let mut rows = vec![];
+        for (i, column) in table.columns.iter().enumerate() {
+            // SQLite's hidden column kinds: 2 is VIRTUAL and 3 STORED
+            let hidden = match &column.generated {
+                None => 0,
+                Some(generated) if generated.stored => 3,
+                Some(_) => 2,
+            };
+            if hidden != 0 && !all {
+                continue;
+            }
+            let pk = key.iter().position(|k| *k == i).map_or(0, |k| k + 1);
+            let mut row = vec![
+                int(if all { i } else { rows.len() } as i64),
+                text(&column.name),
+                text(&declared_type(column.type_name.as_deref())),
+                // the primary key of a WITHOUT ROWID table cannot be NULL
+                int(column.not_null || (table.without_rowid && pk > 0)),
+                text_or_null(column.default_text.as_deref()),
+                int(pk as i64),
+            ];
+            if all {
+                row.push(int(hidden));
+            }
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    /// The names of the columns of a view, each with its declared type, as
+    /// SQLite gives it: the type of the table column it is, through other
+    /// views and subqueries, if that has the affinity of the view column,
+    /// and else the name of that affinity, if it has one.
+    fn view_columns(&self, view: &TableInfo) -> Result<Vec<(String, String)>, anyhow::Error> {
+        let views = self.views()?;
+        let mut ctes = vec![];
+        for def in &views {
+            let names = self.cte_names(def, &mut ctes)?;
+            ctes.push((def.name.clone(), names));
+        }
+        let i = views
+            .iter()
+            .position(|v| v.name.eq_ignore_ascii_case(&view.name))
+            .expect("the view is among the views");
+        let names = ctes[i].1.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+
+        let select = &views[i].select;
+        ctes.truncate(i);
+        let sources = views[..i]
+            .iter()
+            .map(|v| (v.name.as_str(), &*v.select))
+            .collect::<Vec<_>>();
+        let types = self.select_types(select, &sources, &mut ctes)?;
+        Ok(names
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let typ = match types.get(i) {
+                    Some((declared, affinity, flexible)) => {
+                        view_type(declared, *affinity, *flexible)
+                    }
+                    None => String::new(),
+                };
+                (name, typ)
+            })
+            .collect())
+    }
+
+    /// For each result column of a select: the declared type of the table
+    /// column it is, if it is one; its affinity, which for a compound
+    /// select is that of the first select to give it one, unless the other
+    /// selects give values of another type; and whether that is
+    /// numeric only because of a CAST, which SQLite lets stand for any
+    /// number. `sources` are the views and CTEs the select can read, which
+    /// are bound in `ctes`.
+    fn select_types(
+        &self,
+        select: &Select,
+        sources: &[(&str, &Select)],
+        ctes: &mut CteNames,
+    ) -> Result<Vec<ViewColumnType>, anyhow::Error> {
+        let bound = ctes.len();
+        let types = (|| {
+            self.bind_ctes(select.with.as_ref(), ctes)?;
+            let mut sources = sources.to_vec();
+            for def in select.with.iter().flat_map(|w| &w.ctes) {
+                sources.push((&def.name, &*def.select));
+            }
+
+            // the affinity and kinds of value of each column in each select
+            let mut parts = vec![];
+            let mut declared = vec![];
+            for (i, (_, core)) in compound_parts(select).into_iter().enumerate() {
+                let mut from = vec![];
+                let mut columns = vec![];
+                if let Some(clause) = &core.from {
+                    let mut items = vec![];
+                    from_parts(clause, &mut items);
+                    for item in items {
+                        let start = columns.len();
+                        self.source_columns(item, &mut columns, &mut vec![], ctes)?;
+                        let types = self.item_types(item, &sources, ctes)?;
+                        from.extend(
+                            (start..columns.len()).map(|k| types.get(k - start).cloned().flatten()),
+                        );
+                    }
+                }
+                let (_, exprs) = self.result_columns(core, &columns)?;
+                let env = Env {
+                    columns: &columns,
+                    ..Env::new(None)
+                };
+                if i == 0 {
+                    for expr in &exprs {
+                        declared.push(match &**expr {
+                            Expr::Column { table, name } => columns
+                                .iter()
+                                .position(|c| c.matches(table.as_deref(), name))
+                                .and_then(|k| from[k].clone()),
+                            _ => None,
+                        });
+                    }
+                }
+                parts.push(
+                    exprs
+                        .iter()
+                        .map(|e| {
+                            let cast = matches!(&**e, Expr::Cast { .. });
+                            (self.affinity(e, &env), self.value_kinds(e, &env), cast)
+                        })
+                        .collect::<Vec<_>>(),
+                );
+            }
+
+            let mut types = vec![];
+            for (k, declared) in declared.into_iter().enumerate() {
+                let column = |part: &Vec<(Option<Affinity>, u8, bool)>| part.get(k).copied();
+                let mut affinity = None;
+                let mut first = 0;
+                for (i, part) in parts.iter().enumerate() {
+                    affinity = column(part).and_then(|(a, _, _)| a);
+                    first = i;
+                    if affinity.is_some() {
+                        break;
+                    }
+                }
+                let kinds = parts
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != first)
+                    .filter_map(|(_, part)| column(part))
+                    .fold(0, |m, (_, kinds, _)| m | kinds);
+                let numeric = !matches!(affinity, None | Some(Affinity::Text | Affinity::Blob));
+                let mut flexible = false;
+                if parts.len() > 1 {
+                    if affinity == Some(Affinity::Text) && kinds & NUMBER != 0
+                        || numeric && kinds & TEXT != 0
+                    {
+                        affinity = Some(Affinity::Blob);
+                    } else if numeric {
+                        flexible = column(&parts[0]).is_some_and(|(_, _, cast)| cast);
+                    }
+                }
+                types.push((declared, affinity, flexible));
+            }
+            Ok(types)
+        })();
+        ctes.truncate(bound);
+        types
+    }
+
+    /// The declared types of the columns of an item of a FROM clause: those
+    /// of a table, or of the columns of a view, CTE or subquery that are
+    /// table columns.
+    fn item_types(
+        &self,
+        item: &FromClause,
+        sources: &[(&str, &Select)],
+        ctes: &mut CteNames,
+    ) -> Result<Vec<Option<String>>, anyhow::Error> {
+        let select = match item {
+            FromClause::Table { name, .. } => {
+                match sources
+                    .iter()
+                    .rev()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                {
+                    Some((_, select)) => *select,
+                    None => {
+                        let Some(table) = self.db.find_table(name) else {
+                            return Ok(vec![]);
+                        };
+                        return Ok(table
+                            .columns()?
+                            .iter()
+                            .map(|c| c.type_name.as_deref().map(|t| declared_type(Some(t))))
+                            .collect());
+                    }
+                }
+            }
+            FromClause::Subquery { select, .. } => select,
+            _ => return Ok(vec![]),
+        };
+        // a view or CTE only sees the ones before it
+        let visible = sources
+            .iter()
+            .position(|(_, s)| std::ptr::eq(*s, select))
+            .map_or(sources, |i| &sources[..i]);
+        let types = self.select_types(select, visible, ctes)?;
+        Ok(types.into_iter().map(|(declared, _, _)| declared).collect())
+    }
+
+    /// The kinds of value an expression can have, as SQLite guesses them
+    /// for the selects of a compound: [`NUMBER`], [`TEXT`] and [`BLOB`].
+    fn value_kinds(&self, expr: &Expr, env: &Env) -> u8 {
+        match expr {
+            Expr::Collate { expr, .. }
+            | Expr::Unary {
+                op: UnaryOp::Plus,
+                expr,
+            } => self.value_kinds(expr, env),
+            Expr::Literal(ColumnType::Null) => 0,
+            Expr::Literal(ColumnType::String(_)) => TEXT,
+            Expr::Literal(ColumnType::Blob(_)) => BLOB,
+            Expr::Binary {
+                op: BinaryOp::Concat,
+                ..
+            } => TEXT | BLOB,
+            Expr::Parameter(_) | Expr::Function { .. } => NUMBER | TEXT | BLOB,
+            Expr::Column { .. } | Expr::Subquery(_) | Expr::Cast { .. } => {
+                match self.affinity(expr, env) {
+                    Some(Affinity::Text) => TEXT | BLOB,
+                    Some(Affinity::Blob) | None => NUMBER | TEXT | BLOB,
+                    Some(_) => NUMBER | BLOB,
+                }
+            }
+            Expr::Case {
+                branches,
+                else_result,
+                ..
+            } => branches
+                .iter()
+                .map(|(_, then)| then)
+                .chain(else_result.as_deref())
+                .fold(0, |m, e| m | self.value_kinds(e, env)),
+            _ => NUMBER,
+        }
+    }
+
+    /// `PRAGMA index_list`: the indexes of a table, the newest first.
+    fn index_list(&self, table: &str) -> Result<Vec<Row>, anyhow::Error> {
+        let Some(info) = self.db.find_table(table) else {
+            return Ok(vec![]);
+        };
+        Ok(self
+            .table_indexes(&info.table()?)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (index, unique, origin, partial))| {
+                vec![
+                    int(i as i64),
+                    text(&index.name),
+                    int(unique),
+                    text(origin),
+                    int(partial),
+                ]
+            })
+            .collect())
+    }
+
+    /// The indexes of a table, the newest first, each with whether it is
+    /// UNIQUE, how it was made ("c" for CREATE INDEX, "u" for a UNIQUE
+    /// constraint, "pk" for the primary key) and whether it is partial.
+    pub(super) fn table_indexes(
+        &self,
+        schema: &CreateTable,
+    ) -> Vec<(&'d TableInfo, bool, &'static str, bool)> {
+        let table = schema.name.as_str();
+        let autoindexes = schema.autoindexes();
+        // the primary key of a WITHOUT ROWID table is made with the table
+        let mut indexes = vec![];
+        if let Some(index) = self.db.primary_key(table) {
+            indexes.push((index, true, "pk", false));
+        }
+        for index in self
+            .db
+            .schema
+            .iter()
+            .filter(|t| t.typ == "index" && t.table_name.eq_ignore_ascii_case(table))
+        {
+            if index.sql.is_empty() {
+                let primary_key = autoindex_number(index)
+                    .and_then(|n| autoindexes.get(n - 1))
+                    .is_some_and(|(_, primary_key)| *primary_key);
+                let origin = if primary_key { "pk" } else { "u" };
+                indexes.push((index, true, origin, false));
+            } else {
+                let definition = index.index().ok();
+                let unique = definition.as_ref().is_some_and(|d| d.unique);
+                let partial = definition.is_some_and(|d| d.where_clause.is_some());
+                indexes.push((index, unique, "c", partial));
+            }
+        }
+        indexes.reverse();
+        indexes
+    }
+
+    /// `PRAGMA index_info`: the key columns of an index, and with `all`, as
+    /// `index_xinfo`, the other columns of its entries too. A WITHOUT ROWID
+    /// table stands for its primary key.
+    fn index_info(&self, name: &str, all: bool) -> Result<Vec<Row>, anyhow::Error> {
+        let Some(index) = self
+            .db
+            .find_index(name)
+            .or_else(|| self.db.primary_key(name))
+        else {
+            return Ok(vec![]);
+        };
+        let Some(table) = self.db.find_table(&index.table_name) else {
+            return Ok(vec![]);
+        };
+        let Some(fields) = index_fields(index, &table.table()?)? else {
+            return Ok(vec![]);
+        };
+        Ok(fields
+            .into_iter()
+            .filter(|field| all || field.key)
+            .enumerate()
+            .map(|(i, field)| {
+                let mut row = vec![
+                    int(i as i64),
+                    int(field.cid.map_or(-1, |i| i as i64)),
+                    text_or_null(field.name.as_deref()),
+                ];
+                if all {
+                    row.extend([int(field.desc), text(&field.collation), int(field.key)]);
+                }
+                row
+            })
+            .collect())
+    }
+
+    /// `PRAGMA foreign_key_list`: the columns of each foreign key of a
+    /// table, numbered from the last declared.
+    fn foreign_key_list(&self, table: &str) -> Result<Vec<Row>, anyhow::Error> {
+        let Some(info) = self.db.find_table(table) else {
+            return Ok(vec![]);
+        };
+        let mut rows = vec![];
+        for (id, key) in info.table()?.foreign_keys.iter().rev().enumerate() {
+            for (seq, from) in key.columns.iter().enumerate() {
+                rows.push(vec![
+                    int(id as i64),
+                    int(seq as i64),
+                    text(&key.table),
+                    text(from),
+                    text_or_null(key.to.get(seq).map(String::as_str)),
+                    text(&key.on_update),
+                    text(&key.on_delete),
+                    text("NONE"),
+                ]);
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// `PRAGMA function_list`: the built-in functions, a row for each number
+/// of arguments they take, or with -1 for any number.
+fn function_list() -> Vec<Row> {
+    let scalars = functions::FUNCTIONS.iter().map(|f| (f, "s"));
+    let windows = WINDOW_ARITY.iter().map(|f| (f, "w"));
+    let mut rows = vec![];
+    for (&(name, min, max), typ) in scalars.chain(windows) {
+        let flags = match name {
+            _ if typ == "w" => INNOCUOUS,
+            "random" | "randomblob" => INNOCUOUS,
+            _ => INNOCUOUS | DETERMINISTIC,
+        };
+        let arities = if max == usize::MAX {
+            vec![-1]
+        } else {
+            (min as i64..=max as i64).collect()
+        };
+        for narg in arities {
+            rows.push(vec![
+                text(name),
+                int(1),
+                text(typ),
+                text("utf8"),
+                int(narg),
+                int(flags),
+            ]);
+        }
+    }
+    rows
+}
+
+/// The tables, subqueries and functions of a FROM clause, left to right.
+fn from_parts<'s>(from: &'s FromClause, parts: &mut Vec<&'s FromClause>) {
+    match from {
+        FromClause::Join { left, right, .. } => {
+            from_parts(left, parts);
+            from_parts(right, parts);
+        }
+        part => parts.push(part),
+    }
+}
+
+/// A field of the entries of an index, as `PRAGMA index_xinfo` shows it.
+pub(super) struct IndexField {
+    /// The table column, None for the rowid.
+    pub(super) cid: Option<usize>,
+    pub(super) name: Option<String>,
+    pub(super) desc: bool,
+    pub(super) collation: String,
+    /// Whether the field is one of the indexed columns, rather than one
+    /// that finds the row.
+    pub(super) key: bool,
+}
+
+/// The fields of the entries of an index of the table `schema`: the key
+/// columns, then the rowid, or the primary key of a WITHOUT ROWID table,
+/// whose own entries hold every stored column. None for an automatic index
+/// without a constraint to match.
+pub(super) fn index_fields(
+    index: &TableInfo,
+    schema: &CreateTable,
+) -> Result<Option<Vec<IndexField>>, anyhow::Error> {
+    let keys = if index.sql.is_empty() {
+        // the columns of an automatic index are those of its constraint
+        let n = autoindex_number(index).unwrap_or(0);
+        match schema.autoindexes().get(n.wrapping_sub(1)) {
+            Some((columns, _)) => columns.to_vec(),
+            None => return Ok(None),
+        }
+    } else {
+        index.index()?.columns
+    };
+
+    let field = |indexed: &IndexedColumn, key| {
+        let cid = schema
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(&indexed.name));
+        let own = cid.and_then(|i| schema.columns[i].collation.as_ref());
+        IndexField {
+            cid,
+            name: Some(indexed.name.clone()),
+            desc: indexed.desc,
+            collation: indexed
+                .collation
+                .as_ref()
+                .or(own)
+                .map_or("BINARY", |c| c.as_str())
+                .to_string(),
+            key,
+        }
+    };
+    let mut fields = keys.iter().map(|k| field(k, true)).collect::<Vec<_>>();
+    if !schema.without_rowid {
+        fields.push(IndexField {
+            cid: None,
+            name: None,
+            desc: false,
+            collation: "BINARY".to_string(),
+            key: false,
+        });
+    } else if index.primary_key {
+        for i in schema.stored_columns() {
+            if !fields.iter().any(|f| f.cid == Some(i)) {
+                fields.push(IndexField {
+                    cid: Some(i),
+                    name: Some(schema.columns[i].name.clone()),
+                    desc: false,
+                    collation: "BINARY".to_string(),
+                    key: false,
+                });
+            }
+        }
+    } else {
+        for key in &schema.primary_key {
+            let field = field(key, false);
+            let dup = fields.iter().any(|f| {
+                field.cid.is_some()
+                    && f.cid == field.cid
+                    && f.collation.eq_ignore_ascii_case(&field.collation)
+            });
+            if !dup {
+                fields.push(field);
+            }
+        }
+    }
+    Ok(Some(fields))
+}
+
+/// The `n` of an automatic index named `sqlite_autoindex_<table>_<n>`.
+fn autoindex_number(index: &TableInfo) -> Option<usize> {
+    index.name.rsplit('_').next()?.parse().ok()
+}
+
+/// A column's type as SQLite shows it: the standard type names, as STRICT
+/// tables allow, in upper case, and any other as written.
+fn declared_type(type_name: Option<&str>) -> String {
+    let type_name = type_name.unwrap_or("");
+    match ["ANY", "BLOB", "INT", "INTEGER", "REAL", "TEXT"]
+        .iter()
+        .find(|t| t.eq_ignore_ascii_case(type_name))
+    {
+        Some(standard) => standard.to_string(),
+        None => type_name.to_string(),
+    }
+}
+
+/// The declared type, affinity and CAST flag of a view column, as
+/// [`Executor::select_types`] finds them.
+type ViewColumnType = (Option<String>, Option<Affinity>, bool);
+
+/// The kinds of value of [`Executor::value_kinds`].
+const NUMBER: u8 = 0x01;
+const TEXT: u8 = 0x02;
+const BLOB: u8 = 0x04;
+
+/// The type SQLite gives a column of a view, from the declared type of the
+/// table column it is and its affinity.
+fn view_type(declared: &Option<String>, affinity: Option<Affinity>, flexible: bool) -> String {
+    match (declared, affinity) {
+        (_, Some(_)) if flexible => affinity_name(Affinity::Numeric).to_string(),
+        (Some(t), Some(a)) if Affinity::from_type_name(t) == a => t.clone(),
+        (_, Some(a)) => affinity_name(a).to_string(),
+        (_, None) => String::new(),
+    }
+}
+
+/// The name of a type with an affinity, as SQLite gives the columns of
+/// views.
+fn affinity_name(affinity: Affinity) -> &'static str {
+    match affinity {
+        Affinity::Text => "TEXT",
+        Affinity::Numeric => "NUM",
+        Affinity::Integer => "INT",
+        Affinity::Real => "REAL",
+        Affinity::Blob => "BLOB",
+    }
+}
@@ -0,0 +1,486 @@
+//! The register machine running the programs compiled by `codegen.rs`.
+
+use super::*;
+use crate::vdbe::{self, KeyInfo, Opcode, Program, JUMP_IF_NULL, NULL_EQ, P4};
+
+/// An open cursor of a running program.
+enum VmCursor<'d> {
+    Table(btree::Cursor<'d>),
+    /// An index, with the order of each column of its key.
+    Index(btree::Cursor<'d>, Vec<KeyInfo>),
+    Sorter {
+        keys: Vec<KeyInfo>,
+        /// The rows added, each its sort keys followed by its values.
+        rows: Vec<Row>,
+        position: usize,
+    },
+    /// The keys added to an ephemeral set, told apart under collations.
+    Set {
+        keys: CollatedKeys,
+        seen: HashSet<Vec<ValueKey>>,
+    },
+}
+
+impl<'d> Executor<'d> {
+    /// Run a compiled program, collecting the rows it outputs.
+    pub(super) fn run_program(&self, program: &Program) -> Result<ResultSet, anyhow::Error> {
+        let mut r = vec![ColumnType::Null; program.registers + 1];
+        let mut cursors: Vec<Option<VmCursor>> = (0..program.cursors).map(|_| None).collect();
+        // the values accumulated by each aggregate, by register
+        let mut accumulators: HashMap<i64, (Vec<ColumnType>, Collation)> = HashMap::new();
+        let mut collation = Collation::Binary;
+        // the register a min() or max() step tells whether it took the value in
+        let mut hit = None;
+        let mut rows = vec![];
+
+        let mut pc = 0;
+        while let Some(insn) = program.insns.get(pc) {
+            pc += 1;
+            let (p1, p2, p3) = (insn.p1, insn.p2, insn.p3);
+            let jump = p2 as usize;
+            let reg = |n: i64| n as usize;
+            match insn.opcode {
+                Opcode::Init | Opcode::Goto => pc = jump,
+                Opcode::Transaction => {}
+                Opcode::Halt => break,
+                Opcode::OpenRead => {
+                    let cursor = btree::Cursor::new(self.db, p2 as u32);
+                    cursors[reg(p1)] = Some(match &insn.p4 {
+                        P4::KeyInfo(keys) => VmCursor::Index(cursor, keys.clone()),
+                        _ => VmCursor::Table(cursor),
+                    });
+                }
+                Opcode::SorterOpen => {
+                    let P4::KeyInfo(keys) = &insn.p4 else {
+                        bail!("SorterOpen without a key order at {}", pc - 1);
+                    };
+                    cursors[reg(p1)] = Some(VmCursor::Sorter {
+                        keys: keys.clone(),
+                        rows: vec![],
+                        position: 0,
+                    });
+                }
+                Opcode::OpenEphemeral => {
+                    let P4::KeyInfo(keys) = &insn.p4 else {
+                        bail!("OpenEphemeral without a key order at {}", pc - 1);
+                    };
+                    let collations = keys.iter().map(|k| k.collation.clone()).collect();
+                    cursors[reg(p1)] = Some(VmCursor::Set {
+                        keys: CollatedKeys::new(collations),
+                        seen: HashSet::new(),
+                    });
+                }
+                Opcode::Found | Opcode::IdxInsert => {
+                    let Some(Some(VmCursor::Set { keys, seen })) = cursors.get_mut(reg(p1)) else {
+                        bail!("cursor {p1} is not an open set");
+                    };
+                    if insn.opcode == Opcode::Found {
+                        let n = match insn.p4 {
+                            P4::Int32(n) => n as usize,
+                            _ => 1,
+                        };
+                        if seen.contains(&keys.key(&r[reg(p3)..reg(p3) + n])) {
+                            pc = jump;
+                        }
+                    } else {
+                        seen.insert(keys.key(&r[reg(p2)..reg(p2) + reg(p3)]));
+                    }
+                }
+                Opcode::Rewind => {
+                    if !btree_cursor(&mut cursors, p1)?.first()? {
+                        pc = jump;
+                    }
+                }
+                Opcode::Next => {
+                    if btree_cursor(&mut cursors, p1)?.next()? {
+                        pc = jump;
+                    }
+                }
+                Opcode::SeekGE | Opcode::SeekGT
+                    if matches!(cursors.get(reg(p1)), Some(Some(VmCursor::Table(_)))) =>
+                {
+                    // the key is taken as a number, and a real one rounded
+                    // to the rowids past it; NULL and what is not a number
+                    // come after every rowid
+                    let gt = insn.opcode == Opcode::SeekGT;
+                    let key = r[reg(p3)].clone().apply_affinity(Affinity::Numeric);
+                    let start = match key {
+                        ColumnType::Float(f) if f >= 9.3e18 => None,
+                        ColumnType::Float(f) if gt => Some(f.floor() as i64 + 1),
+                        ColumnType::Float(f) => Some(f.ceil() as i64),
+                        key => match key.as_integer() {
+                            Some(row_id) if gt => row_id.checked_add(1),
+                            row_id => row_id,
+                        },
+                    };
+                    let found = match start {
+                        Some(row_id) => btree_cursor(&mut cursors, p1)?.seek_row_id(row_id)?,
+                        None => false,
+                    };
+                    if !found {
+                        pc = jump;
+                    }
+                }
+                Opcode::SeekGE | Opcode::SeekGT | Opcode::IdxGE | Opcode::IdxGT => {
+                    let n = match insn.p4 {
+                        P4::Int32(n) => n as usize,
+                        _ => 1,
+                    };
+                    let key = &r[reg(p3)..reg(p3) + n];
+                    let Some(Some(VmCursor::Index(cursor, keys))) = cursors.get_mut(reg(p1)) else {
+                        bail!("cursor {p1} is not an open index");
+                    };
+                    // the entries before those sought, or before those
+                    // that end the range
+                    let past = match insn.opcode {
+                        Opcode::SeekGE | Opcode::IdxGE => Ordering::Less,
+                        _ => Ordering::Equal,
+                    };
+                    let before = |entry: &Row| compare_index_key(entry, key, keys) <= past;
+                    let jumps = match insn.opcode {
+                        Opcode::SeekGE | Opcode::SeekGT => !cursor.seek_index(&before)?,
+                        _ => !cursor.record().is_some_and(before),
+                    };
+                    if jumps {
+                        pc = jump;
+                    }
+                }
+                Opcode::IdxRowid => {
+                    let entry = btree_cursor(&mut cursors, p1)?.record();
+                    // the rowid is the last column of an index entry
+                    r[reg(p2)] = entry
+                        .and_then(|e| e.last())
+                        .and_then(|v| v.as_integer())
+                        .map_or(ColumnType::Null, ColumnType::Int64);
+                }
+                Opcode::SeekRowid => {
+                    let cursor = btree_cursor(&mut cursors, p1)?;
+                    let key = r[reg(p3)].clone().apply_affinity(Affinity::Numeric);
+                    let row_id = match key {
+                        ColumnType::Float(f) if f.fract() == 0.0 && f.abs() < 9.2e18 => {
+                            Some(f as i64)
+                        }
+                        key => key.as_integer(),
+                    };
+                    let found = match row_id {
+                        Some(row_id) => {
+                            cursor.seek_row_id(row_id)? && cursor.row_id() == Some(row_id)
+                        }
+                        None => false,
+                    };
+                    if !found {
+                        pc = jump;
+                    }
+                }
+                Opcode::Column => {
+                    let record = btree_cursor(&mut cursors, p1)?.record();
+                    // a record written before a column was added lacks it,
+                    // and has its default instead
+                    r[reg(p3)] = match (record.and_then(|record| record.get(p2 as usize)), &insn.p4)
+                    {
+                        (Some(value), _) => value.clone(),
+                        (None, P4::Mem(default)) => default.clone(),
+                        (None, _) => ColumnType::Null,
+                    };
+                }
+                Opcode::Rowid => {
+                    let row_id = btree_cursor(&mut cursors, p1)?.row_id();
+                    r[reg(p2)] = row_id.map_or(ColumnType::Null, ColumnType::Int64);
+                }
+                Opcode::RealAffinity => {
+                    let v = std::mem::replace(&mut r[reg(p1)], ColumnType::Null);
+                    r[reg(p1)] = v.read_with_affinity(Affinity::Real);
+                }
+                Opcode::Integer => r[reg(p2)] = ColumnType::Int64(p1),
+                Opcode::Int64 | Opcode::Real | Opcode::String8 | Opcode::Blob => {
+                    r[reg(p2)] = match &insn.p4 {
+                        P4::Int64(n) => ColumnType::Int64(*n),
+                        P4::Real(x) => ColumnType::Float(*x),
+                        P4::Text(s) => ColumnType::String(s.clone()),
+                        P4::Blob(b) => ColumnType::Blob(b.clone()),
+                        p4 => bail!("{} with operand {p4:?} at {}", insn.opcode, pc - 1),
+                    }
+                }
+                Opcode::Null => {
+                    for v in &mut r[reg(p2)..=reg(p3.max(p2))] {
+                        *v = ColumnType::Null;
+                    }
+                }
+                Opcode::Variable => {
+                    r[reg(p2)] = self
+                        .bindings
+                        .borrow()
+                        .get(reg(p1) - 1)
+                        .cloned()
+                        .unwrap_or(ColumnType::Null);
+                }
+                Opcode::Copy => r[reg(p2)] = r[reg(p1)].clone(),
+                Opcode::Eq | Opcode::Ne | Opcode::Lt | Opcode::Le | Opcode::Gt | Opcode::Ge => {
+                    let P4::Collation(_, collation) = &insn.p4 else {
+                        bail!("{} without a collation at {}", insn.opcode, pc - 1);
+                    };
+                    let affinity = vdbe::affinity_from_code(insn.p5 & 0x47);
+                    let l = with_affinity(r[reg(p3)].clone(), affinity);
+                    let rv = with_affinity(r[reg(p1)].clone(), affinity);
+                    let order = if insn.p5 & NULL_EQ != 0 {
+                        Some(collation.compare(&l, &rv))
+                    } else {
+                        compare(&l, &rv, collation)
+                    };
+                    let holds = match order {
+                        None => insn.p5 & JUMP_IF_NULL != 0,
+                        Some(o) => match insn.opcode {
+                            Opcode::Eq => o == Ordering::Equal,
+                            Opcode::Ne => o != Ordering::Equal,
+                            Opcode::Lt => o == Ordering::Less,
+                            Opcode::Le => o != Ordering::Greater,
+                            Opcode::Gt => o == Ordering::Greater,
+                            _ => o != Ordering::Less,
+                        },
+                    };
+                    if holds {
+                        pc = jump;
+                    }
+                }
+                Opcode::ZeroOrNull => {
+                    r[reg(p2)] = if r[reg(p1)].is_null() || r[reg(p3)].is_null() {
+                        ColumnType::Null
+                    } else {
+                        ColumnType::Int64(0)
+                    };
+                }
+                Opcode::Add
+                | Opcode::Subtract
+                | Opcode::Multiply
+                | Opcode::Divide
+                | Opcode::Remainder
+                | Opcode::Concat
+                | Opcode::BitAnd
+                | Opcode::BitOr
+                | Opcode::ShiftLeft
+                | Opcode::ShiftRight => {
+                    let op = match insn.opcode {
+                        Opcode::Add => BinaryOp::Add,
+                        Opcode::Subtract => BinaryOp::Sub,
+                        Opcode::Multiply => BinaryOp::Mul,
+                        Opcode::Divide => BinaryOp::Div,
+                        Opcode::Remainder => BinaryOp::Rem,
+                        Opcode::Concat => BinaryOp::Concat,
+                        Opcode::BitAnd => BinaryOp::BitAnd,
+                        Opcode::BitOr => BinaryOp::BitOr,
+                        Opcode::ShiftLeft => BinaryOp::ShiftLeft,
+                        _ => BinaryOp::ShiftRight,
+                    };
+                    r[reg(p3)] = binary_value(op, &r[reg(p2)], &r[reg(p1)]);
+                }
+                Opcode::And | Opcode::Or => {
+                    let (a, b) = (r[reg(p1)].truthy(), r[reg(p2)].truthy());
+                    r[reg(p3)] = bool_value(match insn.opcode {
+                        Opcode::And => and3(a, b),
+                        _ => or3(a, b),
+                    });
+                }
+                Opcode::Not => r[reg(p2)] = bool_value(r[reg(p1)].truthy().map(|b| !b)),
+                Opcode::BitNot => {
+                    r[reg(p2)] = match r[reg(p1)].to_i64() {
+                        Some(n) => ColumnType::Int64(!n),
+                        None => ColumnType::Null,
+                    }
+                }
+                Opcode::If | Opcode::IfNot => {
+                    let wanted = insn.opcode == Opcode::If;
+                    let holds = match r[reg(p1)].truthy() {
+                        Some(b) => b == wanted,
+                        None => p3 != 0,
+                    };
+                    if holds {
+                        pc = jump;
+                    }
+                }
+                Opcode::IsNull | Opcode::NotNull => {
+                    if r[reg(p1)].is_null() == (insn.opcode == Opcode::IsNull) {
+                        pc = jump;
+                    }
+                }
+                Opcode::Affinity => {
+                    let P4::Text(affinities) = &insn.p4 else {
+                        bail!("Affinity without affinities at {}", pc - 1);
+                    };
+                    for (i, code) in affinities.bytes().take(reg(p2)).enumerate() {
+                        let v = std::mem::replace(&mut r[reg(p1) + i], ColumnType::Null);
+                        r[reg(p1) + i] = with_affinity(v, vdbe::affinity_from_code(code as u16));
+                    }
+                }
+                Opcode::MustBeInt => {
+                    let v = std::mem::replace(&mut r[reg(p1)], ColumnType::Null);
+                    r[reg(p1)] = ColumnType::Int64(limit_integer(v)?);
+                }
+                Opcode::Cast => {
+                    let Some(affinity) = vdbe::affinity_from_code(p2 as u16) else {
+                        bail!("Cast to unknown affinity {p2} at {}", pc - 1);
+                    };
+                    r[reg(p1)] = r[reg(p1)].cast(affinity);
+                }
+                Opcode::Function => {
+                    let P4::Function(name, argc) = &insn.p4 else {
+                        bail!("Function without a function at {}", pc - 1);
+                    };
+                    r[reg(p3)] = self.call_function(name, &r[reg(p2)..reg(p2) + argc])?;
+                }
+                Opcode::CollSeq => {
+                    if let P4::Collation(_, c) = &insn.p4 {
+                        collation = c.clone();
+                    }
+                    if p1 != 0 {
+                        r[reg(p1)] = ColumnType::Int64(0);
+                        hit = Some(p1);
+                    }
+                }
+                Opcode::AggStep => {
+                    let P4::Function(name, argc) = &insn.p4 else {
+                        bail!("AggStep without a function at {}", pc - 1);
+                    };
+                    let (values, c) = accumulators
+                        .entry(p3)
+                        .or_insert_with(|| (vec![], Collation::Binary));
+                    if *argc == 1 && (name == "min" || name == "max") {
+                        // only the best value is kept; a NULL before any
+                        // value is taken like one
+                        let v = &r[reg(p2)];
+                        let wanted = match name == "max" {
+                            true => Ordering::Greater,
+                            false => Ordering::Less,
+                        };
+                        let new = match values.first() {
+                            None => true,
+                            Some(_) if v.is_null() => false,
+                            Some(best) => collation.compare(v, best) == wanted,
+                        };
+                        if new && !v.is_null() {
+                            *values = vec![v.clone()];
+                            *c = collation.clone();
+                        }
+                        if let Some(hit) = hit.take() {
+                            r[reg(hit)] = ColumnType::Int64(!new as i64);
+                        }
+                    } else if *argc == 0 {
+                        values.push(ColumnType::Int64(1));
+                    } else if !r[reg(p2)].is_null() {
+                        values.push(r[reg(p2)].clone());
+                        *c = collation.clone();
+                    }
+                }
+                Opcode::AggFinal => {
+                    let P4::Function(name, _) = &insn.p4 else {
+                        bail!("AggFinal without a function at {}", pc - 1);
+                    };
+                    let (values, c) = accumulators.remove(&p1).unwrap_or_default();
+                    r[reg(p1)] = aggregate(name, values, None, &c)?;
+                }
+                Opcode::SorterInsert
+                | Opcode::SorterSort
+                | Opcode::SorterData
+                | Opcode::SorterNext => {
+                    let Some(Some(VmCursor::Sorter {
+                        keys,
+                        rows: sorted,
+                        position,
+                    })) = cursors.get_mut(reg(p1))
+                    else {
+                        bail!("cursor {p1} is not an open sorter");
+                    };
+                    match insn.opcode {
+                        Opcode::SorterInsert => {
+                            sorted.push(r[reg(p2)..reg(p2) + reg(p3)].to_vec());
+                        }
+                        Opcode::SorterSort => {
+                            // a stable sort keeps rows with equal keys in the
+                            // order they were added
+                            sorted.sort_by(|a, b| compare_sorter_keys(a, b, keys));
+                            *position = 0;
+                            if sorted.is_empty() {
+                                pc = jump;
+                            }
+                        }
+                        Opcode::SorterData => {
+                            let row = &sorted[*position][keys.len()..];
+                            r[reg(p2)..reg(p2) + reg(p3)].clone_from_slice(row);
+                        }
+                        _ => {
+                            *position += 1;
+                            if *position < sorted.len() {
+                                pc = jump;
+                            }
+                        }
+                    }
+                }
+                Opcode::Gosub => {
+                    r[reg(p1)] = ColumnType::Int64(pc as i64);
+                    pc = jump;
+                }
+                Opcode::Return => {
+                    let Some(back) = r[reg(p1)].as_integer() else {
+                        bail!("Return without an address at {}", pc - 1);
+                    };
+                    pc = back as usize;
+                }
+                Opcode::IfPos => {
+                    if let Some(n) = r[reg(p1)].as_integer().filter(|n| *n > 0) {
+                        r[reg(p1)] = ColumnType::Int64(n - p3);
+                        pc = jump;
+                    }
+                }
+                Opcode::DecrJumpZero => {
+                    let n = r[reg(p1)].as_integer().unwrap_or_default() - 1;
+                    r[reg(p1)] = ColumnType::Int64(n);
+                    if n == 0 {
+                        pc = jump;
+                    }
+                }
+                Opcode::ResultRow => rows.push(r[reg(p1)..reg(p1) + reg(p2)].to_vec()),
+            }
+        }
+
+        Ok(ResultSet {
+            columns: program.columns.clone(),
+            rows,
+        })
+    }
+}
+
+/// The b-tree cursor `i`, which must be open.
+fn btree_cursor<'c, 'd>(
+    cursors: &'c mut [Option<VmCursor<'d>>],
+    i: i64,
+) -> Result<&'c mut btree::Cursor<'d>, anyhow::Error> {
+    match cursors.get_mut(i as usize) {
+        Some(Some(VmCursor::Table(cursor) | VmCursor::Index(cursor, _))) => Ok(cursor),
+        _ => bail!("cursor {i} is not open on a b-tree"),
+    }
+}
+
+/// Compare the first columns of an index entry with a key of as many
+/// columns, in the order of the index.
+fn compare_index_key(entry: &[ColumnType], key: &[ColumnType], keys: &[KeyInfo]) -> Ordering {
+    for (i, (x, y)) in entry.iter().zip(key).enumerate() {
+        let o = match keys.get(i) {
+            Some(k) if k.desc => k.collation.compare(x, y).reverse(),
+            Some(k) => k.collation.compare(x, y),
+            None => Collation::Binary.compare(x, y),
+        };
+        if o != Ordering::Equal {
+            return o;
+        }
+    }
+    Ordering::Equal
+}
+
+fn compare_sorter_keys(a: &[ColumnType], b: &[ColumnType], keys: &[KeyInfo]) -> Ordering {
+    for ((x, y), key) in a.iter().zip(b).zip(keys) {
+        let o = compare_key(x, y, key.desc, key.nulls_first, &key.collation);
+        if o != Ordering::Equal {
+            return o;
+        }
+    }
+    Ordering::Equal
+}
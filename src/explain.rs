@@ -0,0 +1,136 @@
+use std::fmt;
+
+/// The plan of a statement as shown by `EXPLAIN QUERY PLAN`: a tree of
+/// steps, with an estimate of the work each table access and sort takes.
+/// See https://www.sqlite.org/eqp.html
+#[derive(Debug, Default)]
+pub struct QueryPlan {
+    pub steps: Vec<Step>,
+    costs: Vec<Cost>,
+}
+
+/// A line of the plan. Steps whose `parent` is 0 are at the top level.
+#[derive(Debug)]
+pub struct Step {
+    pub id: usize,
+    pub parent: usize,
+    pub detail: String,
+}
+
+/// The estimated number of rows a step reads or sorts, with a description
+/// of what they are.
+#[derive(Debug)]
+struct Cost {
+    rows: f64,
+    description: String,
+}
+
+impl QueryPlan {
+    /// Add a step under `parent`, returning its id.
+    pub fn add(&mut self, parent: usize, detail: impl Into<String>) -> usize {
+        let id = self.steps.len() + 1;
+        self.steps.push(Step {
+            id,
+            parent,
+            detail: detail.into(),
+        });
+        id
+    }
+
+    pub fn add_cost(&mut self, rows: f64, description: String) {
+        self.costs.push(Cost { rows, description });
+    }
+
+    /// The cost estimate in plain English, one sentence per line.
+    pub fn cost_summary(&self) -> String {
+        let mut lines = self
+            .costs
+            .iter()
+            .map(|c| format!("{}.", c.description))
+            .collect::<Vec<_>>();
+        let total = self.costs.iter().fold(0.0, |total, c| total + c.rows);
+        lines.push(format!(
+            "Estimated cost: about {} read or sorted.",
+            rows(total)
+        ));
+        lines.join("\n")
+    }
+
+    fn write_children(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        parent: usize,
+        indent: &str,
+    ) -> fmt::Result {
+        let children = self
+            .steps
+            .iter()
+            .filter(|s| s.parent == parent)
+            .collect::<Vec<_>>();
+        for (i, step) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            writeln!(
+                f,
+                "{indent}{}{}",
+                if last { "`--" } else { "|--" },
+                step.detail
+            )?;
+            let indent = format!("{indent}{}", if last { "   " } else { "|  " });
+            self.write_children(f, step.id, &indent)?;
+        }
+        Ok(())
+    }
+}
+
+/// A number of rows in words, as in `1 row` or `12 rows`.
+pub fn rows(n: f64) -> String {
+    let n = n.round();
+    if n == 1.0 {
+        "1 row".to_string()
+    } else {
+        format!("{n} rows")
+    }
+}
+
+/// The tree drawn by the sqlite3 shell.
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "QUERY PLAN")?;
+        self.write_children(f, 0, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_tree() {
+        let mut plan = QueryPlan::default();
+        let compound = plan.add(0, "COMPOUND QUERY");
+        let left = plan.add(compound, "LEFT-MOST SUBQUERY");
+        plan.add(left, "SCAN a");
+        let union = plan.add(compound, "UNION USING TEMP B-TREE");
+        plan.add(union, "SCAN b");
+        plan.add(0, "USE TEMP B-TREE FOR ORDER BY");
+        assert_eq!(
+            plan.to_string(),
+            "QUERY PLAN\n\
+             |--COMPOUND QUERY\n\
+             |  |--LEFT-MOST SUBQUERY\n\
+             |  |  `--SCAN a\n\
+             |  `--UNION USING TEMP B-TREE\n\
+             |     `--SCAN b\n\
+             `--USE TEMP B-TREE FOR ORDER BY\n"
+        );
+
+        plan.add_cost(4.0, "SCAN a reads all 4 rows of a".to_string());
+        plan.add_cost(3.0, "SCAN b reads all 3 rows of b".to_string());
+        assert_eq!(
+            plan.cost_summary(),
+            "SCAN a reads all 4 rows of a.\n\
+             SCAN b reads all 3 rows of b.\n\
+             Estimated cost: about 7 rows read or sorted."
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! The freelist: the pages of the file no b-tree uses, kept for reuse. The
+//! header gives its first trunk page; each trunk page gives the next, and
+//! lists leaf pages, which hold nothing.
+//! See https://www.sqlite.org/fileformat.html#the_freelist
+
+use crate::db::Database;
+
+/// A trunk page of the freelist.
+pub struct Trunk {
+    pub page: u32,
+    /// The leaf pages it lists, as stored: some may not be pages of the
+    /// file, or be listed twice.
+    pub leaves: Vec<u32>,
+    /// Whether it claims more leaves than a page can list; then none are
+    /// read.
+    pub too_many: bool,
+}
+
+/// The freelist as found by following the chain of trunk pages.
+pub struct Freelist {
+    /// The trunk pages in the order of the chain.
+    pub trunks: Vec<Trunk>,
+    /// The page the chain went on to that is not a page of the file, or was
+    /// seen before, or could not be read; None if the chain ended properly.
+    pub broken: Option<u32>,
+    /// The number of free pages the header gives.
+    pub expected: u32,
+}
+
+impl Freelist {
+    /// Follow the freelist of a database from its first trunk page.
+    pub fn read(db: &Database) -> Freelist {
+        let usable = db.info.usable_size();
+        let pages = db.size();
+        let mut seen = vec![false; pages as usize + 1];
+        let mut freelist = Freelist {
+            trunks: vec![],
+            broken: None,
+            expected: db.info.freelist_count(),
+        };
+        let mut page = db.info.freelist_trunk();
+        while page != 0 {
+            let data = match db.raw_page(page) {
+                Ok(data) if page <= pages && !seen[page as usize] => data,
+                _ => {
+                    freelist.broken = Some(page);
+                    break;
+                }
+            };
+            seen[page as usize] = true;
+            let count = be_u32(data, 4);
+            let too_many = count as usize > usable / 4 - 2;
+            let mut leaves = vec![];
+            if !too_many {
+                for i in 0..count as usize {
+                    let leaf = be_u32(data, 8 + i * 4);
+                    if let Some(seen) = seen.get_mut(leaf as usize) {
+                        *seen = true;
+                    }
+                    leaves.push(leaf);
+                }
+            }
+            freelist.trunks.push(Trunk {
+                page,
+                leaves,
+                too_many,
+            });
+            page = be_u32(data, 0);
+        }
+        freelist
+    }
+
+    /// The free pages, each trunk page followed by its leaves.
+    pub fn pages(&self) -> impl Iterator<Item = u32> + '_ {
+        self.trunks
+            .iter()
+            .flat_map(|t| std::iter::once(t.page).chain(t.leaves.iter().copied()))
+    }
+
+    /// What is wrong with the freelist, if anything: pages listed that are
+    /// not pages of the file or are listed twice, trunk pages claiming too
+    /// many leaves, a broken chain, or a count other than the header's.
+    pub fn problems(&self, db: &Database) -> Vec<String> {
+        let pages = db.size();
+        let mut problems = vec![];
+        let mut seen = vec![false; pages as usize + 1];
+        for trunk in &self.trunks {
+            seen[trunk.page as usize] = true;
+            if trunk.too_many {
+                problems.push(format!("trunk page {} lists too many leaves", trunk.page));
+            }
+            for &leaf in &trunk.leaves {
+                match seen.get_mut(leaf as usize) {
+                    Some(seen) if leaf != 0 && !*seen => *seen = true,
+                    Some(_) if leaf != 0 => problems.push(format!("page {leaf} is listed twice")),
+                    _ => problems.push(format!("leaf page {leaf} is not in the file")),
+                }
+            }
+        }
+        match self.broken {
+            Some(page) if page > pages || db.raw_page(page).is_err() => {
+                problems.push(format!("trunk page {page} is not in the file"));
+            }
+            Some(page) => problems.push(format!("the chain returns to page {page}")),
+            None => {}
+        }
+        let count = self.pages().count();
+        if count != self.expected as usize {
+            problems.push(format!(
+                "{count} free pages found but the header gives {}",
+                self.expected
+            ));
+        }
+        problems
+    }
+}
+
+fn be_u32(data: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::open_bytes;
+
+    #[test]
+    fn test_read() {
+        let mut data = std::fs::read("sample.db").unwrap();
+        let db = open_bytes(&data);
+        assert_eq!(Freelist::read(&db).pages().count(), 0);
+
+        // two more pages, free: a trunk page listing a leaf page
+        data.extend([0; 4096 * 2]);
+        data[28..32].copy_from_slice(&6u32.to_be_bytes());
+        data[32..36].copy_from_slice(&5u32.to_be_bytes());
+        data[36..40].copy_from_slice(&2u32.to_be_bytes());
+        let trunk = 4096 * 4;
+        data[trunk + 4..trunk + 8].copy_from_slice(&1u32.to_be_bytes());
+        data[trunk + 8..trunk + 12].copy_from_slice(&6u32.to_be_bytes());
+        let db = open_bytes(&data);
+        let freelist = Freelist::read(&db);
+        assert_eq!(freelist.pages().collect::<Vec<_>>(), vec![5, 6]);
+        assert!(freelist.problems(&db).is_empty());
+
+        // a leaf listed twice, and a chain that comes back to it
+        data[trunk..trunk + 4].copy_from_slice(&6u32.to_be_bytes());
+        data[trunk + 4..trunk + 8].copy_from_slice(&2u32.to_be_bytes());
+        data[trunk + 12..trunk + 16].copy_from_slice(&6u32.to_be_bytes());
+        let db = open_bytes(&data);
+        let freelist = Freelist::read(&db);
+        assert_eq!(freelist.broken, Some(6));
+        assert_eq!(
+            freelist.problems(&db),
+            vec![
+                "page 6 is listed twice",
+                "the chain returns to page 6",
+                "3 free pages found but the header gives 2"
+            ]
+        );
+    }
+}
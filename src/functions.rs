@@ -0,0 +1,952 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::iter::Peekable;
+use std::str::Chars;
+
+use anyhow::bail;
+
+use crate::datetime;
+use crate::pattern::{self, Regex};
+use crate::table::ColumnType;
+use crate::value::format_real;
+
+/// The most bytes a string or blob may hold, SQLite's default
+/// SQLITE_MAX_LENGTH.
+const MAX_LENGTH: usize = 1_000_000_000;
+
+/// The most digits printf gives a real, SQLite's SQLITE_FP_PRECISION_LIMIT.
+const FP_PRECISION_LIMIT: usize = 100_000_000;
+
+/// Fail as SQLite does for a string or blob of `len` bytes that is too big.
+fn check_length(len: usize) -> Result<(), anyhow::Error> {
+    if len > MAX_LENGTH {
+        bail!("string or blob too big");
+    }
+    Ok(())
+}
+
+/// The built-in scalar functions, with the number of arguments each takes.
+/// `coalesce`, `ifnull` and `iif` are evaluated lazily by the executor.
+/// See https://www.sqlite.org/lang_corefunc.html
+pub(crate) const FUNCTIONS: [(&str, usize, usize); 42] = [
+    ("abs", 1, 1),
+    ("char", 0, usize::MAX),
+    ("coalesce", 2, usize::MAX),
+    ("date", 0, usize::MAX),
+    ("datetime", 0, usize::MAX),
+    ("format", 1, usize::MAX),
+    ("glob", 2, 2),
+    ("hex", 1, 1),
+    ("ifnull", 2, 2),
+    ("julianday", 0, usize::MAX),
+    ("iif", 2, 3),
+    ("instr", 2, 2),
+    ("length", 1, 1),
+    ("like", 2, 3),
+    ("likelihood", 2, 2),
+    ("likely", 1, 1),
+    ("lower", 1, 1),
+    ("ltrim", 1, 2),
+    ("max", 2, usize::MAX),
+    ("min", 2, usize::MAX),
+    ("nullif", 2, 2),
+    ("printf", 1, usize::MAX),
+    ("quote", 1, 1),
+    ("random", 0, 0),
+    ("regexp", 2, 2),
+    ("randomblob", 1, 1),
+    ("replace", 3, 3),
+    ("round", 1, 2),
+    ("rtrim", 1, 2),
+    ("substr", 2, 3),
+    ("strftime", 1, usize::MAX),
+    ("substring", 2, 3),
+    ("time", 0, usize::MAX),
+    ("timediff", 2, 2),
+    ("trim", 1, 2),
+    ("typeof", 1, 1),
+    ("unhex", 1, 2),
+    ("unicode", 1, 1),
+    ("unixepoch", 0, usize::MAX),
+    ("unlikely", 1, 1),
+    ("upper", 1, 1),
+    ("zeroblob", 1, 1),
+];
+
+/// Check that `name` is a scalar function taking `argc` arguments.
+pub(crate) fn check_arity(name: &str, argc: usize) -> Result<(), anyhow::Error> {
+    let Some((_, min, max)) = FUNCTIONS.iter().find(|(n, _, _)| *n == name) else {
+        bail!("no such function: {name}");
+    };
+    if argc < *min || argc > *max {
+        bail!("wrong number of arguments to function {name}()");
+    }
+    Ok(())
+}
+
+/// Call a scalar function on evaluated arguments.
+pub(crate) fn call(name: &str, args: &[ColumnType]) -> Result<ColumnType, anyhow::Error> {
+    check_arity(name, args.len())?;
+    let arg = |i: usize| args.get(i).unwrap_or(&ColumnType::Null);
+
+    Ok(match name {
+        "abs" => abs(arg(0))?,
+        "char" => ColumnType::String(
+            args.iter()
+                .map(|v| {
+                    let c = v.to_i64().unwrap_or_default();
+                    u32::try_from(c)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .unwrap_or('\u{fffd}')
+                })
+                .collect(),
+        ),
+        "date" | "datetime" | "julianday" | "strftime" | "time" | "timediff" | "unixepoch" => {
+            datetime::call(name, args)
+        }
+        "coalesce" | "ifnull" => args
+            .iter()
+            .find(|v| !v.is_null())
+            .cloned()
+            .unwrap_or(ColumnType::Null),
+        "format" | "printf" => match arg(0).to_text() {
+            Some(format) => ColumnType::String(printf(&format, &args[1..])?),
+            None => ColumnType::Null,
+        },
+        "glob" => match (arg(0).to_text(), arg(1).to_text()) {
+            (Some(pattern), Some(s)) => ColumnType::Int64(pattern::glob(&pattern, &s) as i64),
+            _ => ColumnType::Null,
+        },
+        "hex" => ColumnType::String(
+            bytes(arg(0))
+                .unwrap_or_default()
+                .iter()
+                .map(|b| format!("{b:02X}"))
+                .collect(),
+        ),
+        "iif" => match arg(0).truthy() {
+            Some(true) => arg(1).clone(),
+            _ => arg(2).clone(),
+        },
+        "instr" => instr(arg(0), arg(1)),
+        "length" => match arg(0) {
+            ColumnType::Null => ColumnType::Null,
+            ColumnType::Blob(b) => ColumnType::Int64(b.len() as i64),
+            v => ColumnType::Int64(v.to_text().unwrap_or_default().chars().count() as i64),
+        },
+        "likelihood" => {
+            if !arg(1).to_f64().is_some_and(|p| (0.0..=1.0).contains(&p)) {
+                bail!("second argument to likelihood() must be a constant between 0.0 and 1.0");
+            }
+            arg(0).clone()
+        }
+        "like" => like(args, false)?,
+        "likely" | "unlikely" => arg(0).clone(),
+        "lower" => map_text(arg(0), |s| s.to_ascii_lowercase()),
+        "upper" => map_text(arg(0), |s| s.to_ascii_uppercase()),
+        "ltrim" | "rtrim" | "trim" => trim(name, arg(0), args.get(1)),
+        "max" | "min" => {
+            if args.iter().any(|v| v.is_null()) {
+                return Ok(ColumnType::Null);
+            }
+            let wanted = if name == "min" {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            };
+            let mut best = &args[0];
+            for v in &args[1..] {
+                if v.sql_cmp(best) == wanted {
+                    best = v;
+                }
+            }
+            best.clone()
+        }
+        "nullif" => {
+            if !arg(0).is_null() && arg(0).sql_cmp(arg(1)) == Ordering::Equal {
+                ColumnType::Null
+            } else {
+                arg(0).clone()
+            }
+        }
+        "quote" => ColumnType::String(quote(arg(0))),
+        "random" => ColumnType::Int64(random() as i64),
+        "randomblob" => {
+            let n = arg(0).to_i64().unwrap_or_default().max(1) as usize;
+            check_length(n)?;
+            let mut blob = Vec::with_capacity(n + 8);
+            while blob.len() < n {
+                blob.extend(random().to_le_bytes());
+            }
+            blob.truncate(n);
+            ColumnType::Blob(blob)
+        }
+        "regexp" => match (arg(0).to_text(), arg(1).to_text()) {
+            (Some(pattern), Some(s)) => {
+                ColumnType::Int64(Regex::new(&pattern)?.is_match(&s) as i64)
+            }
+            _ => ColumnType::Null,
+        },
+        "replace" => replace(arg(0), arg(1), arg(2)),
+        "round" => round(arg(0), args.get(1)),
+        "substr" | "substring" => substr(arg(0), arg(1), args.get(2)),
+        "typeof" => ColumnType::String(type_name(arg(0)).to_string()),
+        "unhex" => unhex(arg(0), args.get(1)),
+        "unicode" => match arg(0).to_text().and_then(|s| s.chars().next()) {
+            Some(c) => ColumnType::Int64(c as i64),
+            None => ColumnType::Null,
+        },
+        "zeroblob" => {
+            let n = arg(0).to_i64().unwrap_or_default().max(0) as usize;
+            check_length(n)?;
+            ColumnType::Blob(vec![0; n])
+        }
+        _ => bail!("no such function: {name}"),
+    })
+}
+
+/// The storage class name returned by typeof().
+/// `like(pattern, string [, escape])`, which is case-sensitive only under
+/// `PRAGMA case_sensitive_like`.
+pub(crate) fn like(args: &[ColumnType], case_sensitive: bool) -> Result<ColumnType, anyhow::Error> {
+    let escape = match args.get(2).map(|v| v.to_text()) {
+        None => None,
+        Some(None) => return Ok(ColumnType::Null),
+        Some(Some(escape)) => {
+            let mut chars = escape.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => bail!("ESCAPE expression must be a single character"),
+            }
+        }
+    };
+    Ok(match (args[0].to_text(), args[1].to_text()) {
+        (Some(pattern), Some(s)) => {
+            ColumnType::Int64(pattern::like(&pattern, &s, escape, case_sensitive) as i64)
+        }
+        _ => ColumnType::Null,
+    })
+}
+
+pub(crate) fn type_name(v: &ColumnType) -> &'static str {
+    match v {
+        ColumnType::Null => "null",
+        ColumnType::Float(_) => "real",
+        ColumnType::String(_) => "text",
+        ColumnType::Blob(_) => "blob",
+        _ => "integer",
+    }
+}
+
+/// The bytes of a value: a blob as it is, anything else as UTF-8 text.
+fn bytes(v: &ColumnType) -> Option<Vec<u8>> {
+    match v {
+        ColumnType::Blob(b) => Some(b.clone()),
+        v => v.to_text().map(String::into_bytes),
+    }
+}
+
+fn map_text(v: &ColumnType, f: impl Fn(&str) -> String) -> ColumnType {
+    match v.to_text() {
+        Some(s) => ColumnType::String(f(&s)),
+        None => ColumnType::Null,
+    }
+}
+
+fn abs(v: &ColumnType) -> Result<ColumnType, anyhow::Error> {
+    Ok(match v {
+        ColumnType::Null => ColumnType::Null,
+        ColumnType::Float(f) => ColumnType::Float(f.abs()),
+        ColumnType::String(_) | ColumnType::Blob(_) => {
+            ColumnType::Float(v.to_f64().unwrap_or_default().abs())
+        }
+        v => match v.as_integer().unwrap_or_default().checked_abs() {
+            Some(n) => ColumnType::Int64(n),
+            None => bail!("integer overflow"),
+        },
+    })
+}
+
+fn instr(haystack: &ColumnType, needle: &ColumnType) -> ColumnType {
+    if haystack.is_null() || needle.is_null() {
+        return ColumnType::Null;
+    }
+    let position = if let (ColumnType::Blob(h), ColumnType::Blob(n)) = (haystack, needle) {
+        if n.is_empty() {
+            Some(0)
+        } else {
+            h.windows(n.len()).position(|w| w == n.as_slice())
+        }
+    } else {
+        let (h, n) = (
+            haystack.to_text().unwrap_or_default(),
+            needle.to_text().unwrap_or_default(),
+        );
+        h.find(&n).map(|i| h[..i].chars().count())
+    };
+    ColumnType::Int64(position.map_or(0, |i| i as i64 + 1))
+}
+
+fn trim(name: &str, v: &ColumnType, chars: Option<&ColumnType>) -> ColumnType {
+    let chars = match chars {
+        Some(c) => match c.to_text() {
+            Some(c) => c.chars().collect::<Vec<_>>(),
+            None => return ColumnType::Null,
+        },
+        None => vec![' '],
+    };
+    map_text(v, |s| {
+        let s = if name != "rtrim" {
+            s.trim_start_matches(chars.as_slice())
+        } else {
+            s
+        };
+        let s = if name != "ltrim" {
+            s.trim_end_matches(chars.as_slice())
+        } else {
+            s
+        };
+        s.to_string()
+    })
+}
+
+fn replace(v: &ColumnType, from: &ColumnType, to: &ColumnType) -> ColumnType {
+    let (Some(s), Some(from), Some(to)) = (v.to_text(), from.to_text(), to.to_text()) else {
+        return ColumnType::Null;
+    };
+    if from.is_empty() {
+        return v.clone();
+    }
+    ColumnType::String(s.replace(&from, &to))
+}
+
+/// substr(X, Y, Z): Z characters (bytes for a blob) starting at the Y-th,
+/// where a negative Y counts from the end and a negative Z takes the
+/// characters before position Y instead.
+fn substr(v: &ColumnType, start: &ColumnType, len: Option<&ColumnType>) -> ColumnType {
+    if v.is_null() || start.is_null() || len.is_some_and(|l| l.is_null()) {
+        return ColumnType::Null;
+    }
+
+    let blob = matches!(v, ColumnType::Blob(_));
+    let chars = match v {
+        ColumnType::Blob(_) => vec![],
+        v => v.to_text().unwrap_or_default().chars().collect::<Vec<_>>(),
+    };
+    let total = match v {
+        ColumnType::Blob(b) => b.len() as i64,
+        _ => chars.len() as i64,
+    };
+
+    let mut p1 = start.to_i64().unwrap_or_default();
+    let mut p2 = len.map_or(i64::MAX / 2, |l| l.to_i64().unwrap_or_default());
+    let negative_len = p2 < 0;
+    p2 = p2.saturating_abs();
+    if p1 < 0 {
+        p1 += total;
+        if p1 < 0 {
+            p2 = (p2 + p1).max(0);
+            p1 = 0;
+        }
+    } else if p1 > 0 {
+        p1 -= 1;
+    } else if p2 > 0 {
+        p2 -= 1;
+    }
+    if negative_len {
+        p1 -= p2;
+        if p1 < 0 {
+            p2 += p1;
+            p1 = 0;
+        }
+    }
+
+    let from = p1.min(total) as usize;
+    let to = p1.saturating_add(p2).min(total) as usize;
+    match v {
+        ColumnType::Blob(b) if blob => ColumnType::Blob(b[from..to].to_vec()),
+        _ => ColumnType::String(chars[from..to].iter().collect()),
+    }
+}
+
+/// round(X, Y): always a real, rounded half away from zero.
+fn round(v: &ColumnType, digits: Option<&ColumnType>) -> ColumnType {
+    if v.is_null() || digits.is_some_and(|d| d.is_null()) {
+        return ColumnType::Null;
+    }
+    let n = digits.map_or(0, |d| d.to_i64().unwrap_or_default().clamp(0, 30)) as usize;
+    let r = v.to_f64().unwrap_or_default();
+
+    if n == 0 && r.abs() < 4503599627370496.0 {
+        let rounded = (r + if r < 0.0 { -0.5 } else { 0.5 }) as i64;
+        return ColumnType::Float(rounded as f64);
+    }
+    let text = format_float(r, 'f', Some(n), &Flags::default(), true);
+    ColumnType::Float(text.parse().unwrap_or(r))
+}
+
+fn unhex(v: &ColumnType, ignore: Option<&ColumnType>) -> ColumnType {
+    let Some(s) = v.to_text() else {
+        return ColumnType::Null;
+    };
+    let ignore = match ignore {
+        Some(i) => match i.to_text() {
+            Some(i) => i,
+            None => return ColumnType::Null,
+        },
+        None => String::new(),
+    };
+
+    let mut blob = vec![];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        // ignored characters may only appear between pairs of hex digits
+        if ignore.contains(c) {
+            continue;
+        }
+        let high = c.to_digit(16);
+        let low = chars.next().and_then(|c| c.to_digit(16));
+        let (Some(high), Some(low)) = (high, low) else {
+            return ColumnType::Null;
+        };
+        blob.push((high * 16 + low) as u8);
+    }
+    ColumnType::Blob(blob)
+}
+
+/// An SQL literal for a value, as quote() returns.
+pub fn quote(v: &ColumnType) -> String {
+    match v {
+        ColumnType::Null => "NULL".to_string(),
+        ColumnType::String(s) => format!("'{}'", s.replace('\'', "''")),
+        ColumnType::Blob(b) => {
+            let hex = b.iter().map(|b| format!("{b:02X}")).collect::<String>();
+            format!("X'{hex}'")
+        }
+        ColumnType::Float(f) => {
+            // enough digits to read back the same value
+            let text = format_real(*f);
+            if text.parse::<f64>().ok() == Some(*f) {
+                text
+            } else {
+                let flags = Flags {
+                    alt2: true,
+                    ..Flags::default()
+                };
+                format_float(*f, 'e', Some(20), &flags, true)
+            }
+        }
+        v => v.to_text().unwrap_or_default(),
+    }
+}
+
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// The flags of a printf conversion.
+#[derive(Default)]
+struct Flags {
+    left: bool,
+    plus: bool,
+    space: bool,
+    /// `#`
+    alt: bool,
+    zero: bool,
+    /// `,`: thousands separators
+    comma: bool,
+    /// `!`: more digits for reals, characters rather than bytes for strings
+    alt2: bool,
+}
+
+/// SQLite's printf(), which takes its arguments as SQL values. Widths and
+/// precisions are C ints, as there; a result over MAX_LENGTH is an error.
+/// See https://www.sqlite.org/printf.html
+pub(crate) fn printf(format: &str, args: &[ColumnType]) -> Result<String, anyhow::Error> {
+    let mut args = args.iter();
+    let mut next_arg = || args.next().cloned().unwrap_or(ColumnType::Null);
+
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut flags = Flags::default();
+        while let Some(&c) = chars.peek() {
+            match c {
+                '-' => flags.left = true,
+                '+' => flags.plus = true,
+                ' ' => flags.space = true,
+                '#' => flags.alt = true,
+                '0' => flags.zero = true,
+                ',' => flags.comma = true,
+                '!' => flags.alt2 = true,
+                _ => break,
+            }
+            chars.next();
+        }
+
+        // an argument is cut to an int, as SQLite's C code takes it
+        let width = if chars.peek() == Some(&'*') {
+            chars.next();
+            let w = next_arg().to_i64().unwrap_or_default() as i32;
+            flags.left |= w < 0;
+            match w {
+                i32::MIN => 0,
+                w => w.unsigned_abs() as usize,
+            }
+        } else {
+            printf_digits(&mut chars)
+        };
+
+        let mut precision = None;
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                precision = match next_arg().to_i64().unwrap_or_default() as i32 {
+                    i32::MIN => None,
+                    p => Some(p.unsigned_abs() as usize),
+                };
+            } else {
+                precision = Some(printf_digits(&mut chars));
+            }
+        }
+
+        let Some(conversion) = chars.next() else {
+            break;
+        };
+        let (text, numeric) = match conversion {
+            '%' => ("%".to_string(), false),
+            'd' | 'i' | 'u' | 'x' | 'X' | 'o' => {
+                check_length(precision.unwrap_or_default())?;
+                let n = next_arg().to_i64().unwrap_or_default();
+                (format_integer(n, conversion, precision, &flags), true)
+            }
+            'f' | 'e' | 'E' | 'g' | 'G' => {
+                let f = next_arg().to_f64().unwrap_or_default();
+                let precision = precision.map(|p| p.min(FP_PRECISION_LIMIT));
+                (format_float(f, conversion, precision, &flags, false), true)
+            }
+            's' | 'z' => (
+                truncate(next_arg().to_text().unwrap_or_default(), precision),
+                false,
+            ),
+            'c' => {
+                let text = next_arg().to_text().unwrap_or_default();
+                (
+                    text.chars().next().map(String::from).unwrap_or_default(),
+                    false,
+                )
+            }
+            'q' | 'Q' | 'w' => {
+                let v = next_arg();
+                let quote = if conversion == 'w' { '"' } else { '\'' };
+                let text = match v.to_text() {
+                    Some(s) => {
+                        let s = truncate(s, precision).replace(quote, &format!("{quote}{quote}"));
+                        if conversion == 'Q' {
+                            format!("'{s}'")
+                        } else {
+                            s
+                        }
+                    }
+                    None if conversion == 'Q' => "NULL".to_string(),
+                    None => "(NULL)".to_string(),
+                };
+                (text, false)
+            }
+            other => (format!("%{other}"), false),
+        };
+        check_length(width)?;
+        out.push_str(&pad(text, width, &flags, numeric));
+        check_length(out.len())?;
+    }
+    Ok(out)
+}
+
+/// A width or precision written in the format. Like SQLite, this wraps
+/// around past 32 bits, and keeps to the range of an int.
+fn printf_digits(chars: &mut Peekable<Chars>) -> usize {
+    let mut n = 0u32;
+    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+        n = n.wrapping_mul(10).wrapping_add(d);
+        chars.next();
+    }
+    (n & 0x7fff_ffff) as usize
+}
+
+fn truncate(s: String, precision: Option<usize>) -> String {
+    match precision {
+        Some(p) => s.chars().take(p).collect(),
+        None => s,
+    }
+}
+
+/// Pad a converted value to `width`. Zero padding goes after the sign and
+/// any radix prefix.
+fn pad(text: String, width: usize, flags: &Flags, numeric: bool) -> String {
+    let len = text.chars().count();
+    if len >= width {
+        return text;
+    }
+    let fill = width - len;
+    if flags.left {
+        return text + &" ".repeat(fill);
+    }
+    if flags.zero && numeric && !text.ends_with("Inf") && !text.ends_with("NaN") {
+        let prefix_len = text
+            .find(|c: char| c.is_ascii_digit())
+            .map(|i| {
+                if text[i..].starts_with("0x") || text[i..].starts_with("0X") {
+                    i + 2
+                } else {
+                    i
+                }
+            })
+            .unwrap_or(0);
+        let (prefix, digits) = text.split_at(prefix_len);
+        return format!("{prefix}{}{digits}", "0".repeat(fill));
+    }
+    " ".repeat(fill) + &text
+}
+
+fn sign(negative: bool, flags: &Flags) -> &'static str {
+    if negative {
+        "-"
+    } else if flags.plus {
+        "+"
+    } else if flags.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+fn format_integer(n: i64, conversion: char, precision: Option<usize>, flags: &Flags) -> String {
+    let (negative, magnitude) = match conversion {
+        'd' | 'i' => (n < 0, n.unsigned_abs()),
+        _ => (false, n as u64),
+    };
+    let mut digits = match conversion {
+        'x' => format!("{magnitude:x}"),
+        'X' => format!("{magnitude:X}"),
+        'o' => format!("{magnitude:o}"),
+        _ => magnitude.to_string(),
+    };
+    if let Some(p) = precision {
+        if digits.len() < p {
+            digits = "0".repeat(p - digits.len()) + &digits;
+        }
+    }
+    if flags.comma && matches!(conversion, 'd' | 'i' | 'u') {
+        let mut grouped = String::new();
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                grouped.push(',');
+            }
+            grouped.push(c);
+        }
+        digits = grouped;
+    }
+    let prefix = match conversion {
+        'x' if flags.alt && magnitude != 0 => "0x",
+        'X' if flags.alt && magnitude != 0 => "0X",
+        'o' if flags.alt && !digits.starts_with('0') => "0",
+        _ => "",
+    };
+    format!("{}{prefix}{digits}", sign(negative, flags))
+}
+
+/// The significant digits of a non-negative finite float and the decimal
+/// exponent of the first, the way SQLite's printf gets them: the first 19
+/// digits, rounded half up to `round_at` significant digits if there are
+/// more than that and `limit`. Zero is `([0], 0)`.
+fn decimal_digits(f: f64, round_at: i64, limit: usize) -> (Vec<u8>, i64) {
+    if f == 0.0 {
+        return (vec![0], 0);
+    }
+    let sci = format!("{f:.40e}");
+    let (mantissa, exp) = sci.split_once('e').expect("exponent in {:e} output");
+    let mut exp = exp.parse::<i64>().expect("valid exponent");
+    let mut digits = mantissa
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .map(|b| b - b'0')
+        .take(19)
+        .collect::<Vec<_>>();
+
+    let keep = round_at.min(limit as i64);
+    if keep < 0 {
+        return (vec![0], 0);
+    }
+    let keep = keep as usize;
+    if keep < digits.len() {
+        let round_up = digits[keep] >= 5;
+        digits.truncate(keep);
+        if round_up {
+            let mut i = keep;
+            loop {
+                if i == 0 {
+                    digits.insert(0, 1);
+                    exp += 1;
+                    break;
+                }
+                i -= 1;
+                if digits[i] == 9 {
+                    digits[i] = 0;
+                } else {
+                    digits[i] += 1;
+                    break;
+                }
+            }
+        }
+        if digits.is_empty() {
+            return (vec![0], 0);
+        }
+    }
+    (digits, exp)
+}
+
+/// Format a real for printf's `%f`, `%e` and `%g` conversions. Beyond 16
+/// significant digits (19 with the `!` flag) the digits are zeros.
+/// `round` is the flavor round() uses: always `!`, no trailing zeros.
+fn format_float(
+    f: f64,
+    conversion: char,
+    precision: Option<usize>,
+    flags: &Flags,
+    round: bool,
+) -> String {
+    let alt2 = flags.alt2 || round;
+    let negative = f.is_sign_negative() && f != 0.0;
+    let sign = sign(negative, flags);
+    if f.is_nan() {
+        return "NaN".to_string();
+    }
+    if f.is_infinite() {
+        return format!("{sign}Inf");
+    }
+
+    let f = f.abs();
+    let precision = precision.unwrap_or(6) as i64;
+    let limit = if alt2 { 26 } else { 16 };
+    let digit = |digits: &[u8], i: i64| -> char {
+        if i < 0 || i >= digits.len() as i64 {
+            '0'
+        } else {
+            (b'0' + digits[i as usize]) as char
+        }
+    };
+
+    let (_, exp) = decimal_digits(f, 19, 19);
+    let (style, precision, strip) = match conversion {
+        'f' => ('f', precision, alt2),
+        'e' | 'E' => (conversion, precision, alt2),
+        _ => {
+            // %g picks %e or %f style by the exponent after rounding
+            let p = precision.max(1);
+            let (_, x) = decimal_digits(f, p, limit);
+            let e = if conversion == 'G' { 'E' } else { 'e' };
+            if x < -4 || x >= p {
+                (e, p - 1, !flags.alt)
+            } else {
+                ('f', p - 1 - x, !flags.alt)
+            }
+        }
+    };
+
+    let mut text = if style == 'f' {
+        let (digits, exp) = decimal_digits(f, exp + 1 + precision, limit);
+        let mut text = String::new();
+        if exp < 0 {
+            text.push('0');
+        } else {
+            text.extend((0..=exp).map(|i| digit(&digits, i)));
+        }
+        if precision > 0 || flags.alt {
+            text.push('.');
+        }
+        // past the known digits there are only zeros, perhaps very many
+        let known = (digits.len() as i64 - exp - 1).clamp(0, precision);
+        text.extend((exp + 1..exp + 1 + known).map(|i| digit(&digits, i)));
+        text.push_str(&"0".repeat((precision - known) as usize));
+        text
+    } else {
+        let (digits, exp) = decimal_digits(f, precision + 1, limit);
+        let mut text = String::new();
+        text.push(digit(&digits, 0));
+        if precision > 0 || flags.alt {
+            text.push('.');
+        }
+        let known = (digits.len() as i64 - 1).clamp(0, precision);
+        text.extend((1..=known).map(|i| digit(&digits, i)));
+        text.push_str(&"0".repeat((precision - known) as usize));
+        let exp = if f == 0.0 { 0 } else { exp };
+        text + &format!("{style}{}{:02}", if exp < 0 { '-' } else { '+' }, exp.abs())
+    };
+
+    if strip && (text.contains('.') || alt2) {
+        let (mantissa, exponent) = match text.find(['e', 'E']) {
+            Some(i) => text.split_at(i),
+            None => (text.as_str(), ""),
+        };
+        let mut mantissa = match mantissa.contains('.') {
+            true => mantissa.trim_end_matches('0').to_string(),
+            // `!` always shows a decimal point
+            false => format!("{mantissa}."),
+        };
+        if mantissa.ends_with('.') {
+            if alt2 {
+                mantissa.push('0');
+            } else {
+                mantissa.pop();
+            }
+        }
+        text = mantissa + exponent;
+    }
+    format!("{sign}{text}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ColumnType::*;
+
+    fn text(s: &str) -> ColumnType {
+        String(s.to_string())
+    }
+
+    #[test]
+    fn test_substr() {
+        let hello = text("hello");
+        let substr = |y: i64, z: Option<i64>| {
+            let z = z.map(Int64);
+            super::substr(&hello, &Int64(y), z.as_ref())
+        };
+        assert_eq!(substr(2, None), text("ello"));
+        assert_eq!(substr(-3, Some(2)), text("ll"));
+        assert_eq!(substr(0, Some(2)), text("h"));
+        assert_eq!(substr(2, Some(-1)), text("h"));
+        assert_eq!(substr(-10, Some(7)), text("he"));
+        assert_eq!(
+            super::substr(&Blob(vec![1, 2, 3, 4]), &Int64(2), Some(&Int64(2))),
+            Blob(vec![2, 3])
+        );
+    }
+
+    #[test]
+    fn test_round() {
+        assert_eq!(round(&Float(2.5), None), Float(3.0));
+        assert_eq!(round(&Float(-2.5), None), Float(-3.0));
+        assert_eq!(round(&Float(0.125), Some(&Int64(2))), Float(0.13));
+        assert_eq!(round(&Float(1.005), Some(&Int64(2))), Float(1.0));
+        assert_eq!(round(&text("2.567"), Some(&Int64(1))), Float(2.6));
+        assert_eq!(round(&Int64(3), None), Float(3.0));
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote(&text("it's")), "'it''s'");
+        assert_eq!(quote(&Blob(vec![0, 255])), "X'00FF'");
+        assert_eq!(quote(&Float(0.1)), "0.1");
+        assert_eq!(quote(&Float(1.0 / 3.0)), "3.333333333333333148e-01");
+        assert_eq!(quote(&Null), "NULL");
+    }
+
+    #[test]
+    fn test_printf() {
+        let args = [Int64(42), Int64(-42), Int64(1234567), Int64(255)];
+        assert_eq!(
+            printf("%5d|%05d|%,d|%#x", &args).unwrap(),
+            "   42|-0042|1,234,567|0xff"
+        );
+        let args = [Float(3.25), Float(2.675), Float(12345.678), Float(1e-5)];
+        assert_eq!(
+            printf("%f|%.2f|%e|%G", &args).unwrap(),
+            "3.250000|2.67|1.234568e+04|1E-05"
+        );
+        assert_eq!(
+            printf("%.20f", &[Float(1.0 / 3.0)]).unwrap(),
+            "0.33333333333333330000"
+        );
+        assert_eq!(
+            printf("%!.20e", &[Float(0.1)]).unwrap(),
+            "1.000000000000000055e-01"
+        );
+        assert_eq!(
+            printf("%g|%g|%#g", &[Int64(100000), Int64(1000000), Float(1.5)]).unwrap(),
+            "100000|1e+06|1.50000"
+        );
+        let args = [text("it's"), Null, text("abc")];
+        assert_eq!(
+            printf("%q|%Q|%-5.2s|%%", &args).unwrap(),
+            "it''s|NULL|ab   |%"
+        );
+        assert_eq!(printf("%*d|%s", &[Int64(4), Int64(7)]).unwrap(), "   7|");
+
+        // widths and precisions are C ints, and results are limited
+        let args = [Int64(i64::MIN), Int64(1), Int64(4294967301), Int64(2)];
+        assert_eq!(printf("%*d|%*d", &args).unwrap(), "1|    2");
+        assert_eq!(printf("%4294967297d", &[Int64(1)]).unwrap(), "1");
+        assert_eq!(printf("%.*s", &[Int64(-2), text("abc")]).unwrap(), "ab");
+        let too_big = |format: &str, args: &[ColumnType]| {
+            let error = printf(format, args).unwrap_err();
+            assert_eq!(error.to_string(), "string or blob too big");
+        };
+        too_big("%99999999999999999999d", &[Int64(1)]);
+        too_big("%*d", &[Int64(1_000_000_001), Int64(1)]);
+        too_big("%.*d", &[Int64(1_000_000_001), Int64(1)]);
+        // reals get no more than 100000000 digits after the point
+        let f = printf("%.*f", &[Int64(1_000_000_000), Float(1.5)]).unwrap();
+        assert_eq!(f.len(), 100_000_002);
+        let error = call("zeroblob", &[Float(1e18)]).unwrap_err();
+        assert_eq!(error.to_string(), "string or blob too big");
+    }
+
+    #[test]
+    fn test_text_functions() {
+        assert_eq!(call("length", &[text("héllo")]).unwrap(), Int64(5));
+        assert_eq!(call("length", &[Float(12.5)]).unwrap(), Int64(4));
+        assert_eq!(call("upper", &[text("abcé")]).unwrap(), text("ABCé"));
+        assert_eq!(
+            call("ltrim", &[text("xxyxz"), text("xy")]).unwrap(),
+            text("z")
+        );
+        assert_eq!(
+            call("instr", &[text("héllo"), text("l")]).unwrap(),
+            Int64(3)
+        );
+        assert_eq!(
+            call("replace", &[Int64(123), Int64(2), Int64(9)]).unwrap(),
+            text("193")
+        );
+        assert_eq!(call("hex", &[text("é")]).unwrap(), text("C3A9"));
+        assert_eq!(
+            call("unhex", &[text("41 42"), text(" ")]).unwrap(),
+            Blob(vec![0x41, 0x42])
+        );
+        assert_eq!(call("unhex", &[text("4 142"), text(" ")]).unwrap(), Null);
+        assert_eq!(call("char", &[Int64(72), Int64(105)]).unwrap(), text("Hi"));
+    }
+
+    #[test]
+    fn test_type_rules() {
+        assert_eq!(call("abs", &[text("-3")]).unwrap(), Float(3.0));
+        assert!(call("abs", &[Int64(i64::MIN)]).is_err());
+        assert_eq!(
+            call("min", &[Int64(3), Int64(1), text("2")]).unwrap(),
+            Int64(1)
+        );
+        assert_eq!(call("max", &[Int64(3), Null]).unwrap(), Null);
+        assert_eq!(call("nullif", &[Int64(1), text("1")]).unwrap(), Int64(1));
+        assert_eq!(call("typeof", &[True]).unwrap(), text("integer"));
+        assert!(call("likelihood", &[Int64(1), Float(1.5)]).is_err());
+        assert!(call("substr", &[text("x")]).is_err());
+    }
+}
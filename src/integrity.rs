@@ -0,0 +1,501 @@
+//! The checks `PRAGMA integrity_check` makes of the structure of the file:
+//! that each b-tree is well formed, and that every page is used exactly once,
+//! by a b-tree, an overflow chain or the freelist. Problems are described in
+//! the words of SQLite's `sqlite3BtreeIntegrityCheck()`.
+//! See https://www.sqlite.org/fileformat.html#b_tree_pages
+
+use crate::db::Database;
+use crate::freelist::Freelist;
+use crate::page::parse_cell;
+
+/// The page holding the byte at this offset, where SQLite puts its locks,
+/// is never used.
+const PENDING_BYTE: usize = 0x40000000;
+
+/// What checking the b-trees found.
+pub struct Report {
+    /// The problems found, each on a line of its own.
+    pub errors: Vec<String>,
+    /// The number of entries of each b-tree checked: the rows of a table,
+    /// or the entries of an index.
+    pub entries: Vec<u64>,
+    /// Whether a problem was found in each b-tree checked, which makes
+    /// reading its entries unsafe.
+    pub damaged: Vec<bool>,
+}
+
+/// Check the b-trees with the given root pages, stopping after
+/// `max_errors` problems. With `whole_file`, also check the freelist and
+/// that every page of the file is used; otherwise only these b-trees are
+/// looked at, as for a single table.
+pub fn check(db: &Database, roots: &[u32], max_errors: usize, whole_file: bool) -> Report {
+    let page_count = db.size();
+    let mut check = Checker {
+        db,
+        usable: db.info.usable_size() as u32,
+        page_count,
+        used: vec![false; page_count as usize + 1],
+        bad: vec![false; page_count as usize + 1],
+        errors: vec![],
+        remaining: max_errors,
+        prefix: Prefix::None,
+        root: 0,
+        page: 0,
+        cell: 0,
+        entries: 0,
+        damaged: false,
+    };
+    let pending = PENDING_BYTE / db.info.page_size() + 1;
+    if let Some(used) = check.used.get_mut(pending) {
+        *used = true;
+    }
+
+    if whole_file {
+        check.prefix = Prefix::Freelist;
+        check.freelist(&Freelist::read(db));
+        check.prefix = Prefix::None;
+    }
+
+    let mut report = Report {
+        errors: vec![],
+        entries: vec![],
+        damaged: vec![],
+    };
+    for &root in roots {
+        check.entries = 0;
+        check.damaged = false;
+        check.root = root;
+        if root != 0 && check.remaining > 0 {
+            check.tree(root, &mut 0, i64::MAX);
+        }
+        report.entries.push(check.entries);
+        report.damaged.push(check.damaged);
+    }
+
+    if whole_file {
+        for page in 1..=page_count {
+            if check.remaining == 0 {
+                break;
+            }
+            let pointer_map = db.info.auto_vacuum() && check.pointer_map_page(page) == page;
+            match (check.used[page as usize], pointer_map) {
+                (false, false) => check.error(format!("Page {page}: never used")),
+                (true, true) => check.error(format!("Page {page}: pointer map referenced")),
+                _ => {}
+            }
+        }
+    }
+
+    report.errors = check.errors;
+    report
+}
+
+/// What a problem is found in, which starts its message.
+#[derive(Clone, Copy)]
+enum Prefix {
+    None,
+    Freelist,
+    /// A page of a b-tree.
+    Page,
+    /// A cell of a page of a b-tree.
+    Cell,
+}
+
+struct Checker<'d> {
+    db: &'d Database,
+    usable: u32,
+    page_count: u32,
+    /// Whether each page has been seen, by page number.
+    used: Vec<bool>,
+    /// Whether each page has a problem that stops it being read.
+    bad: Vec<bool>,
+    errors: Vec<String>,
+    /// How many more problems to report.
+    remaining: usize,
+    prefix: Prefix,
+    /// The root page of the b-tree being checked, and the page and cell the
+    /// prefix names. Like SQLite, a page inherits the cell of its parent
+    /// until it looks at its own cells.
+    root: u32,
+    page: u32,
+    cell: usize,
+    /// The entries counted in the b-tree being checked.
+    entries: u64,
+    /// Whether a problem was found in the b-tree being checked that would
+    /// stop its pages being read.
+    damaged: bool,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, message: String) {
+        if self.remaining == 0 {
+            return;
+        }
+        self.remaining -= 1;
+        let prefix = match self.prefix {
+            Prefix::None => String::new(),
+            Prefix::Freelist => "Freelist: ".to_string(),
+            Prefix::Page => format!("Tree {} page {}: ", self.root, self.page),
+            Prefix::Cell => format!("Tree {} page {} cell {}: ", self.root, self.page, self.cell),
+        };
+        self.errors.push(prefix + &message);
+    }
+
+    /// Report a problem that stops the b-tree being read.
+    fn damage(&mut self, message: &str) {
+        self.damaged = true;
+        if let Some(bad) = self.bad.get_mut(self.page as usize) {
+            *bad = true;
+        }
+        self.error(message.to_string());
+    }
+
+    /// Mark a page as used, reporting a problem (and returning true) if it
+    /// is not a page of the file or was used before.
+    fn reference(&mut self, page: u32) -> bool {
+        if page == 0 || page > self.page_count {
+            self.error(format!("invalid page number {page}"));
+            return true;
+        }
+        if self.used[page as usize] {
+            self.error(format!("2nd reference to page {page}"));
+            return true;
+        }
+        self.used[page as usize] = true;
+        false
+    }
+
+    /// Check that the pages of the freelist are used by nothing else, and
+    /// that there are as many as the header says.
+    fn freelist(&mut self, freelist: &Freelist) {
+        let errors = self.errors.len();
+        let expected = freelist.expected;
+        let mut left = expected;
+        let mut ended = true;
+        for trunk in &freelist.trunks {
+            if self.remaining == 0 || self.reference(trunk.page) {
+                ended = false;
+                break;
+            }
+            left = left.wrapping_sub(1);
+            if trunk.too_many {
+                let page = trunk.page;
+                self.error(format!("freelist leaf count too big on page {page}"));
+                // SQLite counts such a page twice
+                left = left.wrapping_sub(1);
+            } else {
+                for &leaf in &trunk.leaves {
+                    self.reference(leaf);
+                }
+                left = left.wrapping_sub(trunk.leaves.len() as u32);
+            }
+        }
+        if let (Some(page), true) = (freelist.broken, ended && self.remaining > 0) {
+            if !self.reference(page) {
+                self.error(format!("failed to get page {page}"));
+            }
+        }
+        if left != 0 && errors == self.errors.len() {
+            let found = expected.wrapping_sub(left);
+            self.error(format!("size is {found} but should be {expected}"));
+        }
+    }
+
+    /// Check the chain of `expected` overflow pages of a cell from `first`.
+    fn overflow(&mut self, first: u32, expected: u32) {
+        let errors = self.errors.len();
+        let mut left = expected;
+        let mut page = first;
+        while page != 0 && self.remaining > 0 {
+            if self.reference(page) {
+                break;
+            }
+            left = left.wrapping_sub(1);
+            let Ok(data) = self.db.raw_page(page) else {
+                self.error(format!("failed to get page {page}"));
+                break;
+            };
+            page = be_u32(data, 0);
+        }
+        if left != 0 && errors == self.errors.len() {
+            let found = expected.wrapping_sub(left);
+            self.error(format!(
+                "overflow list length is {found} but should be {expected}"
+            ));
+        }
+    }
+
+    /// Check the page `page` of the b-tree being checked and the pages
+    /// below it, returning its depth: 0 for a leaf. The rowids of a table
+    /// b-tree must be below `max_key`, which the first (rightmost) entry
+    /// of a leaf may also equal; the smallest rowid found is put in
+    /// `min_key`.
+    fn tree(&mut self, page: u32, min_key: &mut i64, mut max_key: i64) -> i32 {
+        if page == 0 {
+            return 0;
+        }
+        if self.reference(page) {
+            // reading a leaf twice is harmless, but another page could
+            // make a loop
+            let bad = self.bad.get(page as usize).copied().unwrap_or(true);
+            let hdr = if page == 1 { 100 } else { 0 };
+            let leaf = self
+                .db
+                .raw_page(page)
+                .is_ok_and(|data| matches!(data[hdr], 0x0a | 0x0d));
+            self.damaged |= bad || !leaf;
+            return 0;
+        }
+        let saved = (self.prefix, self.page, self.cell);
+        self.prefix = Prefix::Page;
+        self.page = page;
+        let depth = self.tree_page(page, min_key, &mut max_key);
+        (self.prefix, self.page, self.cell) = saved;
+        depth + 1
+    }
+
+    fn tree_page(&mut self, page: u32, min_key: &mut i64, max_key: &mut i64) -> i32 {
+        let data = match self.db.raw_page(page) {
+            Ok(data) => data,
+            Err(_) => {
+                self.damage("unable to get the page. error code=11");
+                return -1;
+            }
+        };
+        let hdr = if page == 1 { 100 } else { 0 };
+        let (leaf, int_key) = match data[hdr] {
+            0x02 => (false, false),
+            0x05 => (false, true),
+            0x0a => (true, false),
+            0x0d => (true, true),
+            _ => {
+                self.damage("btreeInitPage() returns error code 11");
+                return -1;
+            }
+        };
+        let cells = be_u16(data, hdr + 3) as usize;
+        if cells > (data.len() - 8) / 6 {
+            self.damage("btreeInitPage() returns error code 11");
+            return -1;
+        }
+        let cell_start = hdr + if leaf { 8 } else { 12 };
+        let content = match be_u16(data, hdr + 5) {
+            0 => 65536,
+            n => n,
+        };
+        if !self.free_space_ok(data, hdr, cell_start + 2 * cells, content) {
+            self.error("free space corruption".to_string());
+            return -1;
+        }
+        self.prefix = Prefix::Cell;
+
+        if leaf || !int_key {
+            self.entries += cells as u64;
+        }
+        let mut depth = -1;
+        let mut key_can_be_equal = true;
+        let mut intervals = vec![];
+        let mut coverage = true;
+        if !leaf {
+            depth = self.tree(be_u32(data, hdr + 8), max_key, *max_key);
+            key_can_be_equal = false;
+        }
+        // the cells from right to left, each bounding the keys to its left
+        for i in (0..cells).rev() {
+            if self.remaining == 0 {
+                break;
+            }
+            self.cell = i;
+            let pc = be_u16(data, cell_start + 2 * i);
+            if pc < content || pc > self.usable - 4 {
+                let end = self.usable - 4;
+                self.damage(&format!("Offset {pc} out of range {content}..{end}"));
+                coverage = false;
+                continue;
+            }
+            let cell = match parse_cell(data, pc as usize, leaf, int_key, self.usable) {
+                Some(cell) if pc + cell.size <= self.usable => cell,
+                _ => {
+                    self.damage("Extends off end of page");
+                    coverage = false;
+                    continue;
+                }
+            };
+            if int_key {
+                let out_of_order = match key_can_be_equal {
+                    true => cell.key > *max_key,
+                    false => cell.key >= *max_key,
+                };
+                if out_of_order {
+                    self.error(format!("Rowid {} out of order", cell.key));
+                }
+                *max_key = cell.key;
+                key_can_be_equal = false;
+            }
+            if cell.payload > cell.local {
+                let pages = (cell.payload - cell.local + self.usable - 5) / (self.usable - 4);
+                let first = be_u32(data, (pc + cell.size - 4) as usize);
+                self.overflow(first, pages);
+            }
+            if leaf {
+                intervals.push((pc, pc + cell.size - 1));
+            } else {
+                let child = self.tree(be_u32(data, pc as usize), max_key, *max_key);
+                key_can_be_equal = false;
+                if child != depth {
+                    self.error("Child page depth differs".to_string());
+                    depth = child;
+                }
+            }
+        }
+        *min_key = *max_key;
+
+        self.prefix = Prefix::None;
+        if coverage && self.remaining > 0 {
+            self.check_coverage(data, hdr, page, cell_start, cells, leaf, int_key, intervals);
+        }
+        depth
+    }
+
+    /// Whether the freeblocks of a page are in order and within the page,
+    /// and the free space they and the fragments make fits between the cell
+    /// pointers and the end of the page, as SQLite's btreeComputeFreeSpace()
+    /// requires.
+    fn free_space_ok(&self, data: &[u8], hdr: usize, first_cell: usize, content: u32) -> bool {
+        let mut free = data[hdr + 7] as u32 + content;
+        let mut pc = be_u16(data, hdr + 1);
+        if pc > 0 {
+            if pc < content {
+                return false;
+            }
+            let (next, size) = loop {
+                if pc > self.usable - 4 {
+                    return false;
+                }
+                let next = be_u16(data, pc as usize);
+                let size = be_u16(data, pc as usize + 2);
+                free += size;
+                if next <= pc + size + 3 {
+                    break (next, size);
+                }
+                pc = next;
+            };
+            if next > 0 || pc + size > self.usable {
+                return false;
+            }
+        }
+        free <= self.usable && free >= first_cell as u32
+    }
+
+    /// Check that the cells and freeblocks of a page cover the cell content
+    /// area without overlapping, and that the bytes left over are the
+    /// fragments the page header counts.
+    #[allow(clippy::too_many_arguments)]
+    fn check_coverage(
+        &mut self,
+        data: &[u8],
+        hdr: usize,
+        page: u32,
+        cell_start: usize,
+        cells: usize,
+        leaf: bool,
+        int_key: bool,
+        mut intervals: Vec<(u32, u32)>,
+    ) {
+        if !leaf {
+            for i in 0..cells {
+                let pc = be_u16(data, cell_start + 2 * i);
+                if let Some(cell) = parse_cell(data, pc as usize, leaf, int_key, self.usable) {
+                    intervals.push((pc, pc + cell.size - 1));
+                }
+            }
+        }
+        let mut freeblock = be_u16(data, hdr + 1);
+        while freeblock > 0 {
+            let size = be_u16(data, freeblock as usize + 2);
+            intervals.push((freeblock, freeblock + size - 1));
+            freeblock = be_u16(data, freeblock as usize);
+        }
+        intervals.sort_unstable();
+
+        let content = match be_u16(data, hdr + 5) {
+            0 => 65536,
+            n => n,
+        };
+        let mut fragments = 0;
+        let mut prev = content - 1;
+        for &(start, end) in &intervals {
+            if prev & 0xffff >= start {
+                self.error(format!("Multiple uses for byte {start} of page {page}"));
+                return;
+            }
+            fragments += start - (prev & 0xffff) - 1;
+            prev = end;
+        }
+        fragments += self.usable - (prev & 0xffff) - 1;
+        if fragments != data[hdr + 7] as u32 {
+            self.error(format!(
+                "Fragmentation of {fragments} bytes reported as {} on page {page}",
+                data[hdr + 7]
+            ));
+        }
+    }
+
+    /// The pointer map page that would hold the entry of `page`.
+    fn pointer_map_page(&self, page: u32) -> u32 {
+        if page < 2 {
+            return 0;
+        }
+        let per_map = self.usable / 5 + 1;
+        let map = (page - 2) / per_map * per_map + 2;
+        let pending = (PENDING_BYTE / self.db.info.page_size() + 1) as u32;
+        if map == pending {
+            map + 1
+        } else {
+            map
+        }
+    }
+}
+
+fn be_u16(data: &[u8], i: usize) -> u32 {
+    u16::from_be_bytes([data[i], data[i + 1]]) as u32
+}
+
+fn be_u32(data: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::open_bytes;
+
+    #[test]
+    fn test_check() {
+        let mut data = std::fs::read("sample.db").unwrap();
+        let report = check(&open_bytes(&data), &[1, 2, 3, 4], 100, true);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.entries, vec![3, 4, 2, 6]);
+
+        // the pages of a table left out are never used
+        let report = check(&open_bytes(&data), &[1, 2, 3], 100, true);
+        assert_eq!(report.errors, vec!["Page 4: never used"]);
+        assert_eq!(check(&open_bytes(&data), &[2], 100, false).errors.len(), 0);
+
+        // a bad page type, and more fragments than the page has
+        let page = 4096 * 3;
+        data[page] = 0x0e;
+        data[4096 + 7] = 9;
+        let report = check(&open_bytes(&data), &[1, 2, 3, 4], 1, true);
+        assert_eq!(
+            report.errors,
+            vec!["Fragmentation of 0 bytes reported as 9 on page 2"]
+        );
+        let report = check(&open_bytes(&data), &[1, 2, 3, 4], 100, true);
+        assert_eq!(
+            report.errors[1],
+            "Tree 4 page 4: btreeInitPage() returns error code 11"
+        );
+        assert_eq!(report.damaged, vec![false, false, false, true]);
+    }
+}
@@ -0,0 +1,307 @@
+use std::ops::Range;
+
+use anyhow::{anyhow, bail};
+
+/// A SQL token. Keywords are not distinguished from identifiers here; the
+/// parser matches them case-insensitively, as SQLite does.
+/// See https://www.sqlite.org/lang_keywords.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// A bare identifier or keyword.
+    Ident(String),
+    /// An identifier quoted with `"..."`, `[...]` or `` `...` ``.
+    QuotedIdent(String),
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Blob(Vec<u8>),
+    /// A parameter, `?`, `?NNN`, `:name`, `@name` or `$name`, with its
+    /// prefix.
+    Variable(String),
+    Symbol(&'static str),
+    Eof,
+}
+
+const SYMBOLS: [&str; 26] = [
+    "||", "<<", ">>", "<=", ">=", "==", "!=", "<>", "->>", "->", "(", ")", ",", ";", ".", "+", "-",
+    "*", "/", "%", "<", ">", "=", "&", "|", "~",
+];
+
+/// Tokenize, returning each token with the byte range of the input it covers.
+pub fn tokenize_spanned(sql: &str) -> Result<Vec<(Token, Range<usize>)>, anyhow::Error> {
+    let chars = sql.chars().collect::<Vec<_>>();
+    let mut offsets = sql.char_indices().map(|(o, _)| o).collect::<Vec<_>>();
+    offsets.push(sql.len());
+
+    let mut tokens = vec![];
+    let mut i = 0;
+    loop {
+        skip_trivia(&chars, &mut i);
+        if i >= chars.len() {
+            break;
+        }
+        let start = i;
+        let token = read_token(&chars, &mut i)?;
+        tokens.push((token, offsets[start]..offsets[i.min(chars.len())]));
+    }
+
+    tokens.push((Token::Eof, sql.len()..sql.len()));
+    Ok(tokens)
+}
+
+/// Skip whitespace and comments.
+fn skip_trivia(chars: &[char], i: &mut usize) {
+    while *i < chars.len() {
+        if chars[*i].is_whitespace() {
+            *i += 1;
+        } else if chars[*i] == '-' && chars.get(*i + 1) == Some(&'-') {
+            while *i < chars.len() && chars[*i] != '\n' {
+                *i += 1;
+            }
+        } else if chars[*i] == '/' && chars.get(*i + 1) == Some(&'*') {
+            *i += 2;
+            while *i < chars.len() && !(chars[*i] == '*' && chars.get(*i + 1) == Some(&'/')) {
+                *i += 1;
+            }
+            *i = (*i + 2).min(chars.len());
+        } else {
+            break;
+        }
+    }
+}
+
+fn read_token(chars: &[char], i: &mut usize) -> Result<Token, anyhow::Error> {
+    let c = chars[*i];
+
+    // blob literal x'0a0b'
+    if (c == 'x' || c == 'X') && chars.get(*i + 1) == Some(&'\'') {
+        let (hex, next) = read_quoted(chars, *i + 1, '\'')?;
+        if hex.len() % 2 != 0 {
+            bail!("malformed blob literal: x'{hex}'");
+        }
+        let blob = (0..hex.len())
+            .step_by(2)
+            .map(|j| u8::from_str_radix(&hex[j..j + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| anyhow!("malformed blob literal: x'{hex}'"))?;
+        *i = next;
+        return Ok(Token::Blob(blob));
+    }
+
+    if c.is_alphabetic() || c == '_' {
+        let start = *i;
+        while *i < chars.len()
+            && (chars[*i].is_alphanumeric() || chars[*i] == '_' || chars[*i] == '$')
+        {
+            *i += 1;
+        }
+        return Ok(Token::Ident(chars[start..*i].iter().collect()));
+    }
+
+    if c.is_ascii_digit() || (c == '.' && chars.get(*i + 1).is_some_and(|c| c.is_ascii_digit())) {
+        let (token, next) = read_number(chars, *i)?;
+        *i = next;
+        return Ok(token);
+    }
+
+    match c {
+        '?' => {
+            let start = *i;
+            *i += 1;
+            while *i < chars.len() && chars[*i].is_ascii_digit() {
+                *i += 1;
+            }
+            Ok(Token::Variable(chars[start..*i].iter().collect()))
+        }
+        ':' | '@' | '$' => {
+            let start = *i;
+            *i += 1;
+            while *i < chars.len()
+                && (chars[*i].is_alphanumeric() || chars[*i] == '_' || chars[*i] == '$')
+            {
+                *i += 1;
+            }
+            if *i == start + 1 {
+                bail!("unrecognized token: \"{c}\"");
+            }
+            Ok(Token::Variable(chars[start..*i].iter().collect()))
+        }
+        '\'' => {
+            let (s, next) = read_quoted(chars, *i, '\'')?;
+            *i = next;
+            Ok(Token::String(s))
+        }
+        '"' | '`' => {
+            let (s, next) = read_quoted(chars, *i, c)?;
+            *i = next;
+            Ok(Token::QuotedIdent(s))
+        }
+        '[' => {
+            let start = *i + 1;
+            while *i < chars.len() && chars[*i] != ']' {
+                *i += 1;
+            }
+            if *i == chars.len() {
+                bail!("unterminated [identifier]");
+            }
+            *i += 1;
+            Ok(Token::QuotedIdent(chars[start..*i - 1].iter().collect()))
+        }
+        _ => {
+            let rest = chars[*i..chars.len().min(*i + 3)]
+                .iter()
+                .collect::<String>();
+            let Some(sym) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) else {
+                bail!("unrecognized token: \"{c}\"");
+            };
+            *i += sym.len();
+            Ok(Token::Symbol(sym))
+        }
+    }
+}
+
+/// Read a quoted string starting at the opening quote; a doubled quote
+/// character inside the string stands for a single one.
+fn read_quoted(
+    chars: &[char],
+    start: usize,
+    quote: char,
+) -> Result<(String, usize), anyhow::Error> {
+    let mut s = String::new();
+    let mut i = start + 1;
+    loop {
+        match chars.get(i) {
+            None => bail!("unterminated string"),
+            Some(c) if *c == quote => {
+                if chars.get(i + 1) == Some(&quote) {
+                    s.push(quote);
+                    i += 2;
+                } else {
+                    return Ok((s, i + 1));
+                }
+            }
+            Some(c) => {
+                s.push(*c);
+                i += 1;
+            }
+        }
+    }
+}
+
+fn read_number(chars: &[char], start: usize) -> Result<(Token, usize), anyhow::Error> {
+    let mut i = start;
+
+    if chars[i] == '0' && matches!(chars.get(i + 1), Some('x') | Some('X')) {
+        i += 2;
+        let digits_start = i;
+        while i < chars.len() && chars[i].is_ascii_hexdigit() {
+            i += 1;
+        }
+        let digits = chars[digits_start..i].iter().collect::<String>();
+        // hex literals are 64-bit twos-complement, so 0xffffffffffffffff is -1
+        let n = u64::from_str_radix(&digits, 16)
+            .map_err(|_| anyhow!("hex literal too big: 0x{digits}"))?;
+        return Ok((Token::Integer(n as i64), i));
+    }
+
+    let mut is_float = false;
+    while i < chars.len() && chars[i].is_ascii_digit() {
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') {
+        is_float = true;
+        i += 1;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+        }
+    }
+    if matches!(chars.get(i), Some('e') | Some('E')) {
+        let mut j = i + 1;
+        if matches!(chars.get(j), Some('+') | Some('-')) {
+            j += 1;
+        }
+        if chars.get(j).is_some_and(|c| c.is_ascii_digit()) {
+            is_float = true;
+            i = j;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+    }
+
+    let text = chars[start..i].iter().collect::<String>();
+    if !is_float {
+        // integers too large for 64 bits become floating point, as in SQLite
+        if let Ok(n) = text.parse::<i64>() {
+            return Ok((Token::Integer(n), i));
+        }
+    }
+
+    let f = text
+        .parse::<f64>()
+        .map_err(|_| anyhow!("malformed number: {text}"))?;
+    Ok((Token::Float(f), i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenize(sql: &str) -> Result<Vec<Token>, anyhow::Error> {
+        Ok(tokenize_spanned(sql)?.into_iter().map(|(t, _)| t).collect())
+    }
+
+    #[test]
+    fn test_tokenize_select() {
+        let tokens = tokenize("SELECT name, \"Color\" FROM apples WHERE id >= 2 -- done").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("SELECT".into()),
+                Token::Ident("name".into()),
+                Token::Symbol(","),
+                Token::QuotedIdent("Color".into()),
+                Token::Ident("FROM".into()),
+                Token::Ident("apples".into()),
+                Token::Ident("WHERE".into()),
+                Token::Ident("id".into()),
+                Token::Symbol(">="),
+                Token::Integer(2),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_literals() {
+        let tokens = tokenize("'it''s' x'0aFF' 1.5e3 0x10 .5 9223372036854775808").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::String("it's".into()),
+                Token::Blob(vec![0x0a, 0xff]),
+                Token::Float(1500.0),
+                Token::Integer(16),
+                Token::Float(0.5),
+                Token::Float(9223372036854775808.0),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_variables() {
+        let tokens = tokenize("? ?12 :a1 @b $c$ ?x").unwrap();
+        let variables = ["?", "?12", ":a1", "@b", "$c$", "?"];
+        for (token, variable) in tokens.iter().zip(variables) {
+            assert_eq!(token, &Token::Variable(variable.into()));
+        }
+        assert_eq!(tokens[6], Token::Ident("x".into()));
+        assert!(tokenize("select :").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_unterminated() {
+        assert!(tokenize("'abc").is_err());
+    }
+}
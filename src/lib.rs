@@ -0,0 +1,50 @@
+//! A reader for SQLite database files: it opens a database, parses SQL and
+//! runs queries against it without writing to the file.
+//!
+//! ```no_run
+//! use std::cmp::Ordering;
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! use codecrafters_sqlite::{parser, ColumnType, Database, Executor};
+//!
+//! let mut reader = BufReader::new(File::open("app.db")?);
+//! let db = Database::open(&mut reader)?;
+//! let mut executor = Executor::new(&db);
+//! executor.register_collation("reverse", |a, b| b.cmp(a));
+//! let mut statement = parser::parse("SELECT name FROM apples WHERE color = ?1")?;
+//! statement.bind(1, ColumnType::String("Red".to_string()))?;
+//! for row in executor.execute(&statement)?.rows {
+//!     println!("{row:?}");
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod analyze;
+pub mod ast;
+mod btree;
+mod collation;
+mod convert;
+mod datetime;
+pub mod db;
+pub mod exec;
+mod explain;
+pub mod freelist;
+pub mod functions;
+mod integrity;
+mod lexer;
+mod page;
+pub mod parser;
+mod pattern;
+mod planner;
+mod stats;
+pub mod table;
+mod util;
+mod value;
+mod vdbe;
+mod window;
+
+pub use ast::Statement;
+pub use db::Database;
+pub use exec::{Executor, ResultSet};
+pub use table::ColumnType;
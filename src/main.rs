@@ -1,11 +1,21 @@
 use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::BufReader;
 
-mod db;
-mod page;
-mod table;
-mod util;
+use codecrafters_sqlite::table::ColumnType;
+use codecrafters_sqlite::{analyze, ast, db, exec, freelist, functions, parser};
+
+/// Values for statement parameters set with `.parameter set`, by name.
+type Parameters = BTreeMap<String, ColumnType>;
+
+/// The settings of the shell that last from one command to the next.
+#[derive(Default)]
+struct Session {
+    parameters: Parameters,
+    /// `.eqp on`: show the query plan of each statement before its results.
+    eqp: bool,
+}
 
 fn main() -> Result<()> {
     // Parse arguments
@@ -18,20 +28,177 @@ fn main() -> Result<()> {
 
     let file = File::open(&args[1])?;
     let mut reader = BufReader::new(&file);
-    let mut file_header = [0; 100];
-    let db_info = db::open_db(&mut reader, &mut file_header).context("open_db")?;
-    let page_info = db_info.read_page(&mut reader, Some(file_header))?;
+    let mut db = db::Database::open(&mut reader).context("open database")?;
+    db.path = std::fs::canonicalize(&args[1])?.display().to_string();
+
+    // each further argument is a command, run in order in one session
+    let executor = exec::Executor::new(&db);
+    let mut session = Session::default();
+    for command in &args[2..] {
+        run_command(&db, &executor, &mut session, command)?;
+    }
+
+    Ok(())
+}
 
-    // Parse command and act accordingly
-    let command = &args[2];
-    match command.as_str() {
+fn run_command(
+    db: &db::Database,
+    executor: &exec::Executor,
+    session: &mut Session,
+    command: &str,
+) -> Result<()> {
+    match command {
         ".dbinfo" => {
-            println!("database page size: {}", db_info.page_size);
+            let page_info = db.read_page(1)?;
+            println!("database page size: {}", db.info.page_size);
             println!("number of tables: {}", page_info.num_cells);
         }
-        ".tables" => page_info.tables.iter().for_each(|t| println!("{}", t.name)),
-        _ => bail!("Missing or invalid command passed: {}", command),
+        ".tables" => db
+            .schema
+            .iter()
+            .filter(|t| t.typ == "table" || t.typ == "view")
+            .for_each(|t| println!("{}", t.name)),
+        ".freelist" => freelist_command(db),
+        c if c.split_whitespace().next() == Some(".analyze") => {
+            let analysis = analyze::analyze(db);
+            match split_args(command)[1..] {
+                [] => print!("{analysis}"),
+                [ref json] if json == "json" => println!("{}", analysis.to_json()),
+                _ => bail!("Usage: .analyze ?json?"),
+            }
+        }
+        c if c.split_whitespace().next() == Some(".parameter") => {
+            parameter_command(executor, &mut session.parameters, &split_args(command)[1..])?
+        }
+        c if c.split_whitespace().next() == Some(".eqp") => {
+            session.eqp = match split_args(command)[1..] {
+                [ref on] if on == "on" => true,
+                [ref off] if off == "off" => false,
+                _ => bail!("Usage: .eqp on|off"),
+            }
+        }
+        c if c.starts_with('.') => bail!("Missing or invalid command passed: {}", command),
+        sql => {
+            for mut statement in parser::parse_statements(sql)? {
+                for i in 1..=statement.parameter_count() {
+                    let value = statement
+                        .parameter_name(i)
+                        .and_then(|name| session.parameters.get(name))
+                        .cloned()
+                        .unwrap_or(ColumnType::Null);
+                    statement.bind(i, value)?;
+                }
+                if statement.explain == Some(ast::Explain::QueryPlan) {
+                    let plan = executor.query_plan(&statement)?;
+                    print!("{plan}");
+                    println!("{}", plan.cost_summary());
+                    continue;
+                }
+                if statement.explain == Some(ast::Explain::Program) {
+                    print!("{}", executor.program(&statement)?);
+                    continue;
+                }
+                if session.eqp {
+                    print!("{}", executor.query_plan(&statement)?);
+                }
+                let result = executor.execute(&statement)?;
+                for row in result.rows {
+                    let line = row.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                    println!("{}", line.join("|"));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `.freelist`: the free pages, each trunk page with the leaf pages it
+/// lists, and the space VACUUM would give back by dropping them, followed by
+/// anything wrong with the freelist.
+fn freelist_command(db: &db::Database) {
+    let freelist = freelist::Freelist::read(db);
+    let free = freelist.pages().count();
+    let pages = db.size();
+    println!("free pages: {free} of {pages}");
+    for trunk in &freelist.trunks {
+        let leaves = trunk.leaves.iter().map(|p| p.to_string());
+        match leaves.collect::<Vec<_>>().join(" ") {
+            none if none.is_empty() => println!("trunk {}, no leaves", trunk.page),
+            leaves => println!("trunk {}, leaves {leaves}", trunk.page),
+        }
+    }
+    let bytes = free * db.info.page_size();
+    let percent = if pages == 0 {
+        0.0
+    } else {
+        free as f64 * 100.0 / pages as f64
+    };
+    println!("reclaimable by VACUUM: {bytes} bytes ({percent:.1}% of the file)");
+    for problem in freelist.problems(db) {
+        println!("problem: {problem}");
     }
+}
 
+/// `.parameter set|unset|list|clear`, like sqlite3's. A value that is not a
+/// valid SQL expression is taken as text.
+fn parameter_command(
+    executor: &exec::Executor,
+    parameters: &mut Parameters,
+    args: &[String],
+) -> Result<()> {
+    let args = args.iter().map(|a| a.as_str()).collect::<Vec<_>>();
+    match args[..] {
+        ["set", name, value] => {
+            if !name.starts_with(['$', ':', '@', '?']) {
+                bail!("PARAMETER should start with one of: $ : @ ?");
+            }
+            let evaluated = parser::parse(&format!("SELECT {value}"))
+                .and_then(|statement| executor.execute(&statement))
+                .ok()
+                .and_then(|result| result.rows.into_iter().next())
+                .and_then(|row| row.into_iter().next());
+            let value = evaluated.unwrap_or_else(|| ColumnType::String(value.to_string()));
+            parameters.insert(name.to_string(), value);
+        }
+        ["unset", name] => {
+            parameters.remove(name);
+        }
+        ["clear"] | ["init"] => parameters.clear(),
+        ["list"] => {
+            let width = parameters.keys().map(|k| k.len()).max().unwrap_or(0);
+            for (name, value) in parameters.iter() {
+                println!("{name:width$} {}", functions::quote(value));
+            }
+        }
+        _ => bail!("Usage: .parameter set PARAMETER VALUE | unset PARAMETER | list | clear"),
+    }
     Ok(())
 }
+
+/// Split a dot command into arguments at whitespace; single or double
+/// quotes group words into one argument and are removed.
+fn split_args(command: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut chars = command.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut arg = String::new();
+        if c == '\'' || c == '"' {
+            chars.next();
+            arg.extend(chars.by_ref().take_while(|&d| d != c));
+        } else {
+            while let Some(&d) = chars.peek() {
+                if d.is_whitespace() {
+                    break;
+                }
+                arg.push(d);
+                chars.next();
+            }
+        }
+        args.push(arg);
+    }
+    args
+}
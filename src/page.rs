@@ -1,7 +1,10 @@
-use crate::table::{ColumnType, TableInfo};
+use anyhow::{bail, Context};
+
+use crate::db::Database;
+use crate::table::ColumnType;
 use crate::util;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum PageType {
     InternalIndex,
     InternalTable,
@@ -12,17 +15,34 @@ pub enum PageType {
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct PageInfo {
-    page_type: PageType,
+    pub(crate) page_type: PageType,
     first_freeblock_idx: u16,
     pub num_cells: u16,
     cell_start_idx: u16,
     num_fragments: u8,
-    rightmost_pointer: Option<u32>, // only PageType::Internal*
-    pub tables: Vec<TableInfo>,
+    pub(crate) rightmost_pointer: Option<u32>, // only PageType::Internal*
+    pub(crate) cells: Vec<Cell>,
+}
+
+/// A cell of a b-tree page, decoded according to the page type.
+/// See https://www.sqlite.org/fileformat.html#b_tree_pages
+#[allow(dead_code)]
+#[derive(Debug)]
+pub(crate) enum Cell {
+    TableLeaf(TableLeaf),
+    TableInterior { left_child: u32, row_id: i64 },
+    IndexLeaf { payload: DbRecord },
+    IndexInterior { left_child: u32, payload: DbRecord },
 }
 
 impl PageInfo {
-    pub fn read(buf: Vec<u8>, page_start: usize) -> Result<PageInfo, anyhow::Error> {
+    pub fn read(
+        db: &Database,
+        page_num: u32,
+        page_start: usize,
+    ) -> Result<PageInfo, anyhow::Error> {
+        let buf = db.raw_page(page_num)?;
+
         // https://www.sqlite.org/fileformat.html#b_tree_pages
         // The b-tree page header is 8 bytes in size for leaf pages and 12 bytes for interior pages.
 
@@ -42,7 +62,7 @@ impl PageInfo {
             0x05 => PageType::InternalTable,
             0x0a => PageType::LeafIndex,
             0x0d => PageType::LeafTable,
-            _e => panic!("invalid page type {_e}"),
+            e => bail!("invalid page type {e} on page {page_num}"),
         };
 
         let mut page_info = PageInfo {
@@ -52,69 +72,105 @@ impl PageInfo {
             cell_start_idx: u16::from_be_bytes([page_header[5], page_header[6]]),
             num_fragments: u8::from_be_bytes([page_header[7]]),
             rightmost_pointer: None,
-            tables: vec![],
+            cells: vec![],
         };
 
-        let mut offset = 8usize;
+        let mut offset = page_start + 8;
 
         if page_info.page_type == PageType::InternalIndex
             || page_info.page_type == PageType::InternalTable
         {
-            page_info.rightmost_pointer =
-                Some(u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]));
+            page_info.rightmost_pointer = Some(u32::from_be_bytes([
+                buf[offset],
+                buf[offset + 1],
+                buf[offset + 2],
+                buf[offset + 3],
+            ]));
             offset += 4;
         }
 
-        page_info.read_cells(buf, page_start + offset)?;
+        page_info
+            .read_cells(db, buf, offset)
+            .with_context(|| format!("read cells of page {page_num}"))?;
 
         Ok(page_info)
     }
 
     // buf is the full page content, so offset is where the cell ptrs start
-    fn read_cells(&mut self, buf: Vec<u8>, offset: usize) -> Result<(), anyhow::Error> {
+    fn read_cells(
+        &mut self,
+        db: &Database,
+        buf: &[u8],
+        offset: usize,
+    ) -> Result<(), anyhow::Error> {
         let mut cell_ptrs = vec![];
-        for i in 0..self.num_cells {
-            cell_ptrs.push(u16::from_be_bytes([
-                buf[offset + (i * 2) as usize],
-                buf[offset + (i * 2 + 1) as usize],
-            ]));
+        for i in 0..self.num_cells as usize {
+            let ptr = bytes(buf, offset + i * 2, 2).context("cell pointer array")?;
+            cell_ptrs.push(u16::from_be_bytes([ptr[0], ptr[1]]));
         }
 
-        match self.page_type {
-            PageType::LeafTable => {
-                for cell_ptr in cell_ptrs {
-                    let (cell, mut cell_ptr_offset) = TableLeaf::new(&buf, cell_ptr as usize)?;
-
-                    let mut col_types = Vec::new();
-                    let limit = cell_ptr_offset + cell.header_len;
-                    while cell_ptr_offset < limit {
-                        let (type_cd, varint_sz) = util::varint_unsigned(&buf[cell_ptr_offset..])?;
-                        col_types.push(type_cd);
-                        cell_ptr_offset += varint_sz;
+        for cell_ptr in cell_ptrs {
+            let cell_ptr = cell_ptr as usize;
+            let cell = match self.page_type {
+                PageType::LeafTable => Cell::TableLeaf(TableLeaf::new(db, buf, cell_ptr)?),
+                PageType::InternalTable => {
+                    let left_child = read_child_ptr(buf, cell_ptr)?;
+                    let (row_id, _) = varint_at(buf, cell_ptr + 4)?;
+                    Cell::TableInterior {
+                        left_child,
+                        row_id: row_id as i64,
                     }
+                }
+                PageType::LeafIndex => Cell::IndexLeaf {
+                    payload: read_index_payload(db, buf, cell_ptr)?,
+                },
+                PageType::InternalIndex => Cell::IndexInterior {
+                    left_child: read_child_ptr(buf, cell_ptr)?,
+                    payload: read_index_payload(db, buf, cell_ptr + 4)?,
+                },
+            };
+            self.cells.push(cell);
+        }
 
-                    assert_eq!(cell_ptr_offset, limit);
+        Ok(())
+    }
 
-                    // see https://www.sqlite.org/fileformat.html#record_format
+    /// Decode a record, i.e. a header of serial types followed by the column
+    /// values, into its columns.
+    /// See https://www.sqlite.org/fileformat.html#record_format
+    pub(crate) fn read_record(payload: &[u8]) -> Result<DbRecord, anyhow::Error> {
+        let (header_len, sz) = util::varint_unsigned(payload)?;
+        let header_len = header_len as usize;
+        if header_len > payload.len() {
+            bail!("record header length {header_len} exceeds payload");
+        }
 
-                    let mut record = DbRecord::new();
-                    let mut idx = limit;
-                    col_types.iter().for_each(|col_type| {
-                        let (len, val) = PageInfo::read_column(*col_type, &buf, idx);
-                        record.push(val);
-                        idx += len;
-                    });
+        let mut col_types = Vec::new();
+        let mut offset = sz;
+        while offset < header_len {
+            let (type_cd, varint_sz) = util::varint_unsigned(&payload[offset..])?;
+            col_types.push(type_cd);
+            offset += varint_sz;
+        }
 
-                    self.tables.push(TableInfo::new(record)?);
-                }
+        let mut record = DbRecord::new();
+        let mut idx = header_len;
+        for col_type in col_types {
+            if col_type == 10 || col_type == 11 {
+                bail!("reserved serial type {col_type}");
             }
-            _ => todo!(),
+            if idx + serial_type_len(col_type) > payload.len() {
+                bail!("record content overflows payload");
+            }
+            let (len, val) = PageInfo::read_column(col_type, payload, idx);
+            record.push(val);
+            idx += len;
         }
 
-        Ok(())
+        Ok(record)
     }
 
-    fn read_column(col_type: u64, buf: &[u8], idx: usize) -> (usize, ColumnType) {
+    pub(crate) fn read_column(col_type: u64, buf: &[u8], idx: usize) -> (usize, ColumnType) {
         match col_type {
             0 => (0, ColumnType::Null),
             1 => (1, ColumnType::Int8(i8::from_be_bytes([buf[idx]]))),
@@ -124,12 +180,10 @@ impl PageInfo {
             ),
             3 => (
                 3,
-                ColumnType::Int24(i32::from_be_bytes([
-                    0,
-                    buf[idx],
-                    buf[idx + 1],
-                    buf[idx + 2],
-                ])),
+                // shift back down to sign-extend the 24 bit value
+                ColumnType::Int24(
+                    i32::from_be_bytes([buf[idx], buf[idx + 1], buf[idx + 2], 0]) >> 8,
+                ),
             ),
             4 => (
                 4,
@@ -142,16 +196,18 @@ impl PageInfo {
             ),
             5 => (
                 6,
-                ColumnType::Int48(i64::from_be_bytes([
-                    0,
-                    buf[idx],
-                    buf[idx + 1],
-                    buf[idx + 2],
-                    buf[idx + 3],
-                    buf[idx + 4],
-                    buf[idx + 5],
-                    buf[idx + 6],
-                ])),
+                ColumnType::Int48(
+                    i64::from_be_bytes([
+                        buf[idx],
+                        buf[idx + 1],
+                        buf[idx + 2],
+                        buf[idx + 3],
+                        buf[idx + 4],
+                        buf[idx + 5],
+                        0,
+                        0,
+                    ]) >> 16,
+                ),
             ),
             6 => (
                 8,
@@ -182,7 +238,7 @@ impl PageInfo {
             8 => (0, ColumnType::False),
             9 => (0, ColumnType::True),
             10 | 11 => unimplemented!("reserved for future use"),
-            n if n / 2 == 0 => {
+            n if n % 2 == 0 => {
                 let len = ((n - 12) / 2) as usize;
                 let mut blob = &buf[idx..];
                 (
@@ -190,10 +246,10 @@ impl PageInfo {
                     ColumnType::Blob(Vec::from(util::read_len(&mut blob, len))),
                 )
             }
-            n if n / 2 != 0 => {
+            n if n % 2 != 0 => {
                 let len = ((n - 13) / 2) as usize;
                 let data = &buf[idx..(idx + len)];
-                let s = std::str::from_utf8(data).expect("valid string").to_string();
+                let s = String::from_utf8_lossy(data).to_string();
 
                 (len, ColumnType::String(s))
             }
@@ -204,6 +260,172 @@ impl PageInfo {
     }
 }
 
+/// The number of content bytes used by a value of the given serial type.
+pub(crate) fn serial_type_len(col_type: u64) -> usize {
+    match col_type {
+        0 | 8 | 9 | 10 | 11 => 0,
+        1 => 1,
+        2 => 2,
+        3 => 3,
+        4 => 4,
+        5 => 6,
+        6 | 7 => 8,
+        n if n % 2 == 0 => ((n - 12) / 2) as usize,
+        n => ((n - 13) / 2) as usize,
+    }
+}
+
+/// The `len` bytes of a page at `idx`, failing if they run off its end.
+fn bytes(buf: &[u8], idx: usize, len: usize) -> Result<&[u8], anyhow::Error> {
+    match buf.get(idx..idx.saturating_add(len)) {
+        Some(bytes) => Ok(bytes),
+        None => bail!("{len} bytes at offset {idx} run off the page"),
+    }
+}
+
+/// The varint at `idx` of a page.
+fn varint_at(buf: &[u8], idx: usize) -> Result<(u64, usize), anyhow::Error> {
+    match buf.get(idx..).filter(|rest| !rest.is_empty()) {
+        Some(rest) => util::varint_unsigned(rest),
+        None => bail!("varint at offset {idx} is off the page"),
+    }
+}
+
+fn read_child_ptr(buf: &[u8], idx: usize) -> Result<u32, anyhow::Error> {
+    let ptr = bytes(buf, idx, 4)?;
+    Ok(u32::from_be_bytes([ptr[0], ptr[1], ptr[2], ptr[3]]))
+}
+
+/*
+Index B-Tree Leaf/Interior Cell (header 0x0a/0x02), after the child pointer if any:
+
+        A varint which is the total number of bytes of key payload, including any overflow
+        The initial portion of the payload that does not spill to overflow pages.
+        A 4-byte big-endian integer page number for the first page of the overflow page list - omitted if all payload fits on the b-tree page.
+*/
+fn read_index_payload(db: &Database, buf: &[u8], idx: usize) -> Result<DbRecord, anyhow::Error> {
+    let (payload_len, sz) = varint_at(buf, idx)?;
+    let usable = db.info.usable_size();
+    let max_local = (usable - 12) * 64 / 255 - 23;
+    let payload = read_payload(db, buf, idx + sz, payload_len as usize, max_local)?;
+    PageInfo::read_record(&payload)
+}
+
+/// Gather `payload_len` bytes of payload starting at `idx`, following the
+/// overflow page chain if the payload does not fit on the page.
+/// See https://www.sqlite.org/fileformat.html#cell_payload
+fn read_payload(
+    db: &Database,
+    buf: &[u8],
+    idx: usize,
+    payload_len: usize,
+    max_local: usize,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let usable = db.info.usable_size();
+    // the length is read from the file, so no more than the file can hold
+    if payload_len > db.size() as usize * usable {
+        bail!("payload of {payload_len} bytes is larger than the file");
+    }
+    let local = local_payload_len(usable, payload_len, max_local);
+
+    let mut payload = Vec::with_capacity(payload_len);
+    payload.extend_from_slice(bytes(buf, idx, local)?);
+
+    if local < payload_len {
+        let mut next = read_child_ptr(buf, idx + local)?;
+        while payload.len() < payload_len {
+            if next == 0 {
+                bail!("overflow chain ends early");
+            }
+            let page = db.raw_page(next)?;
+            next = read_child_ptr(page, 0)?;
+            let take = (payload_len - payload.len()).min(usable - 4);
+            payload.extend_from_slice(&page[4..4 + take]);
+        }
+    }
+
+    Ok(payload)
+}
+
+/// The size and content of a cell, as its header gives them.
+pub(crate) struct CellInfo {
+    /// The bytes the cell takes on its page.
+    pub(crate) size: u32,
+    /// The rowid of a table b-tree cell.
+    pub(crate) key: i64,
+    pub(crate) payload: u32,
+    /// The bytes of the payload on the page, the rest being on overflow
+    /// pages.
+    pub(crate) local: u32,
+}
+
+/// The cell at `pc` of a page of the given kind, read from the raw page
+/// without trusting it: None if the header of the cell runs off the end of
+/// the page.
+pub(crate) fn parse_cell(
+    data: &[u8],
+    pc: usize,
+    leaf: bool,
+    int_key: bool,
+    usable: u32,
+) -> Option<CellInfo> {
+    let varint = |i: usize| {
+        let bytes = data.get(i..).filter(|b| !b.is_empty())?;
+        util::varint_unsigned(bytes).ok()
+    };
+    let usable = usable as usize;
+    if int_key && !leaf {
+        // a left child pointer and a rowid
+        let (key, n) = varint(pc + 4)?;
+        return Some(CellInfo {
+            size: 4 + n as u32,
+            key: key as i64,
+            payload: 0,
+            local: 0,
+        });
+    }
+    let mut offset = pc + if leaf { 0 } else { 4 };
+    let (payload, n) = varint(offset)?;
+    // SQLite keeps the payload size to 32 bits
+    let payload = payload as u32;
+    offset += n;
+    let mut key = 0;
+    let max_local = if int_key {
+        let (rowid, n) = varint(offset)?;
+        key = rowid as i64;
+        offset += n;
+        usable - 35
+    } else {
+        (usable - 12) * 64 / 255 - 23
+    };
+    let local = local_payload_len(usable, payload as usize, max_local) as u32;
+    let mut size = (offset - pc) as u32 + local;
+    if local < payload {
+        size += 4;
+    }
+    Some(CellInfo {
+        size: size.max(4),
+        key,
+        payload,
+        local,
+    })
+}
+
+/// The number of payload bytes stored on the b-tree page itself.
+pub(crate) fn local_payload_len(usable: usize, payload_len: usize, max_local: usize) -> usize {
+    if payload_len <= max_local {
+        return payload_len;
+    }
+
+    let min_local = (usable - 12) * 32 / 255 - 23;
+    let k = min_local + (payload_len - min_local) % (usable - 4);
+    if k <= max_local {
+        k
+    } else {
+        min_local
+    }
+}
+
 /*
 Table B-Tree Leaf Cell (header 0x0d):
 
@@ -214,34 +436,71 @@ Table B-Tree Leaf Cell (header 0x0d):
 */
 #[allow(dead_code)]
 #[derive(Debug)]
-struct TableLeaf {
-    header_len: usize,
-    payload_len: usize,
-    row_id: u64,
-    payload: Option<DbRecord>,
+pub(crate) struct TableLeaf {
+    pub(crate) payload_len: usize,
+    pub(crate) row_id: i64,
+    pub(crate) payload: DbRecord,
 }
 
 impl TableLeaf {
-    fn new(buf: &[u8], idx: usize) -> Result<(TableLeaf, usize), anyhow::Error> {
+    fn new(db: &Database, buf: &[u8], idx: usize) -> Result<TableLeaf, anyhow::Error> {
         let mut offset = idx;
-        let (payload_len, sz) = util::varint_unsigned(&buf[offset..])?;
-        offset += sz;
-        let (row_id, sz) = util::varint_unsigned(&buf[offset..])?;
+        let (payload_len, sz) = varint_at(buf, offset)?;
         offset += sz;
-        let (record_hdr_len, sz) = util::varint_unsigned(&buf[offset..])?;
+        let (row_id, sz) = varint_at(buf, offset)?;
         offset += sz;
-        let header_len = record_hdr_len as usize - sz;
-
-        Ok((
-            TableLeaf {
-                header_len,
-                payload_len: payload_len as usize,
-                row_id,
-                payload: None,
-            },
-            offset,
-        ))
+
+        let max_local = db.info.usable_size() - 35;
+        let payload = read_payload(db, buf, offset, payload_len as usize, max_local)?;
+
+        Ok(TableLeaf {
+            payload_len: payload_len as usize,
+            row_id: row_id as i64,
+            payload: PageInfo::read_record(&payload)?,
+        })
     }
 }
 
 pub(crate) type DbRecord = Vec<ColumnType>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree;
+    use crate::db::testing::open_bytes;
+
+    #[test]
+    fn test_damaged_page() {
+        let sample = std::fs::read("sample.db").unwrap();
+        // page 4 is the leaf of oranges, with 6 cells
+        let page = 4096 * 3;
+        let read = |at: usize, bytes: &[u8]| {
+            let mut data = sample.clone();
+            data[page + at..page + at + bytes.len()].copy_from_slice(bytes);
+            open_bytes(&data).read_page(4).map(|p| p.cells.len())
+        };
+        assert_eq!(read(0, &[]).unwrap(), 6);
+        for (at, bytes, error) in [
+            // more cell pointers than the page holds
+            (3, &[0x08, 0x00][..], "cell pointer array"),
+            // a cell whose rowid would be past the end of the page
+            (8, &[0x0f, 0xff], "varint at offset 4096 is off the page"),
+            // a payload longer than the whole file
+            (0xfe0, &[0xff; 9], "payload of 18446744073709551615 bytes"),
+        ] {
+            let message = format!("{:#}", read(at, bytes).unwrap_err());
+            assert!(message.contains(error), "{message}");
+        }
+
+        // records cut short, down to a cell whose payload is empty
+        for payload in [&[][..], &[0x81], &[0x03, 0x01]] {
+            assert!(PageInfo::read_record(payload).is_err());
+        }
+        let mut data = sample.clone();
+        let cell = u16::from_be_bytes([data[page + 8], data[page + 9]]) as usize;
+        data[page + cell] = 0;
+        let db = open_bytes(&data);
+        let rows = btree::scan_table(&db, 4, &mut |_, _| Ok(true));
+        assert!(rows.is_err());
+    }
+}
@@ -0,0 +1,1988 @@
+use std::ops::Range;
+
+use anyhow::{anyhow, bail};
+
+use crate::ast::*;
+use crate::lexer::{tokenize_spanned, Token};
+use crate::table::ColumnType;
+
+/// Keywords that end an expression or clause and so can never be taken as an
+/// implicit alias.
+const RESERVED: [&str; 42] = [
+    "ALL",
+    "AND",
+    "AS",
+    "ASC",
+    "BETWEEN",
+    "BY",
+    "CASE",
+    "COLLATE",
+    "CROSS",
+    "DESC",
+    "DISTINCT",
+    "ELSE",
+    "END",
+    "EXCEPT",
+    "EXISTS",
+    "FROM",
+    "GLOB",
+    "GROUP",
+    "HAVING",
+    "IN",
+    "INNER",
+    "INTERSECT",
+    "IS",
+    "JOIN",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "NATURAL",
+    "NOT",
+    "NULL",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OUTER",
+    "SELECT",
+    "THEN",
+    "UNION",
+    "USING",
+    "WHEN",
+    "WHERE",
+    "WINDOW",
+];
+
+/// Parse a single SQL statement, optionally terminated by a semicolon.
+pub fn parse(sql: &str) -> Result<Statement, anyhow::Error> {
+    let mut parser = Parser::new(sql)?;
+    let statement = parser.parse_statement()?;
+    parser.eat_sym(";");
+    if parser.peek() != &Token::Eof {
+        bail!("near \"{}\": syntax error", parser.peek_text());
+    }
+    Ok(statement)
+}
+
+/// Parse a sequence of SQL statements separated by semicolons.
+pub fn parse_statements(sql: &str) -> Result<Vec<Statement>, anyhow::Error> {
+    let mut parser = Parser::new(sql)?;
+    let mut statements = vec![];
+    loop {
+        while parser.eat_sym(";") {}
+        if parser.peek() == &Token::Eof {
+            return Ok(statements);
+        }
+        statements.push(parser.parse_statement()?);
+        if parser.peek() != &Token::Eof && !parser.is_sym(";") {
+            bail!("near \"{}\": syntax error", parser.peek_text());
+        }
+    }
+}
+
+/// The largest parameter number, as in `?32766`.
+const MAX_VARIABLE_NUMBER: usize = 32766;
+
+pub struct Parser {
+    sql: String,
+    tokens: Vec<Token>,
+    spans: Vec<Range<usize>>,
+    pos: usize,
+    /// The parameters of the statement being parsed, by index.
+    parameters: Vec<Option<String>>,
+}
+
+impl Parser {
+    pub fn new(sql: &str) -> Result<Self, anyhow::Error> {
+        let (tokens, spans) = tokenize_spanned(sql)?.into_iter().unzip();
+        Ok(Parser {
+            sql: sql.to_string(),
+            tokens,
+            spans,
+            pos: 0,
+            parameters: vec![],
+        })
+    }
+
+    /// The source text from the token at `start` up to the current token.
+    fn text_since(&self, start: usize) -> String {
+        let from = self.spans[start].start;
+        let to = self.spans[self.pos.max(start + 1) - 1].end;
+        self.sql[from..to].to_string()
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn peek_at(&self, n: usize) -> &Token {
+        &self.tokens[(self.pos + n).min(self.tokens.len() - 1)]
+    }
+
+    fn peek_text(&self) -> String {
+        match self.peek() {
+            Token::Ident(s) | Token::QuotedIdent(s) | Token::String(s) => s.clone(),
+            Token::Integer(n) => n.to_string(),
+            Token::Float(f) => f.to_string(),
+            Token::Blob(_) => "x'...'".to_string(),
+            Token::Variable(s) => s.clone(),
+            Token::Symbol(s) => s.to_string(),
+            Token::Eof => "end of input".to_string(),
+        }
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn is_kw(&self, kw: &str) -> bool {
+        self.is_kw_at(0, kw)
+    }
+
+    fn is_kw_at(&self, n: usize, kw: &str) -> bool {
+        matches!(self.peek_at(n), Token::Ident(s) if s.eq_ignore_ascii_case(kw))
+    }
+
+    fn eat_kw(&mut self, kw: &str) -> bool {
+        if self.is_kw(kw) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_kw(&mut self, kw: &str) -> Result<(), anyhow::Error> {
+        if !self.eat_kw(kw) {
+            bail!("near \"{}\": syntax error, expected {kw}", self.peek_text());
+        }
+        Ok(())
+    }
+
+    fn is_sym(&self, sym: &str) -> bool {
+        matches!(self.peek(), Token::Symbol(s) if *s == sym)
+    }
+
+    fn eat_sym(&mut self, sym: &str) -> bool {
+        if self.is_sym(sym) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_sym(&mut self, sym: &str) -> Result<(), anyhow::Error> {
+        if !self.eat_sym(sym) {
+            bail!(
+                "near \"{}\": syntax error, expected \"{sym}\"",
+                self.peek_text()
+            );
+        }
+        Ok(())
+    }
+
+    fn is_reserved(&self) -> bool {
+        matches!(self.peek(), Token::Ident(s) if RESERVED.iter().any(|kw| s.eq_ignore_ascii_case(kw)))
+    }
+
+    /// An identifier: a bare word that is not a reserved keyword, or a quoted
+    /// identifier.
+    fn ident(&mut self) -> Result<String, anyhow::Error> {
+        match self.peek() {
+            Token::Ident(s) if !self.is_reserved() => {
+                let s = s.clone();
+                self.advance();
+                Ok(s)
+            }
+            Token::QuotedIdent(s) | Token::String(s) => {
+                let s = s.clone();
+                self.advance();
+                Ok(s)
+            }
+            _ => bail!(
+                "near \"{}\": syntax error, expected a name",
+                self.peek_text()
+            ),
+        }
+    }
+
+    /// `[schema.]name`; the schema name is dropped since only `main` exists.
+    fn qualified_name(&mut self) -> Result<String, anyhow::Error> {
+        let name = self.ident()?;
+        if self.eat_sym(".") {
+            return self.ident();
+        }
+        Ok(name)
+    }
+
+    /// An optional `[AS] alias`.
+    fn alias(&mut self) -> Result<Option<String>, anyhow::Error> {
+        if self.eat_kw("AS") {
+            return Ok(Some(self.ident()?));
+        }
+        match self.peek() {
+            Token::Ident(_) if !self.is_reserved() => Ok(Some(self.ident()?)),
+            Token::QuotedIdent(_) | Token::String(_) => Ok(Some(self.ident()?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether a select statement starts here.
+    fn is_select_start(&self) -> bool {
+        self.is_kw("SELECT") || self.is_kw("WITH")
+    }
+
+    pub fn parse_statement(&mut self) -> Result<Statement, anyhow::Error> {
+        self.parameters.clear();
+        let explain = if self.eat_kw("EXPLAIN") {
+            if self.eat_kw("QUERY") {
+                self.expect_kw("PLAN")?;
+                Some(Explain::QueryPlan)
+            } else {
+                Some(Explain::Program)
+            }
+        } else {
+            None
+        };
+        let kind = self.parse_statement_kind()?;
+        let mut statement = Statement::new(kind, std::mem::take(&mut self.parameters));
+        statement.explain = explain;
+        Ok(statement)
+    }
+
+    fn parse_statement_kind(&mut self) -> Result<StatementKind, anyhow::Error> {
+        if self.is_select_start() {
+            return Ok(StatementKind::Select(Box::new(self.parse_select()?)));
+        }
+        if self.eat_kw("CREATE") {
+            let unique = self.eat_kw("UNIQUE");
+            let temp = !unique && (self.eat_kw("TEMP") || self.eat_kw("TEMPORARY"));
+            if !unique && self.eat_kw("VIEW") {
+                return Ok(StatementKind::CreateView(self.parse_create_view()?));
+            }
+            if temp {
+                self.expect_kw("TABLE")?;
+                return Ok(StatementKind::CreateTable(self.parse_create_table()?));
+            }
+            if !unique && self.eat_kw("TABLE") {
+                return Ok(StatementKind::CreateTable(self.parse_create_table()?));
+            }
+            if self.eat_kw("INDEX") {
+                return Ok(StatementKind::CreateIndex(self.parse_create_index(unique)?));
+            }
+        }
+        if self.eat_kw("PRAGMA") {
+            return Ok(StatementKind::Pragma(self.parse_pragma()?));
+        }
+        bail!("near \"{}\": syntax error", self.peek_text())
+    }
+
+    fn parse_pragma(&mut self) -> Result<Pragma, anyhow::Error> {
+        let mut schema = None;
+        let mut name = self.ident()?;
+        if self.eat_sym(".") {
+            schema = Some(name);
+            name = self.ident()?;
+        }
+
+        let value = if self.eat_sym("=") {
+            Some(self.parse_pragma_value()?)
+        } else if self.eat_sym("(") {
+            let value = self.parse_pragma_value()?;
+            self.expect_sym(")")?;
+            Some(value)
+        } else {
+            None
+        };
+
+        Ok(Pragma {
+            schema,
+            name,
+            value,
+        })
+    }
+
+    /// A signed number, a name or a string, as text.
+    fn parse_pragma_value(&mut self) -> Result<String, anyhow::Error> {
+        let start = self.pos;
+        if !self.eat_sym("-") {
+            self.eat_sym("+");
+        }
+        match self.peek() {
+            Token::Integer(_) | Token::Float(_) => {
+                self.advance();
+                Ok(self.text_since(start))
+            }
+            Token::Ident(_) | Token::QuotedIdent(_) | Token::String(_) if self.pos == start => {
+                let value = self.peek_text();
+                self.advance();
+                Ok(value)
+            }
+            _ => bail!("near \"{}\": syntax error", self.peek_text()),
+        }
+    }
+
+    fn parse_if_not_exists(&mut self) -> Result<(), anyhow::Error> {
+        if self.eat_kw("IF") {
+            self.expect_kw("NOT")?;
+            self.expect_kw("EXISTS")?;
+        }
+        Ok(())
+    }
+
+    pub fn parse_select(&mut self) -> Result<Select, anyhow::Error> {
+        let with = if self.eat_kw("WITH") {
+            Some(self.parse_with()?)
+        } else {
+            None
+        };
+
+        let body = self.parse_select_core()?;
+
+        let mut compound = vec![];
+        while let Some(op) = self.parse_compound_op() {
+            compound.push((op, self.parse_select_core()?));
+        }
+
+        let mut order_by = vec![];
+        if self.eat_kw("ORDER") {
+            self.expect_kw("BY")?;
+            order_by = self.parse_ordering_terms()?;
+        }
+
+        let (mut limit, mut offset) = (None, None);
+        if self.eat_kw("LIMIT") {
+            let first = self.parse_expr()?;
+            if self.eat_kw("OFFSET") {
+                limit = Some(first);
+                offset = Some(self.parse_expr()?);
+            } else if self.eat_sym(",") {
+                // LIMIT <offset>, <limit>
+                offset = Some(first);
+                limit = Some(self.parse_expr()?);
+            } else {
+                limit = Some(first);
+            }
+        }
+
+        // only the compound select as a whole can be ordered and limited
+        if !order_by.is_empty() || limit.is_some() {
+            if let Some(op) = self.parse_compound_op() {
+                let clause = if order_by.is_empty() {
+                    "LIMIT"
+                } else {
+                    "ORDER BY"
+                };
+                bail!("{clause} clause should come after {} not before", op.name());
+            }
+        }
+
+        Ok(Select {
+            with,
+            body,
+            compound,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    fn parse_compound_op(&mut self) -> Option<CompoundOp> {
+        if self.eat_kw("UNION") {
+            if self.eat_kw("ALL") {
+                Some(CompoundOp::UnionAll)
+            } else {
+                Some(CompoundOp::Union)
+            }
+        } else if self.eat_kw("INTERSECT") {
+            Some(CompoundOp::Intersect)
+        } else if self.eat_kw("EXCEPT") {
+            Some(CompoundOp::Except)
+        } else {
+            None
+        }
+    }
+
+    fn parse_with(&mut self) -> Result<With, anyhow::Error> {
+        let recursive = self.eat_kw("RECURSIVE");
+        let mut ctes = vec![];
+        loop {
+            let name = self.ident()?;
+            let columns = self.parse_column_names()?;
+            self.expect_kw("AS")?;
+            let materialized = if self.eat_kw("MATERIALIZED") {
+                Some(true)
+            } else if self.is_kw("NOT") && self.is_kw_at(1, "MATERIALIZED") {
+                self.advance();
+                self.advance();
+                Some(false)
+            } else {
+                None
+            };
+            self.expect_sym("(")?;
+            let select = self.parse_select()?;
+            self.expect_sym(")")?;
+            ctes.push(CommonTableExpr {
+                name,
+                columns,
+                materialized,
+                select: Box::new(select),
+            });
+            if !self.eat_sym(",") {
+                break;
+            }
+        }
+        Ok(With { recursive, ctes })
+    }
+
+    /// The optional list of column names of a CTE or view, `(a, b)`.
+    fn parse_column_names(&mut self) -> Result<Vec<String>, anyhow::Error> {
+        let mut columns = vec![];
+        if self.eat_sym("(") {
+            loop {
+                columns.push(self.ident()?);
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+            self.expect_sym(")")?;
+        }
+        Ok(columns)
+    }
+
+    fn parse_select_core(&mut self) -> Result<SelectCore, anyhow::Error> {
+        self.expect_kw("SELECT")?;
+        let distinct = self.eat_kw("DISTINCT");
+        if !distinct {
+            self.eat_kw("ALL");
+        }
+
+        let mut columns = vec![];
+        loop {
+            columns.push(self.parse_result_column()?);
+            if !self.eat_sym(",") {
+                break;
+            }
+        }
+
+        let from = if self.eat_kw("FROM") {
+            Some(self.parse_from()?)
+        } else {
+            None
+        };
+
+        let where_clause = if self.eat_kw("WHERE") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        let mut group_by = vec![];
+        let mut having = None;
+        if self.eat_kw("GROUP") {
+            self.expect_kw("BY")?;
+            group_by = self.parse_expr_list()?;
+            if self.eat_kw("HAVING") {
+                having = Some(self.parse_expr()?);
+            }
+        }
+
+        let mut windows = vec![];
+        if self.eat_kw("WINDOW") {
+            loop {
+                let name = self.ident()?;
+                self.expect_kw("AS")?;
+                self.expect_sym("(")?;
+                windows.push((name, self.parse_window_def()?));
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+        }
+
+        Ok(SelectCore {
+            distinct,
+            columns,
+            from,
+            where_clause,
+            group_by,
+            having,
+            windows,
+        })
+    }
+
+    fn parse_result_column(&mut self) -> Result<ResultColumn, anyhow::Error> {
+        if self.eat_sym("*") {
+            return Ok(ResultColumn::Star);
+        }
+        if matches!(self.peek(), Token::Ident(_) | Token::QuotedIdent(_))
+            && matches!(self.peek_at(1), Token::Symbol("."))
+            && matches!(self.peek_at(2), Token::Symbol("*"))
+        {
+            let table = self.ident()?;
+            self.advance();
+            self.advance();
+            return Ok(ResultColumn::TableStar(table));
+        }
+
+        let start = self.pos;
+        let expr = self.parse_expr()?;
+        let text = self.text_since(start);
+        let alias = self.alias()?;
+        Ok(ResultColumn::Expr { expr, alias, text })
+    }
+
+    fn parse_ordering_terms(&mut self) -> Result<Vec<OrderingTerm>, anyhow::Error> {
+        let mut terms = vec![];
+        loop {
+            let expr = self.parse_expr()?;
+            let desc = if self.eat_kw("DESC") {
+                true
+            } else {
+                self.eat_kw("ASC");
+                false
+            };
+            let nulls_first = if self.eat_kw("NULLS") {
+                if self.eat_kw("FIRST") {
+                    Some(true)
+                } else {
+                    self.expect_kw("LAST")?;
+                    Some(false)
+                }
+            } else {
+                None
+            };
+            terms.push(OrderingTerm {
+                expr,
+                desc,
+                nulls_first,
+            });
+            if !self.eat_sym(",") {
+                break;
+            }
+        }
+        Ok(terms)
+    }
+
+    fn parse_from(&mut self) -> Result<FromClause, anyhow::Error> {
+        let mut left = self.parse_table_or_subquery()?;
+
+        loop {
+            // a comma is an inner join without a constraint, which the
+            // planner may reorder; only CROSS JOIN keeps its order
+            let kind = if self.eat_sym(",") || self.eat_kw("JOIN") {
+                JoinKind::Inner
+            } else if self.eat_kw("INNER") {
+                self.expect_kw("JOIN")?;
+                JoinKind::Inner
+            } else if self.eat_kw("CROSS") {
+                self.expect_kw("JOIN")?;
+                JoinKind::Cross
+            } else if self.eat_kw("LEFT") {
+                self.eat_kw("OUTER");
+                self.expect_kw("JOIN")?;
+                JoinKind::Left
+            } else {
+                break;
+            };
+
+            let right = self.parse_table_or_subquery()?;
+
+            let constraint = if self.eat_kw("ON") {
+                Some(JoinConstraint::On(self.parse_expr()?))
+            } else if self.eat_kw("USING") {
+                self.expect_sym("(")?;
+                let mut names = vec![];
+                loop {
+                    names.push(self.ident()?);
+                    if !self.eat_sym(",") {
+                        break;
+                    }
+                }
+                self.expect_sym(")")?;
+                Some(JoinConstraint::Using(names))
+            } else {
+                None
+            };
+
+            left = FromClause::Join {
+                left: Box::new(left),
+                right: Box::new(right),
+                kind,
+                constraint,
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_table_or_subquery(&mut self) -> Result<FromClause, anyhow::Error> {
+        if self.eat_sym("(") {
+            if self.is_select_start() {
+                let select = self.parse_select()?;
+                self.expect_sym(")")?;
+                let alias = self.alias()?;
+                return Ok(FromClause::Subquery {
+                    select: Box::new(select),
+                    alias,
+                });
+            }
+            let from = self.parse_from()?;
+            self.expect_sym(")")?;
+            return Ok(from);
+        }
+
+        let name = self.qualified_name()?;
+        if self.eat_sym("(") {
+            let args = if self.is_sym(")") {
+                vec![]
+            } else {
+                self.parse_expr_list()?
+            };
+            self.expect_sym(")")?;
+            let alias = self.alias()?;
+            return Ok(FromClause::Function { name, args, alias });
+        }
+        let alias = self.alias()?;
+        // like SQLite's eponymous virtual tables, the pragma functions need
+        // no arguments
+        if name.len() > 7
+            && name
+                .get(..7)
+                .is_some_and(|p| p.eq_ignore_ascii_case("pragma_"))
+        {
+            return Ok(FromClause::Function {
+                name,
+                args: vec![],
+                alias,
+            });
+        }
+        Ok(FromClause::Table { name, alias })
+    }
+
+    fn parse_expr_list(&mut self) -> Result<Vec<Expr>, anyhow::Error> {
+        let mut exprs = vec![];
+        loop {
+            exprs.push(self.parse_expr()?);
+            if !self.eat_sym(",") {
+                break;
+            }
+        }
+        Ok(exprs)
+    }
+
+    // Operator precedence, lowest first, follows
+    // https://www.sqlite.org/lang_expr.html#operators_and_parse_affecting_attributes
+
+    pub fn parse_expr(&mut self) -> Result<Expr, anyhow::Error> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut left = self.parse_and()?;
+        while self.eat_kw("OR") {
+            let right = self.parse_and()?;
+            left = binary(BinaryOp::Or, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut left = self.parse_not()?;
+        while self.eat_kw("AND") {
+            let right = self.parse_not()?;
+            left = binary(BinaryOp::And, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, anyhow::Error> {
+        if self.eat_kw("NOT") {
+            let expr = self.parse_not()?;
+            return Ok(Expr::Unary {
+                op: UnaryOp::Not,
+                expr: Box::new(expr),
+            });
+        }
+        self.parse_equality()
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut left = self.parse_relational()?;
+
+        loop {
+            if self.eat_sym("=") || self.eat_sym("==") {
+                let right = self.parse_relational()?;
+                left = binary(BinaryOp::Eq, left, right);
+            } else if self.eat_sym("!=") || self.eat_sym("<>") {
+                let right = self.parse_relational()?;
+                left = binary(BinaryOp::NotEq, left, right);
+            } else if self.eat_kw("IS") {
+                let mut negated = self.eat_kw("NOT");
+                if self.eat_kw("DISTINCT") {
+                    self.expect_kw("FROM")?;
+                    negated = !negated;
+                }
+                let right = self.parse_relational()?;
+                let op = if negated {
+                    BinaryOp::IsNot
+                } else {
+                    BinaryOp::Is
+                };
+                left = binary(op, left, right);
+            } else if self.eat_kw("ISNULL") {
+                left = binary(BinaryOp::Is, left, Expr::Literal(ColumnType::Null));
+            } else if self.eat_kw("NOTNULL") {
+                left = binary(BinaryOp::IsNot, left, Expr::Literal(ColumnType::Null));
+            } else if self.is_kw("NOT") && self.is_kw_at(1, "NULL") {
+                self.advance();
+                self.advance();
+                left = binary(BinaryOp::IsNot, left, Expr::Literal(ColumnType::Null));
+            } else if self.is_kw("IN") || (self.is_kw("NOT") && self.is_kw_at(1, "IN")) {
+                let negated = self.eat_kw("NOT");
+                self.expect_kw("IN")?;
+                left = self.parse_in(left, negated)?;
+            } else if let Some(n) = ["LIKE", "GLOB", "REGEXP"]
+                .iter()
+                .position(|kw| self.is_kw(kw) || (self.is_kw("NOT") && self.is_kw_at(1, kw)))
+            {
+                // `x LIKE y ESCAPE z` is the function call like(y, x, z)
+                let negated = self.eat_kw("NOT");
+                self.advance();
+                let pattern = self.parse_relational()?;
+                let mut args = vec![pattern, left];
+                if self.eat_kw("ESCAPE") {
+                    args.push(self.parse_relational()?);
+                }
+                left = Expr::Function {
+                    name: ["like", "glob", "regexp"][n].to_string(),
+                    args,
+                    distinct: false,
+                    star: false,
+                    filter: None,
+                    over: None,
+                };
+                if negated {
+                    left = Expr::Unary {
+                        op: UnaryOp::Not,
+                        expr: Box::new(left),
+                    };
+                }
+            } else if self.is_kw("BETWEEN") || (self.is_kw("NOT") && self.is_kw_at(1, "BETWEEN")) {
+                let negated = self.eat_kw("NOT");
+                self.expect_kw("BETWEEN")?;
+                let low = self.parse_relational()?;
+                self.expect_kw("AND")?;
+                let high = self.parse_relational()?;
+                left = Expr::Between {
+                    expr: Box::new(left),
+                    negated,
+                    low: Box::new(low),
+                    high: Box::new(high),
+                };
+            } else {
+                break;
+            }
+        }
+
+        Ok(left)
+    }
+
+    fn parse_in(&mut self, expr: Expr, negated: bool) -> Result<Expr, anyhow::Error> {
+        self.expect_sym("(")?;
+
+        if self.is_select_start() {
+            let select = self.parse_select()?;
+            self.expect_sym(")")?;
+            return Ok(Expr::InSelect {
+                expr: Box::new(expr),
+                negated,
+                select: Box::new(select),
+            });
+        }
+
+        let list = if self.is_sym(")") {
+            vec![]
+        } else {
+            self.parse_expr_list()?
+        };
+        self.expect_sym(")")?;
+
+        Ok(Expr::InList {
+            expr: Box::new(expr),
+            negated,
+            list,
+        })
+    }
+
+    fn parse_relational(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut left = self.parse_bitwise()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("<") => BinaryOp::Lt,
+                Token::Symbol("<=") => BinaryOp::LtEq,
+                Token::Symbol(">") => BinaryOp::Gt,
+                Token::Symbol(">=") => BinaryOp::GtEq,
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_bitwise()?;
+            left = binary(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_bitwise(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut left = self.parse_additive()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("&") => BinaryOp::BitAnd,
+                Token::Symbol("|") => BinaryOp::BitOr,
+                Token::Symbol("<<") => BinaryOp::ShiftLeft,
+                Token::Symbol(">>") => BinaryOp::ShiftRight,
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_additive()?;
+            left = binary(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("+") => BinaryOp::Add,
+                Token::Symbol("-") => BinaryOp::Sub,
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_multiplicative()?;
+            left = binary(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut left = self.parse_concat()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("*") => BinaryOp::Mul,
+                Token::Symbol("/") => BinaryOp::Div,
+                Token::Symbol("%") => BinaryOp::Rem,
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_concat()?;
+            left = binary(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_concat(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut left = self.parse_unary()?;
+        while self.eat_sym("||") {
+            let right = self.parse_unary()?;
+            left = binary(BinaryOp::Concat, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, anyhow::Error> {
+        let op = match self.peek() {
+            Token::Symbol("-") => UnaryOp::Neg,
+            Token::Symbol("+") => UnaryOp::Plus,
+            Token::Symbol("~") => UnaryOp::BitNot,
+            _ => return self.parse_collate(),
+        };
+        self.advance();
+        let expr = self.parse_unary()?;
+        Ok(unary(op, expr))
+    }
+
+    /// The value of a DEFAULT constraint: a literal, a signed number, or an
+    /// expression in parentheses. As in SQLite, a bare name is a string.
+    fn parse_default(&mut self) -> Result<Expr, anyhow::Error> {
+        let op = match self.peek() {
+            Token::Symbol("-") => Some(UnaryOp::Neg),
+            Token::Symbol("+") => Some(UnaryOp::Plus),
+            _ => None,
+        };
+        if op.is_some() {
+            self.advance();
+        }
+        let expr = match self.parse_primary()? {
+            Expr::Column { table: None, name } => Expr::Literal(ColumnType::String(name)),
+            expr => expr,
+        };
+        Ok(match op {
+            Some(op) => unary(op, expr),
+            None => expr,
+        })
+    }
+
+    /// The index of a parameter: `?NNN` has index NNN, a name seen before
+    /// has the same index as before, and anything else takes the next one.
+    fn parameter_index(&mut self, name: String) -> Result<usize, anyhow::Error> {
+        if name == "?" {
+            self.parameters.push(None);
+            return Ok(self.parameters.len());
+        }
+        if let Some(number) = name.strip_prefix('?') {
+            let index = number
+                .parse::<usize>()
+                .ok()
+                .filter(|n| (1..=MAX_VARIABLE_NUMBER).contains(n))
+                .ok_or_else(|| {
+                    anyhow!("variable number must be between ?1 and ?{MAX_VARIABLE_NUMBER}")
+                })?;
+            if self.parameters.len() < index {
+                self.parameters.resize(index, None);
+            }
+            self.parameters[index - 1].get_or_insert(name);
+            return Ok(index);
+        }
+        if let Some(i) = self
+            .parameters
+            .iter()
+            .position(|p| p.as_deref() == Some(&name))
+        {
+            return Ok(i + 1);
+        }
+        self.parameters.push(Some(name));
+        Ok(self.parameters.len())
+    }
+
+    /// A primary expression followed by any number of `COLLATE name`.
+    fn parse_collate(&mut self) -> Result<Expr, anyhow::Error> {
+        let mut expr = self.parse_primary()?;
+        while self.eat_kw("COLLATE") {
+            expr = Expr::Collate {
+                expr: Box::new(expr),
+                collation: self.ident()?,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, anyhow::Error> {
+        match self.peek().clone() {
+            Token::Integer(n) => {
+                self.advance();
+                Ok(Expr::Literal(ColumnType::Int64(n)))
+            }
+            Token::Float(f) => {
+                self.advance();
+                Ok(Expr::Literal(ColumnType::Float(f)))
+            }
+            Token::String(s) => {
+                self.advance();
+                Ok(Expr::Literal(ColumnType::String(s)))
+            }
+            Token::Blob(b) => {
+                self.advance();
+                Ok(Expr::Literal(ColumnType::Blob(b)))
+            }
+            Token::Variable(name) => {
+                self.advance();
+                Ok(Expr::Parameter(self.parameter_index(name)?))
+            }
+            Token::Symbol("(") => {
+                self.advance();
+                if self.is_select_start() {
+                    let select = self.parse_select()?;
+                    self.expect_sym(")")?;
+                    return Ok(Expr::Subquery(Box::new(select)));
+                }
+                let expr = self.parse_expr()?;
+                self.expect_sym(")")?;
+                Ok(expr)
+            }
+            Token::Ident(s) if s.eq_ignore_ascii_case("NULL") => {
+                self.advance();
+                Ok(Expr::Literal(ColumnType::Null))
+            }
+            Token::Ident(s) if s.eq_ignore_ascii_case("TRUE") => {
+                self.advance();
+                Ok(Expr::Literal(ColumnType::Int64(1)))
+            }
+            Token::Ident(s) if s.eq_ignore_ascii_case("FALSE") => {
+                self.advance();
+                Ok(Expr::Literal(ColumnType::Int64(0)))
+            }
+            Token::Ident(s)
+                if ["CURRENT_DATE", "CURRENT_TIME", "CURRENT_TIMESTAMP"]
+                    .iter()
+                    .any(|k| s.eq_ignore_ascii_case(k)) =>
+            {
+                let name = match s.to_ascii_uppercase().as_str() {
+                    "CURRENT_DATE" => "date",
+                    "CURRENT_TIME" => "time",
+                    _ => "datetime",
+                };
+                self.advance();
+                Ok(Expr::Function {
+                    name: name.to_string(),
+                    args: vec![],
+                    distinct: false,
+                    star: false,
+                    filter: None,
+                    over: None,
+                })
+            }
+            Token::Ident(s) if s.eq_ignore_ascii_case("CASE") => {
+                self.advance();
+                self.parse_case()
+            }
+            Token::Ident(s) if s.eq_ignore_ascii_case("CAST") => {
+                self.advance();
+                self.expect_sym("(")?;
+                let expr = self.parse_expr()?;
+                self.expect_kw("AS")?;
+                let type_name = self.parse_type_name()?.unwrap_or_default();
+                self.expect_sym(")")?;
+                Ok(Expr::Cast {
+                    expr: Box::new(expr),
+                    type_name,
+                })
+            }
+            Token::Ident(s) if s.eq_ignore_ascii_case("EXISTS") => {
+                self.advance();
+                self.expect_sym("(")?;
+                let select = self.parse_select()?;
+                self.expect_sym(")")?;
+                Ok(Expr::Exists(Box::new(select)))
+            }
+            Token::Ident(_) | Token::QuotedIdent(_) => {
+                if matches!(self.peek_at(1), Token::Symbol("(")) {
+                    return self.parse_function();
+                }
+
+                let name = self.ident()?;
+                if self.eat_sym(".") {
+                    let column = self.ident()?;
+                    return Ok(Expr::Column {
+                        table: Some(name),
+                        name: column,
+                    });
+                }
+                Ok(Expr::Column { table: None, name })
+            }
+            _ => Err(anyhow!("near \"{}\": syntax error", self.peek_text())),
+        }
+    }
+
+    /// The rest of a CASE expression after `CASE`.
+    fn parse_case(&mut self) -> Result<Expr, anyhow::Error> {
+        let operand = if self.is_kw("WHEN") {
+            None
+        } else {
+            Some(Box::new(self.parse_expr()?))
+        };
+
+        let mut branches = vec![];
+        while self.eat_kw("WHEN") {
+            let when = self.parse_expr()?;
+            self.expect_kw("THEN")?;
+            branches.push((when, self.parse_expr()?));
+        }
+        if branches.is_empty() {
+            bail!("near \"{}\": syntax error", self.peek_text());
+        }
+
+        let else_result = if self.eat_kw("ELSE") {
+            Some(Box::new(self.parse_expr()?))
+        } else {
+            None
+        };
+        self.expect_kw("END")?;
+
+        Ok(Expr::Case {
+            operand,
+            branches,
+            else_result,
+        })
+    }
+
+    fn parse_function(&mut self) -> Result<Expr, anyhow::Error> {
+        // like() and glob() are named by keywords
+        let name = if self.is_kw("LIKE") || self.is_kw("GLOB") {
+            let name = self.peek_text();
+            self.advance();
+            name
+        } else {
+            self.ident()?
+        }
+        .to_ascii_lowercase();
+        self.expect_sym("(")?;
+
+        if self.eat_sym("*") {
+            self.expect_sym(")")?;
+            return Ok(Expr::Function {
+                name,
+                args: vec![],
+                distinct: false,
+                star: true,
+                filter: self.parse_filter()?,
+                over: self.parse_over()?,
+            });
+        }
+
+        let distinct = self.eat_kw("DISTINCT");
+        let args = if self.is_sym(")") {
+            vec![]
+        } else {
+            self.parse_expr_list()?
+        };
+        self.expect_sym(")")?;
+
+        Ok(Expr::Function {
+            name,
+            args,
+            distinct,
+            star: false,
+            filter: self.parse_filter()?,
+            over: self.parse_over()?,
+        })
+    }
+
+    fn parse_filter(&mut self) -> Result<Option<Box<Expr>>, anyhow::Error> {
+        if !(self.is_kw("FILTER") && matches!(self.peek_at(1), Token::Symbol("("))) {
+            return Ok(None);
+        }
+        self.advance();
+        self.advance();
+        self.expect_kw("WHERE")?;
+        let filter = self.parse_expr()?;
+        self.expect_sym(")")?;
+        Ok(Some(Box::new(filter)))
+    }
+
+    fn parse_over(&mut self) -> Result<Option<Box<Over>>, anyhow::Error> {
+        if !self.eat_kw("OVER") {
+            return Ok(None);
+        }
+        let over = if self.eat_sym("(") {
+            Over::Spec(Box::new(self.parse_window_def()?))
+        } else {
+            Over::Named(self.ident()?)
+        };
+        Ok(Some(Box::new(over)))
+    }
+
+    /// Parse a window definition after its opening parenthesis, up to and
+    /// including the closing one.
+    fn parse_window_def(&mut self) -> Result<WindowDef, anyhow::Error> {
+        let base = match self.peek() {
+            Token::Ident(_) | Token::QuotedIdent(_)
+                if !["PARTITION", "ORDER", "ROWS", "RANGE", "GROUPS"]
+                    .iter()
+                    .any(|kw| self.is_kw(kw)) =>
+            {
+                Some(self.ident()?)
+            }
+            _ => None,
+        };
+
+        let mut partition_by = vec![];
+        if self.eat_kw("PARTITION") {
+            self.expect_kw("BY")?;
+            partition_by = self.parse_expr_list()?;
+        }
+        let mut order_by = vec![];
+        if self.eat_kw("ORDER") {
+            self.expect_kw("BY")?;
+            order_by = self.parse_ordering_terms()?;
+        }
+
+        let frame = if self.is_kw("ROWS") || self.is_kw("RANGE") || self.is_kw("GROUPS") {
+            Some(self.parse_frame()?)
+        } else {
+            None
+        };
+        self.expect_sym(")")?;
+
+        Ok(WindowDef {
+            base,
+            partition_by,
+            order_by,
+            frame,
+        })
+    }
+
+    fn parse_frame(&mut self) -> Result<Frame, anyhow::Error> {
+        let units = if self.eat_kw("ROWS") {
+            FrameUnits::Rows
+        } else if self.eat_kw("RANGE") {
+            FrameUnits::Range
+        } else {
+            self.expect_kw("GROUPS")?;
+            FrameUnits::Groups
+        };
+
+        let (start, end) = if self.eat_kw("BETWEEN") {
+            let start = self.parse_frame_bound()?;
+            self.expect_kw("AND")?;
+            (start, self.parse_frame_bound()?)
+        } else {
+            (self.parse_frame_bound()?, FrameBound::CurrentRow)
+        };
+
+        // the frame cannot start after it ends, judging by the bound types
+        let rank = |bound: &FrameBound| match bound {
+            FrameBound::UnboundedPreceding => 0,
+            FrameBound::Preceding(_) => 1,
+            FrameBound::CurrentRow => 2,
+            FrameBound::Following(_) => 3,
+            FrameBound::UnboundedFollowing => 4,
+        };
+        if matches!(start, FrameBound::UnboundedFollowing)
+            || matches!(end, FrameBound::UnboundedPreceding)
+            || rank(&start) > rank(&end)
+        {
+            bail!("unsupported frame specification");
+        }
+
+        let exclude = if self.eat_kw("EXCLUDE") {
+            if self.eat_kw("NO") {
+                self.expect_kw("OTHERS")?;
+                FrameExclude::NoOthers
+            } else if self.eat_kw("CURRENT") {
+                self.expect_kw("ROW")?;
+                FrameExclude::CurrentRow
+            } else if self.eat_kw("GROUP") {
+                FrameExclude::Group
+            } else {
+                self.expect_kw("TIES")?;
+                FrameExclude::Ties
+            }
+        } else {
+            FrameExclude::NoOthers
+        };
+
+        Ok(Frame {
+            units,
+            start,
+            end,
+            exclude,
+        })
+    }
+
+    fn parse_frame_bound(&mut self) -> Result<FrameBound, anyhow::Error> {
+        if self.eat_kw("UNBOUNDED") {
+            if self.eat_kw("PRECEDING") {
+                return Ok(FrameBound::UnboundedPreceding);
+            }
+            self.expect_kw("FOLLOWING")?;
+            return Ok(FrameBound::UnboundedFollowing);
+        }
+        if self.eat_kw("CURRENT") {
+            self.expect_kw("ROW")?;
+            return Ok(FrameBound::CurrentRow);
+        }
+        let offset = self.parse_expr()?;
+        if self.eat_kw("PRECEDING") {
+            return Ok(FrameBound::Preceding(offset));
+        }
+        self.expect_kw("FOLLOWING")?;
+        Ok(FrameBound::Following(offset))
+    }
+
+    fn parse_create_table(&mut self) -> Result<CreateTable, anyhow::Error> {
+        self.parse_if_not_exists()?;
+        let name = self.qualified_name()?;
+        self.expect_sym("(")?;
+
+        let mut table = CreateTable {
+            name,
+            columns: vec![],
+            primary_key: vec![],
+            keys: vec![],
+            foreign_keys: vec![],
+            without_rowid: false,
+        };
+        loop {
+            if self.is_kw("CONSTRAINT")
+                || self.is_kw("PRIMARY")
+                || self.is_kw("UNIQUE")
+                || self.is_kw("CHECK")
+                || self.is_kw("FOREIGN")
+            {
+                self.parse_table_constraint(&mut table)?;
+            } else {
+                let column = self.parse_column_def(&mut table)?;
+                table.columns.push(column);
+            }
+            if !self.eat_sym(",") {
+                break;
+            }
+        }
+        self.expect_sym(")")?;
+
+        // table options: WITHOUT ROWID, and others such as STRICT
+        while !matches!(self.peek(), Token::Eof | Token::Symbol(";")) {
+            if self.eat_kw("WITHOUT") {
+                self.expect_kw("ROWID")?;
+                table.without_rowid = true;
+            } else {
+                self.advance();
+            }
+        }
+        if table.without_rowid && table.primary_key.is_empty() {
+            bail!("PRIMARY KEY missing on table {}", table.name);
+        }
+
+        Ok(table)
+    }
+
+    /// A column definition; its constraints that concern the table, such as
+    /// PRIMARY KEY, are added to `table`.
+    fn parse_column_def(&mut self, table: &mut CreateTable) -> Result<ColumnDef, anyhow::Error> {
+        let name = self.ident()?;
+
+        let type_name = self.parse_type_name()?;
+        let mut column = ColumnDef {
+            name,
+            type_name,
+            primary_key: false,
+            not_null: false,
+            collation: None,
+            default: None,
+            default_text: None,
+            generated: None,
+        };
+
+        while !self.is_sym(",") && !self.is_sym(")") {
+            self.parse_column_constraint(&mut column, table)?;
+        }
+
+        Ok(column)
+    }
+
+    /// A type name of one or more words with optional sizes, as in
+    /// `VARCHAR(10)`; `None` if there is none.
+    fn parse_type_name(&mut self) -> Result<Option<String>, anyhow::Error> {
+        let mut type_words = vec![];
+        while matches!(
+            self.peek(),
+            Token::Ident(_) | Token::QuotedIdent(_) | Token::String(_)
+        ) && !self.is_column_constraint_start()
+        {
+            type_words.push(self.peek_text());
+            self.advance();
+        }
+        if type_words.is_empty() {
+            return Ok(None);
+        }
+        let mut type_name = type_words.join(" ");
+        if self.is_sym("(") {
+            type_name.push_str(&self.skip_parens()?);
+        }
+        Ok(Some(type_name))
+    }
+
+    fn is_column_constraint_start(&self) -> bool {
+        [
+            "CONSTRAINT",
+            "PRIMARY",
+            "NOT",
+            "NULL",
+            "UNIQUE",
+            "CHECK",
+            "DEFAULT",
+            "COLLATE",
+            "REFERENCES",
+            "GENERATED",
+            "AS",
+        ]
+        .iter()
+        .any(|kw| self.is_kw(kw))
+    }
+
+    fn parse_column_constraint(
+        &mut self,
+        column: &mut ColumnDef,
+        table: &mut CreateTable,
+    ) -> Result<(), anyhow::Error> {
+        if self.eat_kw("CONSTRAINT") {
+            self.ident()?;
+        }
+
+        let name = column.name.clone();
+        let indexed = |desc| {
+            vec![IndexedColumn {
+                name: name.clone(),
+                collation: None,
+                desc,
+            }]
+        };
+        if self.eat_kw("PRIMARY") {
+            self.expect_kw("KEY")?;
+            column.primary_key = true;
+            let desc = !self.eat_kw("ASC") && self.eat_kw("DESC");
+            table.primary_key = indexed(desc);
+            table.keys.push((indexed(desc), true));
+            self.parse_conflict_clause()?;
+            self.eat_kw("AUTOINCREMENT");
+        } else if self.eat_kw("NOT") {
+            self.expect_kw("NULL")?;
+            column.not_null = true;
+            self.parse_conflict_clause()?;
+        } else if self.eat_kw("UNIQUE") {
+            table.keys.push((indexed(false), false));
+            self.parse_conflict_clause()?;
+        } else if self.eat_kw("NULL") {
+            self.parse_conflict_clause()?;
+        } else if self.eat_kw("CHECK") {
+            self.skip_parens()?;
+        } else if self.eat_kw("DEFAULT") {
+            let start = self.pos;
+            column.default = Some(self.parse_default()?);
+            let text = self.text_since(start);
+            let text = match self.tokens[start] {
+                Token::Symbol("(") => text[1..text.len() - 1].to_string(),
+                _ => text,
+            };
+            column.default_text = Some(text);
+        } else if self.eat_kw("COLLATE") {
+            column.collation = Some(self.ident()?);
+        } else if self.eat_kw("REFERENCES") {
+            let foreign_key = self.parse_foreign_key_clause(vec![column.name.clone()])?;
+            table.foreign_keys.push(foreign_key);
+        } else if self.eat_kw("GENERATED") || self.is_kw("AS") {
+            if !self.eat_kw("AS") {
+                self.expect_kw("ALWAYS")?;
+                self.expect_kw("AS")?;
+            }
+            self.expect_sym("(")?;
+            let expr = self.parse_expr()?;
+            self.expect_sym(")")?;
+            let stored = self.eat_kw("STORED");
+            if !stored {
+                self.eat_kw("VIRTUAL");
+            }
+            column.generated = Some(Generated { expr, stored });
+        } else {
+            bail!("near \"{}\": syntax error", self.peek_text());
+        }
+
+        Ok(())
+    }
+
+    fn parse_table_constraint(&mut self, table: &mut CreateTable) -> Result<(), anyhow::Error> {
+        if self.eat_kw("CONSTRAINT") {
+            self.ident()?;
+        }
+
+        if self.eat_kw("PRIMARY") {
+            self.expect_kw("KEY")?;
+            table.primary_key = self.parse_indexed_columns()?;
+            table.keys.push((table.primary_key.clone(), true));
+            self.parse_conflict_clause()?;
+        } else if self.eat_kw("UNIQUE") {
+            let columns = self.parse_indexed_columns()?;
+            table.keys.push((columns, false));
+            self.parse_conflict_clause()?;
+        } else if self.eat_kw("CHECK") {
+            self.skip_parens()?;
+        } else if self.eat_kw("FOREIGN") {
+            self.expect_kw("KEY")?;
+            let columns = self.parse_column_names()?;
+            self.expect_kw("REFERENCES")?;
+            let foreign_key = self.parse_foreign_key_clause(columns)?;
+            table.foreign_keys.push(foreign_key);
+        } else {
+            bail!("near \"{}\": syntax error", self.peek_text());
+        }
+
+        Ok(())
+    }
+
+    fn parse_conflict_clause(&mut self) -> Result<(), anyhow::Error> {
+        if self.is_kw("ON") && self.is_kw_at(1, "CONFLICT") {
+            self.advance();
+            self.advance();
+            self.advance();
+        }
+        Ok(())
+    }
+
+    /// The part of a foreign key clause after `REFERENCES`, for the key on
+    /// `columns`.
+    fn parse_foreign_key_clause(
+        &mut self,
+        columns: Vec<String>,
+    ) -> Result<ForeignKey, anyhow::Error> {
+        let table = self.ident()?;
+        let to = self.parse_column_names()?;
+        let mut foreign_key = ForeignKey {
+            columns,
+            table,
+            to,
+            on_update: "NO ACTION".to_string(),
+            on_delete: "NO ACTION".to_string(),
+        };
+
+        loop {
+            if self.eat_kw("ON") {
+                // ON DELETE|UPDATE SET NULL|SET DEFAULT|CASCADE|RESTRICT|NO ACTION
+                let update = self.eat_kw("UPDATE");
+                if !update {
+                    self.expect_kw("DELETE")?;
+                }
+                let start = self.pos;
+                if !self.eat_kw("SET") {
+                    self.eat_kw("NO");
+                }
+                self.advance();
+                let action = self.text_since(start).to_ascii_uppercase();
+                if update {
+                    foreign_key.on_update = action;
+                } else {
+                    foreign_key.on_delete = action;
+                }
+            } else if self.eat_kw("MATCH") {
+                self.ident()?;
+            } else if self.is_kw("DEFERRABLE")
+                || (self.is_kw("NOT") && self.is_kw_at(1, "DEFERRABLE"))
+            {
+                self.eat_kw("NOT");
+                self.advance();
+                if self.eat_kw("INITIALLY") {
+                    self.advance();
+                }
+            } else {
+                break;
+            }
+        }
+
+        Ok(foreign_key)
+    }
+
+    fn parse_create_view(&mut self) -> Result<CreateView, anyhow::Error> {
+        self.parse_if_not_exists()?;
+        let name = self.qualified_name()?;
+        let columns = self.parse_column_names()?;
+        self.expect_kw("AS")?;
+        let select = self.parse_select()?;
+
+        Ok(CreateView {
+            name,
+            columns,
+            select: Box::new(select),
+        })
+    }
+
+    fn parse_create_index(&mut self, unique: bool) -> Result<CreateIndex, anyhow::Error> {
+        self.parse_if_not_exists()?;
+        let name = self.qualified_name()?;
+        self.expect_kw("ON")?;
+        let table = self.ident()?;
+        let columns = self.parse_indexed_columns()?;
+        let where_clause = if self.eat_kw("WHERE") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        Ok(CreateIndex {
+            name,
+            table,
+            unique,
+            columns,
+            where_clause,
+        })
+    }
+
+    fn parse_indexed_columns(&mut self) -> Result<Vec<IndexedColumn>, anyhow::Error> {
+        self.expect_sym("(")?;
+        let mut columns = vec![];
+        loop {
+            let name = self.ident()?;
+            let collation = if self.eat_kw("COLLATE") {
+                Some(self.ident()?)
+            } else {
+                None
+            };
+            let desc = if self.eat_kw("DESC") {
+                true
+            } else {
+                self.eat_kw("ASC");
+                false
+            };
+            columns.push(IndexedColumn {
+                name,
+                collation,
+                desc,
+            });
+            if !self.eat_sym(",") {
+                break;
+            }
+        }
+        self.expect_sym(")")?;
+        Ok(columns)
+    }
+
+    /// Skip a balanced parenthesised group, returning its tokens as text.
+    fn skip_parens(&mut self) -> Result<String, anyhow::Error> {
+        self.expect_sym("(")?;
+        let mut text = String::from("(");
+        let mut depth = 1;
+        while depth > 0 {
+            match self.peek() {
+                Token::Eof => bail!("unbalanced parentheses"),
+                Token::Symbol("(") => depth += 1,
+                Token::Symbol(")") => depth -= 1,
+                _ => {}
+            }
+            text.push_str(&self.peek_text());
+            self.advance();
+        }
+        Ok(text)
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+/// A unary operation, with negative literals folded so that e.g.
+/// -9223372036854775808 stays an integer.
+fn unary(op: UnaryOp, expr: Expr) -> Expr {
+    if op == UnaryOp::Neg {
+        match expr {
+            Expr::Literal(ColumnType::Int64(n)) => {
+                return Expr::Literal(ColumnType::Int64(n.wrapping_neg()))
+            }
+            Expr::Literal(ColumnType::Float(f)) if f == -(i64::MIN as f64) => {
+                return Expr::Literal(ColumnType::Int64(i64::MIN))
+            }
+            Expr::Literal(ColumnType::Float(f)) => return Expr::Literal(ColumnType::Float(-f)),
+            _ => {}
+        }
+    }
+    Expr::Unary {
+        op,
+        expr: Box::new(expr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_create_table() {
+        let sql = "CREATE TABLE apples\n(\n\tid integer primary key autoincrement,\n\tname text,\n\tcolor text\n)";
+        let StatementKind::CreateTable(table) = parse(sql).unwrap().kind else {
+            panic!("expected CREATE TABLE");
+        };
+        assert_eq!(table.name, "apples");
+        let names = table
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["id", "name", "color"]);
+        assert!(table.columns[0].primary_key);
+        assert_eq!(table.columns[1].type_name.as_deref(), Some("text"));
+    }
+
+    #[test]
+    fn test_parse_create_table_constraints() {
+        let sql = "CREATE TABLE \"t\" (a INTEGER NOT NULL DEFAULT -1 CHECK (a > 0), b VARCHAR(10) \
+                   REFERENCES other(x) ON DELETE SET NULL, c, PRIMARY KEY (b, c))";
+        let StatementKind::CreateTable(table) = parse(sql).unwrap().kind else {
+            panic!("expected CREATE TABLE");
+        };
+        assert_eq!(table.columns.len(), 3);
+        assert_eq!(table.columns[1].type_name.as_deref(), Some("VARCHAR(10)"));
+        let key = table.primary_key.iter().map(|c| c.name.as_str());
+        assert_eq!(key.collect::<Vec<_>>(), vec!["b", "c"]);
+        assert!(!table.without_rowid);
+
+        let sql = "CREATE TABLE kv (v, k TEXT PRIMARY KEY DESC) WITHOUT ROWID, STRICT";
+        let StatementKind::CreateTable(table) = parse(sql).unwrap().kind else {
+            panic!("expected CREATE TABLE");
+        };
+        assert!(table.without_rowid);
+        assert!(table.primary_key[0].desc);
+        assert_eq!(table.record_columns(), Some(vec![1, 0]));
+        assert!(parse("CREATE TABLE t (a, b) WITHOUT ROWID").is_err());
+    }
+
+    #[test]
+    fn test_parse_generated_columns() {
+        let sql = "CREATE TABLE g (a INT, b AS (a * 2), c TEXT GENERATED ALWAYS AS (a || 'x') \
+                   STORED, d INT AS (b + 1) VIRTUAL NOT NULL, e)";
+        let StatementKind::CreateTable(table) = parse(sql).unwrap().kind else {
+            panic!("expected CREATE TABLE");
+        };
+        let generated = table
+            .columns
+            .iter()
+            .map(|c| c.generated.as_ref().map(|g| g.stored));
+        assert_eq!(
+            generated.collect::<Vec<_>>(),
+            vec![None, Some(false), Some(true), Some(false), None]
+        );
+        assert_eq!(table.columns[2].type_name.as_deref(), Some("TEXT"));
+        // the records hold every column but the VIRTUAL ones
+        assert_eq!(table.stored_columns(), vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let sql = "CREATE TABLE d (a DEFAULT -5, b TEXT DEFAULT 'x' COLLATE nocase, \
+                   c DEFAULT (1 + 2), d DEFAULT abc, e DEFAULT NULL, f)";
+        let StatementKind::CreateTable(table) = parse(sql).unwrap().kind else {
+            panic!("expected CREATE TABLE");
+        };
+        let default = |i: usize| match &table.columns[i].default {
+            Some(Expr::Literal(value)) => Some(value.clone()),
+            _ => None,
+        };
+        assert_eq!(default(0), Some(ColumnType::Int64(-5)));
+        assert_eq!(default(1), Some(ColumnType::String("x".to_string())));
+        assert_eq!(table.columns[1].collation.as_deref(), Some("nocase"));
+        assert!(matches!(
+            table.columns[2].default,
+            Some(Expr::Binary { .. })
+        ));
+        // a bare name is a string
+        assert_eq!(default(3), Some(ColumnType::String("abc".to_string())));
+        assert_eq!(default(4), Some(ColumnType::Null));
+        assert!(table.columns[5].default.is_none());
+        // the text is kept as written, without the parentheses
+        let text = |i: usize| table.columns[i].default_text.as_deref();
+        assert_eq!(text(0), Some("-5"));
+        assert_eq!(text(1), Some("'x'"));
+        assert_eq!(text(2), Some("1 + 2"));
+    }
+
+    #[test]
+    fn test_parse_table_keys() {
+        let sql =
+            "CREATE TABLE k (id INTEGER, a TEXT NOT NULL UNIQUE REFERENCES p ON DELETE CASCADE, \
+                   b, PRIMARY KEY (id), UNIQUE (a COLLATE nocase, b), UNIQUE (a), \
+                   FOREIGN KEY (a, b) REFERENCES q (x, y) ON UPDATE SET NULL MATCH FULL)";
+        let StatementKind::CreateTable(table) = parse(sql).unwrap().kind else {
+            panic!("expected CREATE TABLE");
+        };
+        assert!(table.columns[1].not_null);
+        assert_eq!(table.keys.len(), 4);
+        // the INTEGER PRIMARY KEY is the rowid, and UNIQUE (a) repeats the
+        // first UNIQUE constraint
+        let autoindexes = table.autoindexes();
+        assert_eq!(autoindexes.len(), 2);
+        assert_eq!(autoindexes[1].0.len(), 2);
+        assert_eq!(table.foreign_keys.len(), 2);
+        let key = &table.foreign_keys[0];
+        assert_eq!((key.columns.len(), key.table.as_str()), (1, "p"));
+        assert!(key.to.is_empty());
+        assert_eq!(key.on_delete, "CASCADE");
+        let key = &table.foreign_keys[1];
+        assert_eq!(key.to, ["x", "y"]);
+        assert_eq!(
+            (key.on_update.as_str(), key.on_delete.as_str()),
+            ("SET NULL", "NO ACTION")
+        );
+
+        let sql = "CREATE INDEX i ON k (b) WHERE b > 0";
+        let StatementKind::CreateIndex(index) = parse(sql).unwrap().kind else {
+            panic!("expected CREATE INDEX");
+        };
+        assert!(index.where_clause.is_some());
+        let sql = "SELECT * FROM pragma_table_info('k') AS t, pragma_function_list";
+        let StatementKind::Select(select) = parse(sql).unwrap().kind else {
+            panic!("expected SELECT");
+        };
+        let Some(FromClause::Join { left, right, .. }) = &select.body.from else {
+            panic!("expected a join");
+        };
+        assert!(
+            matches!(&**left, FromClause::Function { args, alias: Some(_), .. } if args.len() == 1)
+        );
+        assert!(matches!(&**right, FromClause::Function { args, .. } if args.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_select_subqueries() {
+        let sql = "SELECT a, (SELECT max(b) FROM u) AS m FROM t WHERE a IN (SELECT x FROM v) \
+                   AND NOT EXISTS (SELECT 1 FROM w WHERE w.id = t.id) ORDER BY 1 DESC LIMIT 5, 10";
+        let StatementKind::Select(select) = parse(sql).unwrap().kind else {
+            panic!("expected SELECT");
+        };
+        assert_eq!(select.body.columns.len(), 2);
+        assert!(matches!(
+            select.body.where_clause,
+            Some(Expr::Binary {
+                op: BinaryOp::And,
+                ..
+            })
+        ));
+        assert!(select.order_by[0].desc);
+        assert!(matches!(
+            select.offset,
+            Some(Expr::Literal(ColumnType::Int64(5)))
+        ));
+        assert!(matches!(
+            select.limit,
+            Some(Expr::Literal(ColumnType::Int64(10)))
+        ));
+    }
+
+    #[test]
+    fn test_parse_with_and_union() {
+        let sql =
+            "WITH RECURSIVE c(x) AS NOT MATERIALIZED (SELECT 1 UNION ALL SELECT x + 1 FROM c), \
+                   d AS (SELECT 2) SELECT x FROM c UNION SELECT * FROM d ORDER BY 1";
+        let StatementKind::Select(select) = parse(sql).unwrap().kind else {
+            panic!("expected SELECT");
+        };
+        let with = select.with.as_ref().unwrap();
+        assert!(with.recursive);
+        assert_eq!(with.ctes.len(), 2);
+        assert_eq!(with.ctes[0].columns, vec!["x"]);
+        assert_eq!(with.ctes[0].materialized, Some(false));
+        assert_eq!(with.ctes[0].select.compound[0].0, CompoundOp::UnionAll);
+        assert_eq!(select.compound.len(), 1);
+        assert_eq!(select.compound[0].0, CompoundOp::Union);
+        assert_eq!(select.order_by.len(), 1);
+
+        let error = parse("SELECT 1 ORDER BY 1 INTERSECT SELECT 2").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "ORDER BY clause should come after INTERSECT not before"
+        );
+    }
+
+    #[test]
+    fn test_parse_create_view() {
+        let sql = "CREATE TEMP VIEW IF NOT EXISTS main.v(a, b) AS SELECT id, name FROM t UNION SELECT 1, 2";
+        let StatementKind::CreateView(view) = parse(sql).unwrap().kind else {
+            panic!("expected CREATE VIEW");
+        };
+        assert_eq!(view.name, "v");
+        assert_eq!(view.columns, vec!["a", "b"]);
+        assert_eq!(view.select.compound.len(), 1);
+
+        let StatementKind::CreateView(view) =
+            parse("CREATE VIEW w AS SELECT * FROM v").unwrap().kind
+        else {
+            panic!("expected CREATE VIEW");
+        };
+        assert!(view.columns.is_empty());
+        assert!(parse("CREATE VIEW w SELECT 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_window() {
+        let sql =
+            "SELECT sum(x) OVER (w ORDER BY y DESC RANGE BETWEEN 2 PRECEDING AND CURRENT ROW \
+                   EXCLUDE TIES), rank() OVER w FROM t WINDOW w AS (PARTITION BY z)";
+        let StatementKind::Select(select) = parse(sql).unwrap().kind else {
+            panic!("expected SELECT");
+        };
+        let ResultColumn::Expr {
+            expr: Expr::Function { over, .. },
+            ..
+        } = &select.body.columns[0]
+        else {
+            panic!("expected function");
+        };
+        let Some(Over::Spec(window)) = over.as_deref() else {
+            panic!("expected window definition");
+        };
+        assert_eq!(window.base.as_deref(), Some("w"));
+        let frame = window.frame.as_ref().unwrap();
+        assert_eq!(frame.units, FrameUnits::Range);
+        assert!(matches!(frame.start, FrameBound::Preceding(_)));
+        assert_eq!(frame.exclude, FrameExclude::Ties);
+        assert_eq!(select.body.windows[0].0, "w");
+
+        let error = parse("SELECT sum(x) OVER (ROWS BETWEEN 1 FOLLOWING AND CURRENT ROW) FROM t");
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "unsupported frame specification"
+        );
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let StatementKind::Select(select) = parse("SELECT 1 + 2 * 3 = 7 OR 0").unwrap().kind else {
+            panic!("expected SELECT");
+        };
+        let ResultColumn::Expr { expr, .. } = &select.body.columns[0] else {
+            panic!("expected expression");
+        };
+        let Expr::Binary { op, left, .. } = expr else {
+            panic!("expected binary expression");
+        };
+        assert_eq!(*op, BinaryOp::Or);
+        assert!(matches!(
+            **left,
+            Expr::Binary {
+                op: BinaryOp::Eq,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_parse_parameters() {
+        let mut statement = parse("SELECT ?, ?5, :a, ?, :a, @b").unwrap();
+        assert_eq!(statement.parameter_count(), 8);
+        assert_eq!(statement.parameter_name(1), None);
+        assert_eq!(statement.parameter_name(5), Some("?5"));
+        assert_eq!(statement.parameter_index(":a"), Some(6));
+        assert_eq!(statement.parameter_index("@b"), Some(8));
+        let StatementKind::Select(select) = &statement.kind else {
+            panic!("expected SELECT");
+        };
+        let indexes = select
+            .body
+            .columns
+            .iter()
+            .map(|c| match c {
+                ResultColumn::Expr {
+                    expr: Expr::Parameter(i),
+                    ..
+                } => *i,
+                _ => panic!("expected a parameter"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(indexes, vec![1, 5, 6, 7, 6, 8]);
+
+        statement.bind(":a", ColumnType::Int64(1)).unwrap();
+        statement.bind(8, ColumnType::Int64(2)).unwrap();
+        assert_eq!(statement.binding(6), ColumnType::Int64(1));
+        assert_eq!(statement.binding(2), ColumnType::Null);
+        assert!(statement.bind(9, ColumnType::Null).is_err());
+        assert!(statement.bind(":b", ColumnType::Null).is_err());
+        assert!(parse("SELECT ?0").is_err());
+    }
+
+    #[test]
+    fn test_parse_explain() {
+        let statement = parse("EXPLAIN QUERY PLAN SELECT 1").unwrap();
+        assert_eq!(statement.explain, Some(Explain::QueryPlan));
+        assert!(matches!(statement.kind, StatementKind::Select(_)));
+        assert_eq!(parse("SELECT 1").unwrap().explain, None);
+        assert_eq!(
+            parse("EXPLAIN SELECT 1").unwrap().explain,
+            Some(Explain::Program)
+        );
+        assert!(parse("EXPLAIN QUERY SELECT 1").is_err());
+    }
+}
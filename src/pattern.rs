@@ -0,0 +1,605 @@
+use anyhow::bail;
+
+/// The wildcards of LIKE or GLOB.
+struct Wildcards {
+    /// `%` or `*`
+    all: Option<char>,
+    /// `_` or `?`
+    one: Option<char>,
+    /// `[` for GLOB character sets
+    set: bool,
+    case_sensitive: bool,
+}
+
+/// `string LIKE pattern ESCAPE escape`, case-insensitive for ASCII letters
+/// unless `case_sensitive`. An escape character that is itself a wildcard
+/// turns that wildcard off.
+pub(crate) fn like(
+    pattern: &str,
+    string: &str,
+    escape: Option<char>,
+    case_sensitive: bool,
+) -> bool {
+    let wildcards = Wildcards {
+        all: Some('%').filter(|c| escape != Some(*c)),
+        one: Some('_').filter(|c| escape != Some(*c)),
+        set: false,
+        case_sensitive,
+    };
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let string = string.chars().collect::<Vec<_>>();
+    compare(&pattern, &string, &wildcards, escape) == Match::Yes
+}
+
+/// `string GLOB pattern`: case-sensitive, with `*`, `?` and `[...]` sets.
+pub(crate) fn glob(pattern: &str, string: &str) -> bool {
+    let wildcards = Wildcards {
+        all: Some('*'),
+        one: Some('?'),
+        set: true,
+        case_sensitive: true,
+    };
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let string = string.chars().collect::<Vec<_>>();
+    compare(&pattern, &string, &wildcards, Some('[')) == Match::Yes
+}
+
+#[derive(PartialEq)]
+enum Match {
+    Yes,
+    No,
+    /// No match, and none is possible by skipping more of the string for
+    /// an earlier wildcard either.
+    Never,
+}
+
+/// SQLite's patternCompare(). `other` is the escape character for LIKE and
+/// `[` for GLOB.
+fn compare(pattern: &[char], string: &[char], wc: &Wildcards, other: Option<char>) -> Match {
+    let (mut p, mut s) = (0, 0);
+    // the position after the last escaped character
+    let mut escaped = None;
+    let same = |a: char, b: char| {
+        a == b || (!wc.case_sensitive && a.is_ascii() && b.is_ascii() && a.eq_ignore_ascii_case(&b))
+    };
+
+    while p < pattern.len() {
+        let mut c = pattern[p];
+        p += 1;
+
+        if Some(c) == wc.all {
+            // several wildcards in a row; each one-character wildcard
+            // still consumes a character
+            let mut next = None;
+            while p < pattern.len() {
+                let n = pattern[p];
+                p += 1;
+                if Some(n) == wc.all {
+                    continue;
+                }
+                if Some(n) == wc.one {
+                    if s >= string.len() {
+                        return Match::Never;
+                    }
+                    s += 1;
+                    continue;
+                }
+                next = Some(n);
+                break;
+            }
+            let Some(mut c) = next else {
+                return Match::Yes;
+            };
+            if Some(c) == other {
+                if !wc.set {
+                    let Some(&escaped) = pattern.get(p) else {
+                        return Match::Never;
+                    };
+                    c = escaped;
+                    p += 1;
+                } else {
+                    // a set right after the wildcard: try every position
+                    while s < string.len() {
+                        let m = compare(&pattern[p - 1..], &string[s..], wc, other);
+                        if m != Match::No {
+                            return m;
+                        }
+                        s += 1;
+                    }
+                    return Match::Never;
+                }
+            }
+            // find each occurrence of the next character and match the rest
+            while s < string.len() {
+                let found = same(c, string[s]);
+                s += 1;
+                if found {
+                    let m = compare(&pattern[p..], &string[s..], wc, other);
+                    if m != Match::No {
+                        return m;
+                    }
+                }
+            }
+            return Match::Never;
+        }
+
+        if Some(c) == other {
+            if !wc.set {
+                let Some(&next) = pattern.get(p) else {
+                    return Match::No;
+                };
+                c = next;
+                p += 1;
+                escaped = Some(p);
+            } else {
+                let Some(&sc) = string.get(s) else {
+                    return Match::No;
+                };
+                s += 1;
+                let (mut seen, mut invert) = (false, false);
+                let mut prior = None;
+                let next = |p: &mut usize| {
+                    let c = pattern.get(*p).copied();
+                    *p += 1;
+                    c
+                };
+                let mut c2 = next(&mut p);
+                if c2 == Some('^') {
+                    invert = true;
+                    c2 = next(&mut p);
+                }
+                if c2 == Some(']') {
+                    seen = sc == ']';
+                    c2 = next(&mut p);
+                }
+                while let Some(c) = c2.filter(|c| *c != ']') {
+                    let following = pattern.get(p).copied();
+                    if c == '-' && following.is_some_and(|f| f != ']') && prior.is_some() {
+                        let high = next(&mut p).unwrap_or_default();
+                        if prior.is_some_and(|low| sc >= low && sc <= high) {
+                            seen = true;
+                        }
+                        prior = None;
+                    } else {
+                        seen |= sc == c;
+                        prior = Some(c);
+                    }
+                    c2 = next(&mut p);
+                }
+                if c2.is_none() || seen == invert {
+                    return Match::No;
+                }
+                continue;
+            }
+        }
+
+        let Some(&sc) = string.get(s) else {
+            return Match::No;
+        };
+        s += 1;
+        if same(c, sc) {
+            continue;
+        }
+        if Some(c) == wc.one && escaped != Some(p) {
+            continue;
+        }
+        return Match::No;
+    }
+
+    if s == string.len() {
+        Match::Yes
+    } else {
+        Match::No
+    }
+}
+
+/// One step of a compiled regular expression.
+#[derive(Clone, Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    /// `[...]`: ranges of characters, negated for `[^...]`
+    Set(bool, Vec<(char, char)>),
+    /// `\d`, `\s` or `\w`, or their negations `\D`, `\S` and `\W`
+    Class(char),
+    Boundary,
+    AtStart,
+    AtEnd,
+    /// Continue at both targets.
+    Fork(usize, usize),
+    Jump(usize),
+    Accept,
+}
+
+/// A regular expression with the syntax of SQLite's regexp extension,
+/// matched without backtracking.
+/// See https://sqlite.org/src/file/ext/misc/regexp.c
+pub(crate) struct Regex {
+    program: Vec<Inst>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, anyhow::Error> {
+        let mut compiler = Compiler {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let mut program = compiler.alternation()?;
+        if compiler.pos < compiler.chars.len() {
+            bail!("unrecognized character");
+        }
+        program.push(Inst::Accept);
+        Ok(Regex { program })
+    }
+
+    /// Whether the expression matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars = text.chars().collect::<Vec<_>>();
+        let mut threads = vec![];
+        for i in 0..=chars.len() {
+            // a new attempt starts at every position
+            self.add_thread(&mut threads, 0, &chars, i);
+            if threads
+                .iter()
+                .any(|pc| matches!(self.program[*pc], Inst::Accept))
+            {
+                return true;
+            }
+            let Some(&c) = chars.get(i) else {
+                break;
+            };
+            let mut next = vec![];
+            for pc in threads {
+                let matched = match &self.program[pc] {
+                    Inst::Char(expected) => *expected == c,
+                    Inst::Any => true,
+                    Inst::Set(negated, ranges) => {
+                        ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+                    }
+                    Inst::Class(class) => class_matches(*class, c),
+                    _ => false,
+                };
+                if matched {
+                    self.add_thread(&mut next, pc + 1, &chars, i + 1);
+                }
+            }
+            threads = next;
+        }
+        false
+    }
+
+    /// Add the thread at `pc`, following forks, jumps and assertions at
+    /// position `i` of the text.
+    fn add_thread(&self, threads: &mut Vec<usize>, pc: usize, chars: &[char], i: usize) {
+        if threads.contains(&pc) {
+            return;
+        }
+        threads.push(pc);
+        match self.program[pc] {
+            Inst::Fork(a, b) => {
+                self.add_thread(threads, a, chars, i);
+                self.add_thread(threads, b, chars, i);
+            }
+            Inst::Jump(to) => self.add_thread(threads, to, chars, i),
+            Inst::AtStart if i == 0 => self.add_thread(threads, pc + 1, chars, i),
+            Inst::AtEnd if i == chars.len() => self.add_thread(threads, pc + 1, chars, i),
+            Inst::Boundary => {
+                let word = |c: Option<&char>| c.is_some_and(|c| class_matches('w', *c));
+                let before = i.checked_sub(1).and_then(|j| chars.get(j));
+                if word(before) != word(chars.get(i)) {
+                    self.add_thread(threads, pc + 1, chars, i);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn class_matches(class: char, c: char) -> bool {
+    let matched = match class.to_ascii_lowercase() {
+        'd' => c.is_ascii_digit(),
+        's' => matches!(c, ' ' | '\t' | '\n' | '\x0b' | '\x0c' | '\r'),
+        _ => c.is_ascii_alphanumeric() || c == '_',
+    };
+    matched != class.is_ascii_uppercase()
+}
+
+struct Compiler {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Compiler {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// `a|b|...`
+    fn alternation(&mut self) -> Result<Vec<Inst>, anyhow::Error> {
+        let mut program = self.sequence()?;
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            let other = self.sequence()?;
+            let mut combined = vec![Inst::Fork(1, program.len() + 2)];
+            combined.extend(relocate(program, 1));
+            let end = combined.len() + 1 + other.len();
+            combined.push(Inst::Jump(end));
+            let offset = combined.len();
+            combined.extend(relocate(other, offset));
+            program = combined;
+        }
+        Ok(program)
+    }
+
+    /// Atoms with their repetitions, up to `|`, `)` or the end.
+    fn sequence(&mut self) -> Result<Vec<Inst>, anyhow::Error> {
+        let mut program = vec![];
+        // where the last atom starts, for the repetition operators
+        let mut prev: Option<usize> = None;
+        while let Some(c) = self.peek() {
+            let start = program.len();
+            self.pos += 1;
+            match c {
+                '|' | ')' => {
+                    self.pos -= 1;
+                    break;
+                }
+                '(' => {
+                    let group = self.alternation()?;
+                    if self.peek() != Some(')') {
+                        bail!("unmatched '('");
+                    }
+                    self.pos += 1;
+                    program.extend(relocate(group, start));
+                }
+                '*' | '+' | '?' => {
+                    let Some(prev) = prev else {
+                        bail!("'{c}' without operand");
+                    };
+                    let (min, max) = match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    };
+                    let atom = program.split_off(prev);
+                    program.extend(repeat(atom, prev, min, max));
+                    continue;
+                }
+                '{' => {
+                    let Some(prev) = prev else {
+                        bail!("'{{m,n}}' without operand");
+                    };
+                    let m = self.number();
+                    let mut n = m;
+                    if self.peek() == Some(',') {
+                        self.pos += 1;
+                        n = self.number();
+                    }
+                    if self.peek() != Some('}') {
+                        bail!("unmatched '{{'");
+                    }
+                    if n > 0 && n < m {
+                        bail!("n less than m in '{{m,n}}'");
+                    }
+                    if m == 0 && n == 0 {
+                        bail!("both m and n are zero in '{{m,n}}'");
+                    }
+                    self.pos += 1;
+                    let atom = program.split_off(prev);
+                    let max = if n == 0 { None } else { Some(n) };
+                    program.extend(repeat(atom, prev, m, max));
+                    continue;
+                }
+                '.' => program.push(Inst::Any),
+                '^' => program.push(Inst::AtStart),
+                '$' => program.push(Inst::AtEnd),
+                '[' => program.push(self.set()?),
+                '\\' => match self.peek() {
+                    Some(class @ ('d' | 'D' | 's' | 'S' | 'w' | 'W')) => {
+                        self.pos += 1;
+                        program.push(Inst::Class(class));
+                    }
+                    Some('b') => {
+                        self.pos += 1;
+                        program.push(Inst::Boundary);
+                    }
+                    _ => program.push(Inst::Char(self.escape()?)),
+                },
+                c => program.push(Inst::Char(c)),
+            }
+            prev = Some(start);
+        }
+        Ok(program)
+    }
+
+    fn number(&mut self) -> usize {
+        let mut n = 0;
+        while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+            n = n * 10 + d as usize;
+            self.pos += 1;
+        }
+        n
+    }
+
+    /// The character after a backslash.
+    fn escape(&mut self) -> Result<char, anyhow::Error> {
+        let hex = |chars: &[char]| {
+            let digits = chars.iter().collect::<String>();
+            u32::from_str_radix(&digits, 16)
+                .ok()
+                .and_then(char::from_u32)
+        };
+        let Some(c) = self.peek() else {
+            return Ok('\0');
+        };
+        for (prefix, len) in [('u', 4), ('x', 2)] {
+            if c == prefix {
+                if let Some(c) = self
+                    .chars
+                    .get(self.pos + 1..self.pos + 1 + len)
+                    .and_then(hex)
+                {
+                    self.pos += 1 + len;
+                    return Ok(c);
+                }
+            }
+        }
+        let c = match c {
+            'a' => '\x07',
+            'f' => '\x0c',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'v' => '\x0b',
+            c if "\\()*.+?[$^{|}]".contains(c) => c,
+            _ => bail!("unknown \\ escape"),
+        };
+        self.pos += 1;
+        Ok(c)
+    }
+
+    /// A set after its `[`, up to the closing `]`. A `]` right at the start
+    /// is part of the set.
+    fn set(&mut self) -> Result<Inst, anyhow::Error> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = vec![];
+        loop {
+            let Some(mut c) = self.peek() else {
+                bail!("unclosed '['");
+            };
+            self.pos += 1;
+            if c == '[' && self.peek() == Some(':') {
+                bail!("POSIX character classes not supported");
+            }
+            if c == '\\' {
+                c = self.escape()?;
+            }
+            if self.peek() == Some('-') {
+                self.pos += 1;
+                let Some(mut high) = self.peek() else {
+                    bail!("unclosed '['");
+                };
+                self.pos += 1;
+                if high == '\\' {
+                    high = self.escape()?;
+                }
+                ranges.push((c, high));
+            } else {
+                ranges.push((c, c));
+            }
+            if self.peek() == Some(']') {
+                self.pos += 1;
+                return Ok(Inst::Set(negated, ranges));
+            }
+        }
+    }
+}
+
+/// Shift the jump targets of a program that is moved to `offset`.
+fn relocate(program: Vec<Inst>, offset: usize) -> Vec<Inst> {
+    program
+        .into_iter()
+        .map(|inst| match inst {
+            Inst::Fork(a, b) => Inst::Fork(a + offset, b + offset),
+            Inst::Jump(to) => Inst::Jump(to + offset),
+            inst => inst,
+        })
+        .collect()
+}
+
+/// `atom{min,max}` for an atom compiled at `offset`, which is also where
+/// the result goes; no `max` is unbounded.
+fn repeat(atom: Vec<Inst>, offset: usize, min: usize, max: Option<usize>) -> Vec<Inst> {
+    // move the atom to the start so each copy can be relocated
+    let atom = atom
+        .into_iter()
+        .map(|inst| match inst {
+            Inst::Fork(a, b) => Inst::Fork(a - offset, b - offset),
+            Inst::Jump(to) => Inst::Jump(to - offset),
+            inst => inst,
+        })
+        .collect::<Vec<_>>();
+    let len = atom.len();
+    let mut program = vec![];
+    for _ in 0..min {
+        let at = offset + program.len();
+        program.extend(relocate(atom.clone(), at));
+    }
+    match max {
+        None => {
+            // fork into another copy or past it, then loop back
+            let at = offset + program.len();
+            program.push(Inst::Fork(at + 1, at + len + 2));
+            program.extend(relocate(atom, at + 1));
+            program.push(Inst::Jump(at));
+        }
+        Some(max) => {
+            for _ in min..max {
+                let at = offset + program.len();
+                program.push(Inst::Fork(at + 1, at + len + 1));
+                program.extend(relocate(atom.clone(), at + 1));
+            }
+        }
+    }
+    program
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_like() {
+        assert!(like("al%", "ALBANY", None, false));
+        assert!(!like("al%", "ALBANY", None, true));
+        assert!(like("a_c", "abc", None, false));
+        assert!(like("%b%", "abc", None, false));
+        assert!(!like("%b", "abc", None, false));
+        assert!(like("a\\_b", "a_b", Some('\\'), false));
+        assert!(!like("a\\_b", "axb", Some('\\'), false));
+        assert!(like("100%%", "100%", Some('%'), false));
+        assert!(!like("100%%", "1000", Some('%'), false));
+        assert!(!like("é", "É", None, false));
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("al*", "alfa"));
+        assert!(!glob("al*", "Alfa"));
+        assert!(glob("a?c", "abc"));
+        assert!(glob("*[0-9]", "x7"));
+        assert!(glob("[^a]*", "bcd"));
+        assert!(!glob("*[!a]", "abc"));
+        assert!(glob("*[]]", "a]"));
+        assert!(glob("*[a-]", "a-"));
+    }
+
+    #[test]
+    fn test_regexp() {
+        let matches = |pattern: &str, text: &str| Regex::new(pattern).unwrap().is_match(text);
+        assert!(matches("b", "abc"));
+        assert!(matches("^(a|x)b+[c-d]$", "abbbd"));
+        assert!(!matches("^(a|x)b+[c-d]$", "abbbe"));
+        assert!(matches("^a{2,3}$", "aaa"));
+        assert!(!matches("^a{2,3}$", "aaaa"));
+        assert!(matches("^a{2,}$", "aaaa"));
+        assert!(matches("\\bcat\\b", "a cat."));
+        assert!(!matches("\\bcat\\b", "concat"));
+        assert!(matches("^\\d+\\s\\w+$", "42 apples"));
+        assert!(matches("^[^0-9]*$", "abc"));
+        assert!(matches("^\\u00e9$", "é"));
+        for (pattern, error) in [
+            ("(", "unmatched '('"),
+            ("*a", "'*' without operand"),
+            ("a)", "unrecognized character"),
+            ("[a", "unclosed '['"),
+            ("a{3,2}", "n less than m in '{m,n}'"),
+            ("\\q", "unknown \\ escape"),
+        ] {
+            assert_eq!(Regex::new(pattern).err().unwrap().to_string(), error);
+        }
+    }
+}
@@ -0,0 +1,1097 @@
+use std::cmp::Ordering;
+
+use crate::ast::{BinaryOp, ColumnDef, CreateTable, Expr};
+use crate::collation::{Collation, Collations};
+use crate::convert::{parse_number, Affinity};
+use crate::db::Database;
+use crate::stats::Statistics;
+use crate::table::{ColumnType, TableInfo};
+
+/// SQLite's guess at the number of rows of a table that was never analyzed.
+const DEFAULT_ROWS: f64 = 1048576.0;
+
+/// SQLite's guess at the number of index entries sharing the values of the
+/// first one, two, ... columns without statistics; 5 for more columns.
+const DEFAULT_EQUAL_ROWS: [f64; 5] = [10.0, 9.0, 8.0, 7.0, 6.0];
+
+/// How the rows of a table are read.
+pub(crate) enum Access<'a> {
+    /// Every row, in rowid order.
+    Scan,
+    /// The rows whose rowids are the values of the key.
+    RowidEq(SeekKey<'a>),
+    /// The rows whose rowids are within the bounds, in rowid order, each
+    /// bound with whether it is inclusive.
+    RowidRange {
+        lower: Option<(SeekKey<'a>, bool)>,
+        upper: Option<(SeekKey<'a>, bool)>,
+    },
+    /// The rows found by looking up keys in an index, in index order.
+    IndexSeek(IndexSeek<'a>),
+    /// The rows found through a range of an index, in index order.
+    IndexRange(IndexRange<'a>),
+    /// Every entry of an index that holds all the columns the query needs,
+    /// in index order.
+    IndexScan(IndexScan<'a>),
+    /// The rows found by each way of reading the table in turn, one for
+    /// each side of an OR, that the side holds for, skipping the rowids
+    /// already found.
+    MultiIndexOr(Vec<(&'a Expr, Plan<'a>)>),
+}
+
+impl Access<'_> {
+    /// How reading the table named `alias` this way is shown by EXPLAIN
+    /// QUERY PLAN, in the words of sqlite3.
+    pub fn describe(&self, alias: &str) -> String {
+        match self {
+            Access::Scan => format!("SCAN {alias}"),
+            Access::RowidEq(_) => format!("SEARCH {alias} USING INTEGER PRIMARY KEY (rowid=?)"),
+            Access::RowidRange { lower, upper } => {
+                let terms = [(lower.is_some(), "rowid>?"), (upper.is_some(), "rowid<?")]
+                    .into_iter()
+                    .filter_map(|(bound, term)| bound.then_some(term))
+                    .collect::<Vec<_>>();
+                format!(
+                    "SEARCH {alias} USING INTEGER PRIMARY KEY ({})",
+                    terms.join(" AND ")
+                )
+            }
+            Access::IndexSeek(seek) => {
+                let mut terms = seek
+                    .columns
+                    .iter()
+                    .take(seek.eq.len())
+                    .map(|c| format!("{c}=?"))
+                    .collect::<Vec<_>>();
+                if let Some(column) = seek.columns.get(seek.eq.len()) {
+                    if seek.lower.is_some() {
+                        terms.push(format!("{column}>?"));
+                    }
+                    if seek.upper.is_some() {
+                        terms.push(format!("{column}<?"));
+                    }
+                }
+                format!(
+                    "SEARCH {alias} USING {} ({})",
+                    index_name(seek.index, seek.covering.is_some()),
+                    terms.join(" AND ")
+                )
+            }
+            Access::IndexRange(range) => format!(
+                "SEARCH {alias} USING {} ({col}>? AND {col}<?)",
+                index_name(range.index, range.covering.is_some()),
+                col = range.column
+            ),
+            Access::IndexScan(scan) => {
+                format!("SCAN {alias} USING {}", index_name(scan.index, true))
+            }
+            // the ways of reading each side are shown below this
+            Access::MultiIndexOr(_) => "MULTI-INDEX OR".to_string(),
+        }
+    }
+
+    /// Whether the rows are made from index entries alone.
+    fn is_covering(&self) -> bool {
+        match self {
+            Access::IndexSeek(IndexSeek { covering, .. })
+            | Access::IndexRange(IndexRange { covering, .. }) => covering.is_some(),
+            Access::IndexScan(_) => true,
+            _ => false,
+        }
+    }
+
+    /// The number of rows expected to be read from a table of `rows` rows.
+    /// Without statistics SQLite assumes that each bound of a range keeps a
+    /// quarter of the rows, so a range with both bounds keeps 1 in 16.
+    pub fn estimated_rows(&self, rows: f64) -> f64 {
+        match self {
+            Access::Scan | Access::IndexScan(_) => rows,
+            Access::RowidEq(key) => rows.min(key.values.len() as f64),
+            Access::RowidRange { lower, upper } => {
+                let bounds = lower.iter().chain(upper).count();
+                (rows * 0.25f64.powi(bounds as i32)).ceil()
+            }
+            Access::IndexSeek(seek) => {
+                let each = (seek.equal_rows.map_or(rows, |r| r.min(rows)) * seek.range).ceil();
+                rows.min(each * seek.seeks)
+            }
+            Access::IndexRange(_) => (rows / 16.0).ceil(),
+            Access::MultiIndexOr(branches) => rows.min(
+                branches
+                    .iter()
+                    .map(|(_, b)| b.access.estimated_rows(rows))
+                    .sum(),
+            ),
+        }
+    }
+
+    /// The cost of reading a table of `rows` rows this way, in the units of
+    /// SQLite's planner: a full scan costs about three per row, a seek the
+    /// depth of the b-tree for each value looked up, and each row found
+    /// through an index `entry` for the index entry, plus three for looking
+    /// it up in the table unless the index holds all the query needs. The
+    /// sides of an OR cost what they cost on their own.
+    fn cost(&self, rows: f64, entry: f64) -> f64 {
+        let depth = rows.max(1.0).log2();
+        let found = self.estimated_rows(rows);
+        match self {
+            Access::Scan => 3.0 * rows,
+            Access::RowidEq(key) => (depth + 1.0) * key.values.len() as f64,
+            Access::RowidRange { .. } => depth + 3.0 * found,
+            Access::IndexScan(_) => entry * rows,
+            Access::IndexSeek(IndexSeek { covering, .. })
+            | Access::IndexRange(IndexRange { covering, .. }) => {
+                let seeks = match self {
+                    Access::IndexSeek(seek) => seek.seeks,
+                    _ => 1.0,
+                };
+                let lookups = if covering.is_some() { 0.0 } else { 3.0 };
+                depth * seeks + (entry + lookups) * found
+            }
+            Access::MultiIndexOr(branches) => branches.iter().map(|(_, b)| b.cost).sum(),
+        }
+    }
+}
+
+/// How EXPLAIN QUERY PLAN names an index; the primary key of a WITHOUT
+/// ROWID table holds the whole rows.
+fn index_name(index: &TableInfo, covering: bool) -> String {
+    if index.primary_key {
+        "PRIMARY KEY".to_string()
+    } else if covering {
+        format!("COVERING INDEX {}", index.name)
+    } else {
+        format!("INDEX {}", index.name)
+    }
+}
+
+/// The values to look up: expressions of the query that do not depend on
+/// the table read, converted to the affinity of the comparison. There are
+/// several for `IN (...)`, each looked up in turn.
+#[derive(Clone)]
+pub(crate) struct SeekKey<'a> {
+    pub values: Vec<&'a Expr>,
+    pub affinity: Option<Affinity>,
+}
+
+/// A term of the query comparing a column of the table with a value that
+/// can be computed before the table is read.
+#[derive(Clone)]
+pub(crate) struct Constraint<'a> {
+    /// The term of the query it comes from.
+    pub term: usize,
+    /// The column of the table, or `None` for the rowid.
+    pub column: Option<usize>,
+    /// `=`, `<`, `<=`, `>` or `>=`, with the column on the left; `=` for
+    /// `IN (...)`, whose key has the values of the list.
+    pub op: BinaryOp,
+    pub key: SeekKey<'a>,
+    /// The collation of the comparison.
+    pub collation: Collation,
+    /// The value of a key of a single value if it is known before the
+    /// query runs, with the affinity applied, for estimates from
+    /// `sqlite_stat4`.
+    pub constant: Option<ColumnType>,
+}
+
+/// A term of the query `a OR b OR ...`: each side, with the constraints it
+/// puts on the columns of the table. Reading the rows each side allows finds
+/// all the rows the term holds for.
+pub(crate) struct Disjunction<'a> {
+    pub term: usize,
+    pub branches: Vec<(&'a Expr, Vec<Constraint<'a>>)>,
+}
+
+/// The index entries whose leading columns equal the `eq` keys and whose
+/// next column is within the bounds.
+pub(crate) struct IndexSeek<'a> {
+    pub index: &'a TableInfo,
+    /// The names of the index columns the seek uses: those the `eq` keys
+    /// are for, then the one the bounds are for. The rowid that ends every
+    /// index entry is `rowid`.
+    pub columns: Vec<String>,
+    /// The collations of those columns.
+    pub collations: Vec<Collation>,
+    /// Whether each of those columns is in descending order.
+    pub desc: Vec<bool>,
+    pub eq: Vec<SeekKey<'a>>,
+    /// The lower and upper bound, with whether each is inclusive.
+    pub lower: Option<(SeekKey<'a>, bool)>,
+    pub upper: Option<(SeekKey<'a>, bool)>,
+    /// The table column of each column of the index, when it holds all
+    /// the columns the query needs, so that rows are made from the index
+    /// entries without reading the table.
+    pub covering: Option<Vec<usize>>,
+    /// The estimated number of entries for one value of the `eq` columns.
+    equal_rows: Option<f64>,
+    /// The number of lookups: one for each combination of the values of
+    /// the `eq` keys.
+    seeks: f64,
+    /// The estimated fraction of those entries within the bounds.
+    range: f64,
+}
+
+impl IndexSeek<'_> {
+    /// Whether an index entry comes before, among or after the entries the
+    /// seek finds, given the values of its keys and bounds.
+    pub fn position(
+        &self,
+        entry: &[ColumnType],
+        eq: &[ColumnType],
+        lower: Option<&ColumnType>,
+        upper: Option<&ColumnType>,
+    ) -> Ordering {
+        let column = |i: usize| entry.get(i).unwrap_or(&ColumnType::Null);
+        // a descending column holds its values in reverse
+        let order = |i: usize, o: Ordering| match self.desc[i] {
+            true => o.reverse(),
+            false => o,
+        };
+        for (i, key) in eq.iter().enumerate() {
+            let o = self.collations[i].compare(column(i), key);
+            if o != Ordering::Equal {
+                return order(i, o);
+            }
+        }
+        let Some(collation) = self.collations.get(eq.len()) else {
+            return Ordering::Equal;
+        };
+        let value = column(eq.len());
+        if let (Some(bound), Some((_, inclusive))) = (lower, &self.lower) {
+            let o = collation.compare(value, bound);
+            if o == Ordering::Less || (o == Ordering::Equal && !inclusive) {
+                return order(eq.len(), Ordering::Less);
+            }
+        } else if value.is_null() {
+            // NULL is before every value and within no bound
+            return order(eq.len(), Ordering::Less);
+        }
+        if let (Some(bound), Some((_, inclusive))) = (upper, &self.upper) {
+            let o = collation.compare(value, bound);
+            if o == Ordering::Greater || (o == Ordering::Equal && !inclusive) {
+                return order(eq.len(), Ordering::Greater);
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+/// The index entries whose first column is text from `low` up to but not
+/// including `high`, followed by the blobs between the same bounds. Blobs
+/// sort after all text, so these are two separate ranges of the index.
+pub(crate) struct IndexRange<'d> {
+    pub index: &'d TableInfo,
+    /// The name of the first column of the index.
+    pub column: String,
+    /// Whether the index compares text with the NOCASE collation.
+    pub nocase: bool,
+    pub low: Vec<u8>,
+    pub high: Vec<u8>,
+    /// As for [`IndexSeek::covering`].
+    pub covering: Option<Vec<usize>>,
+}
+
+/// An index holding all the columns the query needs of a table.
+pub(crate) struct IndexScan<'a> {
+    pub index: &'a TableInfo,
+    /// The table column of each column of the index.
+    pub columns: Vec<usize>,
+}
+
+impl IndexRange<'_> {
+    /// Whether an index key comes before, inside or after the text range,
+    /// or the blob range if `blob`.
+    pub fn position(&self, key: &ColumnType, blob: bool) -> Ordering {
+        let fold = |s: &[u8]| {
+            if self.nocase && !blob {
+                s.to_ascii_lowercase()
+            } else {
+                s.to_vec()
+            }
+        };
+        let key = match key {
+            ColumnType::String(s) if !blob => fold(s.as_bytes()),
+            ColumnType::Blob(b) if blob => b.clone(),
+            ColumnType::Blob(_) => return Ordering::Greater,
+            _ => return Ordering::Less,
+        };
+        if key < fold(&self.low) {
+            Ordering::Less
+        } else if key >= fold(&self.high) {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }
+}
+
+/// The chosen way of reading a table, with its estimates.
+pub(crate) struct Plan<'a> {
+    pub access: Access<'a>,
+    /// The estimated number of rows read, for the number of rows in the
+    /// statistics or SQLite's guess.
+    pub rows: f64,
+    pub cost: f64,
+    /// The terms the access makes use of.
+    pub terms: Vec<usize>,
+}
+
+impl Plan<'_> {
+    /// Reading all of `rows` rows in turn.
+    pub fn scan(rows: f64) -> Self {
+        Plan {
+            access: Access::Scan,
+            rows,
+            cost: Access::Scan.cost(rows, 1.0),
+            terms: vec![],
+        }
+    }
+}
+
+/// What the planner knows besides the query.
+pub(crate) struct Planner<'p> {
+    pub db: &'p Database,
+    pub stats: &'p Statistics,
+    pub collations: &'p Collations,
+    /// `PRAGMA case_sensitive_like`, which decides the LIKE optimization.
+    pub case_sensitive_like: bool,
+}
+
+impl<'p> Planner<'p> {
+    /// The number of rows of a table, from the statistics or as guessed.
+    pub fn table_rows(&self, table: &TableInfo) -> f64 {
+        self.stats.table_rows(&table.name).unwrap_or(DEFAULT_ROWS)
+    }
+
+    /// Choose the cheapest way to read `table`, named `alias` in the query,
+    /// given the constraints on its columns and the terms of the query that
+    /// only concern this table, which may allow the LIKE optimization, and
+    /// the OR terms, each side of which may be read on its own. The terms
+    /// still have to be applied to the rows. `needed` tells which columns
+    /// of the table the query refers to.
+    pub fn plan<'a>(
+        &self,
+        table: &'a TableInfo,
+        alias: &str,
+        constraints: &[Constraint<'a>],
+        terms: &[(usize, &Expr)],
+        ors: &[Disjunction<'a>],
+        needed: &[bool],
+    ) -> Result<Plan<'a>, anyhow::Error>
+    where
+        'p: 'a,
+    {
+        let (db, stats) = (self.db, self.stats);
+        let schema = table.table()?;
+        let columns = &schema.columns;
+        let rows = self.table_rows(table);
+        let table_width = log_est(table_width(&schema));
+
+        // in the order SQLite tries them, which keeps the first of equally
+        // cheap ones: the rowid, then the indexes, the newest first; each
+        // with the cost of an index entry relative to a row
+        let mut candidates = vec![];
+        let rowid = constraints.iter().find(|c| {
+            c.column.is_none()
+                && c.op == BinaryOp::Eq
+                && affinity_usable(c.key.affinity, Affinity::Integer)
+        });
+        if let Some(c) = rowid {
+            candidates.push((Access::RowidEq(c.key.clone()), vec![c.term], 1.0));
+        }
+        let rowid_bound = |ops: &[BinaryOp]| {
+            constraints.iter().find(|c| {
+                c.column.is_none()
+                    && ops.contains(&c.op)
+                    && affinity_usable(c.key.affinity, Affinity::Integer)
+            })
+        };
+        let lower = rowid_bound(&[BinaryOp::Gt, BinaryOp::GtEq]);
+        let upper = rowid_bound(&[BinaryOp::Lt, BinaryOp::LtEq]);
+        if lower.is_some() || upper.is_some() {
+            let access = Access::RowidRange {
+                lower: lower.map(|c| (c.key.clone(), c.op == BinaryOp::GtEq)),
+                upper: upper.map(|c| (c.key.clone(), c.op == BinaryOp::LtEq)),
+            };
+            let terms = lower.iter().chain(&upper).map(|c| c.term).collect();
+            candidates.push((access, terms, 1.0));
+        }
+        // like SQLite, pass over an index with a collation not registered
+        let indexes = db
+            .indexes(&table.name)
+            .filter(|index| !is_partial(index) && collations_known(self.collations, &schema, index))
+            .collect::<Vec<_>>();
+        let shape = |index: &TableInfo| {
+            let mapped = index_columns(&schema, index);
+            let width = log_est(index_width(&schema, mapped.as_deref(), index));
+            // SQLite's estimate of the work per entry, from 1.1 for a
+            // narrow index up to 3 for one as wide as the table
+            let entry = 2f64.powf((1 + 15 * width / table_width.max(1)) as f64 / 10.0);
+            // as in SQLite, the value of a VIRTUAL generated column in an
+            // entry is never used instead of computing it from the row
+            let covering = mapped.filter(|mapped| {
+                needed.iter().enumerate().all(|(i, needed)| {
+                    !needed
+                        || (!schema.without_rowid && columns[i].is_rowid_alias())
+                        || (mapped.contains(&i) && !columns[i].is_virtual())
+                })
+            });
+            (covering, entry, width < table_width)
+        };
+        // the primary key of a WITHOUT ROWID table is its oldest index
+        let primary_key = db.primary_key(&table.name);
+        for index in indexes.into_iter().rev().chain(primary_key) {
+            let (covering, entry, narrow) = shape(index);
+            if let Some((mut seek, used)) =
+                index_seek(stats, self.collations, &schema, index, constraints)?
+            {
+                seek.covering = covering.clone();
+                candidates.push((Access::IndexSeek(seek), used, entry));
+            }
+            if let (Some(columns), true, false) = (covering, narrow, index.primary_key) {
+                candidates.push((
+                    Access::IndexScan(IndexScan { index, columns }),
+                    vec![],
+                    entry,
+                ));
+            }
+        }
+        for (term, expr) in terms {
+            if let Some(mut range) =
+                like_range(db, table, columns, alias, expr, self.case_sensitive_like)
+            {
+                let (covering, entry, _) = shape(range.index);
+                range.covering = covering;
+                candidates.push((Access::IndexRange(range), vec![*term], entry));
+            }
+        }
+        // the rows of each side of an OR are looked up, together with the
+        // other constraints; a side that has to scan the table rules it out
+        'ors: for or in ors {
+            let mut branches = vec![];
+            let mut used = vec![or.term];
+            for (side, branch) in &or.branches {
+                let both = branch
+                    .iter()
+                    .chain(constraints)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let plan = self.plan(table, alias, &both, &[], &[], needed)?;
+                if matches!(plan.access, Access::Scan | Access::IndexScan(_)) {
+                    continue 'ors;
+                }
+                used.extend(&plan.terms);
+                branches.push((*side, plan));
+            }
+            used.sort_unstable();
+            used.dedup();
+            candidates.push((Access::MultiIndexOr(branches), used, 1.0));
+        }
+
+        let mut plans = candidates
+            .into_iter()
+            .map(|(access, terms, entry)| Plan {
+                rows: access.estimated_rows(rows),
+                cost: access.cost(rows, entry),
+                access,
+                terms,
+            })
+            .collect::<Vec<_>>();
+        // an index lookup using the terms another one uses and more is taken
+        // to cost no more than it, and wins a tie with it, as SQLite's
+        // whereLoopAdjustCost() has it
+        let indexed = |p: &Plan| matches!(p.access, Access::IndexSeek(_) | Access::IndexRange(_));
+        let narrower = |p: &Plan, q: &Plan| {
+            indexed(p)
+                && indexed(q)
+                && p.terms.len() < q.terms.len()
+                && p.terms.iter().all(|t| q.terms.contains(t))
+                && (q.access.is_covering() || !p.access.is_covering())
+        };
+        for i in 0..plans.len() {
+            let lowest = plans
+                .iter()
+                .filter(|p| narrower(p, &plans[i]))
+                .fold(plans[i].cost, |cost, p| cost.min(p.cost));
+            plans[i].cost = lowest;
+        }
+        let mut best = Plan::scan(rows);
+        for plan in plans {
+            if plan.cost < best.cost || (plan.cost == best.cost && narrower(&best, &plan)) {
+                best = plan;
+            }
+        }
+        Ok(best)
+    }
+}
+
+/// Whether a comparison with the given affinity can be looked up in a
+/// column with affinity `column`: it has to compare values the way they are
+/// stored, as sqlite3IndexAffinityOk() decides.
+fn affinity_usable(comparison: Option<Affinity>, column: Affinity) -> bool {
+    match comparison {
+        None | Some(Affinity::Blob) => true,
+        Some(Affinity::Text) => column == Affinity::Text,
+        Some(_) => column.is_numeric(),
+    }
+}
+
+/// The seek of `index` that the constraints allow, if any, with the terms
+/// it uses: equality on as many leading columns as possible, then bounds on
+/// the next column.
+fn index_seek<'a>(
+    stats: &Statistics,
+    collations: &Collations,
+    schema: &CreateTable,
+    index: &'a TableInfo,
+    constraints: &[Constraint<'a>],
+) -> Result<Option<(IndexSeek<'a>, Vec<usize>)>, anyhow::Error> {
+    let Ok(definition) = index.index() else {
+        return Ok(None);
+    };
+    let keys = index_keys(collations, schema, index)?;
+
+    let find = |k: usize, ops: &[BinaryOp]| {
+        let key = keys.get(k)?;
+        constraints.iter().find(|c| {
+            c.column == key.column
+                && ops.contains(&c.op)
+                && c.collation == key.collation
+                && affinity_usable(c.key.affinity, key.affinity)
+        })
+    };
+    // with statistics, an IN after an IN of several values is only used
+    // if scanning the entries of the keys before it would read more, as
+    // whereLoopAddBtreeIndex() judges
+    // the statistics of a primary key are under the name of the table
+    let stats_name = match index.primary_key {
+        true => &index.table_name,
+        false => &index.name,
+    };
+    let index_stats = stats.index(stats_name);
+    let mut eq: Vec<&Constraint> = vec![];
+    let mut several = false;
+    while let Some(c) = find(eq.len(), &[BinaryOp::Eq]) {
+        let values = log_est(c.key.values.len() as u64);
+        if let (Some(index_stats), true) = (index_stats, values > 0) {
+            let size_log = est_log(log_est(index_stats.rows[0] as u64));
+            let before = index_stats.rows.get(eq.len()).or(index_stats.rows.last());
+            let before = log_est(before.copied().unwrap_or(1.0) as u64);
+            if several && size_log >= 10 && before + est_log(values) + 10 < values + size_log {
+                break;
+            }
+        }
+        several |= values > 0;
+        eq.push(c);
+    }
+    let lower = find(eq.len(), &[BinaryOp::Gt, BinaryOp::GtEq]);
+    let upper = find(eq.len(), &[BinaryOp::Lt, BinaryOp::LtEq]);
+    if eq.is_empty() && lower.is_none() && upper.is_none() {
+        return Ok(None);
+    }
+
+    let stats = stats.index(stats_name);
+    let samples = stats.filter(|s| !s.samples.is_empty());
+    let equal_rows = if eq.is_empty() {
+        None
+    } else if eq.len() == keys.len() || (eq.len() == definition.columns.len() && definition.unique)
+    {
+        Some(1.0)
+    } else if let (Some(stats), [first], Some(value)) = (samples, &eq[..], &eq[0].constant) {
+        Some(stats.equal_rows(value, &|a, b| first.collation.compare(a, b)))
+    } else if let Some(stats) = stats {
+        stats.rows.get(eq.len()).or(stats.rows.last()).copied()
+    } else {
+        Some(DEFAULT_EQUAL_ROWS.get(eq.len() - 1).copied().unwrap_or(5.0))
+    };
+
+    let bounds = lower.iter().chain(&upper).count();
+    let mut range = 0.25f64.powi(bounds as i32);
+    if let (Some(stats), true, false) = (samples, eq.is_empty(), keys[0].desc) {
+        // the entries before each bound, from the samples
+        let compare = |a: &ColumnType, b: &ColumnType| keys[0].collation.compare(a, b);
+        let total = stats.rows[0];
+        let start = match lower {
+            None => Some(0.0),
+            Some(c) => c
+                .constant
+                .as_ref()
+                .and_then(|v| stats.rows_before(v, c.op == BinaryOp::Gt, &compare)),
+        };
+        let end = match upper {
+            None => Some(total),
+            Some(c) => c
+                .constant
+                .as_ref()
+                .and_then(|v| stats.rows_before(v, c.op == BinaryOp::LtEq, &compare)),
+        };
+        if let (Some(start), Some(end)) = (start, end) {
+            range = (end - start).max(1.0) / total.max(1.0);
+        }
+    }
+
+    let used = eq.len() + lower.is_some() as usize + upper.is_some() as usize;
+    let seek = IndexSeek {
+        index,
+        columns: keys[..used.min(eq.len() + 1)]
+            .iter()
+            .map(|k| k.name.clone())
+            .collect(),
+        collations: keys[..used.min(eq.len() + 1)]
+            .iter()
+            .map(|k| k.collation.clone())
+            .collect(),
+        desc: keys[..used.min(eq.len() + 1)]
+            .iter()
+            .map(|k| k.desc)
+            .collect(),
+        eq: eq.iter().map(|c| c.key.clone()).collect(),
+        lower: lower.map(|c| (c.key.clone(), c.op == BinaryOp::GtEq)),
+        upper: upper.map(|c| (c.key.clone(), c.op == BinaryOp::LtEq)),
+        covering: None,
+        equal_rows,
+        seeks: eq.iter().map(|c| c.key.values.len() as f64).product(),
+        range,
+    };
+    let terms = eq.iter().chain(&lower).chain(&upper).map(|c| c.term);
+    Ok(Some((seek, terms.collect())))
+}
+
+/// A column of the entries of an index.
+pub(crate) struct IndexKey {
+    /// The column of the table, or `None` for the rowid.
+    pub column: Option<usize>,
+    pub name: String,
+    pub collation: Collation,
+    pub affinity: Affinity,
+    pub desc: bool,
+}
+
+/// The columns of the entries of an index in order, as far as they are
+/// columns of the table: those of the index, then the rowid, or the rest
+/// of the primary key of a WITHOUT ROWID table.
+pub(crate) fn index_keys(
+    collations: &Collations,
+    schema: &CreateTable,
+    index: &TableInfo,
+) -> Result<Vec<IndexKey>, anyhow::Error> {
+    let definition = index.index()?;
+    let columns = &schema.columns;
+    let mut keys = vec![];
+    // the entries of a WITHOUT ROWID table's indexes end with the rest of
+    // its primary key instead of the rowid
+    let rest = schema.primary_key.iter().filter(|key| {
+        schema.without_rowid
+            && !definition
+                .columns
+                .iter()
+                .any(|c| c.name.eq_ignore_ascii_case(&key.name))
+    });
+    let entry_columns = definition.columns.iter().chain(rest).collect::<Vec<_>>();
+    for indexed in entry_columns {
+        let Some(i) = columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(&indexed.name))
+        else {
+            break;
+        };
+        let column = &columns[i];
+        let collation = match indexed.collation.as_ref().or(column.collation.as_ref()) {
+            Some(name) => collations.find(name)?,
+            None => Collation::Binary,
+        };
+        keys.push(IndexKey {
+            column: Some(i),
+            name: column.name.clone(),
+            collation,
+            affinity: Affinity::of_column(column.type_name.as_deref()),
+            desc: indexed.desc,
+        });
+    }
+    if keys.len() == definition.columns.len() && !schema.without_rowid {
+        keys.push(IndexKey {
+            column: None,
+            name: "rowid".to_string(),
+            collation: Collation::Binary,
+            affinity: Affinity::Integer,
+            desc: false,
+        });
+    }
+    Ok(keys)
+}
+
+/// The terms of a conjunction `a AND b AND ...`.
+pub(crate) fn conjuncts<'a>(expr: &'a Expr, terms: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Binary {
+            op: BinaryOp::And,
+            left,
+            right,
+        } => {
+            conjuncts(left, terms);
+            conjuncts(right, terms);
+        }
+        expr => terms.push(expr),
+    }
+}
+
+/// The sides of a disjunction `a OR b OR ...`.
+pub(crate) fn disjuncts<'a>(expr: &'a Expr, sides: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Binary {
+            op: BinaryOp::Or,
+            left,
+            right,
+        } => {
+            disjuncts(left, sides);
+            disjuncts(right, sides);
+        }
+        expr => sides.push(expr),
+    }
+}
+
+/// The index range for `column LIKE 'prefix%'` or `column GLOB 'prefix*'`,
+/// if the column is the first one of an index with the right collation:
+/// NOCASE for LIKE, or BINARY for GLOB and for LIKE under
+/// `PRAGMA case_sensitive_like`. This is SQLite's LIKE optimization,
+/// see https://www.sqlite.org/optoverview.html#the_like_optimization
+fn like_range<'d>(
+    db: &'d Database,
+    table: &'d TableInfo,
+    columns: &[ColumnDef],
+    alias: &str,
+    term: &Expr,
+    case_sensitive_like: bool,
+) -> Option<IndexRange<'d>> {
+    let Expr::Function {
+        name,
+        args,
+        over: None,
+        ..
+    } = term
+    else {
+        return None;
+    };
+    let glob = match name.as_str() {
+        "like" => false,
+        "glob" => true,
+        _ => return None,
+    };
+    let (
+        Expr::Literal(ColumnType::String(pattern)),
+        Expr::Column {
+            table: qualifier,
+            name,
+        },
+    ) = (args.first()?, args.get(1)?)
+    else {
+        return None;
+    };
+    if qualifier
+        .as_deref()
+        .is_some_and(|q| !q.eq_ignore_ascii_case(alias))
+    {
+        return None;
+    }
+    let column = columns.iter().find(|c| c.name.eq_ignore_ascii_case(name))?;
+
+    let escape = match args.get(2) {
+        None => None,
+        Some(Expr::Literal(ColumnType::String(e))) if e.len() == 1 && e != "%" && e != "_" => {
+            Some(e.as_bytes()[0])
+        }
+        Some(_) => return None,
+    };
+    let wildcards: &[u8] = if glob { b"*?[" } else { b"%_" };
+    let low = prefix(pattern.as_bytes(), wildcards, escape)?;
+
+    // a prefix that looks like a number might match numbers, which sort
+    // before all text
+    let text_affinity = Affinity::of_column(column.type_name.as_deref()) == Affinity::Text;
+    let nocase = !glob && !case_sensitive_like;
+    let mut high = if nocase {
+        low.to_ascii_lowercase()
+    } else {
+        low.clone()
+    };
+    *high.last_mut()? += 1;
+    let numeric = |text: &[u8]| std::str::from_utf8(text).is_ok_and(|t| parse_number(t).is_some());
+    if !text_affinity && (numeric(&low) || numeric(&high) || low == b"-") {
+        return None;
+    }
+    let low = if nocase {
+        low.to_ascii_uppercase()
+    } else {
+        low
+    };
+
+    let index = db
+        .indexes(&table.name)
+        .filter(|i| !is_partial(i))
+        .find(|index| {
+            let Some(first) = index
+                .index_columns()
+                .ok()
+                .and_then(|c| c.into_iter().next())
+            else {
+                return false;
+            };
+            let collation = first
+                .collation
+                .or_else(|| column.collation.clone())
+                .unwrap_or_else(|| "BINARY".to_string());
+            first.name.eq_ignore_ascii_case(&column.name)
+                && collation.eq_ignore_ascii_case(if nocase { "NOCASE" } else { "BINARY" })
+        })?;
+
+    Some(IndexRange {
+        index,
+        column: column.name.clone(),
+        nocase,
+        low,
+        high,
+        covering: None,
+    })
+}
+
+/// Whether an index is a partial index, which lacks the rows its WHERE
+/// clause leaves out; such indexes are not used.
+/// Whether every key of an index compares with a registered collation.
+fn collations_known(collations: &Collations, schema: &CreateTable, index: &TableInfo) -> bool {
+    let Ok(definition) = index.index() else {
+        return false;
+    };
+    definition.columns.iter().all(|indexed| {
+        let declared = schema
+            .columns
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(&indexed.name))
+            .and_then(|c| c.collation.as_ref());
+        indexed
+            .collation
+            .as_ref()
+            .or(declared)
+            .map_or(true, |name| collations.find(name).is_ok())
+    })
+}
+
+fn is_partial(index: &TableInfo) -> bool {
+    index.index().is_ok_and(|d| d.where_clause.is_some())
+}
+
+/// The table column of each column of the entries of an index, if they all
+/// are columns. Those of a WITHOUT ROWID table end with the columns of the
+/// primary key the index lacks, and its primary key holds every column.
+pub(crate) fn index_columns(schema: &CreateTable, index: &TableInfo) -> Option<Vec<usize>> {
+    if index.primary_key {
+        return schema.record_columns();
+    }
+    let definition = index.index().ok()?;
+    let mut mapped = definition
+        .columns
+        .iter()
+        .map(|indexed| {
+            schema
+                .columns
+                .iter()
+                .position(|c| c.name.eq_ignore_ascii_case(&indexed.name))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if schema.without_rowid {
+        for i in schema.key_columns() {
+            if !mapped.contains(&i) {
+                mapped.push(i);
+            }
+        }
+    }
+    Some(mapped)
+}
+
+/// SQLite's estimate of the size of a value of a column, in units of about
+/// four bytes, from its declared type as sqlite3AffinityType() makes it:
+/// 1 for numbers and untyped columns, 5 for text and blobs, and more for
+/// `VARCHAR(n)` and the like.
+fn column_width(column: &ColumnDef) -> u64 {
+    let Some(type_name) = column.type_name.as_deref() else {
+        return 1;
+    };
+    let affinity = Affinity::of_column(Some(type_name));
+    let type_name = type_name.to_ascii_lowercase();
+    // the length is read after CHAR, or after BLOB followed by "("
+    let length_at = match type_name.find("char") {
+        Some(at) => Some(at + 4),
+        None if affinity == Affinity::Blob => type_name.find("blob(").map(|at| at + 4),
+        None => None,
+    };
+    let size = if affinity.is_numeric() {
+        0
+    } else if let Some(at) = length_at {
+        let digits = type_name[at..]
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect::<String>();
+        digits.parse().unwrap_or(0)
+    } else {
+        16
+    };
+    (size / 4 + 1).min(255)
+}
+
+/// SQLite's estLog(): the LogEst of the logarithm of a number given as a
+/// LogEst, roughly the depth of a b-tree of that many entries.
+fn est_log(n: i64) -> i64 {
+    if n <= 10 {
+        0
+    } else {
+        log_est(n as u64) - 33
+    }
+}
+
+/// The estimated size of a row of a table, as estimateTableWidth() makes
+/// it: a WITHOUT ROWID table counts as having a rowid.
+fn table_width(schema: &CreateTable) -> u64 {
+    let width: u64 = schema.columns.iter().map(column_width).sum();
+    let rowid = schema.without_rowid || !schema.columns.iter().any(ColumnDef::is_rowid_alias);
+    (width + rowid as u64) * 4
+}
+
+/// The estimated size of an entry of an index, its rowid included, as
+/// estimateIndexWidth() makes it.
+fn index_width(schema: &CreateTable, mapped: Option<&[usize]>, index: &TableInfo) -> u64 {
+    let width: u64 = match mapped {
+        Some(mapped) => mapped
+            .iter()
+            .map(|&i| column_width(&schema.columns[i]))
+            .sum(),
+        None => index.index().map_or(1, |d| d.columns.len()) as u64,
+    };
+    let rowid = !schema.without_rowid;
+    (width + rowid as u64) * 4
+}
+
+/// SQLite's logarithmic estimate of a number: about ten times its base 2
+/// logarithm, as sqlite3LogEst() computes it.
+pub(crate) fn log_est(mut x: u64) -> i64 {
+    const FRACTION: [i64; 8] = [0, 2, 3, 5, 6, 7, 8, 9];
+    let mut y = 40;
+    if x < 8 {
+        if x < 2 {
+            return 0;
+        }
+        while x < 8 {
+            y -= 10;
+            x <<= 1;
+        }
+    } else {
+        while x > 255 {
+            y += 40;
+            x >>= 4;
+        }
+        while x > 15 {
+            y += 10;
+            x >>= 1;
+        }
+    }
+    FRACTION[(x & 7) as usize] + y - 10
+}
+
+/// The literal text before the first wildcard of a pattern, unescaped, if
+/// it is not empty and its last byte can be incremented.
+fn prefix(pattern: &[u8], wildcards: &[u8], escape: Option<u8>) -> Option<Vec<u8>> {
+    let mut len = 0;
+    while let Some(&c) = pattern.get(len) {
+        if c == 0 || wildcards.contains(&c) {
+            break;
+        }
+        len += 1;
+        if Some(c) == escape && pattern.get(len).is_some_and(|c| (1..0x80).contains(c)) {
+            len += 1;
+        } else if c >= 0x80 {
+            // take a multibyte character whole
+            let char_len = std::str::from_utf8(&pattern[len - 1..])
+                .ok()
+                .and_then(|s| s.chars().next())
+                .map_or(1, char::len_utf8);
+            len += char_len - 1;
+        }
+    }
+
+    let pattern = &pattern[..len];
+    if !(len > 1 || (len > 0 && Some(pattern[0]) != escape)) || pattern[len - 1] == 0xff {
+        return None;
+    }
+    let mut prefix = vec![];
+    let mut i = 0;
+    while i < len {
+        if Some(pattern[i]) == escape {
+            i += 1;
+        }
+        if let Some(&c) = pattern.get(i) {
+            prefix.push(c);
+        }
+        i += 1;
+    }
+    Some(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(prefix(b"abc%", b"%_", None), Some(b"abc".to_vec()));
+        assert_eq!(prefix(b"a_c", b"%_", None), Some(b"a".to_vec()));
+        assert_eq!(prefix(b"%abc", b"%_", None), None);
+        assert_eq!(prefix(b"a\\%b%", b"%_", Some(b'\\')), Some(b"a%b".to_vec()));
+        assert_eq!(prefix(b"\\%", b"%_", Some(b'\\')), Some(b"%".to_vec()));
+        assert_eq!(prefix(b"ab[c]*", b"*?[", None), Some(b"ab".to_vec()));
+    }
+
+    #[test]
+    fn test_widths() {
+        let sql = "CREATE TABLE companies(id integer primary key, name text, \
+                   country varchar(100), size integer)";
+        let crate::ast::StatementKind::CreateTable(table) = crate::parser::parse(sql).unwrap().kind
+        else {
+            panic!("not a table");
+        };
+        let widths = table.columns.iter().map(column_width).collect::<Vec<_>>();
+        assert_eq!(widths, vec![1, 5, 26, 1]);
+        assert_eq!(table_width(&table), 132);
+        assert_eq!(log_est(132), 70);
+        assert_eq!(log_est(48), 56);
+        assert_eq!(log_est(8), 30);
+        assert_eq!(log_est(1), 0);
+    }
+
+    #[test]
+    fn test_without_rowid_entries() {
+        let info = |typ: &str, name: &str, sql: &str| {
+            let record = vec![
+                ColumnType::String(typ.to_string()),
+                ColumnType::String(name.to_string()),
+                ColumnType::String("m".to_string()),
+                ColumnType::Int64(2),
+                ColumnType::String(sql.to_string()),
+            ];
+            TableInfo::new(record).unwrap()
+        };
+        let table = info(
+            "table",
+            "m",
+            "CREATE TABLE m(a, b integer, c text, d, PRIMARY KEY (c, a)) WITHOUT ROWID",
+        );
+        let schema = table.table().unwrap();
+        // the rows are the primary key, then the other columns
+        let primary_key = TableInfo::primary_key_of(&table);
+        assert_eq!(index_columns(&schema, &primary_key), Some(vec![2, 0, 1, 3]));
+        assert_eq!(index_name(&primary_key, true), "PRIMARY KEY");
+        // the entries of an index end with the rest of the primary key
+        let index = info("index", "m_bc", "CREATE INDEX m_bc ON m(b, c)");
+        assert_eq!(index_columns(&schema, &index), Some(vec![1, 2, 0]));
+        // SQLite counts a rowid even without one
+        assert_eq!(table_width(&schema), 36);
+    }
+}
@@ -0,0 +1,233 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::btree;
+use crate::db::Database;
+use crate::page::{DbRecord, PageInfo};
+use crate::table::ColumnType;
+
+/// The statistics that `ANALYZE` stores in `sqlite_stat1` and
+/// `sqlite_stat4`, from which the planner estimates how many rows each way
+/// of reading a table finds.
+/// See https://www.sqlite.org/fileformat2.html#stat1tab
+#[derive(Debug, Default)]
+pub(crate) struct Statistics {
+    /// The number of rows of each table, keyed by lower case name.
+    tables: HashMap<String, f64>,
+    /// Keyed by lower case index name.
+    indexes: HashMap<String, IndexStats>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct IndexStats {
+    /// The number of entries, then for each leading run of columns the
+    /// average number of entries sharing the values of those columns.
+    pub rows: Vec<f64>,
+    /// Entries sampled by `sqlite_stat4`, in index order, which is the
+    /// order ANALYZE stores them in.
+    pub samples: Vec<Sample>,
+}
+
+/// An index entry sampled by `sqlite_stat4`. Like the averages, the counts
+/// are given for each leading run of columns.
+#[derive(Debug)]
+pub(crate) struct Sample {
+    /// The sampled entry, rowid included.
+    pub key: DbRecord,
+    /// The number of entries equal to the sample.
+    pub eq: Vec<f64>,
+    /// The number of entries before the sample.
+    pub lt: Vec<f64>,
+}
+
+impl Statistics {
+    /// Read the statistics of a database. A database that was never
+    /// analyzed has none.
+    pub fn load(db: &Database) -> Result<Self, anyhow::Error> {
+        let mut stats = Statistics::default();
+        let text = |value: Option<&ColumnType>| match value {
+            Some(ColumnType::String(s)) => Some(s.to_ascii_lowercase()),
+            _ => None,
+        };
+
+        if let Some(stat1) = db.find_table("sqlite_stat1") {
+            btree::scan_table(db, stat1.root_page, &mut |_, record| {
+                let (Some(table), Some(ColumnType::String(stat))) =
+                    (text(record.first()), record.get(2))
+                else {
+                    return Ok(true);
+                };
+                let rows = numbers(stat);
+                if let Some(&n) = rows.first() {
+                    stats.tables.insert(table, n);
+                }
+                if let Some(index) = text(record.get(1)) {
+                    stats.indexes.entry(index).or_default().rows = rows;
+                }
+                Ok(true)
+            })?;
+        }
+
+        if let Some(stat4) = db.find_table("sqlite_stat4") {
+            btree::scan_table(db, stat4.root_page, &mut |_, record| {
+                let (Some(index), Some(ColumnType::String(eq)), Some(ColumnType::String(lt))) =
+                    (text(record.get(1)), record.get(2), record.get(3))
+                else {
+                    return Ok(true);
+                };
+                let Some(ColumnType::Blob(sample)) = record.get(5) else {
+                    return Ok(true);
+                };
+                stats
+                    .indexes
+                    .entry(index)
+                    .or_default()
+                    .samples
+                    .push(Sample {
+                        key: PageInfo::read_record(sample)?,
+                        eq: numbers(eq),
+                        lt: numbers(lt),
+                    });
+                Ok(true)
+            })?;
+        }
+
+        Ok(stats)
+    }
+
+    /// The number of rows of a table, if it was analyzed.
+    pub fn table_rows(&self, table: &str) -> Option<f64> {
+        self.tables.get(&table.to_ascii_lowercase()).copied()
+    }
+
+    pub fn index(&self, index: &str) -> Option<&IndexStats> {
+        self.indexes
+            .get(&index.to_ascii_lowercase())
+            .filter(|stats| !stats.rows.is_empty())
+    }
+}
+
+impl IndexStats {
+    /// The number of entries whose first column equals `value`. A sampled
+    /// value has its count; any other value is assumed to be as common as
+    /// the average of the values that were not sampled. `compare` orders
+    /// first columns as the index does.
+    pub fn equal_rows(
+        &self,
+        value: &ColumnType,
+        compare: &dyn Fn(&ColumnType, &ColumnType) -> Ordering,
+    ) -> f64 {
+        let first = |s: &Sample| s.key.first().cloned().unwrap_or(ColumnType::Null);
+        let same = |a: &ColumnType, b: &ColumnType| compare(a, b) == Ordering::Equal;
+        if let Some(sample) = self.samples.iter().find(|s| same(&first(s), value)) {
+            return sample.eq.first().copied().unwrap_or(1.0);
+        }
+        let average = self.rows.get(1).copied().unwrap_or(1.0);
+        if self.samples.is_empty() {
+            return average;
+        }
+        // take the sampled values out of the average
+        let mut sampled_rows = 0.0;
+        let mut sampled_values = 0.0;
+        for (i, sample) in self.samples.iter().enumerate() {
+            if i > 0 && same(&first(&self.samples[i - 1]), &first(sample)) {
+                continue;
+            }
+            sampled_rows += sample.eq.first().copied().unwrap_or(0.0);
+            sampled_values += 1.0;
+        }
+        let total = self.rows.first().copied().unwrap_or(0.0);
+        let values = (total / average.max(1.0) - sampled_values).max(1.0);
+        ((total - sampled_rows) / values).max(1.0)
+    }
+
+    /// The number of entries whose first column sorts before `value`, or
+    /// before or equal to it if `inclusive`, interpolated between the
+    /// samples on either side. `compare` orders first columns as the index
+    /// does.
+    pub fn rows_before(
+        &self,
+        value: &ColumnType,
+        inclusive: bool,
+        compare: &dyn Fn(&ColumnType, &ColumnType) -> Ordering,
+    ) -> Option<f64> {
+        let total = *self.rows.first()?;
+        let mut before = 0.0;
+        let mut after = total;
+        for sample in &self.samples {
+            let first = sample.key.first()?;
+            let lt = *sample.lt.first()?;
+            let eq = *sample.eq.first()?;
+            match compare(first, value) {
+                Ordering::Less => before = lt + eq,
+                Ordering::Equal => return Some(if inclusive { lt + eq } else { lt }),
+                Ordering::Greater => {
+                    after = lt;
+                    break;
+                }
+            }
+        }
+        if self.samples.is_empty() {
+            return None;
+        }
+        // halfway between the samples around the value
+        Some((before + after) / 2.0)
+    }
+}
+
+/// The leading numbers of a statistics string such as `2000 21 1`; SQLite
+/// may append keywords such as `unordered` or `sz=12`, which are ignored.
+fn numbers(stat: &str) -> Vec<f64> {
+    stat.split_ascii_whitespace()
+        .map_while(|n| n.parse::<u64>().ok())
+        .map(|n| n as f64)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(value: i64, eq: f64, lt: f64) -> Sample {
+        Sample {
+            key: vec![ColumnType::Int64(value), ColumnType::Int64(1)],
+            eq: vec![eq, 1.0],
+            lt: vec![lt, lt],
+        }
+    }
+
+    #[test]
+    fn test_estimates() {
+        assert_eq!(numbers("2000 21 1 unordered"), vec![2000.0, 21.0, 1.0]);
+
+        // 1000 entries with 100 distinct values, of which 3 and 7 are common
+        let stats = IndexStats {
+            rows: vec![1000.0, 10.0],
+            samples: vec![sample(3, 300.0, 100.0), sample(7, 400.0, 500.0)],
+        };
+        let compare = |a: &ColumnType, b: &ColumnType| a.sql_cmp(b);
+        assert_eq!(stats.equal_rows(&ColumnType::Int8(3), &compare), 300.0);
+        // the other 300 entries are spread over 98 values
+        assert_eq!(
+            stats.equal_rows(&ColumnType::Int64(5), &compare),
+            300.0 / 98.0
+        );
+
+        assert_eq!(
+            stats.rows_before(&ColumnType::Int64(7), false, &compare),
+            Some(500.0)
+        );
+        assert_eq!(
+            stats.rows_before(&ColumnType::Int64(7), true, &compare),
+            Some(900.0)
+        );
+        assert_eq!(
+            stats.rows_before(&ColumnType::Int64(5), false, &compare),
+            Some(450.0)
+        );
+        assert_eq!(
+            stats.rows_before(&ColumnType::Int64(9), false, &compare),
+            Some(950.0)
+        );
+    }
+}
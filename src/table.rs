@@ -1,12 +1,24 @@
+use anyhow::bail;
+
+use crate::ast::{ColumnDef, CreateIndex, CreateTable, CreateView, IndexedColumn, StatementKind};
 use crate::page::DbRecord;
+use crate::parser;
 
+/// A row of the `sqlite_schema` table.
+/// See https://www.sqlite.org/schematab.html
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct TableInfo {
-    pub(crate) name: String,
-    table_name: String,
-    root_page: i8,
-    sql: String,
+    /// One of `table`, `index`, `view` or `trigger`.
+    pub typ: String,
+    pub name: String,
+    pub(crate) table_name: String,
+    pub(crate) root_page: u32,
+    /// The original CREATE statement; empty for automatically created indexes.
+    pub(crate) sql: String,
+    /// Whether this is the primary key of a WITHOUT ROWID table, which is
+    /// not in the schema: see [`TableInfo::primary_key_of`].
+    pub(crate) primary_key: bool,
 }
 
 impl TableInfo {
@@ -23,8 +35,6 @@ impl TableInfo {
             unreachable!("asserted that record is not empty");
         };
 
-        assert_eq!(typ, "table".to_string());
-
         let name = if let Some(c) = record.get(1) {
             match c {
                 ColumnType::String(s) => String::from(s),
@@ -43,10 +53,14 @@ impl TableInfo {
             anyhow::bail!("missing table name");
         };
 
+        // views and triggers have no b-tree and store 0 (or NULL) here
         let root_page = if let Some(c) = record.get(3) {
             match c {
-                ColumnType::Int8(s) => *s,
-                _ => anyhow::bail!("column type mismatch"),
+                ColumnType::Null => 0,
+                c => match c.as_integer() {
+                    Some(n) => n as u32,
+                    None => anyhow::bail!("column type mismatch"),
+                },
             }
         } else {
             anyhow::bail!("missing root page");
@@ -55,6 +69,7 @@ impl TableInfo {
         let sql = if let Some(c) = record.get(4) {
             match c {
                 ColumnType::String(s) => String::from(s),
+                ColumnType::Null => String::new(),
                 _ => anyhow::bail!("column type mismatch"),
             }
         } else {
@@ -62,15 +77,84 @@ impl TableInfo {
         };
 
         Ok(TableInfo {
+            typ,
             name,
             table_name,
             root_page,
             sql,
+            primary_key: false,
         })
     }
+
+    /// The primary key of a WITHOUT ROWID table, as the unique index whose
+    /// entries are the rows of the table, named as SQLite names it. It has
+    /// the CREATE TABLE statement of the table.
+    pub(crate) fn primary_key_of(table: &TableInfo) -> TableInfo {
+        TableInfo {
+            typ: "index".to_string(),
+            name: format!("sqlite_autoindex_{}_1", table.name),
+            table_name: table.name.clone(),
+            root_page: table.root_page,
+            sql: table.sql.clone(),
+            primary_key: true,
+        }
+    }
+
+    /// The CREATE TABLE statement of a table.
+    pub(crate) fn table(&self) -> Result<CreateTable, anyhow::Error> {
+        match parser::parse(&self.sql)?.kind {
+            StatementKind::CreateTable(table) => Ok(table),
+            _ => bail!("{} is not a table", self.name),
+        }
+    }
+
+    /// The CREATE VIEW statement of a view.
+    pub(crate) fn view(&self) -> Result<CreateView, anyhow::Error> {
+        match parser::parse(&self.sql)?.kind {
+            StatementKind::CreateView(view) => Ok(view),
+            _ => bail!("{} is not a view", self.name),
+        }
+    }
+
+    /// The column definitions parsed from the CREATE TABLE statement.
+    pub(crate) fn columns(&self) -> Result<Vec<ColumnDef>, anyhow::Error> {
+        Ok(self.table()?.columns)
+    }
+
+    /// The indexed columns parsed from the CREATE INDEX statement.
+    pub(crate) fn index_columns(&self) -> Result<Vec<IndexedColumn>, anyhow::Error> {
+        Ok(self.index()?.columns)
+    }
+
+    /// The CREATE INDEX statement of an index, or the equivalent one for
+    /// the primary key of a WITHOUT ROWID table.
+    pub(crate) fn index(&self) -> Result<CreateIndex, anyhow::Error> {
+        match parser::parse(&self.sql)?.kind {
+            StatementKind::CreateIndex(index) => Ok(index),
+            StatementKind::CreateTable(table) if self.primary_key => {
+                let mut columns: Vec<IndexedColumn> = vec![];
+                for column in table.primary_key {
+                    if !columns
+                        .iter()
+                        .any(|c| c.name.eq_ignore_ascii_case(&column.name))
+                    {
+                        columns.push(column);
+                    }
+                }
+                Ok(CreateIndex {
+                    name: self.name.clone(),
+                    table: table.name,
+                    unique: true,
+                    columns,
+                    where_clause: None,
+                })
+            }
+            _ => bail!("{} is not an index", self.name),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ColumnType {
     Null,
     Int8(i8),
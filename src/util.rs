@@ -21,42 +21,23 @@ pub fn read_len<'a>(input: &mut &'a [u8], len: usize) -> &'a [u8] {
 /// byte are used to reconstruct the 64-bit twos-complement integer. Varints are
 /// big-endian: bits taken from the earlier byte of the varint are more
 /// significant than bits taken from the later bytes.
-// TODO: for some reason, max u64 doesn't work
 pub fn varint_unsigned(inp: &[u8]) -> Result<(u64, usize), anyhow::Error> {
-    assert!(!inp.is_empty());
-
-    let input = &mut &inp[0..];
-    let mut cnt = 0;
     let mut result = 0u64;
-    let mut shift = 0u8;
-    loop {
-        let s = result << shift;
-        let v = input[0] & 0x7f;
-        result = s | v as u64;
-
-        if input[0] & 0x80 == 0 {
-            break;
-        }
-
-        *input = &input[1..];
-
-        if input.is_empty() {
-            return Err(anyhow!("varint not long enough"));
+    for (i, &byte) in inp.iter().enumerate() {
+        if i == 8 {
+            return Ok(((result << 8) | byte as u64, 9));
         }
-
-        cnt += 1;
-
-        if cnt == 9 {
-            return Err(anyhow!("varint too long"));
+        result = (result << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok((result, i + 1));
         }
-
-        shift += 7;
     }
 
-    Ok((result, cnt + 1))
+    Err(anyhow!("varint not long enough"))
 }
 
 // TODO: for some reason, min u64 doesn't work
+#[allow(dead_code)]
 pub fn varint_signed(input: &[u8]) -> Result<(i64, usize), anyhow::Error> {
     let (n, cnt) = varint_unsigned(input)?;
 
@@ -70,20 +51,25 @@ mod tests {
     #[test]
     fn test_varint_positive() {
         let encoded = vec![0x78];
-        let (decoded, cnt) = varint_unsigned(&mut encoded.as_slice()).unwrap();
+        let (decoded, cnt) = varint_unsigned(&encoded).unwrap();
         assert_eq!(decoded, 120);
         assert_eq!(cnt, encoded.len());
 
         let encoded = vec![0x81, 0x16];
-        let (decoded, cnt) = varint_unsigned(&mut encoded.as_slice()).unwrap();
+        let (decoded, cnt) = varint_unsigned(&encoded).unwrap();
         assert_eq!(decoded, 150);
         assert_eq!(cnt, encoded.len());
+
+        let encoded = vec![0x81, 0x9c, 0x24];
+        let (decoded, cnt) = varint_unsigned(&encoded).unwrap();
+        assert_eq!(decoded, 20004);
+        assert_eq!(cnt, encoded.len());
     }
 
     #[test]
     fn test_varint_negative() {
         let encoded = vec![0x80, 0x01];
-        let (decoded, cnt) = varint_signed(&mut encoded.as_slice()).unwrap();
+        let (decoded, cnt) = varint_signed(&encoded).unwrap();
         assert_eq!(decoded, -1);
         assert_eq!(cnt, encoded.len());
     }
@@ -91,16 +77,15 @@ mod tests {
     #[test]
     fn test_varint_zero() {
         let encoded = vec![0x00];
-        let (decoded, cnt) = varint_unsigned(&mut encoded.as_slice()).unwrap();
+        let (decoded, cnt) = varint_unsigned(&encoded).unwrap();
         assert_eq!(decoded, 0);
         assert_eq!(cnt, encoded.len());
     }
 
     #[test]
-    #[ignore]
     fn test_varint_max_u64() {
-        let encoded = vec![0x81, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f];
-        let (decoded, cnt) = varint_unsigned(&mut encoded.as_slice()).unwrap();
+        let encoded = vec![0xff; 9];
+        let (decoded, cnt) = varint_unsigned(&encoded).unwrap();
         assert_eq!(decoded, u64::MAX);
         assert_eq!(cnt, encoded.len());
     }
@@ -109,7 +94,7 @@ mod tests {
     #[ignore]
     fn test_varint_min_i64() {
         let encoded = vec![0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
-        let (decoded, cnt) = varint_signed(&mut encoded.as_slice()).unwrap();
+        let (decoded, cnt) = varint_signed(&encoded).unwrap();
         assert_eq!(decoded, i64::MIN);
         assert_eq!(cnt, encoded.len());
     }
@@ -117,14 +102,21 @@ mod tests {
     #[test]
     fn test_varint_incomplete() {
         let encoded = vec![0x96];
-        let result = varint_unsigned(&mut encoded.as_slice());
+        let result = varint_unsigned(&encoded);
         assert!(result.is_err());
     }
 
     #[test]
-    fn test_varint_too_long() {
+    fn test_varint_ninth_byte() {
+        // all 8 bits of the ninth byte count, and it is always the last
         let encoded = vec![0x80; 10];
-        let result = varint_unsigned(&mut encoded.as_slice());
-        assert!(result.is_err());
+        let (decoded, cnt) = varint_unsigned(&encoded).unwrap();
+        assert_eq!(decoded, 0x80);
+        assert_eq!(cnt, 9);
+
+        let mut encoded = vec![0xff; 8];
+        encoded.push(0xfb);
+        let (decoded, _) = varint_unsigned(&encoded).unwrap();
+        assert_eq!(decoded as i64, -5);
     }
 }
@@ -0,0 +1,185 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::table::ColumnType;
+
+impl ColumnType {
+    /// The value of any of the integer serial types.
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            ColumnType::Int8(n) => Some(*n as i64),
+            ColumnType::Int16(n) => Some(*n as i64),
+            ColumnType::Int24(n) | ColumnType::Int32(n) => Some(*n as i64),
+            ColumnType::Int48(n) | ColumnType::Int64(n) => Some(*n),
+            ColumnType::False => Some(0),
+            ColumnType::True => Some(1),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, ColumnType::Null)
+    }
+
+    /// The storage class rank used when comparing values of different types:
+    /// NULL < INTEGER/REAL < TEXT < BLOB.
+    fn class_rank(&self) -> u8 {
+        match self {
+            ColumnType::Null => 0,
+            ColumnType::String(_) => 2,
+            ColumnType::Blob(_) => 3,
+            _ => 1,
+        }
+    }
+
+    /// Compare two values the way SQLite orders them, with the BINARY
+    /// collating sequence for text.
+    pub fn sql_cmp(&self, other: &ColumnType) -> Ordering {
+        let rank = self.class_rank().cmp(&other.class_rank());
+        if rank != Ordering::Equal {
+            return rank;
+        }
+
+        match (self, other) {
+            (ColumnType::Null, ColumnType::Null) => Ordering::Equal,
+            (ColumnType::String(a), ColumnType::String(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (ColumnType::Blob(a), ColumnType::Blob(b)) => a.cmp(b),
+            (ColumnType::Float(a), ColumnType::Float(b)) => {
+                a.partial_cmp(b).unwrap_or(Ordering::Equal)
+            }
+            (ColumnType::Float(a), b) => {
+                cmp_int_float(b.as_integer().unwrap_or_default(), *a).reverse()
+            }
+            (a, ColumnType::Float(b)) => cmp_int_float(a.as_integer().unwrap_or_default(), *b),
+            (a, b) => a.as_integer().cmp(&b.as_integer()),
+        }
+    }
+
+    /// A hashable key under which values that compare equal coincide.
+    pub fn key(&self) -> ValueKey {
+        match self {
+            ColumnType::Null => ValueKey::Null,
+            ColumnType::String(s) => ValueKey::Text(s.clone()),
+            ColumnType::Blob(b) => ValueKey::Blob(b.clone()),
+            ColumnType::Float(f) => {
+                if f.fract() == 0.0 && *f >= I64_MIN && *f < -I64_MIN {
+                    ValueKey::Int(*f as i64)
+                } else {
+                    ValueKey::Real(f.to_bits())
+                }
+            }
+            v => ValueKey::Int(v.as_integer().unwrap_or_default()),
+        }
+    }
+}
+
+/// See [`ColumnType::key`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ValueKey {
+    Null,
+    Int(i64),
+    Real(u64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// The smallest i64 as a float; its negation is the first float beyond i64.
+const I64_MIN: f64 = i64::MIN as f64;
+
+fn cmp_int_float(i: i64, f: f64) -> Ordering {
+    if f.is_nan() {
+        return Ordering::Greater;
+    }
+    if f < I64_MIN {
+        return Ordering::Greater;
+    }
+    if f >= -I64_MIN {
+        return Ordering::Less;
+    }
+
+    match i.cmp(&(f.trunc() as i64)) {
+        Ordering::Equal => 0.0.partial_cmp(&f.fract()).unwrap_or(Ordering::Equal),
+        o => o,
+    }
+}
+
+/// Format a real the way SQLite renders it as text, which is printf's
+/// `%!.15g`: 15 significant digits, always with a decimal point.
+pub fn format_real(f: f64) -> String {
+    if f.is_nan() {
+        return String::new();
+    }
+    if f.is_infinite() {
+        return if f > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+    if f == 0.0 {
+        return "0.0".to_string();
+    }
+
+    let sci = format!("{f:.14e}");
+    let (mantissa, exp) = sci.split_once('e').expect("exponent in {:e} output");
+    let exp = exp.parse::<i32>().expect("valid exponent");
+
+    if !(-4..15).contains(&exp) {
+        let mantissa = trim_fraction(mantissa);
+        let sign = if exp < 0 { '-' } else { '+' };
+        return format!("{mantissa}e{sign}{:02}", exp.abs());
+    }
+
+    let fixed = format!("{:.*}", (14 - exp) as usize, f);
+    trim_fraction(&fixed)
+}
+
+/// Strip trailing zeros after the decimal point, keeping at least one digit.
+fn trim_fraction(s: &str) -> String {
+    if !s.contains('.') {
+        return format!("{s}.0");
+    }
+    let s = s.trim_end_matches('0');
+    if s.ends_with('.') {
+        format!("{s}0")
+    } else {
+        s.to_string()
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_text().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_real() {
+        assert_eq!(format_real(1.0), "1.0");
+        assert_eq!(format_real(0.1 + 0.2), "0.3");
+        assert_eq!(format_real(-2.5), "-2.5");
+        assert_eq!(format_real(123456789.125), "123456789.125");
+        assert_eq!(format_real(1e15), "1.0e+15");
+        assert_eq!(format_real(1.5e-7), "1.5e-07");
+        assert_eq!(format_real(0.0001), "0.0001");
+        assert_eq!(format_real(f64::INFINITY), "Inf");
+    }
+
+    #[test]
+    fn test_sql_cmp_mixed_types() {
+        use ColumnType::*;
+        assert_eq!(Null.sql_cmp(&Int8(0)), Ordering::Less);
+        assert_eq!(Int8(1).sql_cmp(&Int64(1)), Ordering::Equal);
+        assert_eq!(Int16(2).sql_cmp(&Float(1.5)), Ordering::Greater);
+        assert_eq!(Float(2.0).sql_cmp(&Int32(2)), Ordering::Equal);
+        assert_eq!(Int64(i64::MAX).sql_cmp(&String("0".into())), Ordering::Less);
+        assert_eq!(String("b".into()).sql_cmp(&Blob(vec![0])), Ordering::Less);
+    }
+
+    #[test]
+    fn test_key_equates_numbers() {
+        use ColumnType::*;
+        assert_eq!(Int8(3).key(), Float(3.0).key());
+        assert_ne!(Int8(3).key(), String("3".into()).key());
+    }
+}
@@ -0,0 +1,398 @@
+use std::fmt;
+
+use crate::collation::Collation;
+use crate::convert::Affinity;
+use crate::table::ColumnType;
+
+/// A program for the register machine that runs compiled statements,
+/// modelled on SQLite's virtual database engine. `EXPLAIN` lists it.
+/// See https://www.sqlite.org/opcode.html
+#[derive(Debug, Default)]
+pub struct Program {
+    pub insns: Vec<Insn>,
+    /// The names of the result columns.
+    pub columns: Vec<String>,
+    /// The number of registers used, which are numbered from 1.
+    pub registers: usize,
+    pub cursors: usize,
+}
+
+#[derive(Debug)]
+pub struct Insn {
+    pub opcode: Opcode,
+    pub p1: i64,
+    pub p2: i64,
+    pub p3: i64,
+    pub p4: P4,
+    pub p5: u16,
+}
+
+/// The instructions of the machine. Registers are written `r[N]`; jumps
+/// go to the address in P2 unless stated otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opcode {
+    /// Start of the program: jump to P2, where the constants are loaded.
+    Init,
+    /// Start a read transaction; a no-op for a read-only database.
+    Transaction,
+    Goto,
+    Halt,
+    /// Open cursor P1 on the b-tree rooted at page P2. P4 is the number of
+    /// columns of a table, or the key collations of an index.
+    OpenRead,
+    /// Open cursor P1 as a sorter, with the key order in P4.
+    SorterOpen,
+    /// Open cursor P1 as a set of keys of P2 columns, which are told apart
+    /// with the collations in P4.
+    OpenEphemeral,
+    /// Move cursor P1 to its first entry, or jump if there is none.
+    Rewind,
+    /// Move cursor P1 to its next entry and jump, unless it is at the end.
+    Next,
+    /// Move index cursor P1 to the first entry whose first P4 columns are
+    /// at least the key in r[P3], or jump if there is none. On a table
+    /// cursor, without P4, the key is a rowid.
+    SeekGE,
+    /// Move index cursor P1 to the first entry whose first P4 columns are
+    /// greater than the key in r[P3], or jump if there is none. On a table
+    /// cursor, without P4, the key is a rowid.
+    SeekGT,
+    /// Jump if the first P4 columns of the entry of index cursor P1 are at
+    /// least the key in r[P3].
+    IdxGE,
+    /// Jump if the first P4 columns of the entry of index cursor P1 are
+    /// greater than the key in r[P3].
+    IdxGT,
+    /// r[P2] = the rowid of the entry of index cursor P1.
+    IdxRowid,
+    /// Move table cursor P1 to the row with rowid r[P3], or jump if there
+    /// is none. A key that is not an integer is taken as a number first.
+    SeekRowid,
+    /// Jump if the set of cursor P1 holds the key in r[P3], of P4 columns.
+    Found,
+    /// Add the key in r[P2], of P3 columns, to the set of cursor P1.
+    IdxInsert,
+    /// r[P3] = column P2 of the entry of cursor P1.
+    Column,
+    /// r[P2] = the rowid of the row of table cursor P1.
+    Rowid,
+    /// Convert an integer in r[P1] to a real, for REAL columns.
+    RealAffinity,
+    /// r[P2] = P1
+    Integer,
+    /// r[P2] = P4, a 64-bit integer.
+    Int64,
+    /// r[P2] = P4, a real.
+    Real,
+    /// r[P2] = P4, a text.
+    String8,
+    /// r[P2] = P4, a blob.
+    Blob,
+    /// r[P2..=P3] = NULL, or just r[P2] if P3 is less than P2.
+    Null,
+    /// r[P2] = the value bound to parameter P1.
+    Variable,
+    /// r[P2] = r[P1]
+    Copy,
+    /// Compare r[P3] with r[P1] and jump if the comparison holds. P4 is the
+    /// collation, P5 the affinity applied to both operands and the
+    /// [`JUMP_IF_NULL`] and [`NULL_EQ`] flags.
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// r[P2] = NULL if r[P1] or r[P3] is NULL, else 0; after a comparison
+    /// that stored 1 when it held.
+    ZeroOrNull,
+    /// r[P3] = r[P2] + r[P1], and so on.
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    /// r[P3] = r[P2] || r[P1]
+    Concat,
+    BitAnd,
+    BitOr,
+    /// r[P3] = r[P2] << r[P1]
+    ShiftLeft,
+    ShiftRight,
+    /// r[P3] = r[P1] AND r[P2], with three-valued logic.
+    And,
+    Or,
+    /// r[P2] = NOT r[P1]
+    Not,
+    /// r[P2] = ~r[P1]
+    BitNot,
+    /// Jump if r[P1] is true, or if it is NULL and P3 is not 0.
+    If,
+    /// Jump if r[P1] is false, or if it is NULL and P3 is not 0.
+    IfNot,
+    /// Jump if r[P1] is NULL.
+    IsNull,
+    /// Jump if r[P1] is not NULL.
+    NotNull,
+    /// Convert r[P1] to the affinity in P2, as CAST does.
+    Cast,
+    /// Apply the affinities in P4, one character each, to the P2 registers
+    /// from r[P1], as a comparison does.
+    Affinity,
+    /// Convert r[P1] to an integer, or fail with a datatype mismatch if it
+    /// does not hold one.
+    MustBeInt,
+    /// r[P3] = the function P4 of r[P2] and the registers after it.
+    Function,
+    /// The collation for the next aggregate function. If P1 is not zero,
+    /// a min() or max() step that follows sets r[P1] to 1 unless its
+    /// argument is the new minimum or maximum, and else to 0.
+    CollSeq,
+    /// Add r[P2] to the values of the aggregate P4 accumulated in r[P3],
+    /// unless it is NULL. `count(*)` has no argument and counts every step.
+    AggStep,
+    /// r[P1] = the result of the aggregate P4 accumulated in r[P1].
+    AggFinal,
+    /// Add r[P2..P2+P3] to sorter P1, the sort keys first.
+    SorterInsert,
+    /// Sort sorter P1 and move to its first row, or jump if it is empty.
+    SorterSort,
+    /// r[P2..P2+P3] = the values of the current row of sorter P1, after
+    /// its keys.
+    SorterData,
+    /// Move sorter P1 to its next row and jump, unless it is at the end.
+    SorterNext,
+    /// r[P1] = the address of the next instruction, and jump.
+    Gosub,
+    /// Jump back to the address in r[P1].
+    Return,
+    /// If r[P1] is positive, subtract P3 from it and jump.
+    IfPos,
+    /// r[P1] -= 1, and jump if it is then zero.
+    DecrJumpZero,
+    /// Output r[P1..P1+P2] as a result row.
+    ResultRow,
+}
+
+/// P5 flag of the comparisons: jump if either operand is NULL.
+pub const JUMP_IF_NULL: u16 = 0x10;
+/// P5 flag of the comparisons: NULL equals NULL, as for IS.
+pub const NULL_EQ: u16 = 0x80;
+
+/// The fourth operand, which holds what does not fit in an integer.
+#[derive(Debug, Default, Clone)]
+pub enum P4 {
+    #[default]
+    None,
+    Int32(i32),
+    Int64(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+    /// A collating sequence, by name.
+    Collation(String, Collation),
+    /// The order of the keys of an index or sorter, one entry per key.
+    KeyInfo(Vec<KeyInfo>),
+    /// A function by name, with its number of arguments.
+    Function(String, usize),
+    /// A value, such as the default of a column for records that lack it.
+    Mem(ColumnType),
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyInfo {
+    /// The name of the collation, if any.
+    pub collation_name: Option<String>,
+    pub collation: Collation,
+    pub desc: bool,
+    /// `NULLS FIRST` or `NULLS LAST`, for a sorter.
+    pub nulls_first: Option<bool>,
+}
+
+/// The character that stands for an affinity in P5 and in listings, as in
+/// SQLite.
+pub fn affinity_code(affinity: Affinity) -> u16 {
+    (match affinity {
+        Affinity::Blob => b'A',
+        Affinity::Text => b'B',
+        Affinity::Numeric => b'C',
+        Affinity::Integer => b'D',
+        Affinity::Real => b'E',
+    }) as u16
+}
+
+pub fn affinity_from_code(code: u16) -> Option<Affinity> {
+    Some(match code as u8 {
+        b'A' => Affinity::Blob,
+        b'B' => Affinity::Text,
+        b'C' => Affinity::Numeric,
+        b'D' => Affinity::Integer,
+        b'E' => Affinity::Real,
+        _ => return None,
+    })
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl fmt::Display for P4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            P4::None => Ok(()),
+            P4::Int32(n) => write!(f, "{n}"),
+            P4::Int64(n) => write!(f, "{n}"),
+            P4::Real(x) => write!(f, "{x}"),
+            P4::Text(s) => write!(f, "{s}"),
+            P4::Blob(b) => write!(f, "{}", String::from_utf8_lossy(b)),
+            P4::Collation(name, _) => write!(f, "{name}"),
+            P4::KeyInfo(keys) => {
+                write!(f, "k({}", keys.len())?;
+                for key in keys {
+                    let name = match key.collation_name.as_deref() {
+                        Some(name) if name.eq_ignore_ascii_case("BINARY") => "B",
+                        Some(name) => name,
+                        None => "",
+                    };
+                    write!(f, ",{}{name}", if key.desc { "-" } else { "" })?;
+                }
+                write!(f, ")")
+            }
+            P4::Function(name, argc) => write!(f, "{name}({argc})"),
+            P4::Mem(ColumnType::Null) => write!(f, "NULL"),
+            P4::Mem(ColumnType::Blob(_)) => write!(f, "(blob)"),
+            P4::Mem(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+impl Insn {
+    /// A short description of what the instruction does, like the comments
+    /// of SQLite's listings.
+    pub fn comment(&self) -> String {
+        let (p1, p2, p3) = (self.p1, self.p2, self.p3);
+        let range = |start: i64, count: i64| match count {
+            1 => format!("r[{start}]"),
+            _ => format!("r[{start}..{}]", start + count - 1),
+        };
+        match self.opcode {
+            Opcode::Init => format!("Start at {p2}"),
+            Opcode::OpenRead => format!("root={p2} iDb={p3}"),
+            Opcode::Integer => format!("r[{p2}]={p1}"),
+            Opcode::Int64 | Opcode::Real => format!("r[{p2}]={}", self.p4),
+            Opcode::String8 => format!("r[{p2}]='{}'", self.p4),
+            Opcode::Blob => match &self.p4 {
+                P4::Blob(b) => {
+                    let hex = b.iter().map(|b| format!("{b:02X}")).collect::<String>();
+                    format!("r[{p2}]=x'{hex}'")
+                }
+                _ => String::new(),
+            },
+            Opcode::Null if p3 > p2 => format!("r[{p2}..{p3}]=NULL"),
+            Opcode::Null => format!("r[{p2}]=NULL"),
+            Opcode::Variable => format!("r[{p2}]=parameter({p1})"),
+            Opcode::Copy => format!("r[{p2}]=r[{p1}]"),
+            Opcode::Column => format!("r[{p3}]= cursor {p1} column {p2}"),
+            Opcode::Rowid | Opcode::IdxRowid => format!("r[{p2}]=rowid"),
+            Opcode::SeekRowid => format!("intkey=r[{p3}]"),
+            Opcode::SeekGE | Opcode::SeekGT if !matches!(self.p4, P4::Int32(_)) => {
+                format!("key=r[{p3}]; pk")
+            }
+            Opcode::SeekGE | Opcode::SeekGT | Opcode::IdxGE | Opcode::IdxGT | Opcode::Found => {
+                format!("key={}", range(p3, self.key_count()))
+            }
+            Opcode::IdxInsert => format!("key={}", range(p2, p3)),
+            Opcode::Eq => format!("if r[{p3}]==r[{p1}] goto {p2}"),
+            Opcode::Ne => format!("if r[{p3}]!=r[{p1}] goto {p2}"),
+            Opcode::Lt => format!("if r[{p3}]<r[{p1}] goto {p2}"),
+            Opcode::Le => format!("if r[{p3}]<=r[{p1}] goto {p2}"),
+            Opcode::Gt => format!("if r[{p3}]>r[{p1}] goto {p2}"),
+            Opcode::Ge => format!("if r[{p3}]>=r[{p1}] goto {p2}"),
+            Opcode::ZeroOrNull => format!("r[{p2}] = 0 OR NULL"),
+            Opcode::Add => format!("r[{p3}]=r[{p1}]+r[{p2}]"),
+            Opcode::Subtract => format!("r[{p3}]=r[{p2}]-r[{p1}]"),
+            Opcode::Multiply => format!("r[{p3}]=r[{p1}]*r[{p2}]"),
+            Opcode::Divide => format!("r[{p3}]=r[{p2}]/r[{p1}]"),
+            Opcode::Remainder => format!("r[{p3}]=r[{p2}]%r[{p1}]"),
+            Opcode::Concat => format!("r[{p3}]=r[{p2}]+r[{p1}]"),
+            Opcode::BitAnd => format!("r[{p3}]=r[{p1}]&r[{p2}]"),
+            Opcode::BitOr => format!("r[{p3}]=r[{p1}]|r[{p2}]"),
+            Opcode::ShiftLeft => format!("r[{p3}]=r[{p2}]<<r[{p1}]"),
+            Opcode::ShiftRight => format!("r[{p3}]=r[{p2}]>>r[{p1}]"),
+            Opcode::And => format!("r[{p3}]=(r[{p1}] && r[{p2}])"),
+            Opcode::Or => format!("r[{p3}]=(r[{p1}] || r[{p2}])"),
+            Opcode::Not => format!("r[{p2}]=!r[{p1}]"),
+            Opcode::BitNot => format!("r[{p2}]=~r[{p1}]"),
+            Opcode::IsNull => format!("if r[{p1}]==NULL goto {p2}"),
+            Opcode::NotNull => format!("if r[{p1}]!=NULL goto {p2}"),
+            Opcode::Cast => format!("affinity(r[{p1}])"),
+            Opcode::Affinity => format!("affinity({})", range(p1, p2)),
+            Opcode::Function => {
+                let argc = match self.p4 {
+                    P4::Function(_, argc) => argc as i64,
+                    _ => 0,
+                };
+                format!("r[{p3}]=func({})", range(p2, argc))
+            }
+            Opcode::AggStep => format!("accum=r[{p3}] step(r[{p2}])"),
+            Opcode::AggFinal => format!("accum=r[{p1}] N={p2}"),
+            Opcode::SorterInsert => format!("key={}", range(p2, p3)),
+            Opcode::SorterData => format!("{}=data", range(p2, p3)),
+            Opcode::IfPos => format!("if r[{p1}]>0 then r[{p1}]-={p3}, goto {p2}"),
+            Opcode::DecrJumpZero => format!("if (--r[{p1}])==0 goto {p2}"),
+            Opcode::ResultRow => format!("output={}", range(p1, p2)),
+            _ => String::new(),
+        }
+    }
+
+    fn key_count(&self) -> i64 {
+        match self.p4 {
+            P4::Int32(n) => n as i64,
+            _ => 1,
+        }
+    }
+}
+
+/// The listing printed for `EXPLAIN`, in the layout of the sqlite3 shell:
+/// the bodies of loops are indented.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut indent = vec![0; self.insns.len()];
+        for (addr, insn) in self.insns.iter().enumerate() {
+            let backwards = (insn.p2 as usize) < addr;
+            if matches!(insn.opcode, Opcode::Next | Opcode::SorterNext) && backwards {
+                for i in &mut indent[insn.p2 as usize..addr] {
+                    *i += 1;
+                }
+            }
+        }
+        writeln!(
+            f,
+            "addr  opcode         p1    p2    p3    p4             p5  comment      "
+        )?;
+        writeln!(
+            f,
+            "----  -------------  ----  ----  ----  -------------  --  -------------"
+        )?;
+        for (addr, insn) in self.insns.iter().enumerate() {
+            // indenting keeps the width of the opcode column
+            let opcode = format!("{:<13}", insn.opcode.to_string());
+            writeln!(
+                f,
+                "{:<4}  {}{}  {:<4}  {:<4}  {:<4}  {:<13}  {:<2}  {}",
+                addr,
+                "  ".repeat(indent[addr]),
+                opcode,
+                insn.p1,
+                insn.p2,
+                insn.p3,
+                insn.p4.to_string(),
+                insn.p5,
+                insn.comment()
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use anyhow::bail;
+
+use crate::ast::{BinaryOp, Frame, FrameExclude, FrameUnits, OrderingTerm};
+use crate::collation::Collation;
+use crate::exec::{arithmetic, compare_keys, Row};
+use crate::table::ColumnType;
+
+/// The rows of one window partition, in window order, described by their
+/// ORDER BY keys. Rows with equal keys are peers and form a peer group.
+/// See https://www.sqlite.org/windowfunctions.html
+pub(crate) struct Partition<'a> {
+    keys: &'a [Row],
+    order_by: &'a [OrderingTerm],
+    /// The collation of each ORDER BY term.
+    collations: &'a [Collation],
+    /// The peer group of each row.
+    peer: Vec<usize>,
+    /// The rows of each peer group.
+    groups: Vec<Range<usize>>,
+}
+
+/// A frame bound with its offset evaluated.
+pub(crate) enum Bound {
+    UnboundedPreceding,
+    Preceding(ColumnType),
+    CurrentRow,
+    Following(ColumnType),
+    UnboundedFollowing,
+}
+
+impl<'a> Partition<'a> {
+    pub fn new(keys: &'a [Row], order_by: &'a [OrderingTerm], collations: &'a [Collation]) -> Self {
+        let mut peer = vec![];
+        let mut groups: Vec<Range<usize>> = vec![];
+        for (i, key) in keys.iter().enumerate() {
+            match groups.last_mut() {
+                Some(group)
+                    if compare_keys(&keys[group.start], key, order_by, collations)
+                        == Ordering::Equal =>
+                {
+                    group.end = i + 1;
+                }
+                _ => groups.push(i..i + 1),
+            }
+            peer.push(groups.len() - 1);
+        }
+        Partition {
+            keys,
+            order_by,
+            collations,
+            peer,
+            groups,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn rank(&self, i: usize) -> i64 {
+        self.groups[self.peer[i]].start as i64 + 1
+    }
+
+    pub fn dense_rank(&self, i: usize) -> i64 {
+        self.peer[i] as i64 + 1
+    }
+
+    pub fn percent_rank(&self, i: usize) -> f64 {
+        if self.len() <= 1 {
+            return 0.0;
+        }
+        (self.rank(i) - 1) as f64 / (self.len() - 1) as f64
+    }
+
+    pub fn cume_dist(&self, i: usize) -> f64 {
+        self.groups[self.peer[i]].end as f64 / self.len() as f64
+    }
+
+    /// The bucket of row `i` when the partition is split into `n` buckets
+    /// as evenly as possible, larger buckets first.
+    pub fn ntile(&self, i: usize, n: usize) -> i64 {
+        let size = self.len() / n;
+        let large = self.len() % n;
+        if i < large * (size + 1) {
+            (i / (size + 1)) as i64 + 1
+        } else {
+            ((i - large * (size + 1)) / size) as i64 + large as i64 + 1
+        }
+    }
+
+    /// The rows in the frame of row `i`, in window order.
+    pub fn frame(
+        &self,
+        i: usize,
+        frame: &Frame,
+        start: &Bound,
+        end: &Bound,
+    ) -> Result<Vec<usize>, anyhow::Error> {
+        let first = self.position(i, frame.units, start, true)?;
+        let last = self.position(i, frame.units, end, false)?;
+
+        let group = self.peer[i];
+        Ok((first..last.max(first))
+            .filter(|j| match frame.exclude {
+                FrameExclude::NoOthers => true,
+                FrameExclude::CurrentRow => *j != i,
+                FrameExclude::Group => self.peer[*j] != group,
+                FrameExclude::Ties => *j == i || self.peer[*j] != group,
+            })
+            .collect())
+    }
+
+    /// Where a frame bound falls for row `i`: the first row in the frame
+    /// for the start bound, and one past the last row for the end bound.
+    fn position(
+        &self,
+        i: usize,
+        units: FrameUnits,
+        bound: &Bound,
+        is_start: bool,
+    ) -> Result<usize, anyhow::Error> {
+        let edge = |group: &Range<usize>| if is_start { group.start } else { group.end };
+        let which = if is_start { "starting" } else { "ending" };
+
+        Ok(match bound {
+            Bound::UnboundedPreceding => 0,
+            Bound::UnboundedFollowing => self.len(),
+            Bound::CurrentRow => match units {
+                FrameUnits::Rows => i + !is_start as usize,
+                _ => edge(&self.groups[self.peer[i]]),
+            },
+            Bound::Preceding(offset) | Bound::Following(offset) => {
+                let preceding = matches!(bound, Bound::Preceding(_));
+                match units {
+                    FrameUnits::Rows | FrameUnits::Groups => {
+                        let Some(n) = offset.as_integer().filter(|n| *n >= 0) else {
+                            bail!("frame {which} offset must be a non-negative integer");
+                        };
+                        let n = n as usize;
+                        if units == FrameUnits::Rows {
+                            let row = i + !is_start as usize;
+                            if preceding {
+                                row.saturating_sub(n)
+                            } else {
+                                row.saturating_add(n).min(self.len())
+                            }
+                        } else {
+                            let group = self.peer[i];
+                            match preceding {
+                                true if n > group => 0,
+                                true => edge(&self.groups[group - n]),
+                                false => match self.groups.get(group.saturating_add(n)) {
+                                    Some(g) => edge(g),
+                                    None => self.len(),
+                                },
+                            }
+                        }
+                    }
+                    FrameUnits::Range => self.range_position(i, offset, preceding, is_start)?,
+                }
+            }
+        })
+    }
+
+    /// A RANGE bound `offset PRECEDING` or `offset FOLLOWING`: the frame
+    /// holds the rows whose key is within `offset` of the current row's key.
+    fn range_position(
+        &self,
+        i: usize,
+        offset: &ColumnType,
+        preceding: bool,
+        is_start: bool,
+    ) -> Result<usize, anyhow::Error> {
+        let which = if is_start { "starting" } else { "ending" };
+        if offset.is_null() || offset.to_f64().is_some_and(|n| n < 0.0) {
+            bail!("frame {which} offset must be a non-negative number");
+        }
+        let [term] = self.order_by else {
+            bail!("RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression");
+        };
+
+        // rows with a NULL key only see each other
+        let key = &self.keys[i][0];
+        if key.is_null() {
+            let group = &self.groups[self.peer[i]];
+            return Ok(if is_start { group.start } else { group.end });
+        }
+
+        // preceding rows are smaller keys in ascending order, larger in descending
+        let op = if preceding != term.desc {
+            BinaryOp::Sub
+        } else {
+            BinaryOp::Add
+        };
+        // as in SQLite, the offset does not move a text or blob key, so the
+        // frame of such a row ends at its peers
+        let target = match key {
+            ColumnType::String(_) | ColumnType::Blob(_) => [key.clone()],
+            _ => [arithmetic(op, key, offset)],
+        };
+        let terms = std::slice::from_ref(term);
+        Ok(self.keys.partition_point(|k| {
+            let o = compare_keys(&k[..1], &target, terms, self.collations);
+            if is_start {
+                o == Ordering::Less
+            } else {
+                o != Ordering::Greater
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Expr, FrameBound};
+
+    fn keys(values: &[i64]) -> Vec<Row> {
+        values.iter().map(|v| vec![ColumnType::Int64(*v)]).collect()
+    }
+
+    fn order_by() -> Vec<OrderingTerm> {
+        vec![OrderingTerm {
+            expr: Expr::Literal(ColumnType::Null),
+            desc: false,
+            nulls_first: None,
+        }]
+    }
+
+    fn frame(units: FrameUnits, exclude: FrameExclude) -> Frame {
+        Frame {
+            units,
+            start: FrameBound::CurrentRow,
+            end: FrameBound::CurrentRow,
+            exclude,
+        }
+    }
+
+    #[test]
+    fn test_ranking() {
+        let keys = keys(&[1, 2, 2, 3]);
+        let order_by = order_by();
+        let partition = Partition::new(&keys, &order_by, &[]);
+        let ranks = (0..4).map(|i| partition.rank(i)).collect::<Vec<_>>();
+        assert_eq!(ranks, vec![1, 2, 2, 4]);
+        let dense = (0..4).map(|i| partition.dense_rank(i)).collect::<Vec<_>>();
+        assert_eq!(dense, vec![1, 2, 2, 3]);
+        assert_eq!(partition.cume_dist(1), 0.75);
+        assert_eq!(partition.percent_rank(3), 1.0);
+        let tiles = (0..4).map(|i| partition.ntile(i, 3)).collect::<Vec<_>>();
+        assert_eq!(tiles, vec![1, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_frames() {
+        let keys = keys(&[1, 2, 2, 5, 6]);
+        let order_by = order_by();
+        let partition = Partition::new(&keys, &order_by, &[]);
+        let one = ColumnType::Int64(1);
+
+        let rows = frame(FrameUnits::Rows, FrameExclude::NoOthers);
+        let f = partition.frame(1, &rows, &Bound::Preceding(one.clone()), &Bound::CurrentRow);
+        assert_eq!(f.unwrap(), vec![0, 1]);
+
+        let groups = frame(FrameUnits::Groups, FrameExclude::CurrentRow);
+        let f = partition.frame(
+            2,
+            &groups,
+            &Bound::Preceding(one.clone()),
+            &Bound::CurrentRow,
+        );
+        assert_eq!(f.unwrap(), vec![0, 1]);
+
+        let range = frame(FrameUnits::Range, FrameExclude::Ties);
+        let f = partition.frame(
+            1,
+            &range,
+            &Bound::CurrentRow,
+            &Bound::Following(ColumnType::Int64(3)),
+        );
+        assert_eq!(f.unwrap(), vec![1, 3]);
+
+        let f = partition.frame(
+            4,
+            &range,
+            &Bound::Preceding(one),
+            &Bound::UnboundedFollowing,
+        );
+        assert_eq!(f.unwrap(), vec![3, 4]);
+
+        // text keys are not moved by the offset
+        let mut keys = keys[..2].to_vec();
+        for s in ["a", "a", "b"] {
+            keys.push(vec![ColumnType::String(s.to_string())]);
+        }
+        let partition = Partition::new(&keys, &order_by, &[]);
+        let range = frame(FrameUnits::Range, FrameExclude::NoOthers);
+        let two = ColumnType::Int64(2);
+        let f = partition.frame(
+            3,
+            &range,
+            &Bound::Preceding(two.clone()),
+            &Bound::Following(two),
+        );
+        assert_eq!(f.unwrap(), vec![2, 3]);
+    }
+}